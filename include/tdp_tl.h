@@ -0,0 +1,77 @@
+/* C ABI of tdp-tl, built into the cdylib with `cargo build --release --features ffi`. */
+#ifndef TDP_TL_H
+#define TDP_TL_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Simulation parameters. Start from tdp_tl_params_default(). */
+typedef struct TdpTlParams {
+    /* stop before this layer, 0 for every layer */
+    uint32_t layer;
+    /* maximum distance between deposition points along a move, in millimeters */
+    float step_size;
+    /* consecutive moves are merged into paths of this length, in millimeters */
+    float merge_length;
+    /* moves to coordinates beyond this on any axis are dropped, in millimeters */
+    float coordinate_limit;
+    /* detect bridges, and let them sag */
+    bool bridges;
+    /* bridge sag depth relative to span length */
+    float bridge_sag;
+    /* air gap kept above support material, in millimeters */
+    float support_z_gap;
+} TdpTlParams;
+
+/* Triangle mesh in millimeters: positions and normals as xyz triples, and indices of
+ * triangles. */
+typedef struct TdpTlMesh {
+    const float *positions;
+    const float *normals;
+    size_t vertex_count;
+    const uint32_t *indices;
+    size_t index_count;
+    /* chunk of the mesh, columns [x * 64, (x + 1) * 64) along X and so on */
+    int32_t chunk[2];
+} TdpTlMesh;
+
+typedef struct TdpTlSimulation TdpTlSimulation;
+
+/* Called before each layer change with meshes of chunks changed since the last call,
+ * borrowed for the call only. A chunk mesh replaces earlier meshes of the chunk.
+ * Returns non-zero to stop the simulation. */
+typedef int32_t (*TdpTlLayerCallback)(void *user, uint32_t layer, const TdpTlMesh *mesh);
+
+TdpTlParams tdp_tl_params_default(void);
+
+/* Message of the last failed call on this thread, or NULL. */
+const char *tdp_tl_last_error(void);
+
+/* Simulates len bytes of gcode. Returns NULL on errors, or if the callback stopped the
+ * simulation. params and callback may be NULL. */
+TdpTlSimulation *tdp_tl_simulate(const uint8_t *gcode, size_t len, const TdpTlParams *params,
+                                 TdpTlLayerCallback callback, void *user);
+
+/* Number of occupied voxels. */
+size_t tdp_tl_blocks(const TdpTlSimulation *sim);
+
+/* 1 if voxel [x, y, z] is occupied. Voxels are 0.04mm cubes, centered at
+ * [x, y, z] * 0.04 in millimeters. */
+int32_t tdp_tl_occupied(const TdpTlSimulation *sim, int32_t x, int32_t y, int32_t z);
+
+/* Meshes every exposed face of the model. Free with tdp_tl_mesh_free(). */
+TdpTlMesh *tdp_tl_mesh(const TdpTlSimulation *sim);
+void tdp_tl_mesh_free(TdpTlMesh *mesh);
+
+void tdp_tl_free(TdpTlSimulation *sim);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,174 @@
+use super::{storage, Voxel, LAYER_HEIGHT, UNIT};
+use anyhow::Result;
+use rayon::prelude::*;
+use std::io::Write;
+
+/// Adhesion risk of a part, from its height over the diameter of a disc with its
+/// contact area: low below 2, medium below 4, high from there. A rough rule for parts
+/// knocked loose by the nozzle or pulled up by warping, not a force model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Risk {
+    Low,
+    Medium,
+    High,
+}
+
+impl Risk {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+}
+
+/// Bed contact of a part: a connected region of the first layer, with its brim or raft.
+#[derive(Clone, Debug)]
+pub struct Contact {
+    /// center of the contact area, in millimeters
+    pub center: [f32; 2],
+    /// area touching the bed, in square millimeters
+    pub area: f32,
+    /// top of the part above the contact, from the bed in millimeters
+    pub height: f32,
+}
+
+impl Contact {
+    /// Height over the diameter of a disc with the contact area.
+    pub fn ratio(&self) -> f32 {
+        let diameter = 2f32 * (self.area / std::f32::consts::PI).sqrt();
+        self.height / diameter
+    }
+
+    pub fn risk(&self) -> Risk {
+        match self.ratio() {
+            r if r < 2f32 => Risk::Low,
+            r if r < 4f32 => Risk::Medium,
+            _ => Risk::High,
+        }
+    }
+}
+
+/// Contacts of parts with the bed, from 8-connected first layer columns, largest first.
+/// Regions no taller than two layers, like skirts and purge lines with beads bulging
+/// above the first layer, are left out.
+pub fn contacts<V: Voxel + Sync>(v: &V) -> Vec<Contact> {
+    let bb = v.bounding_box();
+    if bb.count == 0 {
+        return Vec::new();
+    }
+    let (min, max) = (bb.bound_min, bb.bound_max);
+    let h = (LAYER_HEIGHT / UNIT).round() as i32;
+    let first = min[2] + h;
+    let width = (max[0] - min[0] + 1) as usize;
+
+    // top of each column with a first layer voxel, rows from min
+    let tops = (min[1]..=max[1])
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            (min[0]..=max[0]).map(move |x| {
+                let column = v.column(x, y);
+                match column.first() {
+                    Some(r) if r.start < first => column.last().map(|r| r.end),
+                    _ => None,
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut visited = vec![false; tops.len()];
+    let mut contacts = Vec::new();
+    for start in 0..tops.len() {
+        if tops[start].is_none() || visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![start];
+        let (mut cells, mut sum, mut top) = (0usize, [0f64; 2], min[2]);
+        while let Some(i) = stack.pop() {
+            let (x, y) = ((i % width) as i32, (i / width) as i32);
+            cells += 1;
+            sum[0] += x as f64;
+            sum[1] += y as f64;
+            top = top.max(tops[i].unwrap_or(top));
+            // the cell itself is visited already
+            for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= width as i32 {
+                    continue;
+                }
+                let j = ny as usize * width + nx as usize;
+                if j < tops.len() && tops[j].is_some() && !visited[j] {
+                    visited[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+        if top <= first + h {
+            continue;
+        }
+        let center = [0, 1].map(|a| (sum[a] / cells as f64) as f32 + (min[a] as f32 + 0.5));
+        contacts.push(Contact {
+            center: center.map(|c| c * UNIT),
+            area: cells as f32 * UNIT * UNIT,
+            height: (top - min[2]) as f32 * UNIT,
+        });
+    }
+    contacts.sort_by(|a, b| b.area.total_cmp(&a.area));
+    contacts
+}
+
+pub fn write_csv(contacts: &[Contact], path: &str) -> Result<()> {
+    let mut w = storage::create(path)?;
+    writeln!(w, "center_x,center_y,area,height,ratio,risk")?;
+    for c in contacts {
+        writeln!(
+            w,
+            "{:.3},{:.3},{:.3},{:.3},{:.3},{}",
+            c.center[0],
+            c.center[1],
+            c.area,
+            c.height,
+            c.ratio(),
+            c.risk().name()
+        )?;
+    }
+    w.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_contacts() {
+        let mut v = MonotonicVoxel::default();
+        let h = (LAYER_HEIGHT / UNIT).round() as i32;
+        // 10x10 voxel tower with a brim, 40 layers tall
+        for x in -5..15 {
+            for y in -5..15 {
+                let tower = (0..10).contains(&x) && (0..10).contains(&y);
+                let top = if tower { 40 * h } else { h };
+                for z in 0..top {
+                    v.add([x, y, z].into());
+                }
+            }
+        }
+        // skirt around it, bulging above the first layer
+        for x in -10..20 {
+            for z in 0..h + 1 {
+                v.add([x, -10, z].into());
+            }
+        }
+
+        let contacts = contacts(&v);
+        assert_eq!(contacts.len(), 1);
+        let c = &contacts[0];
+        assert_eq!(c.area, 400f32 * UNIT * UNIT);
+        assert_eq!(c.height, (40 * h) as f32 * UNIT);
+        assert!((c.center[0] - 5f32 * UNIT).abs() < 1e-4);
+        assert_eq!(c.risk(), Risk::High);
+    }
+}
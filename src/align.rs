@@ -0,0 +1,498 @@
+use super::{storage, surface, ExportMode, Voxel, VoxelIdx, UNIT};
+use anyhow::Result;
+use nalgebra::{Matrix3, Matrix6, Rotation3, Vector3, Vector6};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+
+/// Scan points kept for refining the alignment, after voxelizing.
+const MAX_POINTS: usize = 50_000;
+
+/// Initial turns around Z.
+const TURNS: usize = 24;
+
+/// Scan points and steps from each initial turn, before refining the best one.
+const COARSE_POINTS: usize = 2_000;
+const COARSE_STEPS: usize = 8;
+
+/// Reads points of a scan in millimeters: vertices of obj, stl or ascii ply meshes, or
+/// xyz point clouds with a point per line.
+pub fn read_points(path: &str) -> Result<Vec<Vector3<f32>>> {
+    let ext = std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let mut data = Vec::new();
+    std::fs::File::open(path)?.read_to_end(&mut data)?;
+
+    let points = match ext.as_str() {
+        "stl" if is_binary_stl(&data) => read_stl(&data)?,
+        "stl" => read_lines(&data, "vertex")?,
+        "obj" => read_lines(&data, "v")?,
+        "ply" => read_ply(&data)?,
+        _ => read_lines(&data, "")?,
+    };
+    anyhow::ensure!(!points.is_empty(), "no points in {}", path);
+    Ok(points)
+}
+
+fn parse_xyz(fields: &[&str]) -> Option<Vector3<f32>> {
+    let v = fields
+        .iter()
+        .take(3)
+        .map(|f| f.parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    (v.len() == 3).then(|| Vector3::new(v[0], v[1], v[2]))
+}
+
+/// Points of lines starting with `keyword`, or of every line if empty.
+fn read_lines(data: &[u8], keyword: &str) -> Result<Vec<Vector3<f32>>> {
+    let mut points = Vec::new();
+    for line in BufReader::new(data).lines() {
+        let line = line?;
+        let fields = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|f| !f.is_empty())
+            .collect::<Vec<_>>();
+        let fields = match (keyword, fields.first()) {
+            ("", _) => &fields[..],
+            (k, Some(first)) if *first == k => &fields[1..],
+            _ => continue,
+        };
+        points.extend(parse_xyz(fields));
+    }
+    Ok(points)
+}
+
+/// Binary stl headers may start with `solid` too, sizes tell them apart.
+fn is_binary_stl(data: &[u8]) -> bool {
+    data.len() >= 84
+        && data.len() == 84 + 50 * u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize
+}
+
+fn read_stl(data: &[u8]) -> Result<Vec<Vector3<f32>>> {
+    anyhow::ensure!(data.len() >= 84, "truncated stl");
+    let n = u32::from_le_bytes(data[80..84].try_into()?) as usize;
+    anyhow::ensure!(data.len() >= 84 + n * 50, "truncated stl");
+    let f = |o: usize| f32::from_le_bytes(data[o..o + 4].try_into().unwrap());
+    let mut points = Vec::with_capacity(n * 3);
+    for t in 0..n {
+        // normal, then 3 vertices
+        let base = 84 + t * 50 + 12;
+        for v in 0..3 {
+            let o = base + v * 12;
+            points.push(Vector3::new(f(o), f(o + 4), f(o + 8)));
+        }
+    }
+    Ok(points)
+}
+
+/// Vertices of ascii ply, with x, y and z properties in any order.
+fn read_ply(data: &[u8]) -> Result<Vec<Vector3<f32>>> {
+    let text = std::str::from_utf8(data)?;
+    let mut lines = text.lines();
+    let (mut vertices, mut props, mut in_vertex) = (0, Vec::new(), false);
+    for line in lines.by_ref() {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        match fields.as_slice() {
+            ["format", format, ..] => {
+                anyhow::ensure!(*format == "ascii", "only ascii ply is supported")
+            }
+            ["element", "vertex", n] => {
+                vertices = n.parse()?;
+                in_vertex = true;
+            }
+            ["element", ..] => in_vertex = false,
+            ["property", _, name] if in_vertex => props.push(name.to_string()),
+            ["end_header"] => break,
+            _ => (),
+        }
+    }
+    let idx = ["x", "y", "z"].map(|a| props.iter().position(|p| p == a));
+    let [Some(ix), Some(iy), Some(iz)] = idx else {
+        anyhow::bail!("ply without x, y and z");
+    };
+    let mut points = Vec::with_capacity(vertices);
+    for line in lines.take(vertices) {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if let Some(p) = parse_xyz(&[fields[ix], fields[iy], fields[iz]]) {
+            points.push(p);
+        }
+    }
+    Ok(points)
+}
+
+/// Mean of points in each cell of `cell` millimeters, so dense and sparse regions of
+/// scans weigh alike.
+pub fn voxelize(points: &[Vector3<f32>], cell: f32) -> Vec<Vector3<f32>> {
+    let mut cells = HashMap::<[i32; 3], (Vector3<f32>, u32)>::new();
+    for p in points {
+        let key = [0, 1, 2].map(|i| (p[i] / cell).floor() as i32);
+        let e = cells.entry(key).or_insert((Vector3::zeros(), 0));
+        e.0 += p;
+        e.1 += 1;
+    }
+    let mut points = cells
+        .into_iter()
+        .map(|(key, (sum, n))| (key, sum / n as f32))
+        .collect::<Vec<_>>();
+    points.sort_by_key(|(key, _)| *key);
+    points.into_iter().map(|(_, p)| p).collect()
+}
+
+/// Outer voxel faces of a simulation, a point for each cell and face direction.
+#[derive(Clone, Debug, Default)]
+pub struct Surface {
+    /// in millimeters
+    pub points: Vec<Vector3<f32>>,
+    /// unit normals of points, up to sign
+    pub normals: Vec<Vector3<f32>>,
+}
+
+impl Surface {
+    /// Centers of faces of `v` seen from outside, like by scanners, voxelized by `cell`
+    /// millimeters.
+    pub fn of<V: Voxel>(v: &V, cell: f32) -> Self {
+        let model = surface::to_model(v, ExportMode::Silhouette);
+        let mut by_axis = [vec![], vec![], vec![]];
+        for face in &model.faces {
+            let corners = face.map(|i| {
+                let c = model.vertices[i];
+                Vector3::new(c[0], c[1], c[2]).cast::<f32>()
+            });
+            // faces are flat along their normal axis
+            let axis = (0..3)
+                .find(|a| corners.iter().all(|c| c[*a] == corners[0][*a]))
+                .unwrap_or(2);
+            by_axis[axis].push(corners.iter().sum::<Vector3<f32>>() * (UNIT / 4f32));
+        }
+
+        let mut surface = Self::default();
+        for (axis, centers) in by_axis.iter().enumerate() {
+            let points = voxelize(centers, cell);
+            let mut normal = Vector3::zeros();
+            normal[axis] = 1f32;
+            surface
+                .normals
+                .extend(std::iter::repeat_n(normal, points.len()));
+            surface.points.extend(points);
+        }
+        surface
+    }
+}
+
+/// Points hashed into cells, for nearest neighbor queries.
+struct Nearest<'a> {
+    points: &'a [Vector3<f32>],
+    cell: f32,
+    cells: HashMap<[i32; 3], Vec<u32>>,
+    /// minimum and maximum keys of cells
+    bounds: [[i32; 3]; 2],
+}
+
+impl<'a> Nearest<'a> {
+    fn new(points: &'a [Vector3<f32>], cell: f32) -> Self {
+        let mut cells = HashMap::<_, Vec<u32>>::new();
+        let mut bounds = [[i32::MAX; 3], [i32::MIN; 3]];
+        for (i, p) in points.iter().enumerate() {
+            let key = Self::key(p, cell);
+            for a in 0..3 {
+                bounds[0][a] = bounds[0][a].min(key[a]);
+                bounds[1][a] = bounds[1][a].max(key[a]);
+            }
+            cells.entry(key).or_default().push(i as u32);
+        }
+        Self {
+            points,
+            cell,
+            cells,
+            bounds,
+        }
+    }
+
+    fn key(p: &Vector3<f32>, cell: f32) -> [i32; 3] {
+        [0, 1, 2].map(|i| (p[i] / cell).floor() as i32)
+    }
+
+    /// Nearest point within `rings` cells around `p`, and its distance.
+    fn find(&self, p: &Vector3<f32>, rings: i32) -> Option<(usize, f32)> {
+        let c = Self::key(p, self.cell);
+        // no cells past the ring covering all of them
+        let [lo, hi] = self.bounds;
+        let rings = (0..3)
+            .map(|a| (c[a] - lo[a]).max(hi[a] - c[a]))
+            .max()
+            .unwrap_or(0)
+            .min(rings);
+        let mut best: Option<(usize, f32)> = None;
+        for r in 0..=rings {
+            // points in ring r are at least (r - 1) cells away
+            if let Some((_, d)) = best {
+                if d < (r - 1) as f32 * self.cell {
+                    break;
+                }
+            }
+            for dz in -r..=r {
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        if dx.abs().max(dy.abs()).max(dz.abs()) != r {
+                            continue;
+                        }
+                        let Some(ids) = self.cells.get(&[c[0] + dx, c[1] + dy, c[2] + dz]) else {
+                            continue;
+                        };
+                        for &i in ids {
+                            let d = (self.points[i as usize] - p).magnitude();
+                            if best.is_none_or(|(_, b)| d < b) {
+                                best = Some((i as usize, d));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Rigid transform from scan to model coordinates.
+#[derive(Clone, Debug)]
+pub struct Alignment {
+    pub rotation: Matrix3<f32>,
+    pub translation: Vector3<f32>,
+    /// root mean square distance of matched points, in millimeters
+    pub rms: f32,
+}
+
+impl Alignment {
+    pub fn apply(&self, p: &Vector3<f32>) -> Vector3<f32> {
+        self.rotation * p + self.translation
+    }
+}
+
+fn centroid(points: &[Vector3<f32>]) -> Vector3<f32> {
+    points.iter().sum::<Vector3<f32>>() / points.len().max(1) as f32
+}
+
+/// Aligns `scan` to the `model` surface: centroids first, then iterative closest points
+/// from a few turns around Z, as parts are scanned in any orientation on the turntable.
+/// The turn with the least error after a few steps on fewer points is refined. Points are expected
+/// `cell` millimeters apart, as from `voxelize`.
+pub fn align(scan: &[Vector3<f32>], model: &Surface, cell: f32, iterations: usize) -> Alignment {
+    let scan = sample(scan, MAX_POINTS);
+    let coarse_scan = sample(&scan, COARSE_POINTS);
+    let c_scan = centroid(&scan);
+    let c_model = centroid(&model.points);
+    let nearest = Nearest::new(&model.points, cell * 2f32);
+
+    let refine = |points: &[Vector3<f32>], mut alignment: Alignment, iterations: usize| {
+        for _ in 0..iterations {
+            let Some((next, motion)) = icp_step(points, model, &nearest, &alignment) else {
+                break;
+            };
+            alignment = next;
+            if motion < cell / 100f32 {
+                break;
+            }
+        }
+        alignment
+    };
+
+    let coarse = (0..TURNS)
+        .into_par_iter()
+        .map(|turn| {
+            let yaw = Rotation3::from_axis_angle(
+                &Vector3::z_axis(),
+                turn as f32 * std::f32::consts::TAU / TURNS as f32,
+            );
+            let rotation = *yaw.matrix();
+            let alignment = Alignment {
+                rotation,
+                translation: c_model - rotation * c_scan,
+                rms: f32::MAX,
+            };
+            refine(&coarse_scan, alignment, COARSE_STEPS)
+        })
+        .min_by(|a, b| a.rms.total_cmp(&b.rms))
+        .unwrap();
+    refine(&scan, coarse, iterations)
+}
+
+/// Every n-th point, for at most `max` points.
+fn sample(points: &[Vector3<f32>], max: usize) -> Vec<Vector3<f32>> {
+    let step = points.len().div_ceil(max).max(1);
+    points.iter().step_by(step).copied().collect()
+}
+
+/// Matches points to their nearest model points, rejects the farthest matches as
+/// outliers, and solves a small motion minimizing distances to the planes of the
+/// matches. Faces of voxels are mostly flat, where planes converge in far fewer steps
+/// than distances to points. Returns the next alignment, and how far it moved points.
+fn icp_step(
+    scan: &[Vector3<f32>],
+    model: &Surface,
+    nearest: &Nearest,
+    current: &Alignment,
+) -> Option<(Alignment, f32)> {
+    let mut pairs = scan
+        .par_iter()
+        .filter_map(|p| {
+            let q = current.apply(p);
+            nearest.find(&q, 16).map(|(i, d)| (q, i, d))
+        })
+        .collect::<Vec<_>>();
+    if pairs.len() < 6 {
+        return None;
+    }
+    pairs.sort_by(|a, b| a.2.total_cmp(&b.2));
+    pairs.truncate(pairs.len() * 9 / 10);
+
+    // linearized: rotation by small angles around the center of matches, then translation
+    let center = pairs.iter().map(|(q, _, _)| q).sum::<Vector3<f32>>() / pairs.len() as f32;
+    let mut ata = Matrix6::<f64>::zeros();
+    let mut atb = Vector6::<f64>::zeros();
+    for (q, i, _) in &pairs {
+        let (m, n) = (model.points[*i], model.normals[*i]);
+        let c = (q - center).cross(&n);
+        let row = Vector6::new(c[0], c[1], c[2], n[0], n[1], n[2]).cast::<f64>();
+        ata += row * row.transpose();
+        atb += row * ((m - q).dot(&n) as f64);
+    }
+    let x = ata.cholesky()?.solve(&atb).cast::<f32>();
+    let (w, t) = (
+        Vector3::new(x[0], x[1], x[2]),
+        Vector3::new(x[3], x[4], x[5]),
+    );
+    let step = Rotation3::new(w);
+    let shift = center - step * center + t;
+    let radius = pairs
+        .iter()
+        .map(|(q, _, _)| (q - center).magnitude())
+        .fold(0f32, f32::max);
+
+    let rms = (pairs
+        .iter()
+        .map(|(q, i, _)| {
+            let d = (step * q + shift - model.points[*i]).dot(&model.normals[*i]);
+            d * d
+        })
+        .sum::<f32>()
+        / pairs.len() as f32)
+        .sqrt();
+    let alignment = Alignment {
+        rotation: step.matrix() * current.rotation,
+        translation: step * current.translation + shift,
+        rms,
+    };
+    // farthest any match moved
+    Some((alignment, t.magnitude() + w.magnitude() * radius))
+}
+
+/// Distances of aligned scan points to the simulated surface.
+#[derive(Clone, Debug)]
+pub struct Deviation {
+    /// per scan point in model coordinates, negative inside simulated material
+    pub points: Vec<(Vector3<f32>, f32)>,
+    pub mean: f32,
+    pub rms: f32,
+    pub p95: f32,
+    pub max: f32,
+}
+
+impl Deviation {
+    pub fn measure<V: Voxel + Sync>(
+        v: &V,
+        scan: &[Vector3<f32>],
+        model: &Surface,
+        cell: f32,
+        alignment: &Alignment,
+    ) -> Self {
+        let nearest = Nearest::new(&model.points, 1f32);
+        let points = scan
+            .par_iter()
+            .map(|p| {
+                let q = alignment.apply(p);
+                let d = match nearest.find(&q, 64) {
+                    // across the face, unless past the cell of the nearest point
+                    Some((i, _)) => {
+                        let off = q - model.points[i];
+                        let across = off.dot(&model.normals[i]);
+                        let along = (off - model.normals[i] * across).magnitude();
+                        across.abs().max(along - cell)
+                    }
+                    None => f32::MAX,
+                };
+                let idx = VoxelIdx::new([0, 1, 2].map(|i| (q[i] / UNIT).floor() as i32));
+                let sign = if v.occupied(idx) { -1f32 } else { 1f32 };
+                (q, d * sign)
+            })
+            .collect::<Vec<_>>();
+
+        let n = points.len().max(1) as f32;
+        let mut abs = points.iter().map(|(_, d)| d.abs()).collect::<Vec<_>>();
+        abs.sort_by(|a, b| a.total_cmp(b));
+        let p95 = abs
+            .get((abs.len() as f32 * 0.95) as usize)
+            .or(abs.last())
+            .copied()
+            .unwrap_or(0f32);
+        Self {
+            mean: points.iter().map(|(_, d)| d).sum::<f32>() / n,
+            rms: (abs.iter().map(|d| d * d).sum::<f32>() / n).sqrt(),
+            p95,
+            max: abs.last().copied().unwrap_or(0f32),
+            points,
+        }
+    }
+
+    /// Writes aligned scan points and their deviations as csv.
+    pub fn write_csv(&self, path: &str) -> Result<()> {
+        use std::io::Write;
+        let mut w = storage::create(path)?;
+        writeln!(w, "x,y,z,deviation")?;
+        for (p, d) in &self.points {
+            writeln!(w, "{:.3},{:.3},{:.3},{:.4}", p[0], p[1], p[2], d)?;
+        }
+        w.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_align() {
+        // 2x1.5x1mm box
+        let mut v = MonotonicVoxel::default();
+        for x in 0..50 {
+            for y in 0..38 {
+                for z in 0..25 {
+                    v.add([x, y, z].into());
+                }
+            }
+        }
+        let model = Surface::of(&v, 0.2);
+
+        // scanned turned by 93 degrees, and moved
+        let turn = Rotation3::from_axis_angle(&Vector3::z_axis(), 93f32.to_radians());
+        let scan = model
+            .points
+            .iter()
+            .map(|p| turn * p + Vector3::new(30f32, -5f32, 2f32))
+            .collect::<Vec<_>>();
+        let alignment = align(&scan, &model, 0.2, 50);
+        assert!(alignment.rms < 0.05, "rms={}", alignment.rms);
+
+        let deviation = Deviation::measure(&v, &scan, &model, 0.2, &alignment);
+        assert!(deviation.p95 < 0.1, "{:?}", deviation.p95);
+
+        let points = read_lines(b"v 1 2 3\nvn 0 0 1\nv 4,5,6\n", "v").unwrap();
+        assert_eq!(
+            points,
+            vec![Vector3::new(1., 2., 3.), Vector3::new(4., 5., 6.)]
+        );
+    }
+}
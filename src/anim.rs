@@ -0,0 +1,421 @@
+use super::overlay::Stats;
+use super::render::{self, Still};
+use super::{read_gcode, simulate_gcode, storage, BoundingBox, Estimate, MonotonicVoxel};
+use super::{Params, Simulation, Voxel};
+use anyhow::Result;
+use log::*;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Seconds the last frame stays, so loops pause on the finished print.
+const HOLD: f32 = 1.5;
+/// Margin of the planned frame around extruding moves in millimeters, for bead widths.
+const MARGIN: f32 = 1.0;
+
+/// Animated image formats, by file extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Gif,
+    Apng,
+}
+
+impl Kind {
+    /// `.gif`, or `.png` and `.apng` for apng.
+    pub fn of_path(path: &str) -> Result<Self> {
+        let lower = path.to_ascii_lowercase();
+        if lower.ends_with(".gif") {
+            Ok(Self::Gif)
+        } else if lower.ends_with(".png") || lower.ends_with(".apng") {
+            Ok(Self::Apng)
+        } else {
+            anyhow::bail!("{}: expected .gif, .png or .apng", path)
+        }
+    }
+}
+
+/// Rgb frames of the same size, looping at `fps`.
+pub struct Animation {
+    pub width: u32,
+    pub height: u32,
+    pub fps: f32,
+    pub frames: Vec<Vec<u8>>,
+}
+
+impl Animation {
+    /// Seconds frame `i` is shown.
+    fn delay(&self, i: usize) -> f32 {
+        let delay = 1f32 / self.fps;
+        match i + 1 == self.frames.len() {
+            true => delay.max(HOLD),
+            false => delay,
+        }
+    }
+
+    /// Writes frames to `path`, as gif or apng by its extension.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let kind = Kind::of_path(path)?;
+        anyhow::ensure!(!self.frames.is_empty(), "{}: no frames", path);
+        let mut sink = storage::create(path)?;
+        match kind {
+            Kind::Gif => self.write_gif(&mut sink)?,
+            Kind::Apng => self.write_apng(&mut sink)?,
+        }
+        sink.finish()
+    }
+
+    pub fn write_apng<W: Write>(&self, w: W) -> Result<()> {
+        let mut encoder = png::Encoder::new(w, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        // loops forever
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+        let mut writer = encoder.write_header()?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let ms = (self.delay(i) * 1000f32)
+                .round()
+                .clamp(1f32, u16::MAX as f32);
+            writer.set_frame_delay(ms as u16, 1000)?;
+            writer.write_image_data(frame)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Writes frames as gif, with a palette of the 256 most common colors of all frames.
+    pub fn write_gif<W: Write>(&self, mut w: W) -> Result<()> {
+        anyhow::ensure!(
+            self.width <= u16::MAX as u32 && self.height <= u16::MAX as u32,
+            "too large for gif: {}x{}",
+            self.width,
+            self.height
+        );
+        let size = [self.width as u16, self.height as u16].map(u16::to_le_bytes);
+        let (palette, lookup) = quantize(&self.frames);
+
+        w.write_all(b"GIF89a")?;
+        // logical screen with a global color table of 256 entries
+        w.write_all(&size.concat())?;
+        w.write_all(&[0xf7, 0, 0])?;
+        for i in 0..256 {
+            w.write_all(&palette.get(i).copied().unwrap_or_default())?;
+        }
+        // loops forever
+        w.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        for (i, frame) in self.frames.iter().enumerate() {
+            // graphic control extension, with the delay in hundredths of seconds
+            let delay = (self.delay(i) * 100f32)
+                .round()
+                .clamp(1f32, u16::MAX as f32);
+            w.write_all(&[0x21, 0xf9, 0x04, 0x00])?;
+            w.write_all(&(delay as u16).to_le_bytes())?;
+            w.write_all(&[0, 0])?;
+            // image descriptor of the whole screen, without a local color table
+            w.write_all(&[0x2c, 0, 0, 0, 0])?;
+            w.write_all(&size.concat())?;
+            w.write_all(&[0])?;
+
+            let indices = frame.chunks(3).map(|p| lookup[bin(p)]).collect::<Vec<_>>();
+            w.write_all(&[8])?;
+            for block in lzw(&indices).chunks(255) {
+                w.write_all(&[block.len() as u8])?;
+                w.write_all(block)?;
+            }
+            w.write_all(&[0])?;
+        }
+        w.write_all(&[0x3b])?;
+        Ok(())
+    }
+}
+
+/// Color of a pixel at 4 bits per channel.
+fn bin(p: &[u8]) -> usize {
+    ((p[0] as usize >> 4) << 8) | ((p[1] as usize >> 4) << 4) | (p[2] as usize >> 4)
+}
+
+/// Palette of up to 256 colors of `frames`, the means of the most common bins, and the
+/// palette index of each bin.
+fn quantize(frames: &[Vec<u8>]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut sums = vec![([0u64; 3], 0u64); 4096];
+    for p in frames.iter().flat_map(|f| f.chunks(3)) {
+        let (sum, count) = &mut sums[bin(p)];
+        for i in 0..3 {
+            sum[i] += p[i] as u64;
+        }
+        *count += 1;
+    }
+    let mut bins = (0..4096).filter(|b| sums[*b].1 > 0).collect::<Vec<_>>();
+    bins.sort_by_key(|b| std::cmp::Reverse(sums[*b].1));
+    bins.truncate(256);
+    let palette = bins
+        .iter()
+        .map(|b| {
+            let (sum, count) = sums[*b];
+            sum.map(|c| (c / count) as u8)
+        })
+        .collect::<Vec<_>>();
+
+    // other bins take the nearest color to their center
+    let mut lookup = (0..4096usize)
+        .map(|b| {
+            let center = [b >> 8, (b >> 4) & 15, b & 15].map(|c| (c * 16 + 8) as i32);
+            let distance = |c: &[u8; 3]| {
+                (0..3)
+                    .map(|i| (c[i] as i32 - center[i]).pow(2))
+                    .sum::<i32>()
+            };
+            (0..palette.len())
+                .min_by_key(|i| distance(&palette[*i]))
+                .unwrap_or(0) as u8
+        })
+        .collect::<Vec<_>>();
+    for (i, b) in bins.iter().enumerate() {
+        lookup[*b] = i as u8;
+    }
+    (palette, lookup)
+}
+
+/// Codes packed from the least significant bit.
+#[derive(Default)]
+struct Bits {
+    out: Vec<u8>,
+    acc: u32,
+    len: u32,
+}
+
+impl Bits {
+    fn push(&mut self, code: u16, size: u32) {
+        self.acc |= (code as u32) << self.len;
+        self.len += size;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// Gif lzw data of 8-bit `indices`, without the sub-block framing. Codes grow from 9 to
+/// 12 bits, and the table is cleared once full.
+fn lzw(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    const FIRST: u16 = 258;
+    const MAX: u16 = 4096;
+
+    let mut bits = Bits::default();
+    let mut table = HashMap::<(u16, u8), u16>::new();
+    let (mut next, mut size) = (FIRST, 9);
+    bits.push(CLEAR, size);
+    let Some((&first, rest)) = indices.split_first() else {
+        bits.push(END, size);
+        return bits.finish();
+    };
+
+    let mut prefix = first as u16;
+    for &k in rest {
+        if let Some(&code) = table.get(&(prefix, k)) {
+            prefix = code;
+            continue;
+        }
+        bits.push(prefix, size);
+        // decoders add the entry of a code on the code after it, so they widen codes
+        // after reading the code at which the table reaches the next power of two
+        if next == 1 << size && size < 12 {
+            size += 1;
+        }
+        if next == MAX {
+            bits.push(CLEAR, size);
+            table.clear();
+            (next, size) = (FIRST, 9);
+        } else {
+            table.insert((prefix, k), next);
+            next += 1;
+        }
+        prefix = k as u16;
+    }
+    bits.push(prefix, size);
+    if next == 1 << size && size < 12 {
+        size += 1;
+    }
+    bits.push(END, size);
+    bits.finish()
+}
+
+/// Simulates gcode of `filename` until `layer`, and writes a frame of every `every`
+/// layers and of the end to `out`, as gif or apng by its extension. The camera frames
+/// extruding moves of a pre-scan, so it stays put across frames.
+pub fn preview(
+    filename: &str,
+    layer: usize,
+    params: &Params,
+    still: &Still,
+    every: usize,
+    fps: f32,
+    out: &str,
+) -> Result<()> {
+    Kind::of_path(out)?;
+    anyhow::ensure!(every > 0, "every must be positive");
+    anyhow::ensure!(fps > 0f32, "fps must be positive");
+    let estimate = Estimate::scan(&read_gcode(filename)?, layer);
+    let planned = estimate.bounds.map(|[lo, hi]| {
+        let lo = [lo[0] - MARGIN, lo[1] - MARGIN, 0f32];
+        BoundingBox::of_mm(lo, hi.map(|c| c + MARGIN))
+    });
+    let total = estimate.volumes.iter().sum::<f32>();
+
+    let (width, height) = still.size();
+    let mut anim = Animation {
+        width,
+        height,
+        fps,
+        frames: Vec::new(),
+    };
+    let mut capture = |sim: &Simulation<MonotonicVoxel>, stats: &Stats| -> Result<()> {
+        let v = &sim.voxel;
+        if v.bounding_box().count == 0 {
+            return Ok(());
+        }
+        let frame = planned.as_ref().unwrap_or(v.bounding_box());
+        let colors = sim.colors.as_ref();
+        anim.frames
+            .push(render::render_pixels(v, colors, still, frame, Some(stats))?);
+        debug!(
+            "preview: layer={}, frames={}",
+            stats.layer,
+            anim.frames.len()
+        );
+        Ok(())
+    };
+    let stats = |sim: &Simulation<MonotonicVoxel>, layer_idx: usize| {
+        let done = estimate.volumes.iter().take(layer_idx).sum::<f32>();
+        Stats {
+            layer: layer_idx,
+            layers: Some(estimate.volumes.len()),
+            z: sim.nozzle[2],
+            time: sim.time,
+            progress: (total > 0f32).then(|| done / total),
+        }
+    };
+
+    let mut last = 0;
+    let sim = simulate_gcode::<MonotonicVoxel, _>(filename, layer, params, |sim, layer_idx| {
+        last = layer_idx;
+        match layer_idx % every {
+            0 => capture(sim, &stats(sim, layer_idx)),
+            _ => Ok(()),
+        }
+    })?;
+    capture(&sim, &stats(&sim, last + 1))?;
+
+    info!("preview: frames={}, out={}", anim.frames.len(), out);
+    anim.write(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Minimal gif lzw decoder, to check the encoder against.
+    fn unlzw(data: &[u8]) -> Vec<u8> {
+        let mut table = (0..256).map(|i| vec![i as u8]).collect::<Vec<_>>();
+        table.extend([vec![], vec![]]);
+        let (mut pos, mut size) = (0usize, 9usize);
+        let mut out = Vec::new();
+        let mut prev: Option<Vec<u8>> = None;
+        loop {
+            let code = (0..size).fold(0usize, |c, i| {
+                let bit = (data[(pos + i) / 8] >> ((pos + i) % 8)) & 1;
+                c | (bit as usize) << i
+            });
+            pos += size;
+            match code {
+                256 => {
+                    table.truncate(258);
+                    size = 9;
+                    prev = None;
+                    continue;
+                }
+                257 => return out,
+                _ => (),
+            }
+            let entry = match (table.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(p)) => [p.clone(), vec![p[0]]].concat(),
+                (None, None) => panic!("unknown first code {}", code),
+            };
+            if let Some(p) = prev {
+                if table.len() < 4096 {
+                    table.push([p, vec![entry[0]]].concat());
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            out.extend(&entry);
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    pub fn test_anim() {
+        // repetitive and noisy data, over a full table and its clear
+        let data = (0..20000u32)
+            .map(|i| match i % 3000 < 1500 {
+                true => (i % 7) as u8,
+                false => (render::random(i) * 256f32) as u8,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(unlzw(&lzw(&data)), data);
+        assert_eq!(unlzw(&lzw(&[])), Vec::<u8>::new());
+
+        let frames = (0..3u8)
+            .map(|f| {
+                (0..4 * 2)
+                    .flat_map(|p| [p * 30, f * 100, 255 - p])
+                    .collect()
+            })
+            .collect::<Vec<_>>();
+        let anim = Animation {
+            width: 4,
+            height: 2,
+            fps: 10.0,
+            frames,
+        };
+        let (palette, lookup) = quantize(&anim.frames);
+        assert_eq!(palette.len(), 3 * 8);
+        assert_eq!(
+            palette[lookup[bin(&[60, 200, 253])] as usize],
+            [60, 200, 253]
+        );
+
+        let mut gif = Vec::new();
+        anim.write_gif(&mut gif).unwrap();
+        assert!(gif.starts_with(b"GIF89a\x04\x00\x02\x00") && gif.ends_with(b"\x3b"));
+        // 3 frames, the last held longer
+        let delays = gif
+            .windows(4)
+            .filter(|w| w[..3] == [0x21, 0xf9, 0x04])
+            .count();
+        assert_eq!(delays, 3);
+        assert_eq!((anim.delay(0), anim.delay(2)), (0.1, HOLD));
+
+        let mut apng = Vec::new();
+        anim.write_apng(&mut apng).unwrap();
+        let decoder = png::Decoder::new(apng.as_slice());
+        let reader = decoder.read_info().unwrap();
+        let control = reader.info().animation_control().unwrap();
+        assert_eq!((control.num_frames, control.num_plays), (3, 0));
+
+        assert_eq!(Kind::of_path("a.GIF").unwrap(), Kind::Gif);
+        assert_eq!(Kind::of_path("a.apng").unwrap(), Kind::Apng);
+        assert!(Kind::of_path("a.mp4").is_err());
+    }
+}
@@ -0,0 +1,476 @@
+use super::gcode::{Event, GcodeStream};
+use super::layer::Layers;
+use super::{BoundingBox, ChunkId, Model, VoxelIdx, LAYER_HEIGHT, UNIT};
+use super::{MonotonicVoxel, RangeSetVoxel, Voxel};
+use anyhow::Result;
+use nalgebra::Vector3;
+use std::ops::Range;
+
+/// Voxel storage for the gcode subcommands.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Backend {
+    Monotonic,
+    RangeSet,
+}
+
+impl Backend {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Monotonic => "monotonic",
+            Self::RangeSet => "rangeset",
+        }
+    }
+}
+
+/// Voxel storage which can be boxed and cloned, for backends chosen at runtime.
+pub trait DynVoxel: Voxel + Send + Sync {
+    fn clone_box(&self) -> Box<dyn DynVoxel>;
+}
+
+impl<V: Voxel + Clone + Send + Sync + 'static> DynVoxel for V {
+    fn clone_box(&self) -> Box<dyn DynVoxel> {
+        Box::new(self.clone())
+    }
+}
+
+/// Boxed backend from a `Registry`, so code generic over voxels is compiled once for
+/// every backend. Defaults to an empty `MonotonicVoxel`.
+pub struct AnyVoxel(Box<dyn DynVoxel>);
+
+impl AnyVoxel {
+    pub fn new<V: DynVoxel + 'static>(voxel: V) -> Self {
+        Self(Box::new(voxel))
+    }
+}
+
+impl Default for AnyVoxel {
+    fn default() -> Self {
+        Self::new(MonotonicVoxel::default())
+    }
+}
+
+impl Clone for AnyVoxel {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_box())
+    }
+}
+
+impl Voxel for AnyVoxel {
+    fn blocks(&self) -> usize {
+        self.0.blocks()
+    }
+
+    fn ranges(&self) -> usize {
+        self.0.ranges()
+    }
+
+    fn bounding_box(&self) -> &BoundingBox {
+        self.0.bounding_box()
+    }
+
+    fn occupied(&self, coord: VoxelIdx) -> bool {
+        self.0.occupied(coord)
+    }
+
+    fn add(&mut self, coord: VoxelIdx) -> bool {
+        self.0.add(coord)
+    }
+
+    fn to_model(&self) -> Model {
+        self.0.to_model()
+    }
+
+    fn chunk_model(&self, chunk: ChunkId) -> Model {
+        self.0.chunk_model(chunk)
+    }
+
+    fn take_dirty(&mut self) -> Vec<ChunkId> {
+        self.0.take_dirty()
+    }
+
+    fn reserve(&mut self, bounds: &BoundingBox, blocks: usize) {
+        self.0.reserve(bounds, blocks)
+    }
+
+    fn column(&self, x: i32, y: i32) -> Vec<Range<i32>> {
+        self.0.column(x, y)
+    }
+}
+
+/// Backends by name. Library users register their own backends next to the built-in
+/// `monotonic` and `rangeset`.
+#[derive(Clone)]
+pub struct Registry {
+    backends: Vec<(String, fn() -> AnyVoxel)>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self {
+            backends: Vec::new(),
+        };
+        registry.register(Backend::Monotonic.name(), || {
+            AnyVoxel::new(MonotonicVoxel::default())
+        });
+        registry.register(Backend::RangeSet.name(), || {
+            AnyVoxel::new(RangeSetVoxel::default())
+        });
+        registry
+    }
+}
+
+impl Registry {
+    /// Adds a backend, replacing any backend of the same name.
+    pub fn register(&mut self, name: &str, create: fn() -> AnyVoxel) {
+        self.backends.retain(|(n, _)| n != name);
+        self.backends.push((name.to_owned(), create));
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.backends.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Empty voxels of the backend `name`.
+    pub fn create(&self, name: &str) -> Result<AnyVoxel> {
+        match self.backends.iter().find(|(n, _)| n == name) {
+            Some((_, create)) => Ok(create()),
+            None => anyhow::bail!(
+                "unknown backend {}, expected one of {}",
+                name,
+                self.names().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+}
+
+// rough per-item sizes, including allocator and btree node overhead
+// MonotonicVoxel: btree entry of a column with inline ranges
+const COLUMN_BYTES: f32 = 80f32;
+// MonotonicVoxel: ranges spilled to the heap, past the inline ranges
+const SPILLED_RANGE_BYTES: f32 = 12f32;
+const INLINE_RANGES: f32 = 4f32;
+// RangeSetVoxel: btree entry of a range
+const RANGE_BYTES: f32 = 40f32;
+// width of beads past the extents of their paths, in millimeters
+const BEAD_WIDTH: f32 = 0.4f32;
+
+/// Memory size in bytes, parsed from `512M`, `8G`, and so on.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct ByteSize(pub u64);
+
+impl std::str::FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let unit = match s[digits.len()..].to_ascii_uppercase().as_str() {
+            "" | "B" => 1u64,
+            "K" | "KB" | "KIB" => 1 << 10,
+            "M" | "MB" | "MIB" => 1 << 20,
+            "G" | "GB" | "GIB" => 1 << 30,
+            "T" | "TB" | "TIB" => 1 << 40,
+            u => return Err(format!("unknown size unit: {}, expected K, M, G or T", u)),
+        };
+        let v = digits
+            .parse::<f64>()
+            .map_err(|e| format!("invalid size: {}: {}", s, e))?;
+        Ok(Self((v * unit as f64) as u64))
+    }
+}
+
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1}MiB", self.0 as f64 / (1u64 << 20) as f64)
+    }
+}
+
+/// Quick pre-scan of gcode, without depositing anything.
+#[derive(Default, Debug)]
+pub struct Estimate {
+    /// extruded volume of each layer in cubic millimeters, up to the target layer
+    pub volumes: Vec<f32>,
+    /// XY bounds of extruding moves of each layer in millimeters, none without any
+    pub extents: Vec<Option<[[f32; 2]; 2]>>,
+    /// bounds of extruding moves in millimeters, with the height of the top layer
+    pub bounds: Option<[[f32; 3]; 2]>,
+    /// sparse infill density from slicer settings, 0 to 1
+    pub infill_density: Option<f32>,
+}
+
+/// Grows `bounds` to include `p`.
+fn extend<const N: usize>(bounds: &mut Option<[[f32; N]; 2]>, p: [f32; N]) {
+    let [lo, hi] = bounds.get_or_insert([p, p]);
+    for i in 0..N {
+        lo[i] = lo[i].min(p[i]);
+        hi[i] = hi[i].max(p[i]);
+    }
+}
+
+impl Estimate {
+    /// Sums extruded volume and bounds of extruding moves per layer like
+    /// `simulate_gcode`, with the same gcode parser and layer changes, by `;LAYER:`
+    /// comments or by Z bands without them. Lines which do not parse are skipped.
+    pub fn scan(gcode: &str, layer: usize) -> Self {
+        let filament_diameter = 1.75f32;
+        let filament_cross_section =
+            0.25f32 * std::f32::consts::PI * filament_diameter * filament_diameter;
+
+        let mut estimate = Self {
+            volumes: vec![0f32],
+            extents: vec![None],
+            ..Self::default()
+        };
+        let mut layers = Layers::new(gcode);
+        let mut pos = Vector3::zeros();
+        // E of the last move, and the most extruded so far, which retractions have to
+        // make up for before extruding again
+        let (mut last_e, mut e) = (0f64, 0f64);
+        for (_, event) in GcodeStream::new(gcode).flatten() {
+            let words = match event {
+                Event::LayerChange(idx) if idx >= layer => break,
+                Event::LayerChange(idx) if idx > 0 => {
+                    estimate.volumes.push(0f32);
+                    estimate.extents.push(None);
+                    continue;
+                }
+                Event::Comment(comment) => {
+                    if let Some(density) = infill_density(&comment) {
+                        estimate.infill_density = Some(density);
+                    }
+                    continue;
+                }
+                Event::Travel(words) => {
+                    pos = words.apply(pos);
+                    continue;
+                }
+                // arcs are moves along their segments
+                Event::Move(words) => words,
+                _ => continue,
+            };
+            let (src, dst) = (pos, words.apply(pos));
+            pos = dst;
+            let Some(dst_e) = words.e else {
+                continue;
+            };
+            if dst_e > last_e {
+                if let Some(idx) = layers.extrude(dst[2]) {
+                    if idx >= layer {
+                        break;
+                    }
+                    estimate.volumes.push(0f32);
+                    estimate.extents.push(None);
+                }
+            }
+            last_e = dst_e;
+            if dst_e > e {
+                *estimate.volumes.last_mut().unwrap() +=
+                    (dst_e - e) as f32 * filament_cross_section;
+                e = dst_e;
+                for p in [src, dst] {
+                    extend(estimate.extents.last_mut().unwrap(), [p[0], p[1]]);
+                    extend(&mut estimate.bounds, p.into());
+                }
+            }
+        }
+        estimate
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volumes.iter().sum()
+    }
+
+    /// Footprint of the model in columns, from the layer with the most material.
+    /// First layers are usually solid, so this is close to the covered area. Never more
+    /// than the XY extents of the layers.
+    fn columns(&self, layers: usize) -> f32 {
+        let max = self.volumes[..layers].iter().copied().fold(0f32, f32::max);
+        let columns = max / LAYER_HEIGHT / (UNIT * UNIT);
+
+        let mut extent = None;
+        for [lo, hi] in self.extents.iter().take(layers).flatten() {
+            extend(&mut extent, *lo);
+            extend(&mut extent, *hi);
+        }
+        match extent {
+            Some([lo, hi]) => {
+                let area = (hi[0] - lo[0] + BEAD_WIDTH) * (hi[1] - lo[1] + BEAD_WIDTH);
+                columns.min(area / (UNIT * UNIT))
+            }
+            None => columns,
+        }
+    }
+
+    /// Number of layers with extruding moves.
+    pub fn layers(&self) -> usize {
+        self.extents.iter().filter(|e| e.is_some()).count()
+    }
+
+    /// Ranges up to `layers`. Beads of consecutive layers rarely merge into a single
+    /// range, so each layer adds a range to every column it covers. Infill density is
+    /// already accounted for by the extruded volume.
+    fn ranges(&self, layers: usize) -> f32 {
+        let volume: f32 = self.volumes[..layers].iter().sum();
+        volume / LAYER_HEIGHT / (UNIT * UNIT)
+    }
+
+    /// Estimated voxel storage of `backend` up to `layers`.
+    pub fn memory_at(&self, backend: Backend, layers: usize) -> ByteSize {
+        let layers = layers.min(self.volumes.len());
+        let ranges = self.ranges(layers);
+        let bytes = match backend {
+            Backend::Monotonic => {
+                let columns = self.columns(layers);
+                let spilled = (ranges - columns * INLINE_RANGES).max(0f32);
+                columns * COLUMN_BYTES + spilled * SPILLED_RANGE_BYTES
+            }
+            Backend::RangeSet => ranges * RANGE_BYTES,
+        };
+        ByteSize(bytes as u64)
+    }
+
+    pub fn memory(&self, backend: Backend) -> ByteSize {
+        self.memory_at(backend, self.volumes.len())
+    }
+
+    /// Backend with the smallest estimated memory, preferring the faster monotonic
+    /// backend on ties. Fails if neither fits in `limit`.
+    pub fn choose(&self, limit: Option<ByteSize>) -> Result<Backend> {
+        let backend = [Backend::Monotonic, Backend::RangeSet]
+            .into_iter()
+            .min_by_key(|b| self.memory(*b))
+            .unwrap();
+
+        let limit = match limit {
+            Some(limit) => limit,
+            None => return Ok(backend),
+        };
+        if self.memory(backend) <= limit {
+            return Ok(backend);
+        }
+
+        // voxel size is fixed, so only fewer layers fit
+        let fits = (1..self.volumes.len())
+            .take_while(|n| self.memory_at(backend, *n) <= limit)
+            .last();
+        match fits {
+            Some(n) => anyhow::bail!(
+                "estimated memory {} exceeds limit {}, try --layer {}",
+                self.memory(backend),
+                limit,
+                n
+            ),
+            None => anyhow::bail!(
+                "estimated memory {} exceeds limit {}",
+                self.memory(backend),
+                limit
+            ),
+        }
+    }
+}
+
+/// Sparse infill density from Cura (`infill_sparse_density = 20`), PrusaSlicer
+/// (`fill_density = 20%`) or Orca (`sparse_infill_density = 20%`) settings comments.
+pub(crate) fn infill_density(comment: &str) -> Option<f32> {
+    // cura settings are a single escaped line, split on escaped newlines first
+    comment.split("\\\\n").find_map(|setting| {
+        let (key, value) = setting.split_once('=')?;
+        match key.trim() {
+            "infill_sparse_density" | "fill_density" | "sparse_infill_density" => {
+                let value = value.trim().trim_end_matches('%');
+                value.parse::<f32>().ok().map(|v| v / 100f32)
+            }
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_estimate() {
+        let gcode = "\
+;LAYER:0
+G1 X10 E1
+G1 X20 E0.5
+G1 X30 E2 ; comment
+;LAYER:1
+G20
+G1 X10 E0.15
+G21
+G92 E0
+G1 X10 E3
+;LAYER:2
+G1 X10 E10
+; fill_density = 15%
+";
+        let estimate = Estimate::scan(gcode, 2);
+        let area = 0.25f32 * std::f32::consts::PI * 1.75f32 * 1.75f32;
+        assert_eq!(estimate.volumes.len(), 2);
+        assert!((estimate.volumes[0] - 2f32 * area).abs() < 1e-4);
+        // 0.15 inches, then 3 millimeters from E reset by G92
+        assert!((estimate.volumes[1] - 4.81f32 * area).abs() < 1e-3);
+        assert_eq!(estimate.infill_density, None);
+        assert_eq!(estimate.layers(), 2);
+        assert_eq!(estimate.extents[0], Some([[0f32, 0f32], [30f32, 0f32]]));
+        assert_eq!(
+            estimate.bounds,
+            Some([[0f32, 0f32, 0f32], [254f32, 0f32, 0f32]])
+        );
+
+        let estimate = Estimate::scan(gcode, usize::MAX);
+        assert_eq!(estimate.infill_density, Some(0.15));
+
+        // relative moves, and retractions which are not extruded again
+        let relative = Estimate::scan("M83\nG1 X5 E1\nG1 E-0.5\nG1 E0.5\nG91\nG1 X5 E1\n", 1);
+        assert!((relative.volumes[0] - 2f32 * area).abs() < 1e-4);
+        assert_eq!(relative.extents[0], Some([[0f32, 0f32], [10f32, 0f32]]));
+        // E reset by G92 of each layer still extrudes
+        let reset = Estimate::scan(
+            "G1 X5 E1\n;LAYER:1\nG92 E0\nG1 X10 E1\n;LAYER:2\nG92\nG1 X5 E1\n",
+            usize::MAX,
+        );
+        assert!(reset.volumes.iter().all(|v| (v - area).abs() < 1e-4));
+        assert_eq!(reset.extents[2], Some([[10f32, 0f32], [15f32, 0f32]]));
+        // vase mode without layer comments, a layer for each layer height Z rises
+        let vase = "G1 X0 Y0 Z0.2\nG1 X10 E1\nG1 Y10 Z0.3 E2\nG1 X0 Z0.4 E3\nG1 Y0 Z0.5 E4\n";
+        let estimate = Estimate::scan(vase, usize::MAX);
+        assert_eq!(estimate.layers(), 3);
+        assert!((estimate.volumes[1] - 2f32 * area).abs() < 1e-4);
+        assert_eq!(estimate.extents[2], Some([[0f32, 0f32], [0f32, 10f32]]));
+        let estimate = Estimate::scan(vase, 2);
+        assert_eq!(estimate.layers(), 2);
+        assert_eq!(
+            estimate.bounds,
+            Some([[0f32, 0f32, 0.2f32], [10f32, 10f32, 0.4f32]])
+        );
+        // few ranges per column, where ranges are cheaper than columns
+        assert_eq!(estimate.choose(None).unwrap(), Backend::RangeSet);
+        // a tall model has many ranges per column
+        let tall = Estimate {
+            volumes: vec![1f32; 100],
+            ..Estimate::default()
+        };
+        assert_eq!(tall.choose(None).unwrap(), Backend::Monotonic);
+        assert!(estimate.choose(Some(ByteSize(1))).is_err());
+
+        let registry = Registry::default();
+        let mut v = registry.create(Backend::RangeSet.name()).unwrap();
+        v.add([0, 0, 0].into());
+        let snapshot = v.clone();
+        v.add([0, 0, 1].into());
+        assert_eq!((snapshot.blocks(), v.blocks()), (1, 2));
+        assert_eq!(v.column(0, 0), vec![0..2]);
+        assert!(registry.create("octree").is_err());
+
+        assert_eq!("8G".parse::<ByteSize>().unwrap(), ByteSize(8 << 30));
+        assert_eq!("1.5k".parse::<ByteSize>().unwrap(), ByteSize(1536));
+        assert!("8X".parse::<ByteSize>().is_err());
+        assert_eq!(
+            infill_density("SETTING_3 {\\\\n\\\\ninfill_sparse_density = 20\\\\n"),
+            Some(0.2)
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use super::inertia::Moments;
+use super::{storage, Marker, Observer, Simulation, Voxel, LAYER_HEIGHT, UNIT};
+use anyhow::Result;
+use nalgebra::Vector3;
+use rayon::prelude::*;
+use std::io::Write;
+
+/// Center of mass of deposited voxels, after a layer.
+#[derive(Clone, Debug)]
+pub struct LayerBalance {
+    pub layer: usize,
+    pub blocks: usize,
+    /// in millimeters
+    pub com: [f32; 3],
+    /// distance from the center of mass to the edge of the footprint in millimeters,
+    /// negative outside
+    pub margin: f32,
+}
+
+impl LayerBalance {
+    /// Center of mass outside the footprint, so the part would tip over if it is not
+    /// held by the bed.
+    pub fn tipping(&self) -> bool {
+        self.margin < 0f32
+    }
+}
+
+/// Tracks the center of mass of each layer against the first layer footprint. Brims and
+/// skirts are left out of the footprint, as only first layer columns below the second
+/// layer count.
+#[derive(Default)]
+pub struct Balance {
+    /// convex hull of the footprint in millimeters, counterclockwise
+    hull: Vec<[f32; 2]>,
+    /// footprint is final, after the second layer
+    settled: bool,
+    pub layers: Vec<LayerBalance>,
+}
+
+fn occupied_in(ranges: &[std::ops::Range<i32>], band: &std::ops::Range<i32>) -> bool {
+    ranges
+        .iter()
+        .any(|r| r.start < band.end && band.start < r.end)
+}
+
+/// Outline of first layer columns, under the second layer if there is one yet.
+fn footprint<V: Voxel + Sync>(v: &V) -> Vec<[f32; 2]> {
+    let bb = v.bounding_box();
+    if bb.count == 0 {
+        return Vec::new();
+    }
+    let (min, max) = (bb.bound_min, bb.bound_max);
+    let h = (LAYER_HEIGHT / UNIT).round() as i32;
+    let first = min[2]..min[2] + h;
+    let second = min[2] + h..min[2] + 2 * h;
+    let two_layers = max[2] >= second.start;
+
+    // ends of each row are enough for the hull
+    let points = (min[1]..=max[1])
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            let mut ends: Option<(i32, i32)> = None;
+            for x in min[0]..=max[0] {
+                let column = v.column(x, y);
+                if !occupied_in(&column, &first) || (two_layers && !occupied_in(&column, &second)) {
+                    continue;
+                }
+                ends = Some(match ends {
+                    Some((lo, _)) => (lo, x + 1),
+                    None => (x, x + 1),
+                });
+            }
+            ends.into_iter().flat_map(move |(lo, hi)| {
+                [[lo, y], [lo, y + 1], [hi, y], [hi, y + 1]]
+                    .map(|[x, y]| [x as f32 * UNIT, y as f32 * UNIT])
+            })
+        })
+        .collect::<Vec<_>>();
+    convex_hull(points)
+}
+
+/// Counterclockwise convex hull, with the monotone chain algorithm.
+fn convex_hull(mut points: Vec<[f32; 2]>) -> Vec<[f32; 2]> {
+    points.sort_by(|a, b| a[0].total_cmp(&b[0]).then(a[1].total_cmp(&b[1])));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let cross = |o: [f32; 2], a: [f32; 2], b: [f32; 2]| {
+        (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+    };
+    let mut hull: Vec<[f32; 2]> = Vec::with_capacity(points.len() * 2);
+    for pass in [points.clone(), points.into_iter().rev().collect()] {
+        let start = hull.len();
+        for p in pass {
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0f32
+            {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        // last point is the first of the other pass
+        hull.pop();
+    }
+    hull
+}
+
+/// Distance from `p` to the nearest edge of the counterclockwise `hull`, negative
+/// outside.
+fn margin(hull: &[[f32; 2]], p: [f32; 2]) -> f32 {
+    if hull.len() < 3 {
+        return f32::NEG_INFINITY;
+    }
+    (0..hull.len())
+        .map(|i| {
+            let (a, b) = (hull[i], hull[(i + 1) % hull.len()]);
+            let (ex, ey) = (b[0] - a[0], b[1] - a[1]);
+            let len = (ex * ex + ey * ey).sqrt().max(f32::EPSILON);
+            // left of the edge is inside
+            (ex * (p[1] - a[1]) - ey * (p[0] - a[0])) / len
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+impl Balance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the center of mass of `v` after `layer`.
+    pub fn measure<V: Voxel + Sync>(&mut self, v: &V, layer: usize) -> &LayerBalance {
+        if !self.settled {
+            let bb = v.bounding_box();
+            let h = (LAYER_HEIGHT / UNIT).round() as i32;
+            self.settled = bb.count > 0 && bb.bound_max[2] >= bb.bound_min[2] + 2 * h;
+            self.hull = footprint(v);
+        }
+
+        let moments = Moments::of(v);
+        let com = moments.center().map(|c| (c * UNIT as f64) as f32);
+        self.layers.push(LayerBalance {
+            layer,
+            blocks: moments.count as usize,
+            com,
+            margin: margin(&self.hull, [com[0], com[1]]),
+        });
+        self.layers.last().unwrap()
+    }
+
+    /// Records the state after the last layer, which has no layer change.
+    pub fn finish<V: Voxel + Sync>(&mut self, v: &V) {
+        let layer = self.layers.last().map_or(0, |l| l.layer + 1);
+        self.measure(v, layer);
+    }
+
+    /// Layers where the part would tip over.
+    pub fn tipping(&self) -> impl Iterator<Item = &LayerBalance> {
+        self.layers.iter().filter(|l| l.tipping())
+    }
+
+    pub fn write_csv(&self, path: &str) -> Result<()> {
+        let mut w = storage::create(path)?;
+        writeln!(w, "layer,blocks,com_x,com_y,com_z,margin,tipping")?;
+        for l in &self.layers {
+            writeln!(
+                w,
+                "{},{},{:.3},{:.3},{:.3},{:.3},{}",
+                l.layer,
+                l.blocks,
+                l.com[0],
+                l.com[1],
+                l.com[2],
+                l.margin,
+                l.tipping()
+            )?;
+        }
+        w.finish()
+    }
+}
+
+/// Measures each layer, and marks the center of mass in exported frames, as `com`, or
+/// `com-tipping` outside the footprint.
+impl<V: Voxel + Sync> Observer<V> for Balance {
+    fn on_layer_complete(&mut self, sim: &mut Simulation<V>, layer: usize) -> Result<()> {
+        let l = self.measure(&sim.voxel, layer);
+        let name = if l.tipping() { "com-tipping" } else { "com" };
+        let marker = Marker {
+            name: name.to_owned(),
+            pos: Vector3::from(l.com),
+        };
+        sim.markers.retain(|m| !m.name.starts_with("com"));
+        sim.markers.push(marker);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_balance() {
+        // 2x2mm base, and a tower leaning over +X
+        let mut v = MonotonicVoxel::default();
+        for x in 0..50 {
+            for y in 0..50 {
+                for z in 0..10 {
+                    v.add([x, y, z].into());
+                }
+            }
+        }
+        let mut balance = Balance::new();
+        let l = balance.measure(&v, 1);
+        assert!((l.com[0] - 1f32).abs() < 1e-3, "{:?}", l.com);
+        assert!((l.margin - 1f32).abs() < 1e-3, "{}", l.margin);
+
+        for z in 10..200 {
+            let x0 = z / 2;
+            for x in x0..x0 + 50 {
+                for y in 0..50 {
+                    v.add([x, y, z].into());
+                }
+            }
+        }
+        let l = balance.measure(&v, 2);
+        assert!(l.tipping(), "{:?}", l);
+        assert_eq!(balance.tipping().count(), 1);
+
+        let hull = convex_hull(vec![[0., 0.], [1., 0.], [0.5, 0.5], [1., 1.], [0., 1.]]);
+        assert_eq!(hull, vec![[0., 0.], [1., 0.], [1., 1.], [0., 1.]]);
+        assert_eq!(margin(&hull, [0.5, 0.25]), 0.25);
+        assert_eq!(margin(&hull, [1.5, 0.5]), -0.5);
+    }
+}
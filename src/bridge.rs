@@ -0,0 +1,164 @@
+use super::{to_intpos, Path, Voxel};
+use nalgebra::Vector3;
+
+/// Unsupported spans shorter than this are ignored, e.g. infill crossing sparse infill
+/// below, in millimeters.
+const MIN_SPAN: f32 = 2f32;
+
+/// Unsupported spans of an extrusion path with both ends anchored.
+#[derive(Debug)]
+pub struct Bridge {
+    /// (start, end) distance from the start of the path, in millimeters
+    spans: Vec<(f32, f32)>,
+}
+
+impl Bridge {
+    /// Samples support below beads of `depth` voxels along the path every `step`
+    /// millimeters. Returns `None` if the path does not bridge.
+    pub fn detect<V: Voxel>(v: &V, path: &Path, step: f32, depth: i32) -> Option<Self> {
+        let len = path.len();
+        if len < MIN_SPAN {
+            return None;
+        }
+
+        let samples = (len / step).ceil() as usize;
+        let mut spans = Vec::new();
+        // distance of the last supported sample, and whether unsupported samples follow
+        let mut anchor = None;
+        let mut unsupported = false;
+        for i in 0..=samples {
+            let d = (i as f32 * step).min(len);
+            if !supported(v, path.at(d), depth) {
+                unsupported = true;
+                continue;
+            }
+
+            if let (Some(start), true) = (anchor, unsupported) {
+                if d - start >= MIN_SPAN {
+                    spans.push((start, d));
+                }
+            }
+            anchor = Some(d);
+            unsupported = false;
+        }
+
+        if spans.is_empty() {
+            return None;
+        }
+        Some(Self { spans })
+    }
+
+    /// Drop below the path at distance `d` from the start of the path, with
+    /// `ratio` of sag depth over span length, in millimeters.
+    pub fn sag(&self, d: f32, ratio: f32) -> f32 {
+        for &(start, end) in &self.spans {
+            if d > start && d < end {
+                let span = end - start;
+                return catenary(span, span * ratio, d - (start + end) * 0.5);
+            }
+        }
+        0f32
+    }
+}
+
+/// Checks material, or the bed, directly below the bead of `depth` voxels at `pos`.
+fn supported<V: Voxel>(v: &V, pos: Vector3<f32>, depth: i32) -> bool {
+    let c = to_intpos([pos[0], pos[1], pos[2]]);
+    let z = c[2] - depth;
+    if z <= 0 {
+        return true;
+    }
+
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            for dz in [z - 1, z] {
+                if v.occupied([c[0] + dx, c[1] + dy, dz].into()) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Drop of a catenary hanging over `span`, with `depth` at the middle, at offset `u`
+/// from the middle.
+fn catenary(span: f32, depth: f32, u: f32) -> f32 {
+    if span <= 0f32 || depth <= 0f32 {
+        return 0f32;
+    }
+    let half = span as f64 * 0.5;
+    let depth = depth as f64;
+
+    // solve depth = a * (cosh(half / a) - 1) for a. depth decreases with a, bisect in
+    // log space. lower bound keeps cosh() finite.
+    let (mut lo, mut hi) = (half / 700f64, half * 1e6);
+    for _ in 0..100 {
+        let a = (lo * hi).sqrt();
+        if a * ((half / a).cosh() - 1f64) > depth {
+            lo = a;
+        } else {
+            hi = a;
+        }
+    }
+    let a = (lo * hi).sqrt();
+
+    let drop = depth - a * ((u as f64 / a).cosh() - 1f64);
+    drop.max(0f64) as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{MonotonicVoxel, Z_OFFSET};
+
+    #[test]
+    pub fn test_catenary() {
+        let sag = catenary(10f32, 0.5f32, 0f32);
+        assert!((sag - 0.5).abs() < 1e-3);
+        assert!(catenary(10f32, 0.5f32, 5f32) < 1e-3);
+
+        // symmetric, and decreasing toward anchors
+        let s1 = catenary(10f32, 0.5f32, 2f32);
+        let s2 = catenary(10f32, 0.5f32, -2f32);
+        assert!((s1 - s2).abs() < 1e-6);
+        assert!(s1 < sag && s1 > 0f32);
+    }
+
+    #[test]
+    pub fn test_detect() {
+        // two pillars, 0.2mm high, 10mm apart
+        let mut v = MonotonicVoxel::default();
+        for x in (0..10).chain(250..260) {
+            for y in -5..5 {
+                for z in 0..5 {
+                    v.add([x, y, z].into());
+                }
+            }
+        }
+
+        let from = Vector3::new(0.2, 0.0, 0.4);
+        let mut path = Path::default();
+        path.push(from, Vector3::new(10.2, 0.0, 0.4), 1.0, 20.0);
+        let bridge = Bridge::detect(&v, &path, 0.1, Z_OFFSET).unwrap();
+        assert_eq!(bridge.spans.len(), 1);
+
+        let (start, end) = bridge.spans[0];
+        assert!(start > 0.1 && start < 0.5);
+        assert!(end > 9.4 && end < 9.9);
+        assert!(bridge.sag(5f32, 0.02) > 0.15);
+        assert_eq!(bridge.sag(0.1, 0.02), 0f32);
+
+        // anchored at one end only
+        path.clear();
+        path.push(from, Vector3::new(5.0, 0.0, 0.4), 1.0, 20.0);
+        assert!(Bridge::detect(&v, &path, 0.1, Z_OFFSET).is_none());
+
+        // thin beads of a thin layer above the pillars are not anchored
+        let from = Vector3::new(0.2, 0.0, 0.32);
+        path.clear();
+        path.push(from, Vector3::new(10.2, 0.0, 0.32), 1.0, 20.0);
+        assert!(Bridge::detect(&v, &path, 0.1, Z_OFFSET).is_some());
+        assert!(Bridge::detect(&v, &path, 0.1, 2).is_none());
+    }
+}
@@ -0,0 +1,207 @@
+use super::metadata::{hash_bytes, hash_continue, HashWriter};
+use super::{explain, export_metadata, export_model, s3, storage};
+use super::{Format, Metadata, Output, Params, Simulation, Voxel};
+use anyhow::Result;
+use log::*;
+use std::io::Write;
+
+/// Content-addressed cache of obj frames in a local directory. Each frame is keyed by a
+/// hash of the gcode up to its layer change, what the simulation reads from the whole
+/// gcode, the parameters, export options and markers, so reruns after editing later
+/// layers reuse frames before the first changed layer, and only export frames from there
+/// on. Layers before it are still simulated, as simulations are not cached, which costs
+/// much less than exporting their frames. Entries are never evicted.
+pub struct FrameCache {
+    dir: String,
+    /// hash of the parameters and export options
+    seed: u64,
+    /// hash of the first n lines of the gcode, at n
+    prefix: Vec<u64>,
+    hits: usize,
+}
+
+impl FrameCache {
+    /// Cache in `dir` of frames of `gcode`, with `whole_file` from
+    /// `Simulator::whole_file_hash`.
+    pub fn new(
+        dir: &str,
+        gcode: &str,
+        whole_file: u64,
+        params: &Params,
+        output: &Output,
+    ) -> Result<Self> {
+        // metadata lines of cached frames are replaced, which only obj has
+        anyhow::ensure!(
+            output.format == Format::Obj,
+            "frame cache needs obj frames, not {:?}",
+            output.format
+        );
+        // frames are read back from the cache, which only local directories do
+        anyhow::ensure!(
+            s3::split(dir).is_none(),
+            "{}: frame caches are local directories only, not s3",
+            dir
+        );
+        std::fs::create_dir_all(dir)?;
+        let seed = format!(
+            "{} {:016x} {} {:?}",
+            env!("CARGO_PKG_VERSION"),
+            whole_file,
+            explain::model(params),
+            output
+        );
+        let mut prefix = vec![hash_bytes(&[])];
+        let mut h = prefix[0];
+        for line in gcode.lines() {
+            h = hash_continue(hash_continue(h, line.as_bytes()), b"\n");
+            prefix.push(h);
+        }
+        Ok(Self {
+            dir: dir.to_owned(),
+            seed: hash_bytes(seed.as_bytes()),
+            prefix,
+            hits: 0,
+        })
+    }
+
+    /// Frames reused so far.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Cache file of the frame of `sim`.
+    fn path<V>(&self, sim: &Simulation<V>) -> String {
+        let prefix = self.prefix[sim.line.min(self.prefix.len() - 1)];
+        let key = format!("{:016x} {:016x} {:?}", self.seed, prefix, sim.markers);
+        format!("{}/{:016x}.obj", self.dir, hash_bytes(key.as_bytes()))
+    }
+
+    /// Writes the model of `sim` to `filename` like `export_model`, from the cache if it
+    /// has the frame, or exporting it into the cache first.
+    pub fn export<V: Voxel>(
+        &mut self,
+        sim: &Simulation<V>,
+        output: &Output,
+        meta: Metadata,
+        filename: &str,
+    ) -> Result<u64> {
+        let path = self.path(sim);
+        if std::path::Path::new(&path).exists() {
+            debug!("frame cache hit: {}", path);
+            self.hits += 1;
+        } else {
+            // renamed once complete, so interrupted runs leave no partial frames
+            let tmp = format!("{}.tmp", path);
+            export_model(sim, output, meta.clone(), &tmp)?;
+            std::fs::rename(&tmp, &path)?;
+        }
+
+        // the metadata of cached frames may be of another input file
+        let data = std::fs::read(&path)?;
+        let mut body = &data[..];
+        while body.starts_with(b"# ") {
+            let end = body
+                .iter()
+                .position(|b| *b == b'\n')
+                .map_or(body.len(), |i| i + 1);
+            body = &body[end..];
+        }
+        let mut w = HashWriter::new(storage::create(filename)?);
+        for (key, value) in export_metadata(meta, output).entries() {
+            writeln!(&mut w, "# {}: {}", key, value)?;
+        }
+        w.write_all(body)?;
+        let hash = w.hash();
+        w.into_inner().finish()?;
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{generate_gcode, ExportMode, MonotonicVoxel, Pacing, Shrinkage, Units};
+
+    #[test]
+    pub fn test_frame_cache() {
+        let dir = std::env::temp_dir().join(format!("tdp-tl-cache-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let output = Output {
+            mode: ExportMode::Full,
+            format: Format::Obj,
+            units: Units::Millimeter,
+            precision: 2,
+            shrinkage: Shrinkage::default(),
+            toolhead: false,
+        };
+        let layers = |last: &str| {
+            format!(
+                ";LAYER:0\nG1 X10 Y10 Z0.2\nG1 X20 Y10 E0.1\n;LAYER:1\nG1 Z0.4\n\
+                G1 X10 Y10 E0.2\n;LAYER:2\nG1 Z0.6\n{}\n;LAYER:3\n",
+                last
+            )
+        };
+        let run_with = |gcode: String, out: &str, params: &Params| {
+            let input = format!("{}/{}.gcode", dir, out);
+            std::fs::create_dir_all(format!("{}/{}", dir, out)).unwrap();
+            std::fs::write(&input, gcode).unwrap();
+            let cache = format!("{}/cache", dir);
+            generate_gcode(
+                MonotonicVoxel::default(),
+                &input,
+                &format!("{}/{}", dir, out),
+                usize::MAX,
+                true,
+                Pacing::default(),
+                &output,
+                params,
+                Some(&cache),
+                &mut (),
+            )
+            .unwrap();
+            std::fs::read_dir(&cache).unwrap().count()
+        };
+        let run = |gcode: String, out: &str| run_with(gcode, out, &Params::default());
+        let frame = |out: &str, layer: usize| {
+            std::fs::read_to_string(format!("{}/{}/gcode_{:03}.obj", dir, out, layer)).unwrap()
+        };
+
+        assert_eq!(run(layers("G1 X20 Y10 E0.3"), "a"), 3);
+        // only the frame after the edited layer is exported again
+        assert_eq!(run(layers("G1 X10 Y20 E0.3"), "b"), 4);
+        let body = |frame: &str| {
+            let lines = frame.lines().filter(|l| !l.starts_with("# "));
+            lines.collect::<Vec<_>>().join("\n")
+        };
+        for layer in 1..=3 {
+            let (a, b) = (frame("a", layer), frame("b", layer));
+            assert_eq!(body(&a) == body(&b), layer < 3);
+            // with the metadata of this run
+            assert!(b.contains("b.gcode\n"));
+        }
+
+        // colors of slicer settings at the end of the gcode change every frame
+        let colors = Params {
+            colors: true,
+            ..Params::default()
+        };
+        let settings = |color: &str| {
+            format!(
+                "{}; filament_colour = {}\n",
+                layers("G1 X10 Y20 E0.3"),
+                color
+            )
+        };
+        assert_eq!(run_with(settings("#FF0000"), "c", &colors), 7);
+        assert_eq!(run_with(settings("#FF0000"), "d", &colors), 7);
+        assert_eq!(run_with(settings("#00FF00"), "e", &colors), 10);
+
+        // metadata lines are replaced, which only obj has
+        let stl = Output {
+            format: Format::Stl,
+            ..output.clone()
+        };
+        assert!(FrameCache::new(dir, "", 0, &Params::default(), &stl).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
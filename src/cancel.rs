@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Cooperative cancellation of long-running conversions. Clones share the flag, so an
+/// application keeps one clone and cancels from another thread, while loops of the
+/// simulation and meshing stop at the next check and return what they have so far.
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{simulate, MonotonicVoxel, Params, Simulation, Voxel};
+
+    #[test]
+    pub fn test_cancel() {
+        let gcode = "G1 X10 Y10 Z0.2\n;LAYER:0\nG1 X20 Y10 E0.1\n\
+            ;LAYER:1\nG1 X20 Y20 Z0.4 E0.2\n;LAYER:2\nG1 X10 Y20 Z0.6 E0.3\n";
+        let params = Params::default();
+        let cancel = params.cancel.clone();
+
+        let sim = simulate(
+            gcode,
+            usize::MAX,
+            &params,
+            |_: &mut Simulation<MonotonicVoxel>, layer| {
+                if layer == 1 {
+                    cancel.cancel();
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(sim.cancelled);
+        assert!(sim.voxel.blocks() > 0);
+        assert_eq!(sim.segments.len(), 1);
+    }
+}
@@ -0,0 +1,310 @@
+use super::timer::Stopwatch;
+use super::{BoundingBox, Cancel, Voxel, VoxelIdx, UNIT};
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::ops::Range;
+
+/// Columns per chunk, along X and Y.
+pub const CHUNK_SIZE: i32 = 64;
+
+/// Chunk coordinates, columns `[x * CHUNK_SIZE, (x + 1) * CHUNK_SIZE)` along X, and so on.
+pub type ChunkId = [i32; 2];
+
+pub fn chunk_of(x: i32, y: i32) -> ChunkId {
+    [x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE)]
+}
+
+/// Chunks reserved up front at most, so a wild hint does not allocate much.
+const MAX_RESERVED_CHUNKS: i64 = 1 << 16;
+
+/// Chunks covering `bounds`, for backends to implement `Voxel::reserve`. Empty if
+/// there are too many.
+pub fn chunks_in(bounds: &BoundingBox) -> Vec<ChunkId> {
+    if bounds.count == 0 {
+        return Vec::new();
+    }
+    let lo = chunk_of(bounds.bound_min[0], bounds.bound_min[1]);
+    let hi = chunk_of(bounds.bound_max[0], bounds.bound_max[1]);
+    let count = (hi[0] - lo[0] + 1) as i64 * (hi[1] - lo[1] + 1) as i64;
+    if count > MAX_RESERVED_CHUNKS {
+        return Vec::new();
+    }
+    (lo[1]..=hi[1])
+        .flat_map(|y| (lo[0]..=hi[0]).map(move |x| [x, y]))
+        .collect()
+}
+
+/// Chunks changed since the last `take`, for backends to implement
+/// `Voxel::take_dirty`.
+#[derive(Default, Clone)]
+pub struct Dirty {
+    chunks: BTreeSet<ChunkId>,
+    // chunk of the last voxel added off chunk edges, skips set lookups for runs of
+    // voxels in the same chunk
+    last: Option<ChunkId>,
+}
+
+impl Dirty {
+    pub fn add(&mut self, coord: VoxelIdx) {
+        let chunk = chunk_of(coord[0], coord[1]);
+        // faces of the neighbor column change too, if it is in another chunk
+        let edge = |c: i32| match c.rem_euclid(CHUNK_SIZE) {
+            0 => -1,
+            r if r == CHUNK_SIZE - 1 => 1,
+            _ => 0,
+        };
+        let (ex, ey) = (edge(coord[0]), edge(coord[1]));
+        if ex == 0 && ey == 0 {
+            if self.last == Some(chunk) {
+                return;
+            }
+            self.last = Some(chunk);
+        }
+
+        self.chunks.insert(chunk);
+        if ex != 0 {
+            self.chunks.insert([chunk[0] + ex, chunk[1]]);
+        }
+        if ey != 0 {
+            self.chunks.insert([chunk[0], chunk[1] + ey]);
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<ChunkId> {
+        self.last = None;
+        std::mem::take(&mut self.chunks).into_iter().collect()
+    }
+}
+
+/// Triangle mesh of a chunk, in millimeters.
+#[derive(Default, Debug)]
+pub struct ChunkMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+}
+
+impl ChunkMesh {
+    #[cfg_attr(not(feature = "view"), allow(dead_code))]
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Appends faces of `other`.
+    pub fn append(&mut self, other: Self) {
+        let base = self.positions.len() as u32;
+        self.positions.extend(other.positions);
+        self.normals.extend(other.normals);
+        self.indices
+            .extend(other.indices.into_iter().map(|i| i + base));
+    }
+
+    /// Adds a quad with corners in counterclockwise order, seen from `normal`.
+    fn add_quad(&mut self, corners: [[i32; 3]; 4], normal: [f32; 3]) {
+        let base = self.positions.len() as u32;
+        for c in corners {
+            self.positions.push(c.map(|v| v as f32 * UNIT));
+            self.normals.push(normal);
+        }
+        self.indices
+            .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+}
+
+fn contains(ranges: &[Range<i32>], z: i32) -> bool {
+    ranges.iter().any(|r| r.contains(&z))
+}
+
+/// Meshes every exposed voxel face of columns in `chunk`.
+pub fn mesh_chunk<V: Voxel>(v: &V, chunk: ChunkId) -> ChunkMesh {
+    let mut mesh = ChunkMesh::default();
+    chunk_quads(v, chunk, |quad, normal| mesh.add_quad(quad, normal));
+    mesh
+}
+
+/// Calls `f` with every exposed voxel face of columns in `chunk`, as corners in voxels
+/// in counterclockwise order seen from outside, and its normal.
+pub fn chunk_quads<V: Voxel>(v: &V, chunk: ChunkId, mut f: impl FnMut([[i32; 3]; 4], [f32; 3])) {
+    let x0 = chunk[0] * CHUNK_SIZE;
+    let y0 = chunk[1] * CHUNK_SIZE;
+
+    for y in y0..y0 + CHUNK_SIZE {
+        for x in x0..x0 + CHUNK_SIZE {
+            let column = v.column(x, y);
+            if column.is_empty() {
+                continue;
+            }
+
+            for r in &column {
+                if !contains(&column, r.end) {
+                    let z = r.end;
+                    let quad = [[x, y, z], [x + 1, y, z], [x + 1, y + 1, z], [x, y + 1, z]];
+                    f(quad, [0f32, 0f32, 1f32]);
+                }
+                if !contains(&column, r.start - 1) {
+                    let z = r.start;
+                    let quad = [[x, y, z], [x, y + 1, z], [x + 1, y + 1, z], [x + 1, y, z]];
+                    f(quad, [0f32, 0f32, -1f32]);
+                }
+            }
+
+            let sides = [([1, 0], [1f32, 0f32, 0f32]), ([-1, 0], [-1f32, 0f32, 0f32])];
+            let sides = sides
+                .into_iter()
+                .chain([([0, 1], [0f32, 1f32, 0f32]), ([0, -1], [0f32, -1f32, 0f32])]);
+            for ([dx, dy], normal) in sides {
+                let neighbor = v.column(x + dx, y + dy);
+                for r in &column {
+                    for z in r.clone() {
+                        if contains(&neighbor, z) {
+                            continue;
+                        }
+                        let (z0, z1) = (z, z + 1);
+                        let quad = match (dx, dy) {
+                            (1, _) => [
+                                [x + 1, y, z0],
+                                [x + 1, y + 1, z0],
+                                [x + 1, y + 1, z1],
+                                [x + 1, y, z1],
+                            ],
+                            (-1, _) => [[x, y + 1, z0], [x, y, z0], [x, y, z1], [x, y + 1, z1]],
+                            (_, 1) => [
+                                [x + 1, y + 1, z0],
+                                [x, y + 1, z0],
+                                [x, y + 1, z1],
+                                [x + 1, y + 1, z1],
+                            ],
+                            _ => [[x, y, z0], [x + 1, y, z0], [x + 1, y, z1], [x, y, z1]],
+                        };
+                        f(quad, normal);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Meshes `chunks` in parallel. Once `cancel` is set, remaining chunks are skipped, so
+/// only chunks meshed before are returned.
+pub fn mesh_chunks<V: Voxel + Sync>(
+    v: &V,
+    chunks: Vec<ChunkId>,
+    cancel: &Cancel,
+) -> Vec<(ChunkId, ChunkMesh)> {
+    chunks
+        .into_par_iter()
+        .filter(|_| !cancel.is_cancelled())
+        .map(|id| (id, mesh_chunk(v, id)))
+        .collect()
+}
+
+/// Meshes dirty chunks over several calls, for live views which keep their frame rate
+/// instead of waiting for every chunk of a frame.
+#[derive(Default)]
+pub struct Mesher {
+    /// oldest first, so busy chunks do not starve the others
+    queue: VecDeque<ChunkId>,
+    queued: HashSet<ChunkId>,
+}
+
+impl Mesher {
+    /// Queues dirty chunks of `v`, and meshes queued chunks until `budget` is spent. A
+    /// batch is meshed on every call, so the queue drains however small the budget is.
+    /// Without a clock on wasm32, every queued chunk is meshed.
+    pub fn to_model_budgeted<V: Voxel + Sync>(
+        &mut self,
+        v: &mut V,
+        budget: std::time::Duration,
+    ) -> Vec<(ChunkId, ChunkMesh)> {
+        for id in v.take_dirty() {
+            if self.queued.insert(id) {
+                self.queue.push_back(id);
+            }
+        }
+
+        let sw = Stopwatch::start_new();
+        let batch = rayon::current_num_threads();
+        let mut meshes = Vec::new();
+        while !self.queue.is_empty() && (meshes.is_empty() || sw.elapsed() < budget) {
+            let n = batch.min(self.queue.len());
+            let ids = self.queue.drain(..n).collect::<Vec<_>>();
+            for id in &ids {
+                self.queued.remove(id);
+            }
+            meshes.par_extend(ids.into_par_iter().map(|id| (id, mesh_chunk(&*v, id))));
+        }
+        meshes
+    }
+
+    /// Chunks left for later calls.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{MonotonicVoxel, RangeSetVoxel};
+
+    #[test]
+    pub fn test_mesh_chunk() {
+        let mut mv = MonotonicVoxel::default();
+        let mut rv = RangeSetVoxel::default();
+        // 2x1x2 block, crossing into the chunk at -X
+        for [x, y, z] in [[-1, 0, 0], [0, 0, 0], [-1, 0, 1], [0, 0, 1]] {
+            mv.add([x, y, z].into());
+            rv.add([x, y, z].into());
+        }
+
+        for mesh in [mesh_chunk(&mv, [0, 0]), mesh_chunk(&rv, [0, 0])] {
+            // one column: top, bottom, 3 exposed sides with 2 faces each
+            assert_eq!(mesh.indices.len(), (2 + 3 * 2) * 6);
+            assert!(mesh.normals.iter().all(|n| *n != [-1f32, 0f32, 0f32]));
+        }
+        assert_eq!(mesh_chunk(&mv, [-1, 0]).indices.len(), 8 * 6);
+        assert!(mesh_chunk(&mv, [1, 0]).is_empty());
+
+        let cancel = Cancel::new();
+        let dirty = mv.take_dirty();
+        assert_eq!(mesh_chunks(&mv, dirty.clone(), &cancel).len(), dirty.len());
+        cancel.cancel();
+        assert!(mesh_chunks(&mv, vec![[0, 0]], &cancel).is_empty());
+    }
+
+    #[test]
+    pub fn test_mesher() {
+        let mut v = MonotonicVoxel::default();
+        let chunks = rayon::current_num_threads() as i32 * 2;
+        for x in 0..chunks {
+            v.add([x * CHUNK_SIZE + 10, 10, 0].into());
+        }
+
+        let mut mesher = Mesher::default();
+        let first = mesher.to_model_budgeted(&mut v, std::time::Duration::ZERO);
+        assert_eq!(first.len(), rayon::current_num_threads());
+        assert_eq!(mesher.pending(), chunks as usize - first.len());
+
+        // chunks dirty again are queued once
+        v.add([CHUNK_SIZE * (chunks - 1) + 11, 10, 0].into());
+        v.add([10, 10, 1].into());
+        let rest = mesher.to_model_budgeted(&mut v, std::time::Duration::MAX);
+        assert_eq!(rest.len(), chunks as usize - first.len() + 1);
+        assert_eq!(mesher.pending(), 0);
+    }
+
+    #[test]
+    pub fn test_take_dirty() {
+        let mut v = MonotonicVoxel::default();
+        v.add([10, 10, 0].into());
+        v.add([11, 10, 0].into());
+        assert_eq!(v.take_dirty(), vec![[0, 0]]);
+        assert!(v.take_dirty().is_empty());
+
+        // on the edge of the chunk, the neighbor chunk is dirty too
+        let mut v = RangeSetVoxel::default();
+        v.add([64, 10, 0].into());
+        v.add([64, 10, 0].into());
+        assert_eq!(v.take_dirty(), vec![[0, 0], [1, 0]]);
+    }
+}
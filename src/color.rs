@@ -0,0 +1,293 @@
+use super::{surface, BoundingBox, ChunkId, Model, MonotonicVoxel, Voxel, VoxelIdx};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+pub type Rgb = [u8; 3];
+
+/// Used for tools without a color in slicer settings or `--palette`.
+const DEFAULT_PALETTE: [Rgb; 4] = [
+    [0xe0, 0xe0, 0xe0],
+    [0xd0, 0x30, 0x30],
+    [0x30, 0x90, 0xd0],
+    [0xf0, 0xc0, 0x20],
+];
+
+/// Purge transitions are quantized into this many colors.
+const BLEND_STEPS: f32 = 8f32;
+
+/// Parses `#rrggbb`, with or without `#`.
+pub fn parse_hex(s: &str) -> Option<Rgb> {
+    let s = s.trim();
+    let s = s.strip_prefix('#').unwrap_or(s);
+    if s.len() != 6 {
+        return None;
+    }
+    let mut rgb = [0u8; 3];
+    for (i, c) in rgb.iter_mut().enumerate() {
+        *c = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(rgb)
+}
+
+pub fn parse_color(s: &str) -> Result<Rgb, String> {
+    parse_hex(s).ok_or_else(|| format!("invalid color: {}, expected #rrggbb", s))
+}
+
+/// Filament colors and purge volumes of each tool.
+#[derive(Clone, Debug, Default)]
+pub struct Palette {
+    colors: Vec<Rgb>,
+    /// purge volume from tool i to tool j, in cubic millimeters, flattened
+    purge: Vec<f32>,
+}
+
+impl Palette {
+    /// Reads `filament_colour` and `wiping_volumes_matrix` from slicer settings
+    /// embedded in gcode comments, e.g. by PrusaSlicer and its forks.
+    pub fn from_gcode(gcode: &str) -> Self {
+        let mut palette = Self::default();
+        for line in gcode.lines() {
+            let (key, value) = match line.strip_prefix(';').and_then(|l| l.split_once('=')) {
+                Some(kv) => kv,
+                None => continue,
+            };
+            match key.trim() {
+                "filament_colour" => {
+                    palette.colors = value.split(';').filter_map(parse_hex).collect();
+                }
+                "wiping_volumes_matrix" => {
+                    palette.purge = value
+                        .split(',')
+                        .filter_map(|v| v.trim().parse().ok())
+                        .collect();
+                }
+                _ => (),
+            }
+        }
+        palette
+    }
+
+    pub fn with_colors(mut self, colors: &[Rgb]) -> Self {
+        self.colors = colors.to_vec();
+        self
+    }
+
+    pub fn color(&self, tool: usize) -> Rgb {
+        match self.colors.get(tool) {
+            Some(c) => *c,
+            None => DEFAULT_PALETTE[tool % DEFAULT_PALETTE.len()],
+        }
+    }
+
+    /// Purge volume from tool `from` to `to`, or `default` if slicer settings do not
+    /// have it.
+    pub fn purge_volume(&self, from: usize, to: usize, default: f32) -> f32 {
+        let n = (self.purge.len() as f64).sqrt() as usize;
+        if from >= n || to >= n || n * n != self.purge.len() {
+            return default;
+        }
+        self.purge[from * n + to]
+    }
+}
+
+/// Active filament color in the nozzle. After a tool change, the previous filament is
+/// mixed into the extruded material until the purge volume is extruded.
+#[derive(Clone, Debug)]
+pub struct Mixer {
+    palette: Palette,
+    default_purge: f32,
+    tool: usize,
+    /// color at the tool change
+    from: Rgb,
+    /// purge volume of the current transition, and the volume extruded since
+    purge: f32,
+    purged: f32,
+}
+
+impl Mixer {
+    pub fn new(palette: Palette, default_purge: f32) -> Self {
+        let from = palette.color(0);
+        Self {
+            palette,
+            default_purge,
+            tool: 0,
+            from,
+            purge: 0f32,
+            purged: 0f32,
+        }
+    }
+
+    pub fn tool_change(&mut self, tool: usize) {
+        if tool == self.tool {
+            return;
+        }
+        self.from = self.color();
+        self.purge = self
+            .palette
+            .purge_volume(self.tool, tool, self.default_purge);
+        self.purged = 0f32;
+        self.tool = tool;
+    }
+
+    /// Color of the material at the nozzle.
+    pub fn color(&self) -> Rgb {
+        let to = self.palette.color(self.tool);
+        if self.purged >= self.purge {
+            return to;
+        }
+        let t = (self.purged / self.purge * BLEND_STEPS).floor() / BLEND_STEPS;
+        let mut rgb = [0u8; 3];
+        for i in 0..3 {
+            let c = self.from[i] as f32 * (1f32 - t) + to[i] as f32 * t;
+            rgb[i] = c.round() as u8;
+        }
+        rgb
+    }
+
+    /// Extrudes `volume` cubic millimeters, and returns the color of the extruded
+    /// material.
+    pub fn extrude(&mut self, volume: f32) -> Rgb {
+        let color = self.color();
+        self.purged += volume;
+        color
+    }
+}
+
+/// Voxels by the color of the filament which deposited them.
+#[derive(Clone)]
+pub struct Colors {
+    pub mixer: Mixer,
+    sets: BTreeMap<Rgb, MonotonicVoxel>,
+}
+
+impl Colors {
+    pub fn new(mixer: Mixer) -> Self {
+        Self {
+            mixer,
+            sets: BTreeMap::new(),
+        }
+    }
+
+    pub fn color_of(&self, coord: VoxelIdx) -> Option<Rgb> {
+        for (color, set) in &self.sets {
+            if set.occupied(coord) {
+                return Some(*color);
+            }
+        }
+        None
+    }
+
+    /// Wraps `v`, so voxels added through the wrapper are recorded with `color`.
+    pub fn coloring<'a, V: Voxel>(&'a mut self, v: &'a mut V, color: Rgb) -> Coloring<'a, V> {
+        Coloring {
+            voxel: v,
+            colors: self,
+            color,
+        }
+    }
+
+    /// Colors each vertex of `model` with the average color of faces around it.
+    pub fn paint<V: Voxel>(&self, model: &mut Model, v: &V) {
+        let mut sums = vec![[0u32; 4]; model.vertices.len()];
+        for face in &model.faces {
+            let (c0, c1, _) = surface::face_cells(model, face);
+            let coord = if v.occupied(c0) { c0 } else { c1 };
+            let color = match self.color_of(coord) {
+                Some(color) => color,
+                None => continue,
+            };
+            for &i in face {
+                for ch in 0..3 {
+                    sums[i][ch] += color[ch] as u32;
+                }
+                sums[i][3] += 1;
+            }
+        }
+
+        model.colors = sums
+            .into_iter()
+            .map(|[r, g, b, n]| match n {
+                0 => DEFAULT_PALETTE[0],
+                n => [(r / n) as u8, (g / n) as u8, (b / n) as u8],
+            })
+            .collect();
+    }
+}
+
+pub struct Coloring<'a, V> {
+    voxel: &'a mut V,
+    colors: &'a mut Colors,
+    color: Rgb,
+}
+
+impl<'a, V: Voxel> Voxel for Coloring<'a, V> {
+    fn blocks(&self) -> usize {
+        self.voxel.blocks()
+    }
+
+    fn ranges(&self) -> usize {
+        self.voxel.ranges()
+    }
+
+    fn bounding_box(&self) -> &BoundingBox {
+        self.voxel.bounding_box()
+    }
+
+    fn occupied(&self, coord: VoxelIdx) -> bool {
+        self.voxel.occupied(coord)
+    }
+
+    fn add(&mut self, coord: VoxelIdx) -> bool {
+        if !self.voxel.add(coord) {
+            return false;
+        }
+        self.colors.sets.entry(self.color).or_default().add(coord);
+        true
+    }
+
+    fn to_model(&self) -> Model {
+        self.voxel.to_model()
+    }
+
+    fn chunk_model(&self, chunk: ChunkId) -> Model {
+        self.voxel.chunk_model(chunk)
+    }
+
+    fn take_dirty(&mut self) -> Vec<ChunkId> {
+        self.voxel.take_dirty()
+    }
+
+    fn reserve(&mut self, bounds: &BoundingBox, blocks: usize) {
+        self.voxel.reserve(bounds, blocks)
+    }
+
+    fn column(&self, x: i32, y: i32) -> Vec<Range<i32>> {
+        self.voxel.column(x, y)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_mixer() {
+        let gcode = "; filament_colour = #FF0000;#0000FF\n; wiping_volumes_matrix = 0,80,80,0\n";
+        let palette = Palette::from_gcode(gcode);
+        assert_eq!(palette.color(1), [0, 0, 0xff]);
+        assert_eq!(palette.purge_volume(0, 1, 0f32), 80f32);
+
+        let mut mixer = Mixer::new(palette, 0f32);
+        assert_eq!(mixer.extrude(10f32), [0xff, 0, 0]);
+
+        mixer.tool_change(1);
+        // mostly red right after the change, blue after the purge volume
+        let c = mixer.extrude(40f32);
+        assert!(c[0] > c[2]);
+        mixer.extrude(40f32);
+        assert_eq!(mixer.extrude(1f32), [0, 0, 0xff]);
+
+        assert_eq!(parse_color("123456").unwrap(), [0x12, 0x34, 0x56]);
+        assert!(parse_color("#00ff0").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use super::{support, Simulation, UNIT};
+
+/// Filament properties, for mass and cost of simulated parts.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Filament {
+    /// in grams per cubic centimeter
+    pub density: f32,
+    /// per kilogram, in any currency
+    pub price: f32,
+}
+
+impl Default for Filament {
+    /// PLA
+    fn default() -> Self {
+        Self {
+            density: 1.24,
+            price: 20.0,
+        }
+    }
+}
+
+/// Returns true for `;TYPE:` comments of skirts and brims, from Cura (`SKIRT`, which
+/// covers brims too) and PrusaSlicer (`Skirt/Brim`, `Skirt`).
+pub fn is_skirt(feature: &str) -> bool {
+    let lower = feature.to_ascii_lowercase();
+    lower.contains("skirt") || lower.contains("brim")
+}
+
+/// Material of deposited voxels.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Usage {
+    pub blocks: usize,
+    /// in cubic millimeters
+    pub volume: f32,
+    /// in grams
+    pub mass: f32,
+    pub cost: f32,
+}
+
+impl Filament {
+    /// Material of voxels deposited by `sim`, without skirts or supports if excluded.
+    /// Voxels deposited over others are not counted, so over-extrusion is only as heavy
+    /// as the volume it fills.
+    pub fn usage<V>(&self, sim: &Simulation<V>, no_skirt: bool, no_support: bool) -> Usage {
+        let blocks = sim
+            .deposited
+            .iter()
+            .filter(|(feature, _)| !(no_skirt && is_skirt(feature)))
+            .filter(|(feature, _)| !(no_support && support::is_support(feature)))
+            .map(|(_, blocks)| blocks)
+            .sum::<usize>();
+        let volume = blocks as f32 * UNIT * UNIT * UNIT;
+        // cubic millimeters to cubic centimeters, grams to kilograms
+        let mass = volume / 1000f32 * self.density;
+        Usage {
+            blocks,
+            volume,
+            mass,
+            cost: mass / 1000f32 * self.price,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{simulate, MonotonicVoxel, Params, Voxel};
+
+    #[test]
+    pub fn test_usage() {
+        let gcode = "G1 X10 Y10 Z0.2\n;TYPE:SKIRT\nG1 X20 Y10 E0.5\n\
+            ;TYPE:WALL-OUTER\nG1 X20 Y30 E1.5\nG1 X10 Y30\n";
+        let sim =
+            simulate::<MonotonicVoxel, _>(gcode, usize::MAX, &Params::default(), |_, _| Ok(()))
+                .unwrap();
+
+        let filament = Filament::default();
+        let all = filament.usage(&sim, false, false);
+        assert_eq!(all.blocks, sim.voxel.blocks());
+        let part = filament.usage(&sim, true, true);
+        assert_eq!(part.blocks, sim.deposited["WALL-OUTER"]);
+        assert!(part.blocks < all.blocks);
+
+        // 1.5mm of 1.75mm filament
+        let filament_volume = 1.5f32 * std::f32::consts::PI * 0.875 * 0.875;
+        assert!((all.volume - filament_volume).abs() / filament_volume < 0.05);
+        assert!((all.mass - all.volume / 1000f32 * 1.24).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,288 @@
+use super::filter::{FilterSpec, Onset};
+use super::metadata::Metadata;
+use super::project::read_gcode;
+use super::render::{self, Still};
+use super::{export_model, simulate_into, BoundingBox, Estimate, Observer, Output, Params};
+use super::{storage, Simulation, Voxel};
+use anyhow::Result;
+use log::*;
+use std::path::{Path, PathBuf};
+
+/// Failure scenario of a dataset, like `clog: clog=layer20:0:30` in a spec file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+    pub filters: Vec<FilterSpec>,
+    /// layer where the print detaches, for spaghetti
+    pub detach_layer: Option<usize>,
+}
+
+impl Scenario {
+    pub fn params(&self, params: &Params) -> Params {
+        let mut params = params.clone();
+        params.filters.extend(self.filters.iter().cloned());
+        if self.detach_layer.is_some() {
+            params.detach_layer = self.detach_layer;
+        }
+        params
+    }
+
+    /// Starts of injected failures. Flow changes are settings, not failures.
+    pub fn onsets(&self) -> Vec<Onset> {
+        let mut onsets = self
+            .filters
+            .iter()
+            .filter_map(|f| match f {
+                FilterSpec::Clog { at, .. } | FilterSpec::Shift { at, .. } => Some(*at),
+                FilterSpec::DropTool(_) => Some(Onset::Time(0f32)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        onsets.extend(self.detach_layer.map(Onset::Layer));
+        onsets
+    }
+
+    /// Label of a frame before `layer`, at print time `time`: whether any failure has
+    /// started.
+    pub fn failed(&self, layer: usize, time: f32) -> bool {
+        self.onsets().iter().any(|onset| match onset {
+            Onset::Time(seconds) => time >= *seconds,
+            Onset::Layer(l) => layer > *l,
+        })
+    }
+}
+
+/// Scenarios of a spec file, one per line as `name: failure failure ...`. Failures are
+/// `--filter` values like `clog=layer20:0:30`, or `detach=30` for spaghetti. A scenario
+/// without failures is a clean print. Lines starting with `#` are comments.
+pub fn parse_spec(spec: &str) -> Result<Vec<Scenario>> {
+    let mut scenarios: Vec<Scenario> = Vec::new();
+    for (i, line) in spec.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let context = || format!("spec line {}: {}", i + 1, line);
+        let Some((name, failures)) = line.split_once(':') else {
+            anyhow::bail!("{}: expected name: failures", context());
+        };
+        let name = name.trim();
+        anyhow::ensure!(
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "{}: names are letters, digits, - and _",
+            context()
+        );
+        anyhow::ensure!(
+            scenarios.iter().all(|s| s.name != name),
+            "{}: duplicate scenario {}",
+            context(),
+            name
+        );
+
+        let mut scenario = Scenario {
+            name: name.to_owned(),
+            filters: Vec::new(),
+            detach_layer: None,
+        };
+        for failure in failures.split_whitespace() {
+            match failure.strip_prefix("detach=") {
+                Some(layer) => {
+                    scenario.detach_layer = Some(
+                        layer
+                            .parse()
+                            .map_err(|e| anyhow::anyhow!("{}: {}", context(), e))?,
+                    )
+                }
+                None => scenario.filters.push(
+                    failure
+                        .parse()
+                        .map_err(|e: anyhow::Error| e.context(context()))?,
+                ),
+            }
+        }
+        scenarios.push(scenario);
+    }
+    anyhow::ensure!(!scenarios.is_empty(), "no scenarios in the spec");
+    Ok(scenarios)
+}
+
+/// Gcode files and 3MF projects of `dir`, sorted by name.
+pub fn gcode_files(dir: &str) -> Result<Vec<PathBuf>> {
+    let mut files = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|p| {
+            p.extension().is_some_and(|ext| {
+                ext.eq_ignore_ascii_case("gcode") || ext.eq_ignore_ascii_case("3mf")
+            })
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    anyhow::ensure!(!files.is_empty(), "no .gcode or .3mf files in {}", dir);
+    Ok(files)
+}
+
+/// Frames of a sample.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// last layer to simulate
+    pub layer: usize,
+    /// a frame every this many layers
+    pub every: usize,
+    pub output: Output,
+    /// rendered images instead of meshes
+    pub still: Option<Still>,
+}
+
+/// Writes frames of each layer, with their labels.
+struct Frames<'a> {
+    scenario: &'a Scenario,
+    options: &'a Options,
+    dir: &'a Path,
+    meta: &'a Metadata,
+    /// camera frame of images, the same for every frame of a sample
+    bounds: Option<BoundingBox>,
+    frames: Vec<serde_json::Value>,
+}
+
+impl Frames<'_> {
+    fn write<V: Voxel + Sync>(&mut self, sim: &Simulation<V>, layer: usize) -> Result<()> {
+        let ext = if self.options.still.is_some() {
+            "png"
+        } else {
+            "obj"
+        };
+        let file = format!("frame_{:03}.{}", layer, ext);
+        let path = self.dir.join(&file);
+        let path = path.to_str().unwrap_or_default();
+        match (&self.options.still, &self.bounds) {
+            (Some(still), Some(bounds)) => {
+                render::render_still_in(&sim.voxel, sim.colors.as_ref(), still, bounds, path)?
+            }
+            (Some(still), None) => {
+                render::render_still(&sim.voxel, sim.colors.as_ref(), still, path)?
+            }
+            (None, _) => {
+                let meta = self.meta.with("layer", layer);
+                export_model(sim, &self.options.output, meta, path)?;
+            }
+        }
+        self.frames.push(serde_json::json!({
+            "file": file,
+            "layer": layer,
+            "time": sim.time,
+            "blocks": sim.voxel.blocks(),
+            "failed": self.scenario.failed(layer, sim.time),
+        }));
+        Ok(())
+    }
+}
+
+impl<V: Voxel + Sync> Observer<V> for Frames<'_> {
+    fn on_layer_complete(&mut self, sim: &mut Simulation<V>, layer: usize) -> Result<()> {
+        if !layer.is_multiple_of(self.options.every.max(1)) || sim.voxel.blocks() == 0 {
+            return Ok(());
+        }
+        self.write(sim, layer)
+    }
+}
+
+/// Simulates `gcode` with the failures of `scenario` into `voxel`, writing frames and
+/// `sample.json` with their labels to `dir`. Returns the sample metadata.
+pub fn sample<V: Voxel + Clone + Sync>(
+    voxel: V,
+    gcode: &str,
+    scenario: &Scenario,
+    params: &Params,
+    options: &Options,
+    dir: &str,
+) -> Result<serde_json::Value> {
+    storage::create_dir_all(dir)?;
+    let params = scenario.params(params);
+    let meta = Metadata::gcode(gcode, &params, &options.output)?.with("scenario", &scenario.name);
+    let text = read_gcode(gcode)?;
+
+    // strands and shifts may leave the planned bounds, by a margin
+    let estimate = Estimate::scan(&text, options.layer);
+    let bounds = estimate.bounds.map(|[lo, hi]| {
+        let margin = 5f32;
+        BoundingBox::of_mm(
+            [lo[0] - margin, lo[1] - margin, 0f32],
+            [hi[0] + margin, hi[1] + margin, hi[2] + margin],
+        )
+    });
+    let mut frames = Frames {
+        scenario,
+        options,
+        dir: Path::new(dir),
+        meta: &meta,
+        bounds,
+        frames: Vec::new(),
+    };
+    let sim = simulate_into(voxel, &text, options.layer, &params, &mut frames)?;
+    // the last frame, unless the last layer change just wrote it. Layers of the plan, as
+    // failures like clogs may leave the last layers without segments.
+    let layer = estimate.volumes.len();
+    if frames.frames.last().and_then(|f| f["layer"].as_u64()) != Some(layer as u64) {
+        frames.write(&sim, layer)?;
+    }
+    info!(
+        "dataset: {} {}, frames={}",
+        gcode,
+        scenario.name,
+        frames.frames.len()
+    );
+
+    let onsets = scenario
+        .onsets()
+        .iter()
+        .map(|o| o.to_string())
+        .collect::<Vec<_>>();
+    let sample = serde_json::json!({
+        "metadata": meta.to_json(),
+        "scenario": scenario.name,
+        "failures": scenario.filters.iter().map(|f| format!("{:?}", f)).collect::<Vec<_>>(),
+        "detach_layer": scenario.detach_layer,
+        "onsets": onsets,
+        "frames": frames.frames,
+    });
+    let json = serde_json::to_vec_pretty(&sample)?;
+    storage::write(&format!("{}/sample.json", dir), json)?;
+    Ok(sample)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_spec() {
+        let spec = "# failures\nclean:\nclog: clog=layer2:0:30\n\
+            mixed: shift=10:1,0 detach=5\n";
+        let scenarios = parse_spec(spec).unwrap();
+        assert_eq!(scenarios.len(), 3);
+        assert!(scenarios[0].onsets().is_empty());
+        assert!(!scenarios[0].failed(100, 1e6));
+
+        // labels of frames before layers: layer 2 is done in the frame before layer 3
+        let clog = &scenarios[1];
+        assert!(!clog.failed(2, 0.0));
+        assert!(clog.failed(3, 0.0));
+
+        let mixed = &scenarios[2];
+        assert_eq!(mixed.detach_layer, Some(5));
+        assert!(mixed.failed(1, 10.0));
+        assert!(mixed.failed(6, 0.0));
+        let params = mixed.params(&Params::default());
+        assert_eq!((params.filters.len(), params.detach_layer), (1, Some(5)));
+
+        assert!(parse_spec("clean\n").is_err());
+        assert!(parse_spec("a:\na: flow=1\n").is_err());
+        assert!(parse_spec("a: clog=x\n").is_err());
+        assert!(parse_spec("# nothing\n").is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use super::raster::{self, Sweep};
+use super::{inject_at, Voxel, VoxelIdx, UNIT};
+use anyhow::Result;
+use nalgebra::Vector3;
+use std::sync::Arc;
+
+/// Material deposited by a step along an extrusion path, as seen by a `DepositionModel`.
+#[derive(Clone, Debug)]
+pub struct Bead {
+    /// nozzle at the start and end of the step, in millimeters
+    pub from: Vector3<f32>,
+    pub to: Vector3<f32>,
+    /// voxel at the end of the step, after bridge sag and support gaps
+    pub pos: VoxelIdx,
+    /// lowest and highest Z of voxels in the bead, in voxels
+    pub zlow: i32,
+    pub zhigh: i32,
+    /// voxels to add
+    pub blocks: usize,
+    /// filament extruded during the step, in millimeters of E
+    pub e: f32,
+    /// speed of the nozzle, in millimeters per second
+    pub speed: f32,
+}
+
+/// Bead model, turning extruded filament into voxels. Implement this to try other bead
+/// shapes without changing the simulation, and set it with `Deposition::custom`.
+pub trait DepositionModel: Send + Sync {
+    /// Adds voxels of `bead` to `v`, returning how many were added.
+    fn deposit(&self, v: &mut dyn Voxel, bead: &Bead) -> usize;
+}
+
+/// Bead model of a simulation.
+#[derive(Clone, Default)]
+pub enum Deposition {
+    /// material spreads from the nozzle to the nearest empty voxels, see `inject_at`
+    #[default]
+    Inject,
+    /// elliptical cross-section swept along the step, overlapping material is lost
+    Ellipse,
+    /// fixed cross-section of a preset swept along the step, see `BeadShape`
+    Preset(BeadShape),
+    Custom(String, Arc<dyn DepositionModel>),
+}
+
+/// Named bead cross-sections: width and height in millimeters, and the exponent of the
+/// super-ellipse bounding them.
+const PRESETS: &[(&str, f32, f32, f32)] = &[
+    ("oval-0.4x0.2", 0.4, 0.2, 2.0),
+    ("oval-0.6x0.3", 0.6, 0.3, 2.0),
+    ("flat-0.4x0.2", 0.4, 0.2, 4.0),
+    ("flat-0.6x0.3", 0.6, 0.3, 4.0),
+];
+
+/// Bead cross-section of a preset, like `oval-0.4x0.2`, or with another super-ellipse
+/// exponent, like `oval-0.4x0.2:n=3`. The bead hangs from the nozzle, and fills from the
+/// bottom up to the extruded volume; material beyond the section is lost.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeadShape {
+    pub name: String,
+    /// in millimeters
+    pub width: f32,
+    pub height: f32,
+    /// 2 for an ellipse, higher for flatter sides and top
+    pub exponent: f32,
+}
+
+impl std::str::FromStr for BeadShape {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (preset, exponent) = match s.split_once(':') {
+            Some((preset, n)) => {
+                let n = n
+                    .strip_prefix("n=")
+                    .ok_or_else(|| anyhow::anyhow!("expected n=exponent, got {}", n))?;
+                let n = n.parse::<f32>()?;
+                anyhow::ensure!(n >= 1f32, "super-ellipse exponent below 1: {}", n);
+                (preset, Some(n))
+            }
+            None => (s, None),
+        };
+        let (_, width, height, n) = PRESETS
+            .iter()
+            .find(|(name, ..)| *name == preset)
+            .ok_or_else(|| anyhow::anyhow!("unknown bead preset {}", preset))?;
+        Ok(Self {
+            name: s.to_owned(),
+            width: *width,
+            height: *height,
+            exponent: exponent.unwrap_or(*n),
+        })
+    }
+}
+
+impl Deposition {
+    pub fn custom<M: DepositionModel + 'static>(name: &str, model: M) -> Self {
+        Self::Custom(name.to_owned(), Arc::new(model))
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Inject => "inject",
+            Self::Ellipse => "ellipse",
+            Self::Preset(shape) => &shape.name,
+            Self::Custom(name, _) => name,
+        }
+    }
+
+    /// Built-in models are called without dynamic dispatch, as they run for every step.
+    pub fn deposit<V: Voxel>(&self, v: &mut V, bead: &Bead) -> usize {
+        match self {
+            Self::Inject => inject_at(v, bead.zlow, bead.zhigh, bead.pos, bead.blocks),
+            Self::Ellipse => ellipse(v, bead),
+            Self::Preset(shape) => preset(v, bead, shape),
+            Self::Custom(_, model) => model.deposit(v, bead),
+        }
+    }
+}
+
+impl std::fmt::Debug for Deposition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl PartialEq for Deposition {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Custom(_, a), Self::Custom(_, b)) => Arc::ptr_eq(a, b),
+            _ => self.name() == other.name(),
+        }
+    }
+}
+
+impl std::str::FromStr for Deposition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "inject" => Ok(Self::Inject),
+            "ellipse" => Ok(Self::Ellipse),
+            _ => s.parse().map(Self::Preset).map_err(|e| {
+                let presets = PRESETS.iter().map(|(name, ..)| *name);
+                anyhow::anyhow!(
+                    "unknown deposition model {}, expected inject, ellipse or a bead preset: \
+                    {}: {}",
+                    s,
+                    presets.collect::<Vec<_>>().join(", "),
+                    e
+                )
+            }),
+        }
+    }
+}
+
+/// Fills voxels within an ellipse of the bead height, as wide as the volume of the step
+/// needs, swept from `from` to `pos`.
+fn ellipse<V: Voxel>(v: &mut V, bead: &Bead) -> usize {
+    if bead.blocks == 0 {
+        return 0;
+    }
+    let end = [bead.pos[0] as f32, bead.pos[1] as f32];
+    let start = [bead.from[0] / UNIT, bead.from[1] / UNIT];
+    let dir = [end[0] - start[0], end[1] - start[1]];
+    let len = (dir[0] * dir[0] + dir[1] * dir[1]).sqrt();
+
+    let half_height = (bead.zhigh - bead.zlow + 1) as f32 / 2f32;
+    let zc = (bead.zlow + bead.zhigh) as f32 / 2f32;
+    let blocks = bead.blocks as f32;
+    let half_width = if len < 1f32 {
+        // spheroid, for steps shorter than a voxel
+        (3f32 * blocks / (4f32 * std::f32::consts::PI * half_height)).sqrt()
+    } else {
+        blocks / (len * std::f32::consts::PI * half_height)
+    }
+    .max(0.5);
+
+    // distance in XY from the step
+    let dist = |x: f32, y: f32| {
+        let (px, py) = (x - start[0], y - start[1]);
+        let t = if len < 1f32 {
+            1f32
+        } else {
+            ((px * dir[0] + py * dir[1]) / (len * len)).clamp(0f32, 1f32)
+        };
+        let (dx, dy) = (px - dir[0] * t, py - dir[1] * t);
+        (dx * dx + dy * dy).sqrt()
+    };
+
+    let r = half_width.ceil() as i32;
+    let (x0, x1) = (
+        start[0].min(end[0]).floor() as i32,
+        start[0].max(end[0]).ceil() as i32,
+    );
+    let (y0, y1) = (
+        start[1].min(end[1]).floor() as i32,
+        start[1].max(end[1]).ceil() as i32,
+    );
+    let mut added = 0;
+    for z in bead.zlow..=bead.zhigh {
+        let dz = (z as f32 - zc) / half_height;
+        for y in y0 - r..=y1 + r {
+            for x in x0 - r..=x1 + r {
+                let dxy = dist(x as f32, y as f32) / half_width;
+                if dxy * dxy + dz * dz > 1f32 {
+                    continue;
+                }
+                if v.add([x, y, z].into()) {
+                    added += 1;
+                    if added == bead.blocks {
+                        return added;
+                    }
+                }
+            }
+        }
+    }
+    added
+}
+
+/// Fills the section of `shape` swept from `from` to `pos`, with its top at the nozzle.
+fn preset<V: Voxel>(v: &mut V, bead: &Bead, shape: &BeadShape) -> usize {
+    let (half_width, half_height) = (shape.width / UNIT / 2f32, shape.height / UNIT / 2f32);
+    // the voxel of the nozzle is the top of the bead
+    let zc = bead.pos[2] as f32 + 0.5f32 - half_height;
+    let b = Vector3::new(bead.pos[0] as f32, bead.pos[1] as f32, zc);
+    let a = Vector3::new(bead.from[0] / UNIT, bead.from[1] / UNIT, zc);
+    let sweep = Sweep {
+        a,
+        b,
+        half_width,
+        half_height,
+        exponent: shape.exponent,
+    };
+    raster::fill(v, &sweep, bead.blocks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    pub fn test_deposition() {
+        let bead = Bead {
+            from: Vector3::new(0.0, 0.0, 0.2),
+            to: Vector3::new(1.0, 0.0, 0.2),
+            pos: [25, 0, 5].into(),
+            zlow: 0,
+            zhigh: 5,
+            blocks: 500,
+            e: 0.0,
+            speed: 20.0,
+        };
+        for model in ["inject", "ellipse", "flat-0.4x0.2"] {
+            let model = model.parse::<Deposition>().unwrap();
+            let mut v = MonotonicVoxel::default();
+            let added = model.deposit(&mut v, &bead);
+            assert!(added > 450 && added <= 500, "{:?}: {}", model, added);
+            let bb = v.bounding_box();
+            assert!(bb.bound_min[2] >= 0 && bb.bound_max[2] <= 5, "{:?}", model);
+        }
+        // the ellipse spans the step, instead of spreading from its end
+        let mut v = MonotonicVoxel::default();
+        Deposition::Ellipse.deposit(&mut v, &bead);
+        assert!(v.occupied([2, 0, 3].into()));
+        assert!("blob".parse::<Deposition>().is_err());
+
+        // a 0.4x0.2 oval a millimeter long, with rounded ends, holds about 1.2k voxels;
+        // the rest is lost
+        let oval = "oval-0.4x0.2:n=2".parse::<Deposition>().unwrap();
+        assert_eq!(oval.name(), "oval-0.4x0.2:n=2");
+        let mut v = MonotonicVoxel::default();
+        let bead = Bead {
+            blocks: 2000,
+            ..bead
+        };
+        let added = oval.deposit(&mut v, &bead);
+        assert!(added > 1100 && added < 1400, "{}", added);
+        let bb = v.bounding_box();
+        assert_eq!((bb.bound_min[2], bb.bound_max[2]), (1, 5));
+        assert!("oval-0.4x0.2:3".parse::<Deposition>().is_err());
+        assert!("oval-0.4x0.2:n=0.5".parse::<Deposition>().is_err());
+
+        struct Counter(AtomicUsize);
+        impl DepositionModel for Counter {
+            fn deposit(&self, v: &mut dyn Voxel, bead: &Bead) -> usize {
+                self.0.fetch_add(bead.blocks, Ordering::Relaxed);
+                v.add(bead.pos) as usize
+            }
+        }
+        let model = Deposition::custom("counter", Counter(AtomicUsize::new(0)));
+        let mut v = MonotonicVoxel::default();
+        assert_eq!(model.deposit(&mut v, &bead), 1);
+        assert_eq!(format!("{:?}", model), "counter");
+    }
+}
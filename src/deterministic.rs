@@ -0,0 +1,56 @@
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Merges results of parallel loops in input order from now on, so floating point sums
+/// are identical to single-threaded runs, whatever the thread count and scheduling.
+/// Golden-file regression tests need it; parallel merges are faster otherwise.
+pub fn enable(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Like `ParallelIterator::reduce`. Deterministic runs collect results in input order,
+/// and fold them like a single thread would.
+pub fn reduce<I, T, ID, OP>(iter: I, identity: ID, op: OP) -> T
+where
+    I: ParallelIterator<Item = T>,
+    T: Send,
+    ID: Fn() -> T + Sync + Send,
+    OP: Fn(T, T) -> T + Sync + Send,
+{
+    if is_enabled() {
+        iter.collect::<Vec<_>>().into_iter().fold(identity(), op)
+    } else {
+        iter.reduce(identity, op)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_reduce() {
+        // sums of these differ by the order of additions
+        let values = (0..10000)
+            .map(|i| 1f32 / (i as f32 + 1f32) * if i % 3 == 0 { 1e4 } else { 1f32 })
+            .collect::<Vec<_>>();
+        let single = values.iter().fold(0f32, |a, b| a + b);
+
+        enable(true);
+        for threads in [1, 3, 8] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let sum = pool.install(|| reduce(values.par_iter().copied(), || 0f32, |a, b| a + b));
+            assert_eq!(sum, single);
+        }
+        enable(false);
+    }
+}
@@ -0,0 +1,164 @@
+use super::{render, BoundingBox, Voxel};
+use anyhow::Result;
+use rayon::prelude::*;
+use std::ops::Range;
+
+/// Voxels of two runs compared column by column, over both bounding boxes.
+pub struct Diff {
+    size: [usize; 2],
+    /// voxels of each column only in A, only in B, and in both
+    columns: Vec<[u32; 3]>,
+}
+
+/// Sorted and merged, so overlapping ranges are not counted twice.
+fn normalize(mut ranges: Vec<Range<i32>>) -> Vec<Range<i32>> {
+    ranges.retain(|r| !r.is_empty());
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<i32>> = Vec::with_capacity(ranges.len());
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+fn len(ranges: &[Range<i32>]) -> u32 {
+    ranges.iter().map(|r| (r.end - r.start) as u32).sum()
+}
+
+fn overlap(a: &[Range<i32>], b: &[Range<i32>]) -> u32 {
+    let (mut i, mut j, mut n) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        let lo = a[i].start.max(b[j].start);
+        let hi = a[i].end.min(b[j].end);
+        if lo < hi {
+            n += (hi - lo) as u32;
+        }
+        if a[i].end < b[j].end {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    n
+}
+
+impl Diff {
+    pub fn build<A: Voxel + Sync, B: Voxel + Sync>(a: &A, b: &B) -> Self {
+        let bb = a.bounding_box().union(b.bounding_box());
+        if bb.count == 0 {
+            return Self {
+                size: [0, 0],
+                columns: Vec::new(),
+            };
+        }
+        let (min, max) = (bb.bound_min, bb.bound_max);
+        let size = [0, 1].map(|i| (max[i] - min[i] + 1) as usize);
+
+        let columns = (0..size[1])
+            .into_par_iter()
+            .flat_map_iter(|y| {
+                (0..size[0]).map(move |x| {
+                    let (x, y) = (min[0] + x as i32, min[1] + y as i32);
+                    let ca = normalize(a.column(x, y));
+                    let cb = normalize(b.column(x, y));
+                    let both = overlap(&ca, &cb);
+                    [len(&ca) - both, len(&cb) - both, both]
+                })
+            })
+            .collect();
+        Self { size, columns }
+    }
+
+    /// Voxels only in A, only in B, and in both.
+    pub fn totals(&self) -> [usize; 3] {
+        let mut totals = [0usize; 3];
+        for c in &self.columns {
+            for i in 0..3 {
+                totals[i] += c[i] as usize;
+            }
+        }
+        totals
+    }
+
+    /// Writes a top view png of differing voxels per column: red where A has more, blue
+    /// where B has more, and gray where both agree. Rows are flipped so +Y is up.
+    pub fn write_heatmap(&self, path: &str) -> Result<()> {
+        let [w, h] = self.size;
+        anyhow::ensure!(w > 0 && h > 0, "no columns");
+        let max = self
+            .columns
+            .iter()
+            .map(|c| c[0].max(c[1]))
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        let mut data = Vec::with_capacity(w * h * 3);
+        for row in self.columns.chunks(w).rev() {
+            for &[only_a, only_b, both] in row {
+                data.extend_from_slice(&diff_color(only_a, only_b, both, max));
+            }
+        }
+        render::write_png(path, w as u32, h as u32, png::ColorType::Rgb, false, &data)
+    }
+}
+
+fn diff_color(only_a: u32, only_b: u32, both: u32, max: u32) -> [u8; 3] {
+    if only_a == 0 && only_b == 0 {
+        return if both > 0 { [64, 64, 64] } else { [0, 0, 0] };
+    }
+    // dim but visible for a single voxel
+    let ramp = |n: u32| {
+        if n == 0 {
+            0f32
+        } else {
+            0.3 + 0.7 * (n as f32 / max as f32).sqrt()
+        }
+    };
+    let (r, b) = (ramp(only_a), ramp(only_b));
+    [r, r.min(b) * 0.5, b].map(|c| (c * 255f32).round() as u8)
+}
+
+impl BoundingBox {
+    /// Bounding box of both, for renders of different models to line up.
+    pub fn union(&self, other: &Self) -> Self {
+        match (self.count, other.count) {
+            (0, _) => other.clone(),
+            (_, 0) => self.clone(),
+            _ => Self {
+                bound_min: self.bound_min.bb_min(&other.bound_min),
+                bound_max: self.bound_max.bb_max(&other.bound_max),
+                count: self.count + other.count,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{MonotonicVoxel, RangeSetVoxel};
+
+    #[test]
+    pub fn test_diff() {
+        let mut a = MonotonicVoxel::default();
+        let mut b = RangeSetVoxel::default();
+        for z in 0..4 {
+            a.add([0, 0, z].into());
+            b.add([0, 0, z + 2].into());
+        }
+        a.add([3, 1, 0].into());
+
+        let diff = Diff::build(&a, &b);
+        assert_eq!(diff.size, [4, 2]);
+        assert_eq!(diff.columns[0], [2, 2, 2]);
+        assert_eq!(diff.columns[7], [1, 0, 0]);
+        assert_eq!(diff.totals(), [3, 2, 2]);
+
+        assert_eq!(overlap(&[0..2, 4..8], &[1..5, 7..10]), 3);
+        assert_eq!(normalize(vec![4..6, 0..2, 1..3, 5..5]), vec![0..3, 4..6]);
+    }
+}
@@ -0,0 +1,196 @@
+use super::{Voxel, VoxelIdx};
+use rayon::prelude::*;
+
+/// Squared distance transform of `f` along a line, in place, by the lower envelope of
+/// parabolas of Felzenszwalb and Huttenlocher. Cells are 0 at features and infinite
+/// elsewhere, or squared distances along other axes from earlier passes.
+pub fn transform_1d(f: &mut [f32]) {
+    let src = f.to_vec();
+    let h = |p: usize| src[p] + (p * p) as f32;
+    // parabolas of the lower envelope and where each starts, none of infinite cells
+    let mut v: Vec<usize> = Vec::new();
+    let mut z: Vec<f32> = Vec::new();
+    for q in (0..src.len()).filter(|q| src[*q].is_finite()) {
+        let mut s = f32::NEG_INFINITY;
+        while let (Some(&p), Some(&start)) = (v.last(), z.last()) {
+            s = (h(q) - h(p)) / (2 * (q - p)) as f32;
+            if s > start {
+                break;
+            }
+            v.pop();
+            z.pop();
+            s = f32::NEG_INFINITY;
+        }
+        v.push(q);
+        z.push(s);
+    }
+    if v.is_empty() {
+        return;
+    }
+    let mut k = 0;
+    for (q, out) in f.iter_mut().enumerate() {
+        while k + 1 < v.len() && z[k + 1] < q as f32 {
+            k += 1;
+        }
+        let d = q as f32 - v[k] as f32;
+        *out = d * d + src[v[k]];
+    }
+}
+
+/// Exact squared Euclidean distance transform of a grid of `dims` cells, x fastest, in
+/// place: 0 at features and infinite elsewhere becomes the squared distance in cells to
+/// the nearest feature, infinite without any. Separable, a pass of `transform_1d` along
+/// each axis, with lines in parallel; works for any number of dimensions.
+pub fn transform(grid: &mut [f32], dims: &[usize]) {
+    assert_eq!(grid.len(), dims.iter().product::<usize>());
+    let mut stride = 1;
+    for &n in dims {
+        if n == 0 {
+            return;
+        }
+        // blocks of lines along this axis are contiguous, and independent
+        grid.par_chunks_mut(stride * n).for_each(|block| {
+            let mut line = vec![0f32; n];
+            for offset in 0..stride {
+                line.iter_mut()
+                    .enumerate()
+                    .for_each(|(k, c)| *c = block[offset + k * stride]);
+                transform_1d(&mut line);
+                line.iter()
+                    .enumerate()
+                    .for_each(|(k, c)| block[offset + k * stride] = *c);
+            }
+        });
+        stride *= n;
+    }
+}
+
+/// Signed distance of the cells of the bounding box of voxels, padded on each side, to
+/// their surface in voxels: negative inside, positive outside, and 0 halfway between an
+/// occupied and an empty cell. A float per cell, and another while it is computed.
+pub struct DistanceField {
+    /// first cell
+    min: VoxelIdx,
+    dims: [usize; 3],
+    /// x fastest, then y, then z
+    d: Vec<f32>,
+}
+
+impl DistanceField {
+    /// Field of `v`, padded by `pad` cells, so distances outside reach at least that far.
+    pub fn new<V: Voxel + Sync>(v: &V, pad: usize) -> Self {
+        let bb = v.bounding_box();
+        if bb.count == 0 {
+            return Self {
+                min: bb.bound_min,
+                dims: [0; 3],
+                d: Vec::new(),
+            };
+        }
+        let pad = pad as i32;
+        let min = bb.bound_min - VoxelIdx::new([pad; 3]);
+        let max = bb.bound_max + VoxelIdx::new([pad; 3]);
+        let dims = [0, 1, 2].map(|i| (max[i] - min[i] + 1) as usize);
+        let columns = (min[1]..=max[1])
+            .into_par_iter()
+            .flat_map_iter(|y| (min[0]..=max[0]).map(move |x| v.column(x, y)))
+            .collect::<Vec<_>>();
+        let plane = dims[0] * dims[1];
+        let occupied = |i: usize| {
+            let z = min[2] + (i / plane) as i32;
+            columns[i % plane].iter().any(|r| r.contains(&z))
+        };
+
+        // distance to occupied cells outside, and to empty cells inside
+        let mut outside = (0..plane * dims[2])
+            .into_par_iter()
+            .map(|i| if occupied(i) { 0f32 } else { f32::INFINITY })
+            .collect::<Vec<_>>();
+        transform(&mut outside, &dims);
+        let mut inside = outside
+            .par_iter()
+            .map(|d| if *d == 0f32 { f32::INFINITY } else { 0f32 })
+            .collect::<Vec<_>>();
+        transform(&mut inside, &dims);
+        let d = outside
+            .into_par_iter()
+            .zip(inside)
+            .map(|(o, i)| {
+                if o > 0f32 {
+                    o.sqrt() - 0.5f32
+                } else {
+                    0.5f32 - i.sqrt()
+                }
+            })
+            .collect();
+        Self { min, dims, d }
+    }
+
+    /// Coordinate of the first cell.
+    pub fn min(&self) -> VoxelIdx {
+        self.min
+    }
+
+    /// Number of cells along x, y and z.
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    /// Distances of all cells, x fastest, then y, then z.
+    pub fn values(&self) -> &[f32] {
+        &self.d
+    }
+
+    /// Signed distance at `coord` in voxels, none outside of the field.
+    pub fn at(&self, coord: VoxelIdx) -> Option<f32> {
+        let mut i = 0;
+        let mut stride = 1;
+        for a in 0..3 {
+            let c = coord[a] - self.min[a];
+            if c < 0 || c as usize >= self.dims[a] {
+                return None;
+            }
+            i += c as usize * stride;
+            stride *= self.dims[a];
+        }
+        Some(self.d[i])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_distance() {
+        let mut line = [f32::INFINITY, 0f32, f32::INFINITY, f32::INFINITY, 0f32];
+        transform_1d(&mut line);
+        assert_eq!(line, [1f32, 0f32, 1f32, 1f32, 0f32]);
+
+        // 2d with a single feature, by the same passes
+        let mut grid = vec![f32::INFINITY; 4 * 3];
+        grid[0] = 0f32;
+        transform(&mut grid, &[4, 3]);
+        assert_eq!(grid[3 + 2 * 4], 9f32 + 4f32);
+
+        // 4x4x4 cube
+        let mut v = MonotonicVoxel::default();
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    v.add([x, y, z].into());
+                }
+            }
+        }
+        let field = DistanceField::new(&v, 3);
+        assert_eq!(field.dims(), [10; 3]);
+        assert_eq!(field.at([0, 0, 0].into()), Some(-0.5));
+        assert_eq!(field.at([1, 1, 1].into()), Some(-1.5));
+        assert_eq!(field.at([-1, 2, 2].into()), Some(0.5));
+        assert_eq!(field.at([-3, 1, 1].into()), Some(2.5));
+        let corner = field.at([-1, -1, -1].into()).unwrap();
+        assert!((corner - (3f32.sqrt() - 0.5)).abs() < 1e-6);
+        assert_eq!(field.at([-4, 0, 0].into()), None);
+    }
+}
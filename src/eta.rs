@@ -0,0 +1,352 @@
+use super::gcode::{Event, Parser};
+use super::layer::Layers;
+use super::storage;
+use anyhow::Result;
+use nalgebra::Vector3;
+
+/// Move times of the toolhead. Without acceleration, moves take their length over their
+/// feedrate. With acceleration, moves speed up and slow down in trapezoids, slowing down
+/// at corners by the cosine of the turn, and stopping at reversals and dwells. Exit
+/// speeds depend on the next move, so each move is timed when the next one starts.
+#[derive(Clone, Debug, Default)]
+pub struct Motion {
+    /// move waiting for the next one: length, direction, speed and entry speed
+    pending: Option<(f32, Vector3<f32>, f32, f32)>,
+}
+
+impl Motion {
+    /// Follows a move from `from` to `to` at `speed` in millimeters per second, with
+    /// `acceleration` in millimeters per second squared, 0 to ignore it. Returns
+    /// seconds of the moves timed by this one.
+    pub fn advance(
+        &mut self,
+        from: Vector3<f32>,
+        to: Vector3<f32>,
+        speed: f32,
+        acceleration: f32,
+    ) -> f32 {
+        let len = (to - from).magnitude();
+        if acceleration <= 0f32 {
+            return len / speed;
+        }
+        if len <= 0f32 {
+            return self.stop(acceleration);
+        }
+        let dir = (to - from) / len;
+        let Some((_, prev_dir, prev_speed, _)) = self.pending else {
+            self.pending = Some((len, dir, speed, 0f32));
+            return 0f32;
+        };
+        let junction = prev_speed.min(speed) * prev_dir.dot(&dir).max(0f32);
+        let t = self.finish(junction, acceleration);
+        self.pending = Some((len, dir, speed, junction));
+        t
+    }
+
+    /// Stops the toolhead, like dwells and the end of gcode. Returns seconds of the last
+    /// move.
+    pub fn stop(&mut self, acceleration: f32) -> f32 {
+        let t = self.finish(0f32, acceleration);
+        self.pending = None;
+        t
+    }
+
+    fn finish(&self, exit: f32, a: f32) -> f32 {
+        let Some((len, _, v, entry)) = self.pending else {
+            return 0f32;
+        };
+        let ramps = (2f32 * v * v - entry * entry - exit * exit) / (2f32 * a);
+        if ramps <= len {
+            return (2f32 * v - entry - exit) / a + (len - ramps) / v;
+        }
+        // too short to reach its speed, peaking in between
+        let peak = ((2f32 * a * len + entry * entry + exit * exit) / 2f32).sqrt();
+        if peak < entry.max(exit) {
+            return 2f32 * len / (entry + exit);
+        }
+        (2f32 * peak - entry - exit) / a
+    }
+}
+
+/// Temperature of heaters at the start of prints, in celsius.
+const AMBIENT: f32 = 25f32;
+// heating rates in celsius per second, of a typical hotend and bed
+const HOTEND_RATE: f32 = 2.5f32;
+const BED_RATE: f32 = 0.6f32;
+
+/// Heater warming up at a fixed rate towards its target, cooling down at once.
+#[derive(Clone, Debug)]
+struct Heater {
+    rate: f32,
+    /// temperature when the target was set, and print time then
+    temp: f32,
+    since: f32,
+    target: f32,
+}
+
+impl Heater {
+    fn new(rate: f32) -> Self {
+        Self {
+            rate,
+            temp: AMBIENT,
+            since: 0f32,
+            target: AMBIENT,
+        }
+    }
+
+    fn at(&self, time: f32) -> f32 {
+        (self.temp + self.rate * (time - self.since)).min(self.target)
+    }
+
+    fn set(&mut self, target: f32, time: f32) {
+        self.temp = self.at(time);
+        self.since = time;
+        self.target = target;
+    }
+}
+
+/// Hotend and bed, from ambient temperature, for the time of heat-up waits like M109 and
+/// M190 which dominate the start of prints.
+#[derive(Clone, Debug)]
+pub struct Heaters {
+    hotend: Heater,
+    bed: Heater,
+}
+
+impl Default for Heaters {
+    fn default() -> Self {
+        Self {
+            hotend: Heater::new(HOTEND_RATE),
+            bed: Heater::new(BED_RATE),
+        }
+    }
+}
+
+impl Heaters {
+    /// Sets the target of the bed or the hotend at print time `time`, without waiting.
+    pub fn set(&mut self, bed: bool, target: f32, time: f32) {
+        let heater = if bed { &mut self.bed } else { &mut self.hotend };
+        heater.set(target, time);
+    }
+
+    /// Sets the target like `set`, and returns seconds until it is reached.
+    pub fn wait(&mut self, bed: bool, target: f32, time: f32) -> f32 {
+        self.set(bed, target, time);
+        let heater = if bed { &self.bed } else { &self.hotend };
+        (target - heater.temp).max(0f32) / heater.rate
+    }
+}
+
+/// Elapsed print time of a layer, estimated by the slicer and simulated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerTime {
+    pub layer: usize,
+    /// seconds from the start of the print, at the last slicer estimate of the layer
+    pub slicer: f32,
+    pub simulated: f32,
+}
+
+/// Slicer estimates against simulated times.
+#[derive(Clone, Debug, Default)]
+pub struct Comparison {
+    /// total print time estimated by the slicer, in seconds
+    pub slicer_total: Option<f32>,
+    pub simulated_total: f32,
+    pub layers: Vec<LayerTime>,
+}
+
+impl Comparison {
+    /// Time of each layer, from the previous estimate, as (layer, slicer, simulated)
+    /// in seconds.
+    pub fn durations(&self) -> Vec<(usize, f32, f32)> {
+        let mut prev = (0f32, 0f32);
+        self.layers
+            .iter()
+            .map(|l| {
+                let d = (l.layer, l.slicer - prev.0, l.simulated - prev.1);
+                prev = (l.slicer, l.simulated);
+                d
+            })
+            .collect()
+    }
+
+    pub fn write_csv(&self, path: &str) -> Result<()> {
+        use std::io::Write;
+        let mut w = storage::create(path)?;
+        writeln!(w, "layer,slicer_s,simulated_s,delta_s,delta_pct")?;
+        for (layer, slicer, simulated) in self.durations() {
+            let pct = if slicer > 0f32 {
+                (simulated - slicer) / slicer * 100f32
+            } else {
+                0f32
+            };
+            writeln!(
+                w,
+                "{},{:.2},{:.2},{:.2},{:.1}",
+                layer,
+                slicer,
+                simulated,
+                simulated - slicer,
+                pct
+            )?;
+        }
+        w.finish()
+    }
+}
+
+/// `h:mm:ss` of `seconds`.
+pub fn clock(seconds: f32) -> String {
+    let s = seconds.max(0f32) as u64;
+    format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+/// Seconds of durations like `1d 2h 3m 4s`, of PrusaSlicer and Bambu Studio headers.
+fn parse_duration(s: &str) -> Option<f32> {
+    let mut total = 0f32;
+    for part in s.split_whitespace() {
+        let unit = part.chars().last()?;
+        let n = part[..part.len() - 1].parse::<f32>().ok()?;
+        total += n * match unit {
+            'd' => 86400f32,
+            'h' => 3600f32,
+            'm' => 60f32,
+            's' => 1f32,
+            _ => return None,
+        };
+    }
+    Some(total)
+}
+
+/// Remaining minutes of `M73 P.. R..` progress lines, of normal mode.
+fn remaining_minutes(line: &str) -> Option<f32> {
+    let code = line.split(';').next()?.trim();
+    let mut words = code.split_whitespace();
+    if words.next()? != "M73" {
+        return None;
+    }
+    words.find_map(|w| w.strip_prefix('R')?.parse().ok())
+}
+
+/// Compares time estimates in comments of `gcode` against simulated time, at the lines
+/// of the estimates: `;TIME_ELAPSED:` of Cura, and `M73 R` remaining minutes of
+/// PrusaSlicer, Bambu Studio and OrcaSlicer. Layers are numbered like simulations.
+pub fn compare(gcode: &str, acceleration: f32) -> Result<Comparison> {
+    let mut parser = Parser::default();
+    let mut layers = Layers::new(gcode);
+    let mut motion = Motion::default();
+    let mut cmp = Comparison::default();
+    let (mut pos, mut e, mut feedrate) = (Vector3::<f32>::zeros(), 0f64, 1500f32);
+    let (mut layer, mut time) = (0usize, 0f32);
+    // remaining minutes at the start, for elapsed time from M73
+    let mut start_minutes = None;
+
+    for line in gcode.lines() {
+        let trimmed = line.trim_start();
+        let mut slicer = None;
+        if let Some(t) = trimmed.strip_prefix(";TIME:") {
+            cmp.slicer_total = t.trim().parse().ok();
+        } else if let Some(t) = trimmed.strip_prefix(";TIME_ELAPSED:") {
+            slicer = t.trim().parse::<f32>().ok();
+        } else if let Some((_, t)) = trimmed
+            .strip_prefix("; estimated printing time (normal mode)")
+            .or_else(|| trimmed.strip_prefix("; total estimated time"))
+            .and_then(|t| t.split_once(['=', ':']))
+        {
+            cmp.slicer_total = parse_duration(t.trim());
+        } else if let Some(minutes) = remaining_minutes(trimmed) {
+            let start = *start_minutes.get_or_insert(minutes);
+            slicer = Some((start - minutes) * 60f32);
+        }
+
+        for (_, event) in parser.parse(line)? {
+            match event {
+                Event::LayerChange(l) => layer = l,
+                Event::Dwell(seconds) => time += motion.stop(acceleration) + seconds,
+                Event::Travel(words) | Event::Move(words) => {
+                    let dst = words.apply(pos);
+                    if let Some(f) = words.f {
+                        feedrate = f;
+                    }
+                    let dst_e = words.e.unwrap_or(e);
+                    if dst_e > e {
+                        if let Some(l) = layers.extrude(dst[2]) {
+                            layer = l;
+                        }
+                    }
+                    time += motion.advance(pos, dst, feedrate / 60f32, acceleration);
+                    pos = dst;
+                    e = dst_e;
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(slicer) = slicer {
+            let row = LayerTime {
+                layer,
+                slicer,
+                simulated: time,
+            };
+            // the last estimate of each layer
+            match cmp.layers.last_mut() {
+                Some(last) if last.layer == layer => *last = row,
+                _ => cmp.layers.push(row),
+            }
+        }
+    }
+    cmp.simulated_total = time + motion.stop(acceleration);
+    if cmp.slicer_total.is_none() {
+        cmp.slicer_total = start_minutes
+            .map(|m| m * 60f32)
+            .or(cmp.layers.last().map(|l| l.slicer));
+    }
+    Ok(cmp)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_compare() {
+        // a 100mm square at 100mm/s takes 4s, with a second of estimates per layer
+        let square = "G1 X100 Y0 E1\nG1 X100 Y100 E2\nG1 X0 Y100 E3\nG1 X0 Y0 E4\n";
+        let gcode = format!(
+            ";TIME:9\nG1 F6000\n;LAYER:0\n{};TIME_ELAPSED:5\n;LAYER:1\n{};TIME_ELAPSED:9\n",
+            square,
+            square.replace('E', "E1")
+        );
+        let cmp = compare(&gcode, 0f32).unwrap();
+        assert_eq!(cmp.slicer_total, Some(9f32));
+        assert_eq!(cmp.simulated_total, 8f32);
+        assert_eq!(cmp.durations(), [(0, 5f32, 4f32), (1, 4f32, 4f32)]);
+
+        // corners stop, and each side ramps up and down: 1 / 1000 * 100 * 2 - 0.1 extra
+        let accel = compare(&gcode, 1000f32).unwrap();
+        assert!((accel.simulated_total - 8f32 * 1.1).abs() < 1e-3);
+
+        let prusa = "; estimated printing time (normal mode) = 1h 2m 3s\nM73 P0 R10\n\
+            G1 X100 F6000\nM73 P50 R5\n";
+        let cmp = compare(prusa, 0f32).unwrap();
+        assert_eq!(cmp.slicer_total, Some(3723f32));
+        assert_eq!(cmp.layers.last().unwrap().slicer, 300f32);
+        assert_eq!(parse_duration("2m 5s"), Some(125f32));
+
+        // straight moves keep their speed
+        let mut motion = Motion::default();
+        let mut t = 0f32;
+        for x in 0..10 {
+            let from = Vector3::new(x as f32 * 10f32, 0.0, 0.0);
+            t += motion.advance(from, from + Vector3::x() * 10f32, 100f32, 1000f32);
+        }
+        t += motion.stop(1000f32);
+        assert!((t - 1.1).abs() < 1e-4);
+
+        // the hotend heats while waiting for the bed, and is not waited for once hot
+        let mut heaters = Heaters::default();
+        heaters.set(false, 200f32, 0f32);
+        assert!((heaters.wait(true, 55f32, 0f32) - 50f32).abs() < 1e-3);
+        assert!((heaters.wait(false, 200f32, 50f32) - 20f32).abs() < 1e-3);
+        assert_eq!(heaters.wait(false, 180f32, 100f32), 0f32);
+    }
+}
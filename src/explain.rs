@@ -0,0 +1,386 @@
+use super::Params;
+use serde_json::{json, Value};
+
+/// Tunable parameter of `Params`, for `explain-params`.
+struct Param {
+    name: &'static str,
+    unit: Option<&'static str>,
+    /// settable by `Params::set`
+    settable: bool,
+    /// modules which read it
+    consumers: &'static [&'static str],
+    description: &'static str,
+    value: fn(&Params) -> Value,
+}
+
+/// Shortest decimal of `v`, so 0.1 is not printed as 0.10000000149011612.
+fn float(v: f32) -> Value {
+    json!(v.to_string().parse::<f64>().unwrap_or_default())
+}
+
+const PARAMS: &[Param] = &[
+    Param {
+        name: "step_size",
+        unit: Some("mm"),
+        settable: true,
+        consumers: &["deposit", "path"],
+        description: "maximum distance between deposition points along a move",
+        value: |p| float(p.step_size),
+    },
+    Param {
+        name: "merge_length",
+        unit: Some("mm"),
+        settable: true,
+        consumers: &["path"],
+        description: "consecutive moves are merged into paths of this length",
+        value: |p| float(p.merge_length),
+    },
+    Param {
+        name: "coordinate_limit",
+        unit: Some("mm"),
+        settable: false,
+        consumers: &["guard"],
+        description: "moves to coordinates beyond this on any axis are dropped",
+        value: |p| float(p.coordinate_limit),
+    },
+    Param {
+        name: "arc_tolerance",
+        unit: Some("mm"),
+        settable: false,
+        consumers: &["gcode"],
+        description: "largest distance of segments of G2 and G3 arcs from the arc",
+        value: |p| float(p.arc_tolerance),
+    },
+    Param {
+        name: "bridges",
+        unit: None,
+        settable: true,
+        consumers: &["bridge", "tags"],
+        description: "detect and tag bridges",
+        value: |p| json!(p.bridges),
+    },
+    Param {
+        name: "bridge_sag",
+        unit: None,
+        settable: true,
+        consumers: &["bridge"],
+        description: "bridge sag depth relative to span length",
+        value: |p| float(p.bridge_sag),
+    },
+    Param {
+        name: "seams",
+        unit: None,
+        settable: true,
+        consumers: &["seam", "tags"],
+        description: "detect and tag seams of perimeter loops",
+        value: |p| json!(p.seams),
+    },
+    Param {
+        name: "seam_blob",
+        unit: Some("mm3"),
+        settable: true,
+        consumers: &["seam", "deposit"],
+        description: "extra material deposited at each seam",
+        value: |p| float(p.seam_blob),
+    },
+    Param {
+        name: "ringing_frequency",
+        unit: Some("Hz"),
+        settable: false,
+        consumers: &["ringing"],
+        description: "resonance of the toolhead for emulated ringing, 0 to disable",
+        value: |p| float(p.ringing_frequency),
+    },
+    Param {
+        name: "ringing_damping",
+        unit: None,
+        settable: false,
+        consumers: &["ringing"],
+        description: "damping ratio of emulated ringing",
+        value: |p| float(p.ringing_damping),
+    },
+    Param {
+        name: "fuzzy_skin",
+        unit: Some("mm"),
+        settable: false,
+        consumers: &["fuzzy"],
+        description: "random sideways perturbation of outer walls, 0 to disable",
+        value: |p| float(p.fuzzy_skin),
+    },
+    Param {
+        name: "fuzzy_spacing",
+        unit: Some("mm"),
+        settable: false,
+        consumers: &["fuzzy"],
+        description: "distance between random points of fuzzy skin",
+        value: |p| float(p.fuzzy_spacing),
+    },
+    Param {
+        name: "seed",
+        unit: None,
+        settable: false,
+        consumers: &["fuzzy", "spaghetti"],
+        description: "seed of random perturbations",
+        value: |p| json!(p.seed),
+    },
+    Param {
+        name: "support_z_gap",
+        unit: Some("mm"),
+        settable: true,
+        consumers: &["support"],
+        description: "air gap kept above support material",
+        value: |p| float(p.support_z_gap),
+    },
+    Param {
+        name: "colors",
+        unit: None,
+        settable: false,
+        consumers: &["color"],
+        description: "track filament colors across tool changes",
+        value: |p| json!(p.colors),
+    },
+    Param {
+        name: "palette",
+        unit: Some("#rrggbb"),
+        settable: false,
+        consumers: &["color"],
+        description: "color of each tool, overrides slicer settings if not empty",
+        value: |p| {
+            let colors = p.palette.iter();
+            json!(colors
+                .map(|[r, g, b]| format!("#{:02x}{:02x}{:02x}", r, g, b))
+                .collect::<Vec<_>>())
+        },
+    },
+    Param {
+        name: "purge_volume",
+        unit: Some("mm3"),
+        settable: false,
+        consumers: &["color"],
+        description: "purge volume if not set by the slicer",
+        value: |p| float(p.purge_volume),
+    },
+    Param {
+        name: "features",
+        unit: None,
+        settable: false,
+        consumers: &["schematic"],
+        description: "tag voxels by the class of the feature which deposited them",
+        value: |p| json!(p.features),
+    },
+    Param {
+        name: "flow",
+        unit: None,
+        settable: true,
+        consumers: &["deposit"],
+        description: "extrusion multiplier, like flow of slicers",
+        value: |p| float(p.flow),
+    },
+    Param {
+        name: "spread_depth",
+        unit: Some("voxels"),
+        settable: true,
+        consumers: &["deposit"],
+        description: "depth of beads below the nozzle at the nominal layer height",
+        value: |p| json!(p.spread_depth),
+    },
+    Param {
+        name: "deposit_model",
+        unit: None,
+        settable: true,
+        consumers: &["deposit"],
+        description: "bead model turning extruded filament into voxels",
+        value: |p| json!(p.deposition.name()),
+    },
+    Param {
+        name: "filters",
+        unit: None,
+        settable: false,
+        consumers: &["filter"],
+        description: "rewrite gcode events before they are simulated, in order",
+        value: |p| {
+            json!(p
+                .filters
+                .iter()
+                .map(|f| format!("{:?}", f))
+                .collect::<Vec<_>>())
+        },
+    },
+    Param {
+        name: "detach_layer",
+        unit: Some("layer"),
+        settable: false,
+        consumers: &["spaghetti"],
+        description: "the print detaches from the bed at this layer",
+        value: |p| json!(p.detach_layer),
+    },
+    Param {
+        name: "exclude_purge",
+        unit: None,
+        settable: false,
+        consumers: &["purge"],
+        description: "leave purge lines, skirts and brims out",
+        value: |p| json!(p.exclude_purge),
+    },
+    Param {
+        name: "acceleration",
+        unit: Some("mm/s2"),
+        settable: false,
+        consumers: &["eta"],
+        description: "acceleration of the toolhead for print time, 0 to ignore it",
+        value: |p| float(p.acceleration),
+    },
+    Param {
+        name: "deposit_log",
+        unit: Some("voxels"),
+        settable: false,
+        consumers: &["journal"],
+        description: "log the last voxels added by deposits with their moves, 0 to disable",
+        value: |p| json!(p.deposit_log),
+    },
+    Param {
+        name: "paranoid",
+        unit: None,
+        settable: false,
+        consumers: &["invariant"],
+        description: "check invariants of the voxels after each layer",
+        value: |p| json!(p.paranoid),
+    },
+    Param {
+        name: "build_order",
+        unit: None,
+        settable: false,
+        consumers: &["journal", "ply"],
+        description:
+            "record the move which deposited each voxel, for exports colored by build order",
+        value: |p| json!(p.build_order),
+    },
+];
+
+/// Parameters which change the simulated model or its frames, so exports and cached
+/// frames of different values differ. `deposit_log` and `paranoid` only check the
+/// simulation, and `cancel` only stops it.
+const MODEL: &[&str] = &[
+    "step_size",
+    "merge_length",
+    "coordinate_limit",
+    "arc_tolerance",
+    "bridges",
+    "bridge_sag",
+    "seams",
+    "seam_blob",
+    "ringing_frequency",
+    "ringing_damping",
+    "fuzzy_skin",
+    "fuzzy_spacing",
+    "seed",
+    "support_z_gap",
+    "colors",
+    "palette",
+    "purge_volume",
+    "features",
+    "flow",
+    "spread_depth",
+    "deposit_model",
+    "filters",
+    "detach_layer",
+    "exclude_purge",
+    "acceleration",
+    "build_order",
+];
+
+/// Parameters of `MODEL` in `params` as `name=value`, separated by spaces, for metadata
+/// of exports and keys of cached frames.
+pub fn model(params: &Params) -> String {
+    let model = PARAMS.iter().filter(|p| MODEL.contains(&p.name));
+    model
+        .map(|p| format!("{}={}", p.name, (p.value)(params)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Every tunable parameter with its value in `params`, unit, whether `--set` changes it,
+/// and the modules which read it.
+pub fn explain(params: &Params) -> Value {
+    let params = PARAMS
+        .iter()
+        .map(|p| {
+            json!({
+                "name": p.name,
+                "value": (p.value)(params),
+                "unit": p.unit,
+                "settable": p.settable,
+                "consumers": p.consumers,
+                "description": p.description,
+            })
+        })
+        .collect::<Vec<_>>();
+    params.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_explain() {
+        let mut params = Params::default();
+        params.set("flow", "0.9").unwrap();
+        let explained = explain(&params);
+        let flow = explained
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| p["name"] == "flow")
+            .unwrap();
+        assert_eq!(flow["value"], 0.9);
+
+        for p in PARAMS {
+            let value = (p.value)(&params);
+            let value = value.as_str().map_or(value.to_string(), str::to_owned);
+            assert_eq!(params.clone().set(p.name, &value).is_ok(), p.settable);
+        }
+
+        // every field except cancel, which is not tunable
+        let debug = format!("{:#?}", params);
+        let fields = debug
+            .lines()
+            .filter_map(|l| l.strip_prefix("    ")?.split_once(": ").map(|(k, _)| k))
+            .filter(|k| !k.starts_with(' ') && *k != "cancel")
+            .map(|k| {
+                if k == "deposition" {
+                    "deposit_model"
+                } else {
+                    k
+                }
+            })
+            .collect::<Vec<_>>();
+        let names = PARAMS.iter().map(|p| p.name).collect::<Vec<_>>();
+        assert_eq!(fields, names);
+    }
+
+    #[test]
+    pub fn test_model() {
+        for name in MODEL {
+            assert!(PARAMS.iter().any(|p| p.name == *name), "{}", name);
+        }
+
+        let mut params = Params::default();
+        let model = model(&params);
+        assert!(
+            model.starts_with("step_size=0.1 merge_length=0.0 "),
+            "{}",
+            model
+        );
+        assert!(model.contains(" flow=1.0 "), "{}", model);
+        assert!(!model.contains("cancel"));
+
+        // stopping early or checking invariants leaves the model as is
+        params.deposit_log = 100;
+        params.paranoid = true;
+        params.cancel.cancel();
+        assert_eq!(super::model(&params), model);
+        params.flow = 0.9;
+        assert_ne!(super::model(&params), model);
+    }
+}
@@ -0,0 +1,277 @@
+use super::chunk::{self, ChunkMesh, CHUNK_SIZE};
+use super::{simulate, MonotonicVoxel, Params, Simulation, Voxel};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// Simulation parameters. Start from `tdp_tl_params_default`, so fields added later
+/// keep their defaults.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TdpTlParams {
+    /// stop before this layer, 0 for every layer
+    pub layer: u32,
+    pub step_size: f32,
+    pub merge_length: f32,
+    pub coordinate_limit: f32,
+    pub bridges: bool,
+    pub bridge_sag: f32,
+    pub support_z_gap: f32,
+}
+
+impl From<&TdpTlParams> for Params {
+    fn from(p: &TdpTlParams) -> Self {
+        Params {
+            step_size: p.step_size,
+            merge_length: p.merge_length,
+            coordinate_limit: p.coordinate_limit,
+            bridges: p.bridges,
+            bridge_sag: p.bridge_sag,
+            support_z_gap: p.support_z_gap,
+            ..Params::default()
+        }
+    }
+}
+
+/// Triangle mesh in millimeters: positions and normals as xyz triples, and indices of
+/// triangles. Meshes passed to callbacks are borrowed for the call only.
+#[repr(C)]
+pub struct TdpTlMesh {
+    pub positions: *const f32,
+    pub normals: *const f32,
+    pub vertex_count: usize,
+    pub indices: *const u32,
+    pub index_count: usize,
+    /// chunk of the mesh, columns `[x * 64, (x + 1) * 64)` along X and so on
+    pub chunk: [i32; 2],
+}
+
+impl TdpTlMesh {
+    fn new(mesh: &ChunkMesh, chunk: [i32; 2]) -> Self {
+        Self {
+            positions: mesh.positions.as_ptr() as *const f32,
+            normals: mesh.normals.as_ptr() as *const f32,
+            vertex_count: mesh.positions.len(),
+            indices: mesh.indices.as_ptr(),
+            index_count: mesh.indices.len(),
+            chunk,
+        }
+    }
+}
+
+/// Mesh returned to the caller, freed with `tdp_tl_mesh_free`.
+#[repr(C)]
+struct OwnedMesh {
+    view: TdpTlMesh,
+    mesh: ChunkMesh,
+}
+
+/// Called before each layer change with meshes of chunks changed since the last call.
+/// Returns non-zero to stop the simulation.
+pub type TdpTlLayerCallback =
+    Option<extern "C" fn(user: *mut c_void, layer: u32, mesh: *const TdpTlMesh) -> i32>;
+
+pub struct TdpTlSimulation(Simulation<MonotonicVoxel>);
+
+#[no_mangle]
+pub extern "C" fn tdp_tl_params_default() -> TdpTlParams {
+    let p = Params::default();
+    TdpTlParams {
+        layer: 0,
+        step_size: p.step_size,
+        merge_length: p.merge_length,
+        coordinate_limit: p.coordinate_limit,
+        bridges: p.bridges,
+        bridge_sag: p.bridge_sag,
+        support_z_gap: p.support_z_gap,
+    }
+}
+
+/// Message of the last failed call on this thread, valid until the next call fails.
+#[no_mangle]
+pub extern "C" fn tdp_tl_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        e.borrow()
+            .as_ref()
+            .map(|e| e.as_ptr())
+            .unwrap_or(std::ptr::null())
+    })
+}
+
+/// Simulates `len` bytes of gcode. Returns null on errors, or if the callback stopped
+/// the simulation. `params` and `callback` may be null.
+///
+/// # Safety
+/// `gcode` must point to `len` readable bytes, and `params` to a `TdpTlParams`.
+#[no_mangle]
+pub unsafe extern "C" fn tdp_tl_simulate(
+    gcode: *const u8,
+    len: usize,
+    params: *const TdpTlParams,
+    callback: TdpTlLayerCallback,
+    user: *mut c_void,
+) -> *mut TdpTlSimulation {
+    if gcode.is_null() {
+        set_error("gcode is null".to_owned());
+        return std::ptr::null_mut();
+    }
+    let gcode = std::slice::from_raw_parts(gcode, len);
+    let params = params
+        .as_ref()
+        .copied()
+        .unwrap_or_else(|| tdp_tl_params_default());
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let gcode = std::str::from_utf8(gcode)?;
+        let layer = match params.layer {
+            0 => usize::MAX,
+            l => l as usize,
+        };
+        let on_layer = |sim: &mut Simulation<MonotonicVoxel>, layer: usize| {
+            let Some(callback) = callback else {
+                return Ok(());
+            };
+            for id in sim.voxel.take_dirty() {
+                let mesh = chunk::mesh_chunk(&sim.voxel, id);
+                if mesh.is_empty() {
+                    continue;
+                }
+                if callback(user, layer as u32, &TdpTlMesh::new(&mesh, id)) != 0 {
+                    anyhow::bail!("stopped by callback at layer {}", layer);
+                }
+            }
+            Ok(())
+        };
+        simulate(gcode, layer, &Params::from(&params), on_layer)
+    }));
+
+    match result {
+        Ok(Ok(sim)) => Box::into_raw(Box::new(TdpTlSimulation(sim))),
+        Ok(Err(e)) => {
+            set_error(e.to_string());
+            std::ptr::null_mut()
+        }
+        Err(_) => {
+            set_error("panic in simulation".to_owned());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+/// `sim` must be returned by `tdp_tl_simulate`, and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn tdp_tl_blocks(sim: *const TdpTlSimulation) -> usize {
+    (*sim).0.voxel.blocks()
+}
+
+/// Returns 1 if the voxel at the index is occupied. Voxels are 0.04mm cubes, voxel
+/// `[x, y, z]` centered at `[x, y, z] * 0.04` in millimeters.
+///
+/// # Safety
+/// `sim` must be returned by `tdp_tl_simulate`, and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn tdp_tl_occupied(
+    sim: *const TdpTlSimulation,
+    x: i32,
+    y: i32,
+    z: i32,
+) -> i32 {
+    (*sim).0.voxel.occupied([x, y, z].into()) as i32
+}
+
+/// Meshes every exposed face of the model.
+///
+/// # Safety
+/// `sim` must be returned by `tdp_tl_simulate`, and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn tdp_tl_mesh(sim: *const TdpTlSimulation) -> *mut TdpTlMesh {
+    let v = &(*sim).0.voxel;
+    let mut mesh = ChunkMesh::default();
+    let bb = v.bounding_box();
+    if bb.count > 0 {
+        let [x0, y0] = [0, 1].map(|i| bb.bound_min[i].div_euclid(CHUNK_SIZE));
+        let [x1, y1] = [0, 1].map(|i| bb.bound_max[i].div_euclid(CHUNK_SIZE));
+        for cy in y0..=y1 {
+            for cx in x0..=x1 {
+                mesh.append(chunk::mesh_chunk(v, [cx, cy]));
+            }
+        }
+    }
+
+    // buffers of the vectors stay in place when the mesh moves into the box
+    let owned = Box::new(OwnedMesh {
+        view: TdpTlMesh::new(&mesh, [0, 0]),
+        mesh,
+    });
+    Box::into_raw(owned) as *mut TdpTlMesh
+}
+
+/// # Safety
+/// `mesh` must be returned by `tdp_tl_mesh`, and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn tdp_tl_mesh_free(mesh: *mut TdpTlMesh) {
+    if !mesh.is_null() {
+        drop(Box::from_raw(mesh as *mut OwnedMesh));
+    }
+}
+
+/// # Safety
+/// `sim` must be returned by `tdp_tl_simulate`, and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn tdp_tl_free(sim: *mut TdpTlSimulation) {
+    if !sim.is_null() {
+        drop(Box::from_raw(sim));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    extern "C" fn count(user: *mut c_void, _layer: u32, mesh: *const TdpTlMesh) -> i32 {
+        let n = unsafe { &mut *(user as *mut usize) };
+        *n += unsafe { (*mesh).index_count };
+        0
+    }
+
+    #[test]
+    pub fn test_ffi() {
+        let gcode = b"G1 X10 Y10 Z0.2\n;LAYER:0\nG1 X20 Y10 E1\n;LAYER:1\nG1 X20 Y20 Z0.4 E2\n";
+        let mut indices = 0usize;
+        let user = &mut indices as *mut usize as *mut c_void;
+        unsafe {
+            let sim = tdp_tl_simulate(
+                gcode.as_ptr(),
+                gcode.len(),
+                std::ptr::null(),
+                Some(count),
+                user,
+            );
+            assert!(!sim.is_null());
+            assert!(tdp_tl_blocks(sim) > 0);
+            assert_eq!(tdp_tl_occupied(sim, 375, 250, 5), 1);
+            assert_eq!(tdp_tl_occupied(sim, 0, 0, 0), 0);
+
+            let mesh = tdp_tl_mesh(sim);
+            assert!((*mesh).index_count > indices);
+            assert_eq!((*mesh).index_count % 6, 0);
+            tdp_tl_mesh_free(mesh);
+            tdp_tl_free(sim);
+
+            let sim = tdp_tl_simulate(b"\xff".as_ptr(), 1, std::ptr::null(), None, user);
+            assert!(sim.is_null());
+            assert!(!tdp_tl_last_error().is_null());
+        }
+        assert!(indices > 0);
+    }
+}
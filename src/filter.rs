@@ -0,0 +1,709 @@
+use super::eta::Heaters;
+use super::gcode::{Event, Words};
+use super::region::Region;
+use anyhow::Result;
+use nalgebra::Vector3;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+/// Rewrites gcode events before they are simulated.
+pub trait Filter: Send {
+    /// Pushes events to simulate instead of `event` of gcode `line` to `out`: the event
+    /// itself, changed events, or none to drop it.
+    fn filter(&mut self, line: usize, event: Event, out: &mut Vec<(usize, Event)>);
+}
+
+/// Passes `event` of `line` through `filters` in order.
+pub fn apply(filters: &mut [Box<dyn Filter>], line: usize, event: Event) -> Vec<(usize, Event)> {
+    let mut events = vec![(line, event)];
+    for filter in filters {
+        let mut out = Vec::with_capacity(events.len());
+        for (line, event) in events {
+            filter.filter(line, event, &mut out);
+        }
+        events = out;
+    }
+    events
+}
+
+/// Filter of a simulation, built when the simulation starts.
+#[derive(Clone)]
+pub enum FilterSpec {
+    /// extrusion multiplied by this
+    Flow(f32),
+    /// moves of this tool extrude nothing
+    DropTool(usize),
+    /// partial or full clog: from `at`, extrusion falls to `flow` over `ramp` seconds,
+    /// while the toolhead moves on; 0 flow prints in the air
+    Clog { at: Onset, flow: f32, ramp: f32 },
+    /// layer shift: from `at`, moves are offset by `offset` in millimeters, again every
+    /// `every` seconds or layers unless 0
+    Shift {
+        at: Onset,
+        offset: [f32; 2],
+        every: f32,
+    },
+    /// no extrusion inside `region` on `layers`, like cancelled objects
+    Exclude {
+        region: Region,
+        layers: RangeInclusive<usize>,
+    },
+    /// object `name` of `EXCLUDE_OBJECT_START` cancelled from `layer`, see `Event::Object`
+    CancelObject { name: String, layer: usize },
+    /// filters of library users, by name
+    Custom(String, Arc<dyn Fn() -> Box<dyn Filter> + Send + Sync>),
+}
+
+impl FilterSpec {
+    pub fn custom<F>(name: &str, build: F) -> Self
+    where
+        F: Fn() -> Box<dyn Filter> + Send + Sync + 'static,
+    {
+        Self::Custom(name.to_owned(), Arc::new(build))
+    }
+
+    pub fn build(&self) -> Box<dyn Filter> {
+        match self {
+            Self::Flow(flow) => Box::new(Extrusion::new(*flow, Onset::Time(0f32), 0f32, None)),
+            Self::DropTool(tool) => {
+                Box::new(Extrusion::new(0f32, Onset::Time(0f32), 0f32, Some(*tool)))
+            }
+            Self::Clog { at, flow, ramp } => Box::new(Extrusion::new(*flow, *at, *ramp, None)),
+            Self::Shift { at, offset, every } => Box::new(Shift {
+                at: *at,
+                offset: *offset,
+                every: *every,
+                clock: Clock::default(),
+            }),
+            Self::Exclude { region, layers } => Box::new(Exclude {
+                region: region.clone(),
+                layers: layers.clone(),
+                clock: Clock::default(),
+                e_in: 0f64,
+                e_out: 0f64,
+            }),
+            Self::CancelObject { name, layer } => Box::new(CancelObject {
+                name: name.clone(),
+                layer: *layer,
+                clock: Clock::default(),
+                object: None,
+                skipped: None,
+                e_in: 0f64,
+                e_out: 0f64,
+            }),
+            Self::Custom(_, build) => build(),
+        }
+    }
+}
+
+impl std::fmt::Debug for FilterSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Flow(flow) => write!(f, "flow={}", flow),
+            Self::DropTool(tool) => write!(f, "drop-tool={}", tool),
+            Self::Clog { at, flow, ramp } => write!(f, "clog={}:{}:{}", at, flow, ramp),
+            Self::Shift { at, offset, every } => {
+                write!(f, "shift={}:{},{}:{}", at, offset[0], offset[1], every)
+            }
+            Self::Exclude { region, layers } => {
+                write!(f, "exclude={}", region)?;
+                match (*layers.start(), *layers.end()) {
+                    (0, usize::MAX) => Ok(()),
+                    (from, usize::MAX) => write!(f, ":layer{}-", from),
+                    (from, to) => write!(f, ":layer{}-{}", from, to),
+                }
+            }
+            Self::CancelObject { name, layer } => write!(f, "cancel-object={}@{}", name, layer),
+            Self::Custom(name, _) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl PartialEq for FilterSpec {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Custom(_, a), Self::Custom(_, b)) => Arc::ptr_eq(a, b),
+            _ => format!("{:?}", self) == format!("{:?}", other),
+        }
+    }
+}
+
+impl std::str::FromStr for FilterSpec {
+    type Err = anyhow::Error;
+
+    /// `flow=0.9`, `drop-tool=1`, or `clog=onset[:flow[:ramp]]`: `clog=120:0.3` for 30%
+    /// flow after 120 seconds, `clog=layer20` for no extrusion from layer 20, and
+    /// `clog=60:0.2:30` for flow falling to 20% within 30 seconds from 60 seconds;
+    /// `shift=onset:dx,dy[:every]`: `shift=layer20:1,0` for a 1mm shift in X from layer
+    /// 20, and `shift=layer20:0,-0.5:10` for another shift every 10 layers;
+    /// `exclude=region[:layerN-M]`: `exclude=10,10;50,10;50,50;10,50` for no extrusion
+    /// within the square, and `exclude=plate.geojson:layer20-` for polygons of a GeoJSON
+    /// file from layer 20, see `Region`; `cancel-object=name[@layer]`: `cancel-object=part_1@20`
+    /// for object part_1 cancelled at layer 20, like `EXCLUDE_OBJECT NAME=part_1` of Klipper.
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, value)) = s.split_once('=') else {
+            anyhow::bail!("expected filter as name=value, got {}", s);
+        };
+        let number = |v: &str| -> Result<f32> {
+            let n = v
+                .parse::<f32>()
+                .map_err(|e| anyhow::anyhow!("{}: {}", s, e))?;
+            anyhow::ensure!(n >= 0f32, "{}: must not be negative", s);
+            Ok(n)
+        };
+        match name {
+            "flow" => Ok(Self::Flow(number(value)?)),
+            "drop-tool" => Ok(Self::DropTool(
+                value.parse().map_err(|e| anyhow::anyhow!("{}: {}", s, e))?,
+            )),
+            "clog" => {
+                let mut parts = value.split(':');
+                let at = parts.next().unwrap_or_default().parse()?;
+                let flow = parts.next().map(number).transpose()?.unwrap_or(0f32);
+                let ramp = match parts.next() {
+                    Some(ramp) => number(ramp.strip_suffix('s').unwrap_or(ramp))?,
+                    None => 0f32,
+                };
+                anyhow::ensure!(
+                    parts.next().is_none(),
+                    "{}: expected clog=onset[:flow[:ramp]]",
+                    s
+                );
+                Ok(Self::Clog { at, flow, ramp })
+            }
+            "shift" => {
+                let parts = value.split(':').collect::<Vec<_>>();
+                let (at, offset, every) = match parts[..] {
+                    [at, offset] => (at, offset, "0"),
+                    [at, offset, every] => (at, offset, every),
+                    _ => anyhow::bail!("{}: expected shift=onset:dx,dy[:every]", s),
+                };
+                let Some((dx, dy)) = offset.split_once(',') else {
+                    anyhow::bail!("{}: expected offset as dx,dy", s);
+                };
+                let mm = |v: &str| -> Result<f32> {
+                    v.parse().map_err(|e| anyhow::anyhow!("{}: {}", s, e))
+                };
+                Ok(Self::Shift {
+                    at: at.parse()?,
+                    offset: [mm(dx)?, mm(dy)?],
+                    every: number(every.strip_suffix('s').unwrap_or(every))?,
+                })
+            }
+            "exclude" => {
+                let (region, layers) = match value.rsplit_once(':') {
+                    Some((region, layers)) if layers.starts_with("layer") => (
+                        region,
+                        layer_range(layers).map_err(|e| e.context(s.to_owned()))?,
+                    ),
+                    _ => (value, 0..=usize::MAX),
+                };
+                Ok(Self::Exclude {
+                    region: region
+                        .parse()
+                        .map_err(|e: anyhow::Error| e.context(s.to_owned()))?,
+                    layers,
+                })
+            }
+            "cancel-object" => {
+                let (name, layer) = match value.rsplit_once('@') {
+                    Some((name, layer)) => (
+                        name,
+                        layer.parse().map_err(|e| anyhow::anyhow!("{}: {}", s, e))?,
+                    ),
+                    None => (value, 0),
+                };
+                anyhow::ensure!(!name.is_empty(), "{}: expected cancel-object=name@layer", s);
+                Ok(Self::CancelObject {
+                    name: name.to_owned(),
+                    layer,
+                })
+            }
+            _ => anyhow::bail!(
+                "unknown filter {}, expected one of flow, drop-tool, clog, shift, exclude, \
+                cancel-object",
+                name
+            ),
+        }
+    }
+}
+
+/// `layer20-40`, or `layer20-` for layers from 20 on.
+fn layer_range(s: &str) -> Result<RangeInclusive<usize>> {
+    let range = s.strip_prefix("layer").unwrap_or(s);
+    let Some((from, to)) = range.split_once('-') else {
+        anyhow::bail!("expected layers as layerN-M or layerN-, got {}", s);
+    };
+    let layer = |v: &str| -> Result<usize> {
+        v.parse()
+            .map_err(|e| anyhow::anyhow!("layers {}: {}", s, e))
+    };
+    let to = if to.is_empty() {
+        usize::MAX
+    } else {
+        layer(to)?
+    };
+    Ok(layer(from)?..=to)
+}
+
+/// Start of a failure, in print time or at a layer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Onset {
+    /// seconds of print time, from feedrates
+    Time(f32),
+    /// `;LAYER:` comment of the gcode
+    Layer(usize),
+}
+
+impl Onset {
+    /// Times the onset has come at `clock`, repeating every `every` seconds or layers,
+    /// or only once if 0.
+    fn count(&self, clock: &Clock, every: f32) -> usize {
+        let since = match *self {
+            Self::Time(seconds) if clock.time >= seconds => clock.time - seconds,
+            Self::Layer(layer) if clock.layer >= layer => (clock.layer - layer) as f32,
+            _ => return 0,
+        };
+        if every > 0f32 {
+            (since / every) as usize + 1
+        } else {
+            1
+        }
+    }
+}
+
+impl std::fmt::Display for Onset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Time(seconds) => write!(f, "{}s", seconds),
+            Self::Layer(layer) => write!(f, "layer{}", layer),
+        }
+    }
+}
+
+impl std::str::FromStr for Onset {
+    type Err = anyhow::Error;
+
+    /// `120` or `120s` for seconds, `layer20` for a layer.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(layer) = s.strip_prefix("layer") {
+            let layer = layer
+                .parse()
+                .map_err(|e| anyhow::anyhow!("onset {}: {}", s, e))?;
+            return Ok(Self::Layer(layer));
+        }
+        let seconds = s
+            .strip_suffix('s')
+            .unwrap_or(s)
+            .parse::<f32>()
+            .map_err(|e| anyhow::anyhow!("onset {}: expected seconds or layerN, {}", s, e))?;
+        anyhow::ensure!(seconds >= 0f32, "onset {}: must not be negative", s);
+        Ok(Self::Time(seconds))
+    }
+}
+
+/// Print time, position and layer of the gcode, as filters see it before rewriting.
+struct Clock {
+    /// print time from feedrates, like `Simulation::time`
+    time: f32,
+    pos: Vector3<f32>,
+    feedrate: f32,
+    layer: usize,
+    tool: usize,
+    heaters: Heaters,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self {
+            time: 0f32,
+            pos: Vector3::zeros(),
+            feedrate: 1500f32,
+            layer: 0,
+            tool: 0,
+            heaters: Heaters::default(),
+        }
+    }
+}
+
+impl Clock {
+    /// Follows `event`, before the filter rewrites it.
+    fn follow(&mut self, event: &Event) {
+        match event {
+            Event::Travel(words) | Event::Move(words) => {
+                if let Some(f) = words.f {
+                    self.feedrate = f;
+                }
+                let dst = words.apply(self.pos);
+                self.time += (dst - self.pos).magnitude() / (self.feedrate / 60f32);
+                self.pos = dst;
+            }
+            Event::Dwell(seconds) => self.time += *seconds,
+            Event::Temperature { bed, target, wait } if *wait => {
+                self.time += self.heaters.wait(*bed, *target, self.time);
+            }
+            Event::Temperature { bed, target, .. } => self.heaters.set(*bed, *target, self.time),
+            Event::ToolChange(tool) => self.tool = *tool,
+            Event::LayerChange(layer) => self.layer = *layer,
+            _ => (),
+        }
+    }
+}
+
+/// Scales E of moves, from an onset or for a tool. E is rewritten from its deltas, so
+/// later moves continue from the scaled E.
+struct Extrusion {
+    scale: f32,
+    at: Onset,
+    /// seconds from no scaling to `scale`
+    ramp: f32,
+    tool: Option<usize>,
+    clock: Clock,
+    /// print time of the onset, once reached
+    started: Option<f32>,
+    /// last E of the gcode, and as rewritten
+    e_in: f64,
+    e_out: f64,
+}
+
+impl Extrusion {
+    fn new(scale: f32, at: Onset, ramp: f32, tool: Option<usize>) -> Self {
+        Self {
+            scale,
+            at,
+            ramp,
+            tool,
+            clock: Clock::default(),
+            started: None,
+            e_in: 0f64,
+            e_out: 0f64,
+        }
+    }
+
+    /// Multiplier of extrusion at the current time.
+    fn multiplier(&mut self) -> f32 {
+        if self.tool.is_some_and(|t| t != self.clock.tool) {
+            return 1f32;
+        }
+        if self.started.is_none() {
+            if self.at.count(&self.clock, 0f32) == 0 {
+                return 1f32;
+            }
+            self.started = Some(self.clock.time);
+        }
+        let elapsed = self.clock.time - self.started.unwrap_or(self.clock.time);
+        let t = if self.ramp > 0f32 {
+            (elapsed / self.ramp).min(1f32)
+        } else {
+            1f32
+        };
+        1f32 + (self.scale - 1f32) * t
+    }
+}
+
+impl Filter for Extrusion {
+    fn filter(&mut self, line: usize, mut event: Event, out: &mut Vec<(usize, Event)>) {
+        self.clock.follow(&event);
+        if let Event::Move(Words { e: Some(e), .. }) = &mut event {
+            let k = self.multiplier();
+            self.e_out += (*e - self.e_in) * k as f64;
+            self.e_in = *e;
+            *e = self.e_out;
+        }
+        out.push((line, event));
+    }
+}
+
+/// Offsets XY of moves from an onset, like a belt skipping teeth. Repeated shifts add up.
+struct Shift {
+    at: Onset,
+    offset: [f32; 2],
+    every: f32,
+    clock: Clock,
+}
+
+impl Filter for Shift {
+    fn filter(&mut self, line: usize, mut event: Event, out: &mut Vec<(usize, Event)>) {
+        self.clock.follow(&event);
+        let shifts = self.at.count(&self.clock, self.every) as f32;
+        if let (Event::Travel(words) | Event::Move(words), true) = (&mut event, shifts > 0f32) {
+            // both axes, so moves along one axis keep the shift of the other
+            words.x = Some(self.clock.pos[0] + self.offset[0] * shifts);
+            words.y = Some(self.clock.pos[1] + self.offset[1] * shifts);
+        }
+        out.push((line, event));
+    }
+}
+
+/// Drops extrusion inside a region on some layers, like objects cancelled by Klipper's
+/// exclude_object. Moves are split where they cross the region, and parts inside move
+/// without extruding. E is rewritten from its deltas, like `Extrusion`.
+struct Exclude {
+    region: Region,
+    layers: RangeInclusive<usize>,
+    clock: Clock,
+    e_in: f64,
+    e_out: f64,
+}
+
+impl Filter for Exclude {
+    fn filter(&mut self, line: usize, event: Event, out: &mut Vec<(usize, Event)>) {
+        let from = self.clock.pos;
+        self.clock.follow(&event);
+        let Event::Move(mut words) = event else {
+            out.push((line, event));
+            return;
+        };
+        let Some(e) = words.e else {
+            out.push((line, event));
+            return;
+        };
+        let de = e - self.e_in;
+        self.e_in = e;
+
+        let to = self.clock.pos;
+        let (a, b) = ([from[0], from[1]], [to[0], to[1]]);
+        // retractions and moves elsewhere keep their extrusion
+        if de <= 0f64 || a == b || !self.layers.contains(&self.clock.layer) {
+            self.e_out += de;
+            words.e = Some(self.e_out);
+            out.push((line, Event::Move(words)));
+            return;
+        }
+
+        let mut t0 = 0f32;
+        let mut ts = self.region.crossings(a, b);
+        ts.push(1f32);
+        for t in ts {
+            let mid = from + (to - from) * (t0 + t) / 2f32;
+            if !self.region.contains([mid[0], mid[1]]) {
+                self.e_out += de * (t - t0) as f64;
+            }
+            let mut part = words;
+            if t < 1f32 {
+                let p = from + (to - from) * t;
+                (part.x, part.y, part.z) = (Some(p[0]), Some(p[1]), Some(p[2]));
+            }
+            part.e = Some(self.e_out);
+            out.push((line, Event::Move(part)));
+            t0 = t;
+        }
+    }
+}
+
+/// Drops moves of an object from a layer, like Klipper does after `EXCLUDE_OBJECT`. The
+/// toolhead travels to the end of dropped moves before the next move, and E is rewritten
+/// from its deltas, like `Extrusion`.
+struct CancelObject {
+    name: String,
+    layer: usize,
+    clock: Clock,
+    object: Option<String>,
+    /// position after dropped moves, not reached yet
+    skipped: Option<Vector3<f32>>,
+    e_in: f64,
+    e_out: f64,
+}
+
+impl Filter for CancelObject {
+    fn filter(&mut self, line: usize, mut event: Event, out: &mut Vec<(usize, Event)>) {
+        self.clock.follow(&event);
+        if let Event::Object(object) = &event {
+            self.object = object.clone();
+        }
+        let (Event::Travel(words) | Event::Move(words)) = &mut event else {
+            out.push((line, event));
+            return;
+        };
+        let de = words.e.map_or(0f64, |e| e - self.e_in);
+        self.e_in = words.e.unwrap_or(self.e_in);
+
+        let cancelled = self.clock.layer >= self.layer
+            && self
+                .object
+                .as_ref()
+                .is_some_and(|o| o.eq_ignore_ascii_case(&self.name));
+        if cancelled {
+            self.skipped = Some(self.clock.pos);
+            return;
+        }
+        if let Some(pos) = self.skipped.take() {
+            let travel = Words {
+                x: Some(pos[0]),
+                y: Some(pos[1]),
+                z: Some(pos[2]),
+                ..Words::default()
+            };
+            out.push((line, Event::Travel(travel)));
+        }
+        if words.e.is_some() {
+            self.e_out += de;
+            words.e = Some(self.e_out);
+        }
+        out.push((line, event));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gcode::GcodeStream;
+
+    fn run(specs: &[&str], gcode: &str) -> Vec<(usize, Event)> {
+        let mut filters = specs
+            .iter()
+            .map(|s| s.parse::<FilterSpec>().unwrap().build())
+            .collect::<Vec<_>>();
+        GcodeStream::new(gcode)
+            .flat_map(|e| {
+                let (line, event) = e.unwrap();
+                apply(&mut filters, line, event)
+            })
+            .collect()
+    }
+
+    fn e(events: &[(usize, Event)]) -> Vec<f32> {
+        events
+            .iter()
+            .filter_map(|(_, e)| match e {
+                Event::Move(words) => words.e.map(|e| e as f32),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_filters() {
+        // 10mm moves at 10mm/s
+        let gcode = "G1 X10 E1 F600\nT1\nG1 X20 E2\nT0\nG1 X30 E3\nG1 X40 E2.5\nG1 X50 E3.5\n";
+        assert_eq!(e(&run(&[], gcode)), vec![1.0, 2.0, 3.0, 2.5, 3.5]);
+        assert_eq!(
+            e(&run(&["flow=0.5"], gcode)),
+            vec![0.5, 1.0, 1.5, 1.25, 1.75]
+        );
+        assert_eq!(
+            e(&run(&["drop-tool=1"], gcode)),
+            vec![1.0, 1.0, 2.0, 1.5, 2.5]
+        );
+        // clogged from the third move, retraction included
+        assert_eq!(
+            e(&run(&["clog=2.5:0"], gcode)),
+            vec![1.0, 2.0, 2.0, 2.0, 2.0]
+        );
+        // filters chain in order
+        assert_eq!(
+            e(&run(&["drop-tool=1", "flow=2"], gcode)),
+            vec![2.0, 2.0, 4.0, 3.0, 5.0]
+        );
+
+        struct NoTools;
+        impl Filter for NoTools {
+            fn filter(&mut self, line: usize, event: Event, out: &mut Vec<(usize, Event)>) {
+                if !matches!(event, Event::ToolChange(_)) {
+                    out.push((line, event));
+                }
+            }
+        }
+        let spec = FilterSpec::custom("no-tools", || Box::new(NoTools));
+        let events = GcodeStream::new(gcode)
+            .flat_map(|e| {
+                let (line, event) = e.unwrap();
+                apply(&mut [spec.build()], line, event)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 5);
+
+        // air printing from layer 1, and a clog closing over 2 seconds
+        let layers = ";LAYER:0\nG1 X10 E1 F600\n;LAYER:1\nG1 X20 E2\nG1 X30 E3\n";
+        assert_eq!(e(&run(&["clog=layer1"], layers)), vec![1.0, 1.0, 1.0]);
+        assert_eq!(
+            e(&run(&["clog=0.5:0:2s"], gcode)),
+            vec![1.0, 1.5, 1.5, 1.5, 1.5]
+        );
+
+        assert!("clog=10:0:1:2".parse::<FilterSpec>().is_err());
+        assert!("clog=layerx".parse::<FilterSpec>().is_err());
+        assert!("flow=-1".parse::<FilterSpec>().is_err());
+        let clog = "clog=120:0.3".parse::<FilterSpec>().unwrap();
+        assert_eq!(
+            clog,
+            FilterSpec::Clog {
+                at: Onset::Time(120.0),
+                flow: 0.3,
+                ramp: 0.0
+            }
+        );
+        assert_eq!(format!("{:?}", clog).parse::<FilterSpec>().unwrap(), clog);
+
+        // shifted by 1mm in Y from layer 1, and again every layer
+        let xy = |events: &[(usize, Event)]| -> Vec<[Option<f32>; 2]> {
+            events
+                .iter()
+                .filter_map(|(_, e)| match e {
+                    Event::Move(words) => Some([words.x, words.y]),
+                    _ => None,
+                })
+                .collect()
+        };
+        let layers = ";LAYER:0\nG1 X10 E1\n;LAYER:1\nG1 X20 E2\nG1 Y5\n;LAYER:2\nG1 X30\n";
+        assert_eq!(
+            xy(&run(&["shift=layer1:0,1"], layers)),
+            vec![
+                [Some(10.0), None],
+                [Some(20.0), Some(1.0)],
+                [Some(20.0), Some(6.0)],
+                [Some(30.0), Some(6.0)]
+            ]
+        );
+        assert_eq!(
+            xy(&run(&["shift=layer1:0,1:1"], layers))[3],
+            [Some(30.0), Some(7.0)]
+        );
+        let shift = "shift=layer1:-0.5,0:2".parse::<FilterSpec>().unwrap();
+        assert_eq!(format!("{:?}", shift).parse::<FilterSpec>().unwrap(), shift);
+        assert!("shift=layer1:1".parse::<FilterSpec>().is_err());
+
+        // the middle of the move crosses the square from layer 1, and is not extruded
+        let layers = ";LAYER:0\nG1 X10 E1\n;LAYER:1\nG1 X0 Y5\nG1 X20 E3\nG1 E2\n";
+        let exclude = "exclude=5,0;15,0;15,10;5,10:layer1-";
+        let events = run(&[exclude], layers);
+        assert_eq!(e(&events), vec![1.0, 1.5, 1.5, 2.0, 1.0]);
+        assert_eq!(
+            xy(&events)[2..5],
+            [
+                [Some(5.0), Some(5.0)],
+                [Some(15.0), Some(5.0)],
+                [Some(20.0), None]
+            ]
+        );
+        assert_eq!(
+            e(&run(&["exclude=5,0;15,0;15,10;5,10:layer2-3"], layers)),
+            vec![1.0, 3.0, 2.0]
+        );
+        let exclude = exclude.parse::<FilterSpec>().unwrap();
+        assert_eq!(
+            format!("{:?}", exclude).parse::<FilterSpec>().unwrap(),
+            exclude
+        );
+        assert!("exclude=0,0;1,0;1,1:layerx-".parse::<FilterSpec>().is_err());
+
+        // object a cancelled at layer 1, and a travel to where it ended before object b
+        let objects = ";LAYER:0\nEXCLUDE_OBJECT_START NAME=a\nG1 X10 E1\nEXCLUDE_OBJECT_END\n\
+            EXCLUDE_OBJECT_START NAME=b\nG1 X20 E2\n;LAYER:1\nEXCLUDE_OBJECT_START NAME=a\n\
+            G1 X10 Y1 E3\nEXCLUDE_OBJECT_START NAME=b\nG1 X20 Y1 E4\n";
+        let events = run(&["cancel-object=A@1"], objects);
+        assert_eq!(e(&events), vec![1.0, 2.0, 3.0]);
+        let travel = events.iter().find_map(|(_, e)| match e {
+            Event::Travel(words) => Some([words.x, words.y]),
+            _ => None,
+        });
+        assert_eq!(travel, Some([Some(10.0), Some(1.0)]));
+        assert_eq!(
+            e(&run(&["cancel-object=a@2"], objects)),
+            vec![1.0, 2.0, 3.0, 4.0]
+        );
+        let cancel = "cancel-object=a".parse::<FilterSpec>().unwrap();
+        assert_eq!(format!("{:?}", cancel), "cancel-object=a@0");
+        assert!("cancel-object=a@x".parse::<FilterSpec>().is_err());
+    }
+}
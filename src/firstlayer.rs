@@ -0,0 +1,279 @@
+use super::{render, Segment, Voxel, UNIT};
+use anyhow::Result;
+
+/// 2D grid over XY plane, in voxel units.
+struct Grid<T> {
+    min: [i32; 2],
+    size: [i32; 2],
+    cells: Vec<T>,
+}
+
+impl<T: Copy + Default> Grid<T> {
+    fn new(min: [i32; 2], max: [i32; 2]) -> Self {
+        let size = [max[0] - min[0] + 1, max[1] - min[1] + 1];
+        Self {
+            min,
+            size,
+            cells: vec![T::default(); size[0] as usize * size[1] as usize],
+        }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let dx = x - self.min[0];
+        let dy = y - self.min[1];
+        if dx < 0 || dy < 0 || dx >= self.size[0] || dy >= self.size[1] {
+            return None;
+        }
+        Some((dy * self.size[0] + dx) as usize)
+    }
+
+    fn get(&self, x: i32, y: i32) -> T {
+        match self.index(x, y) {
+            Some(i) => self.cells[i],
+            None => T::default(),
+        }
+    }
+
+    fn set(&mut self, x: i32, y: i32, v: T) {
+        if let Some(i) = self.index(x, y) {
+            self.cells[i] = v;
+        }
+    }
+}
+
+/// Connected region of the intended footprint without deposited material.
+#[derive(Debug)]
+pub struct Gap {
+    /// center, in millimeters
+    pub center: [f32; 2],
+    /// area, in square millimeters
+    pub area: f32,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    /// area covered by toolpaths, with nominal line width, in square millimeters
+    pub intended: f32,
+    /// intended area with deposited material, in square millimeters
+    pub covered: f32,
+    /// gaps larger than the threshold, largest first
+    pub gaps: Vec<Gap>,
+}
+
+impl Report {
+    pub fn coverage(&self) -> f32 {
+        if self.intended == 0f32 {
+            return 0f32;
+        }
+        self.covered / self.intended
+    }
+}
+
+/// Height of the topmost voxel of each column, in micrometers.
+struct Heightmap(Grid<u16>);
+
+impl Heightmap {
+    fn build<V: Voxel>(v: &V) -> Self {
+        let bb = v.bounding_box();
+        let (min, max) = (bb.bound_min, bb.bound_max);
+        let mut grid = Grid::new([min[0], min[1]], [max[0], max[1]]);
+
+        for y in min[1]..=max[1] {
+            for x in min[0]..=max[0] {
+                let top = (min[2]..=max[2])
+                    .rev()
+                    .find(|&z| v.occupied([x, y, z].into()));
+                if let Some(z) = top {
+                    let um = ((z + 1) as f32 * UNIT * 1000f32).round();
+                    grid.set(x, y, um.clamp(1f32, u16::MAX as f32) as u16);
+                }
+            }
+        }
+        Self(grid)
+    }
+
+    /// Writes heightmap as 16-bit grayscale png, one pixel per voxel column. Rows are
+    /// flipped so +Y is up.
+    fn write_png(&self, path: &str) -> Result<()> {
+        let grid = &self.0;
+        let [w, h] = grid.size;
+
+        let mut data = Vec::with_capacity(grid.cells.len() * 2);
+        for y in (0..h).rev() {
+            for x in 0..w {
+                let v = grid.get(grid.min[0] + x, grid.min[1] + y);
+                data.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+        render::write_png(
+            path,
+            w as u32,
+            h as u32,
+            png::ColorType::Grayscale,
+            true,
+            &data,
+        )
+    }
+}
+
+/// Rasterizes first-layer toolpaths with nominal `line_width`, and compares against
+/// deposited voxels. Gaps smaller than `min_gap` square millimeters are ignored.
+fn inspect<V: Voxel>(v: &V, segments: &[Segment], line_width: f32, min_gap: f32) -> Report {
+    let segments = segments.iter().filter(|s| s.layer == 0).collect::<Vec<_>>();
+    let radius = line_width * 0.5 / UNIT;
+
+    let bb = v.bounding_box();
+    let mut min = [bb.bound_min[0], bb.bound_min[1]];
+    let mut max = [bb.bound_max[0], bb.bound_max[1]];
+    for s in &segments {
+        for p in [s.from, s.to] {
+            for axis in 0..2 {
+                let c = p[axis] / UNIT;
+                min[axis] = min[axis].min((c - radius).floor() as i32);
+                max[axis] = max[axis].max((c + radius).ceil() as i32);
+            }
+        }
+    }
+
+    let mut intended = Grid::<bool>::new(min, max);
+    for s in &segments {
+        let p0 = [s.from[0] / UNIT, s.from[1] / UNIT];
+        let p1 = [s.to[0] / UNIT, s.to[1] / UNIT];
+
+        let x0 = (p0[0].min(p1[0]) - radius).floor() as i32;
+        let x1 = (p0[0].max(p1[0]) + radius).ceil() as i32;
+        let y0 = (p0[1].min(p1[1]) - radius).floor() as i32;
+        let y1 = (p0[1].max(p1[1]) + radius).ceil() as i32;
+
+        let d = [p1[0] - p0[0], p1[1] - p0[1]];
+        let len_squared = d[0] * d[0] + d[1] * d[1];
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                // distance from voxel center to the segment
+                let c = [x as f32 - p0[0], y as f32 - p0[1]];
+                let t = if len_squared > 0f32 {
+                    ((c[0] * d[0] + c[1] * d[1]) / len_squared).clamp(0f32, 1f32)
+                } else {
+                    0f32
+                };
+                let dx = c[0] - d[0] * t;
+                let dy = c[1] - d[1] * t;
+                if dx * dx + dy * dy <= radius * radius {
+                    intended.set(x, y, true);
+                }
+            }
+        }
+    }
+
+    let column_occupied =
+        |x: i32, y: i32| (bb.bound_min[2]..=bb.bound_max[2]).any(|z| v.occupied([x, y, z].into()));
+
+    let mut covered = Grid::<bool>::new(min, max);
+    let mut intended_cells = 0usize;
+    let mut covered_cells = 0usize;
+    for y in min[1]..=max[1] {
+        for x in min[0]..=max[0] {
+            if !intended.get(x, y) {
+                continue;
+            }
+            intended_cells += 1;
+            if bb.count > 0 && column_occupied(x, y) {
+                covered.set(x, y, true);
+                covered_cells += 1;
+            }
+        }
+    }
+
+    // 4-connected components of intended, uncovered cells
+    let cell_area = UNIT * UNIT;
+    let mut visited = Grid::<bool>::new(min, max);
+    let mut gaps = Vec::new();
+    for y in min[1]..=max[1] {
+        for x in min[0]..=max[0] {
+            if !intended.get(x, y) || covered.get(x, y) || visited.get(x, y) {
+                continue;
+            }
+
+            let mut stack = vec![(x, y)];
+            visited.set(x, y, true);
+            let mut cells = 0usize;
+            let mut sum = [0f64; 2];
+            while let Some((cx, cy)) = stack.pop() {
+                cells += 1;
+                sum[0] += cx as f64;
+                sum[1] += cy as f64;
+                for (nx, ny) in [(cx + 1, cy), (cx - 1, cy), (cx, cy + 1), (cx, cy - 1)] {
+                    if intended.get(nx, ny) && !covered.get(nx, ny) && !visited.get(nx, ny) {
+                        visited.set(nx, ny, true);
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+
+            let area = cells as f32 * cell_area;
+            if area < min_gap {
+                continue;
+            }
+            gaps.push(Gap {
+                center: [
+                    (sum[0] / cells as f64) as f32 * UNIT,
+                    (sum[1] / cells as f64) as f32 * UNIT,
+                ],
+                area,
+            });
+        }
+    }
+    gaps.sort_by(|a, b| b.area.total_cmp(&a.area));
+
+    Report {
+        intended: intended_cells as f32 * cell_area,
+        covered: covered_cells as f32 * cell_area,
+        gaps,
+    }
+}
+
+/// Writes heightmap of the first layer to `out`, and returns coverage report.
+pub fn first_layer<V: Voxel>(
+    v: &V,
+    segments: &[Segment],
+    out: &str,
+    line_width: f32,
+    min_gap: f32,
+) -> Result<Report> {
+    if v.bounding_box().count > 0 {
+        Heightmap::build(v).write_png(out)?;
+    }
+    Ok(inspect(v, segments, line_width, min_gap))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_inspect() {
+        // 2mm line along X at y=0, with 0.4mm width
+        let segments = [Segment {
+            from: [0f32, 0f32, 0.2f32].into(),
+            to: [2f32, 0f32, 0.2f32].into(),
+            layer: 0,
+        }];
+
+        // cover left half of the line only
+        let mut v = MonotonicVoxel::default();
+        for x in -5..25 {
+            for y in -5..5 {
+                v.add([x, y, 0].into());
+            }
+        }
+
+        let report = inspect(&v, &segments, 0.4, 0.01);
+        let coverage = report.coverage();
+        assert!(coverage > 0.45 && coverage < 0.6, "coverage={}", coverage);
+        assert_eq!(report.gaps.len(), 1);
+
+        let gap = &report.gaps[0];
+        assert!(gap.center[0] > 1.2 && gap.center[0] < 1.6);
+    }
+}
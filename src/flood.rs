@@ -0,0 +1,345 @@
+use super::{Voxel, VoxelIdx};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Neighbors of a voxel for flood fills.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Connectivity {
+    /// 6 neighbors sharing a face
+    Face,
+    /// 26 neighbors sharing a face, an edge or a corner
+    Vertex,
+}
+
+impl Connectivity {
+    pub fn offsets(&self) -> Vec<VoxelIdx> {
+        let all =
+            (-1..=1).flat_map(|z| (-1..=1).flat_map(move |y| (-1i32..=1).map(move |x| [x, y, z])));
+        all.filter(|d| match self {
+            Self::Face => d.iter().map(|c| c.abs()).sum::<i32>() == 1,
+            Self::Vertex => *d != [0, 0, 0],
+        })
+        .map(VoxelIdx::from)
+        .collect()
+    }
+}
+
+/// Visits nodes connected to `seeds` through `neighbors`. `visit` marks a node reached,
+/// returning false for nodes reached already or not to be filled, whose neighbors are
+/// not followed. Callers keep reached nodes in whatever fits their space, like a grid or
+/// column ranges.
+pub fn fill<T, I>(
+    seeds: impl IntoIterator<Item = T>,
+    mut visit: impl FnMut(&T) -> bool,
+    neighbors: impl Fn(&T) -> I,
+) where
+    I: IntoIterator<Item = T>,
+{
+    let mut stack = seeds.into_iter().filter(|s| visit(s)).collect::<Vec<_>>();
+    while let Some(node) = stack.pop() {
+        stack.extend(neighbors(&node).into_iter().filter(|n| visit(n)));
+    }
+}
+
+/// Z range of a column, the node of volume fills.
+type Run = ([i32; 2], Range<i32>);
+
+/// Runs of `columns` connected to `run`, within `min` and `max`. Runs of a column are
+/// separated by at least a voxel, so connected runs are in neighbor columns only.
+fn connected(
+    run: &Run,
+    connectivity: Connectivity,
+    min: VoxelIdx,
+    max: VoxelIdx,
+    columns: &impl Fn(i32, i32) -> Vec<Range<i32>>,
+) -> Vec<Run> {
+    let ([x, y], z) = run;
+    // diagonal neighbors reach one voxel further up and down
+    let (sides, window) = match connectivity {
+        Connectivity::Face => (&[[1, 0], [-1, 0], [0, 1], [0, -1]][..], z.clone()),
+        Connectivity::Vertex => (
+            &[
+                [1, 0],
+                [-1, 0],
+                [0, 1],
+                [0, -1],
+                [1, 1],
+                [1, -1],
+                [-1, 1],
+                [-1, -1],
+            ][..],
+            z.start - 1..z.end + 1,
+        ),
+    };
+    let mut runs = Vec::new();
+    for [dx, dy] in sides {
+        let (x, y) = (x + dx, y + dy);
+        if x < min[0] || x > max[0] || y < min[1] || y > max[1] {
+            continue;
+        }
+        for z in columns(x, y) {
+            let z = z.start.max(min[2])..z.end.min(max[2] + 1);
+            if !z.is_empty() && z.start < window.end && window.start < z.end {
+                runs.push(([x, y], z));
+            }
+        }
+    }
+    runs
+}
+
+/// Z ranges of empty space of a column with `occupied` ranges, in ascending order.
+fn gaps(occupied: &[Range<i32>]) -> Vec<Range<i32>> {
+    let mut gaps = Vec::new();
+    let mut start = i32::MIN;
+    for r in occupied {
+        if start < r.start {
+            gaps.push(start..r.start);
+        }
+        start = r.end;
+    }
+    if start < i32::MAX {
+        gaps.push(start..i32::MAX);
+    }
+    gaps
+}
+
+/// Space reached by a fill over the bounding box of voxels padded by a voxel, so the
+/// outside of the voxels is a single connected region. Reached space is stored as Z
+/// ranges per column, like the backends store voxels, so masks take memory by the
+/// surface of the fill instead of the volume of the bounding box. Everything beyond the
+/// padding is in the mask.
+#[derive(Clone)]
+pub struct Mask {
+    min: VoxelIdx,
+    // inclusive, below min without voxels
+    max: VoxelIdx,
+    columns: BTreeMap<[i32; 2], Vec<Range<i32>>>,
+}
+
+impl Mask {
+    /// Empty mask over the padded bounding box of `v`, of no voxels without any.
+    pub fn new<V: Voxel>(v: &V) -> Self {
+        let bb = v.bounding_box();
+        let min = bb.bound_min - VoxelIdx::unit();
+        let max = match bb.count {
+            0 => min - VoxelIdx::unit(),
+            _ => bb.bound_max + VoxelIdx::unit(),
+        };
+        Self {
+            min,
+            max,
+            columns: BTreeMap::new(),
+        }
+    }
+
+    /// First voxel of the padded bounding box, always outside of the voxels.
+    pub fn min(&self) -> VoxelIdx {
+        self.min
+    }
+
+    fn inside(&self, coord: VoxelIdx) -> bool {
+        (0..3).all(|a| coord[a] >= self.min[a] && coord[a] <= self.max[a])
+    }
+
+    pub fn contains(&self, coord: VoxelIdx) -> bool {
+        if !self.inside(coord) {
+            return true;
+        }
+        let ranges = self.columns.get(&[coord[0], coord[1]]);
+        ranges.is_some_and(|ranges| ranges.iter().any(|r| r.contains(&coord[2])))
+    }
+
+    /// Voxels in the mask within the padded bounding box.
+    pub fn count(&self) -> usize {
+        let ranges = self.columns.values().flatten();
+        ranges.map(|r| (r.end - r.start) as usize).sum()
+    }
+
+    /// Adds voxels connected to `seeds` through `passable` Z ranges of columns, within
+    /// the padded bounding box. Seeds are added if they pass.
+    pub fn flood(
+        &mut self,
+        seeds: impl IntoIterator<Item = VoxelIdx>,
+        connectivity: Connectivity,
+        passable: impl Fn(i32, i32) -> Vec<Range<i32>>,
+    ) {
+        let (min, max) = (self.min, self.max);
+        let seeds = seeds
+            .into_iter()
+            .filter(|seed| self.inside(*seed))
+            .filter_map(|seed| {
+                let z = passable(seed[0], seed[1])
+                    .into_iter()
+                    .find(|z| z.contains(&seed[2]))?;
+                Some((
+                    [seed[0], seed[1]],
+                    z.start.max(min[2])..z.end.min(max[2] + 1),
+                ))
+            })
+            .collect::<Vec<_>>();
+        let columns = &mut self.columns;
+        fill(
+            seeds,
+            |(coord, z)| {
+                // runs are maximal, so a run is reached if its start is
+                let ranges = columns.entry(*coord).or_default();
+                match ranges.binary_search_by_key(&z.start, |r| r.start) {
+                    Ok(_) => false,
+                    Err(i) => {
+                        ranges.insert(i, z.clone());
+                        true
+                    }
+                }
+            },
+            |run| connected(run, connectivity, min, max, &passable),
+        );
+    }
+}
+
+/// Empty voxels connected to the outside of the bounding box of `v`.
+pub fn exterior<V: Voxel>(v: &V, connectivity: Connectivity) -> Mask {
+    let mut mask = Mask::new(v);
+    let min = mask.min();
+    mask.flood([min], connectivity, |x, y| gaps(&v.column(x, y)));
+    mask
+}
+
+/// Class of a voxel by an `exterior` mask.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Class {
+    Occupied,
+    /// empty and connected to the outside
+    Exterior,
+    /// empty and enclosed by occupied voxels, like pores and closed cavities
+    Interior,
+}
+
+pub fn classify<V: Voxel>(v: &V, exterior: &Mask, coord: VoxelIdx) -> Class {
+    if v.occupied(coord) {
+        Class::Occupied
+    } else if exterior.contains(coord) {
+        Class::Exterior
+    } else {
+        Class::Interior
+    }
+}
+
+/// Connected components of occupied voxels, labeled from 1 by order of their first
+/// voxel, z slowest.
+pub struct Components {
+    /// voxels of each component, from label 1
+    pub sizes: Vec<usize>,
+    // labels of occupied Z ranges of each column
+    labels: BTreeMap<[i32; 2], Vec<(Range<i32>, u32)>>,
+}
+
+impl Components {
+    pub fn new<V: Voxel>(v: &V, connectivity: Connectivity) -> Self {
+        let mask = Mask::new(v);
+        let (min, max) = (mask.min, mask.max);
+        let mut labels = BTreeMap::new();
+        let mut runs = Vec::new();
+        for y in min[1]..=max[1] {
+            for x in min[0]..=max[0] {
+                let column = v.column(x, y);
+                if column.is_empty() {
+                    continue;
+                }
+                runs.extend(column.iter().map(|z| (z.start, y, x)));
+                labels.insert(
+                    [x, y],
+                    column.into_iter().map(|z| (z, 0)).collect::<Vec<_>>(),
+                );
+            }
+        }
+        // the first run of a component in this order starts at its first voxel
+        runs.sort_unstable();
+
+        let mut sizes = Vec::new();
+        for (z, y, x) in runs {
+            let column = &labels[&[x, y]];
+            let i = column.partition_point(|(r, _)| r.start < z);
+            if column[i].1 != 0 {
+                continue;
+            }
+            let seed = ([x, y], column[i].0.clone());
+            let label = sizes.len() as u32 + 1;
+            let mut count = 0;
+            fill(
+                [seed],
+                |(coord, z)| {
+                    let column = labels.get_mut(coord).unwrap();
+                    let i = column.partition_point(|(r, _)| r.start < z.start);
+                    if column[i].1 != 0 {
+                        return false;
+                    }
+                    column[i].1 = label;
+                    count += (z.end - z.start) as usize;
+                    true
+                },
+                |run| connected(run, connectivity, min, max, &|x, y| v.column(x, y)),
+            );
+            sizes.push(count);
+        }
+        Self { sizes, labels }
+    }
+
+    /// Label of the component of `coord`, none if it is empty.
+    pub fn label(&self, coord: VoxelIdx) -> Option<u32> {
+        let column = self.labels.get(&[coord[0], coord[1]])?;
+        let (_, label) = column.iter().find(|(z, _)| z.contains(&coord[2]))?;
+        Some(*label)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_flood() {
+        let mut v = MonotonicVoxel::default();
+        // hollow 5x5x5 cube with a cavity at its center, and a voxel touching its corner
+        for x in 0..5 {
+            for y in 0..5 {
+                for z in 0..5 {
+                    if [x, y, z] != [2, 2, 2] {
+                        v.add([x, y, z].into());
+                    }
+                }
+            }
+        }
+        v.add([5, 5, 5].into());
+
+        assert_eq!(Connectivity::Face.offsets().len(), 6);
+        assert_eq!(Connectivity::Vertex.offsets().len(), 26);
+
+        let ext = exterior(&v, Connectivity::Face);
+        assert_eq!(classify(&v, &ext, [2, 2, 2].into()), Class::Interior);
+        assert_eq!(classify(&v, &ext, [0, 0, 0].into()), Class::Occupied);
+        assert_eq!(classify(&v, &ext, [5, 0, 0].into()), Class::Exterior);
+        assert_eq!(classify(&v, &ext, [-9, 0, 0].into()), Class::Exterior);
+        // padded bounding box of 8^3, without 125 occupied and 1 enclosed
+        assert_eq!(ext.count(), 8 * 8 * 8 - 125 - 1);
+
+        let faces = Components::new(&v, Connectivity::Face);
+        assert_eq!(faces.sizes, vec![124, 1]);
+        assert_eq!(faces.label([5, 5, 5].into()), Some(2));
+        assert_eq!(faces.label([2, 2, 2].into()), None);
+        let vertices = Components::new(&v, Connectivity::Vertex);
+        assert_eq!(vertices.sizes, vec![125]);
+
+        // far apart voxels, reached space is a range per column, not a bit per voxel
+        let mut v = MonotonicVoxel::default();
+        v.add([0, 0, 0].into());
+        v.add([200, 200, 5000].into());
+        let ext = exterior(&v, Connectivity::Face);
+        assert_eq!(ext.count(), 203 * 203 * 5003 - 2);
+        let ranges = ext.columns.values().map(Vec::len).sum::<usize>();
+        assert_eq!(ranges, 203 * 203 + 2);
+        let vertices = Components::new(&v, Connectivity::Vertex);
+        assert_eq!(vertices.sizes, vec![1, 1]);
+        assert_eq!(vertices.label([200, 200, 5000].into()), Some(2));
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Lines appended to a file which is still being written, like `tail -f`. Lines are
+/// only returned once complete, so a move is never cut in half by a slow writer.
+pub struct Tail {
+    file: File,
+    /// bytes read so far
+    offset: u64,
+    /// partial last line
+    pending: Vec<u8>,
+}
+
+impl Tail {
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(Self {
+            file: File::open(path)?,
+            offset: 0,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Complete lines appended since the last call, or none if there are no new lines.
+    /// Fails if the file is truncated, as the lines already simulated are gone.
+    pub fn read(&mut self) -> Result<Option<String>> {
+        let len = self.file.metadata()?.len();
+        anyhow::ensure!(len >= self.offset, "followed file was truncated");
+        if len > self.offset {
+            self.file.seek(SeekFrom::Start(self.offset))?;
+            let read = (&mut self.file)
+                .take(len - self.offset)
+                .read_to_end(&mut self.pending)?;
+            self.offset += read as u64;
+        }
+
+        let Some(end) = self.pending.iter().rposition(|b| *b == b'\n') else {
+            return Ok(None);
+        };
+        let rest = self.pending.split_off(end + 1);
+        let lines = std::mem::replace(&mut self.pending, rest);
+        Ok(Some(String::from_utf8_lossy(&lines).into_owned()))
+    }
+
+    /// Last line without a newline, once the writer is done.
+    pub fn rest(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let rest = std::mem::take(&mut self.pending);
+        Some(String::from_utf8_lossy(&rest).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    pub fn test_tail() {
+        let path = std::env::temp_dir().join(format!("tdp-tl-tail-{}", std::process::id()));
+        let mut f = File::create(&path).unwrap();
+        let mut tail = Tail::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(tail.read().unwrap(), None);
+
+        write!(f, "G1 X1\nG1 X").unwrap();
+        assert_eq!(tail.read().unwrap().as_deref(), Some("G1 X1\n"));
+        assert_eq!(tail.read().unwrap(), None);
+        write!(f, "2\nG1 X3").unwrap();
+        assert_eq!(tail.read().unwrap().as_deref(), Some("G1 X2\n"));
+        assert_eq!(tail.rest().as_deref(), Some("G1 X3"));
+
+        f.set_len(0).unwrap();
+        assert!(tail.read().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
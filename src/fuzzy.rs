@@ -0,0 +1,87 @@
+use super::{render::random, Path};
+use nalgebra::Vector3;
+
+/// Returns true for `;TYPE:` comments of outer walls, from Cura (`WALL-OUTER`) and
+/// PrusaSlicer (`External perimeter`).
+pub fn is_outer_wall(feature: &str) -> bool {
+    let feature = feature.to_ascii_lowercase();
+    feature == "wall-outer" || feature == "external perimeter"
+}
+
+/// Random perturbation of outer walls, like fuzzy skin of slicers. Deposition points
+/// move sideways by noise interpolated between knots along each path. Knots are hashed
+/// from the seed and a running count, so the same seed gives the same surface.
+#[derive(Clone, Debug)]
+pub struct FuzzySkin {
+    /// maximum offset from the path, in millimeters
+    thickness: f32,
+    /// distance between knots along the path, in millimeters
+    spacing: f32,
+    seed: u32,
+    /// knots used by previous paths
+    knots: u32,
+}
+
+impl FuzzySkin {
+    pub fn new(thickness: f32, spacing: f32, seed: u32) -> Self {
+        Self {
+            thickness,
+            spacing: spacing.max(1e-3),
+            seed,
+            knots: 0,
+        }
+    }
+
+    fn knot(&self, i: u32) -> f32 {
+        let s = self
+            .seed
+            .wrapping_mul(0x632be5ab)
+            .wrapping_add(self.knots.wrapping_add(i));
+        (random(s) * 2f32 - 1f32) * self.thickness
+    }
+
+    /// Sideways offset from `path` at arc length `d`, in millimeters.
+    pub fn offset(&self, path: &Path, d: f32) -> Vector3<f32> {
+        let t = d / self.spacing;
+        let i = t.floor();
+        let noise = self.knot(i as u32) * (1f32 - (t - i)) + self.knot(i as u32 + 1) * (t - i);
+
+        let dir = path.direction(d);
+        Vector3::new(-dir[1], dir[0], 0f32) * noise
+    }
+
+    /// Moves on to fresh knots after depositing `path`.
+    pub fn finish(&mut self, path: &Path) {
+        self.knots = self
+            .knots
+            .wrapping_add((path.len() / self.spacing) as u32 + 2);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_fuzzy_skin() {
+        let mut path = Path::default();
+        path.push(Vector3::zeros(), Vector3::new(10.0, 0.0, 0.0), 1.0, 20.0);
+
+        let mut fuzzy = FuzzySkin::new(0.3, 0.8, 42);
+        let offsets = (0..100)
+            .map(|i| fuzzy.offset(&path, i as f32 * 0.1))
+            .collect::<Vec<_>>();
+        // sideways only, bounded, and not flat
+        assert!(offsets.iter().all(|o| o[0] == 0f32 && o[1].abs() <= 0.3));
+        assert!(offsets.iter().any(|o| o[1].abs() > 0.05));
+        // same seed, same surface
+        let again = FuzzySkin::new(0.3, 0.8, 42);
+        assert_eq!(again.offset(&path, 3.3), offsets[33]);
+
+        fuzzy.finish(&path);
+        assert_ne!(fuzzy.offset(&path, 3.3), offsets[33]);
+
+        assert!(is_outer_wall("WALL-OUTER"));
+        assert!(!is_outer_wall("WALL-INNER"));
+    }
+}
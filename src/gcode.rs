@@ -0,0 +1,596 @@
+use super::{guard, Cancel};
+use anyhow::Result;
+use nalgebra::{Vector2, Vector3};
+use smallvec::{smallvec, SmallVec};
+
+/// Default largest distance of arc segments from the arc, in millimeters.
+pub const ARC_TOLERANCE: f32 = 0.01;
+
+/// Events of a line with its line number, usually none or one, and segments of arcs.
+pub type Events = SmallVec<[(usize, Event); 1]>;
+
+/// Words of a move, in millimeters and millimeters per minute. Words not given are
+/// none, and axes without them stay where they are. E is double precision, as it adds
+/// up over whole prints with relative extrusion.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Words {
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub z: Option<f32>,
+    pub e: Option<f64>,
+    pub f: Option<f32>,
+}
+
+impl Words {
+    /// Destination of the move from `pos`.
+    pub fn apply(&self, pos: Vector3<f32>) -> Vector3<f32> {
+        Vector3::new(
+            self.x.unwrap_or(pos[0]),
+            self.y.unwrap_or(pos[1]),
+            self.z.unwrap_or(pos[2]),
+        )
+    }
+}
+
+/// Gcode line, as the simulation sees it. Lengths are in millimeters, also after G20,
+/// and positions and E absolute, also after G91 and M83, and continuous across G92,
+/// which sets no event.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Event {
+    /// G0, which never extrudes
+    Travel(Words),
+    /// G1, which extrudes if E rises, and segments of G2 and G3 arcs
+    Move(Words),
+    /// G4, in seconds
+    Dwell(f32),
+    /// M104 and M140 setting the temperature of the hotend or the bed, in celsius, and
+    /// M109 and M190 which also wait for it
+    Temperature { bed: bool, target: f32, wait: bool },
+    /// Tn
+    ToolChange(usize),
+    /// `;LAYER:n`
+    LayerChange(usize),
+    /// `;TYPE:name`
+    Feature(String),
+    /// object being printed from `EXCLUDE_OBJECT_START NAME=name` of Klipper, none from
+    /// `EXCLUDE_OBJECT_END`
+    Object(Option<String>),
+    /// any other comment line, without the semicolon
+    Comment(String),
+}
+
+/// Parser state between lines, so gcode appended later continues with the units,
+/// positioning modes and line numbers of earlier lines.
+#[derive(Clone, Debug)]
+pub struct Parser {
+    /// lines parsed so far
+    line: usize,
+    // millimeters per gcode length unit, inches after G20
+    scale: f32,
+    // relative positioning after G91, and relative extrusion after M83 or G91
+    relative: bool,
+    relative_e: bool,
+    // position and extruder after the last move, where arcs and relative moves start
+    pos: Vector3<f32>,
+    e: f64,
+    // added to absolute coordinates since G92, which renames the position
+    offset: Vector3<f32>,
+    e_offset: f64,
+    arc_tolerance: f32,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self {
+            line: 0,
+            scale: 1f32,
+            relative: false,
+            relative_e: false,
+            pos: Vector3::zeros(),
+            e: 0f64,
+            offset: Vector3::zeros(),
+            e_offset: 0f64,
+            arc_tolerance: ARC_TOLERANCE,
+        }
+    }
+}
+
+impl Parser {
+    /// Splits arcs in segments at most `tolerance` millimeters from the arc.
+    pub fn with_arc_tolerance(mut self, tolerance: f32) -> Self {
+        self.arc_tolerance = tolerance;
+        self
+    }
+
+    /// Parses the next line, returning its events with its line number. Arcs are moves
+    /// along their segments, all with the line number of the arc.
+    pub fn parse(&mut self, line: &str) -> Result<Events> {
+        self.line += 1;
+        let events = self
+            .events(line)
+            .map_err(|e| e.context(format!("line {}: {}", self.line, line)))?;
+        for event in &events {
+            if let Event::Travel(words) | Event::Move(words) = event {
+                self.pos = words.apply(self.pos);
+                self.e = words.e.unwrap_or(self.e);
+            }
+        }
+        Ok(events.into_iter().map(|event| (self.line, event)).collect())
+    }
+
+    fn events(&mut self, line: &str) -> Result<SmallVec<[Event; 1]>> {
+        use nom_gcode::{GCodeLine::*, Mnemonic};
+
+        // extended commands of Klipper, which are not gcode words
+        let command = line.split(';').next().unwrap_or_default().trim();
+        let mut chars = command.chars();
+        if let (Some(a), Some(b)) = (chars.next(), chars.next()) {
+            if a.is_ascii_alphabetic() && (b.is_ascii_alphabetic() || b == '_') {
+                return Ok(extended(command).into_iter().collect());
+            }
+        }
+
+        let (_, item) = nom_gcode::parse_gcode(line)?;
+        let code = match item {
+            Some(Comment(comment)) => {
+                let comment = comment.0;
+                if let Some(ty) = comment.strip_prefix("TYPE:") {
+                    return Ok(smallvec![Event::Feature(ty.to_owned())]);
+                }
+                if let Some(layer) = comment.strip_prefix("LAYER:") {
+                    return Ok(smallvec![Event::LayerChange(layer.parse()?)]);
+                }
+                return Ok(smallvec![Event::Comment(comment.to_owned())]);
+            }
+            Some(GCode(code)) => code,
+            _ => return Ok(SmallVec::new()),
+        };
+        if code.mnemonic == Mnemonic::ToolChange {
+            return Ok(smallvec![Event::ToolChange(code.major as usize)]);
+        }
+        if code.mnemonic == Mnemonic::Miscellaneous {
+            let (bed, wait) = match code.major {
+                82 | 83 => {
+                    self.relative_e = code.major == 83;
+                    return Ok(SmallVec::new());
+                }
+                104 => (false, false),
+                109 => (false, true),
+                140 => (true, false),
+                190 => (true, true),
+                _ => return Ok(SmallVec::new()),
+            };
+            // R waits for cooling too, which is not timed
+            let target = code.arguments().find_map(|(letter, value)| match letter {
+                'S' | 'R' => *value,
+                _ => None,
+            });
+            return Ok(target
+                .map(|target| Event::Temperature { bed, target, wait })
+                .into_iter()
+                .collect());
+        }
+        if code.mnemonic != Mnemonic::General {
+            return Ok(SmallVec::new());
+        }
+
+        let event = match code.major {
+            0 | 1 => {
+                let mut words = Words::default();
+                for (letter, value) in code.arguments() {
+                    let Some(v) = value else {
+                        continue;
+                    };
+                    self.word(&mut words, *letter, *v * self.scale);
+                }
+                if code.major == 0 {
+                    Event::Travel(words)
+                } else {
+                    Event::Move(words)
+                }
+            }
+            2 | 3 => {
+                let mut words = Words::default();
+                let (mut offset, mut radius) = (Vector2::zeros(), None);
+                for (letter, value) in code.arguments() {
+                    let Some(v) = value else {
+                        continue;
+                    };
+                    let v = *v * self.scale;
+                    match letter {
+                        'I' => offset[0] = v,
+                        'J' => offset[1] = v,
+                        'R' => radius = Some(v),
+                        _ => self.word(&mut words, *letter, v),
+                    }
+                }
+                return self.arc(words, offset, radius, code.major == 2);
+            }
+            4 => {
+                // dwell, P in milliseconds or S in seconds
+                let mut seconds = 0f32;
+                for (letter, value) in code.arguments() {
+                    match (*letter, value) {
+                        ('P', Some(v)) => seconds += *v / 1000f32,
+                        ('S', Some(v)) => seconds += *v,
+                        _ => (),
+                    }
+                }
+                Event::Dwell(seconds)
+            }
+            20 => {
+                self.scale = guard::MM_PER_INCH;
+                return Ok(SmallVec::new());
+            }
+            21 => {
+                self.scale = 1f32;
+                return Ok(SmallVec::new());
+            }
+            90 | 91 => {
+                self.relative = code.major == 91;
+                return Ok(SmallVec::new());
+            }
+            92 => {
+                // the current position becomes the given one, all zeros without words
+                let mut words = code.arguments().peekable();
+                let all = words.peek().is_none();
+                for (letter, value) in words {
+                    let v = value.unwrap_or(0f32) * self.scale;
+                    match letter {
+                        'X' => self.offset[0] = self.pos[0] - v,
+                        'Y' => self.offset[1] = self.pos[1] - v,
+                        'Z' => self.offset[2] = self.pos[2] - v,
+                        'E' => self.e_offset = self.e - v as f64,
+                        _ => (),
+                    }
+                }
+                if all {
+                    self.offset = self.pos;
+                    self.e_offset = self.e;
+                }
+                return Ok(SmallVec::new());
+            }
+            _ => return Ok(SmallVec::new()),
+        };
+        Ok(smallvec![event])
+    }
+
+    /// Sets the axis or feedrate of `letter` in `words` to `v` millimeters, from the last
+    /// position in relative modes.
+    fn word(&self, words: &mut Words, letter: char, v: f32) {
+        let axis = |i: usize| {
+            if self.relative {
+                self.pos[i] + v
+            } else {
+                self.offset[i] + v
+            }
+        };
+        match letter {
+            'X' => words.x = Some(axis(0)),
+            'Y' => words.y = Some(axis(1)),
+            'Z' => words.z = Some(axis(2)),
+            'E' if self.relative || self.relative_e => words.e = Some(self.e + v as f64),
+            'E' => words.e = Some(self.e_offset + v as f64),
+            'F' if v > 0f32 => words.f = Some(v),
+            _ => (),
+        }
+    }
+
+    /// Moves along segments of an arc in XY from the last position to `words`, clockwise
+    /// or not, around the center at `offset` from the start, or of `radius`, on the
+    /// larger side if negative. Z and E change evenly along the arc. A full circle ends
+    /// where it starts.
+    fn arc(
+        &self,
+        words: Words,
+        offset: Vector2<f32>,
+        radius: Option<f32>,
+        clockwise: bool,
+    ) -> Result<SmallVec<[Event; 1]>> {
+        anyhow::ensure!(
+            self.arc_tolerance > 0f32,
+            "arc tolerance must be positive: {}",
+            self.arc_tolerance
+        );
+        let (start, end) = (self.pos, words.apply(self.pos));
+        let (a, b) = (start.xy(), end.xy());
+        let center = match radius {
+            Some(r) => {
+                let (chord, mid) = (b - a, (a + b) / 2f32);
+                anyhow::ensure!(chord.norm() > 0f32, "arc of radius without end");
+                // distance of the center from the chord, right of it clockwise
+                let h = (r * r - chord.norm_squared() / 4f32).max(0f32).sqrt();
+                let right = Vector2::new(chord[1], -chord[0]) / chord.norm();
+                let side = if clockwise == (r > 0f32) { 1f32 } else { -1f32 };
+                mid + right * h * side
+            }
+            None => a + offset,
+        };
+        let r = (a - center).norm();
+        let angle = |p: Vector2<f32>| (p[1] - center[1]).atan2(p[0] - center[0]);
+        let mut sweep = angle(b) - angle(a);
+        if clockwise && sweep >= 0f32 {
+            sweep -= std::f32::consts::TAU;
+        } else if !clockwise && sweep <= 0f32 {
+            sweep += std::f32::consts::TAU;
+        }
+
+        // segments of an angle whose sagitta is the tolerance
+        let step = 2f32 * (1f32 - (self.arc_tolerance / r).min(1f32)).acos();
+        let n = if r > 0f32 {
+            (sweep.abs() / step).ceil().max(1f32) as usize
+        } else {
+            1
+        };
+        let e = words.e.unwrap_or(self.e);
+        let (a0, e0) = (angle(a), self.e);
+        Ok((1..=n)
+            .map(|i| {
+                let t = i as f32 / n as f32;
+                let p = if i == n {
+                    b
+                } else {
+                    let a = a0 + sweep * t;
+                    center + Vector2::new(a.cos(), a.sin()) * r
+                };
+                Event::Move(Words {
+                    x: Some(p[0]),
+                    y: Some(p[1]),
+                    z: words.z.map(|_| start[2] + (end[2] - start[2]) * t),
+                    e: words.e.map(|_| e0 + (e - e0) * t as f64),
+                    f: words.f,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Event of an extended command like `EXCLUDE_OBJECT_START NAME=part`, if it has one.
+fn extended(command: &str) -> Option<Event> {
+    let mut words = command.split_whitespace();
+    let name = words.next()?.to_ascii_uppercase();
+    let mut arg = |key: &str| {
+        words.find_map(|w| {
+            let (k, v) = w.split_once('=')?;
+            k.eq_ignore_ascii_case(key)
+                .then(|| v.trim_matches('"').to_owned())
+        })
+    };
+    match name.as_str() {
+        "EXCLUDE_OBJECT_START" => Some(Event::Object(Some(arg("NAME")?))),
+        "EXCLUDE_OBJECT_END" => Some(Event::Object(None)),
+        _ => None,
+    }
+}
+
+/// Events of gcode text, with line numbers from 1. Lines are parsed as they are read, so
+/// parsing stops as soon as it is cancelled, and lines which are not events are skipped.
+pub struct GcodeStream<'a> {
+    lines: std::str::Lines<'a>,
+    parser: Parser,
+    cancel: Cancel,
+    // events of the last line not returned yet
+    pending: smallvec::IntoIter<[(usize, Event); 1]>,
+}
+
+impl<'a> GcodeStream<'a> {
+    pub fn new(gcode: &'a str) -> Self {
+        Self::resume(gcode, Parser::default())
+    }
+
+    /// Continues with gcode appended after the lines of `parser`.
+    pub fn resume(gcode: &'a str, parser: Parser) -> Self {
+        Self {
+            lines: gcode.lines(),
+            parser,
+            cancel: Cancel::default(),
+            pending: Events::new().into_iter(),
+        }
+    }
+
+    /// Ends the stream once `cancel` is set.
+    pub fn with_cancel(mut self, cancel: &Cancel) -> Self {
+        self.cancel = cancel.clone();
+        self
+    }
+
+    /// Splits arcs in segments at most `tolerance` millimeters from the arc.
+    pub fn with_arc_tolerance(mut self, tolerance: f32) -> Self {
+        self.parser.arc_tolerance = tolerance;
+        self
+    }
+
+    pub fn parser(&self) -> &Parser {
+        &self.parser
+    }
+}
+
+impl Iterator for GcodeStream<'_> {
+    type Item = Result<(usize, Event)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.cancel.is_cancelled() {
+                return None;
+            }
+            if let Some(event) = self.pending.next() {
+                return Some(Ok(event));
+            }
+            match self.parser.parse(self.lines.next()?) {
+                Ok(events) => self.pending = events.into_iter(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_gcode_stream() {
+        let gcode = "; header\n;LAYER:0\nG21\nG1 X10 Y10 Z0.2 F1200\n;TYPE:WALL-OUTER\n\
+            M104 S200\nG20\nG0 X1 E2\nG4 P500\nT1\nEXCLUDE_OBJECT_DEFINE NAME=a POLYGON=[[0,0]]\n\
+            EXCLUDE_OBJECT_START NAME=a ; part\nEXCLUDE_OBJECT_END NAME=a\nPRINT_END\n";
+        let events = GcodeStream::new(gcode).collect::<Result<Vec<_>>>().unwrap();
+        let words = Words {
+            x: Some(10.0),
+            y: Some(10.0),
+            z: Some(0.2),
+            e: None,
+            f: Some(1200.0),
+        };
+        assert_eq!(
+            events,
+            vec![
+                (1, Event::Comment(" header".to_owned())),
+                (2, Event::LayerChange(0)),
+                (4, Event::Move(words)),
+                (5, Event::Feature("WALL-OUTER".to_owned())),
+                (
+                    6,
+                    Event::Temperature {
+                        bed: false,
+                        target: 200.0,
+                        wait: false,
+                    }
+                ),
+                (
+                    8,
+                    Event::Travel(Words {
+                        x: Some(25.4),
+                        e: Some(50.8f32 as f64),
+                        ..Words::default()
+                    })
+                ),
+                (9, Event::Dwell(0.5)),
+                (10, Event::ToolChange(1)),
+                (12, Event::Object(Some("a".to_owned()))),
+                (13, Event::Object(None)),
+            ]
+        );
+        assert_eq!(words.apply(Vector3::zeros()), Vector3::new(10.0, 10.0, 0.2));
+
+        // appended lines keep inches, and count lines on
+        let mut stream = GcodeStream::new("G20\n");
+        assert!(stream.next().is_none());
+        let (line, event) = GcodeStream::resume("G1 X1\n", stream.parser().clone())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(line, 2);
+        assert_eq!(
+            event,
+            Event::Move(Words {
+                x: Some(25.4),
+                ..Words::default()
+            })
+        );
+
+        // a quarter circle around the origin counterclockwise, and back clockwise by radius
+        let arcs = GcodeStream::new("G1 X10 Y0 E1\nG3 X0 Y10 I-10 J0 E2 F600\nG2 X10 Y0 R10\n")
+            .with_arc_tolerance(0.01)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let moves = |line, mut pos: Vector3<f32>| {
+            arcs.iter()
+                .filter(|(l, _)| *l == line)
+                .map(|(_, event)| match event {
+                    Event::Move(words) => {
+                        let (from, to) = (pos, words.apply(pos));
+                        pos = to;
+                        (from, *words)
+                    }
+                    _ => panic!("{:?}", event),
+                })
+                .collect::<Vec<_>>()
+        };
+        let (ccw, cw) = (moves(2, Vector3::x() * 10.0), moves(3, Vector3::y() * 10.0));
+        assert_eq!(ccw.len(), 18);
+        assert_eq!(
+            ccw.last().unwrap().1.apply(Vector3::zeros()),
+            Vector3::new(0.0, 10.0, 0.0)
+        );
+        assert_eq!(ccw.last().unwrap().1.e, Some(2.0));
+        assert_eq!(ccw[8].1.e, Some(1.5));
+        for (from, words) in &ccw {
+            let to = words.apply(*from);
+            assert!((to.norm() - 10.0).abs() < 1e-4);
+            assert!((from + to).norm() / 2.0 > 10.0 - 0.01);
+            assert!(to[1] > from[1] && words.f == Some(600.0));
+        }
+        assert_eq!(cw.len(), 18);
+        assert!(cw
+            .iter()
+            .all(|(from, words)| words.apply(*from)[0] > from[0]));
+        assert!(cw.iter().all(|(_, words)| words.e.is_none()));
+
+        // relative moves and extrusion are absolute, until G90 and M82
+        let relative = GcodeStream::new(
+            "G1 X1 Y1 E1\nM83\nG1 X2 E0.5\nG91\nG1 X1 Z0.2 E-0.5\nG90\nG1 Y3 E1\nM82\nG1 E1\n",
+        )
+        .map(|event| match event.unwrap().1 {
+            Event::Move(words) => words,
+            event => panic!("{:?}", event),
+        })
+        .collect::<Vec<_>>();
+        let positions = relative
+            .iter()
+            .scan(Vector3::zeros(), |pos, words| {
+                *pos = words.apply(*pos);
+                Some((*pos, words.e))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            vec![
+                (Vector3::new(1.0, 1.0, 0.0), Some(1.0)),
+                (Vector3::new(2.0, 1.0, 0.0), Some(1.5)),
+                (Vector3::new(3.0, 1.0, 0.2), Some(1.0)),
+                (Vector3::new(3.0, 3.0, 0.2), Some(2.0)),
+                (Vector3::new(3.0, 3.0, 0.2), Some(1.0)),
+            ]
+        );
+
+        // relative extrusion of a long print adds up without losing small moves
+        let long = format!("M83\n{}", "G1 X1 E0.01\nG1 X0 E0.01\n".repeat(100000));
+        let e = GcodeStream::new(&long)
+            .filter_map(|event| match event.unwrap().1 {
+                Event::Move(words) => words.e,
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(e.len(), 200000);
+        assert!((e[e.len() - 1] - 2000.0).abs() < 1e-3);
+        assert!(e.windows(2).all(|w| (w[1] - w[0] - 0.01).abs() < 1e-6));
+
+        // G92 renames the position, and moves stay continuous
+        let renamed = GcodeStream::new(
+            "G1 X10 E5\nG92 E0\nG1 X20 E1\nG92 X0\nG1 X5 E2\nG92\nG1 X1 Y1 E0.5\n",
+        )
+        .filter_map(|event| match event.unwrap().1 {
+            Event::Move(words) => Some((words.x, words.y, words.e)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+        assert_eq!(
+            renamed,
+            vec![
+                (Some(10.0), None, Some(5.0)),
+                (Some(20.0), None, Some(6.0)),
+                (Some(25.0), None, Some(7.0)),
+                (Some(26.0), Some(1.0), Some(7.5)),
+            ]
+        );
+
+        let err = GcodeStream::new("G1 X1\n;LAYER:x\n")
+            .collect::<Result<Vec<_>>>()
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).starts_with("line 2: ;LAYER:x"),
+            "{:#}",
+            err
+        );
+    }
+}
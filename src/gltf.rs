@@ -0,0 +1,188 @@
+use super::{Model, VoxelIdx};
+use anyhow::Result;
+use serde_json::json;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const CHUNK_JSON: u32 = 0x4e4f534a;
+const CHUNK_BIN: u32 = 0x004e4942;
+
+// accessor component types and buffer view targets
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const UNSIGNED_BYTE: u32 = 5121;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+fn pad(buf: &mut Vec<u8>, fill: u8) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(fill);
+    }
+}
+
+/// Binary glTF of `model`, as a single mesh of indexed triangles. Vertices are scaled
+/// by `scale` and moved by `offset` like obj exports, then turned Y up as glTF expects.
+/// Vertex colors are kept, and metadata goes to `asset.extras`.
+pub fn to_glb(model: &Model, offset: [f32; 3], scale: f32) -> Vec<u8> {
+    glb_with(model, |idx| {
+        [0, 1, 2].map(|i| idx[i] as f32 * scale + offset[i])
+    })
+}
+
+/// Writes `model` as binary glTF to `w`, with vertex positions from `position`.
+pub fn write_glb<W, F>(model: &Model, mut w: W, position: F) -> Result<()>
+where
+    W: std::io::Write,
+    F: Fn(VoxelIdx) -> [f32; 3],
+{
+    let glb = glb_with(model, position);
+    anyhow::ensure!(
+        glb.len() <= u32::MAX as usize,
+        "model too large for glb: {} bytes",
+        glb.len()
+    );
+    w.write_all(&glb)?;
+    Ok(())
+}
+
+fn glb_with<F: Fn(VoxelIdx) -> [f32; 3]>(model: &Model, position: F) -> Vec<u8> {
+    let mut bin = Vec::new();
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for idx in &model.vertices {
+        let [x, y, z] = position(*idx);
+        let p = [x, z, -y];
+        for i in 0..3 {
+            min[i] = min[i].min(p[i]);
+            max[i] = max[i].max(p[i]);
+            bin.extend_from_slice(&p[i].to_le_bytes());
+        }
+    }
+    let positions = bin.len();
+
+    for [i0, i1, i2, i3] in &model.faces {
+        for i in [i0, i1, i2, i0, i2, i3] {
+            bin.extend_from_slice(&(*i as u32).to_le_bytes());
+        }
+    }
+    let indices = bin.len() - positions;
+
+    let colored = !model.colors.is_empty();
+    for [r, g, b] in &model.colors {
+        bin.extend_from_slice(&[*r, *g, *b, 255]);
+    }
+
+    let meta = model.metadata.to_json();
+    let mut gltf = json!({
+        "asset": { "version": "2.0", "generator": env!("CARGO_PKG_NAME"), "extras": meta },
+        "scene": 0,
+        "scenes": [{ "nodes": [] }],
+    });
+    if !model.faces.is_empty() {
+        let mut attributes = json!({ "POSITION": 0 });
+        let mut views = vec![
+            json!({ "buffer": 0, "byteLength": positions, "target": ARRAY_BUFFER }),
+            json!({
+                "buffer": 0,
+                "byteOffset": positions,
+                "byteLength": indices,
+                "target": ELEMENT_ARRAY_BUFFER,
+            }),
+        ];
+        let mut accessors = vec![
+            json!({
+                "bufferView": 0,
+                "componentType": FLOAT,
+                "count": model.vertices.len(),
+                "type": "VEC3",
+                "min": min,
+                "max": max,
+            }),
+            json!({
+                "bufferView": 1,
+                "componentType": UNSIGNED_INT,
+                "count": model.faces.len() * 6,
+                "type": "SCALAR",
+            }),
+        ];
+        if colored {
+            attributes["COLOR_0"] = json!(2);
+            views.push(json!({
+                "buffer": 0,
+                "byteOffset": positions + indices,
+                "byteLength": model.colors.len() * 4,
+                "target": ARRAY_BUFFER,
+            }));
+            accessors.push(json!({
+                "bufferView": 2,
+                "componentType": UNSIGNED_BYTE,
+                "normalized": true,
+                "count": model.colors.len(),
+                "type": "VEC4",
+            }));
+        }
+
+        gltf["scenes"][0]["nodes"] = json!([0]);
+        gltf["nodes"] = json!([{ "mesh": 0 }]);
+        gltf["meshes"] = json!([{
+            "primitives": [{ "attributes": attributes, "indices": 1, "mode": 4 }],
+        }]);
+        gltf["buffers"] = json!([{ "byteLength": bin.len() }]);
+        gltf["bufferViews"] = json!(views);
+        gltf["accessors"] = json!(accessors);
+    }
+
+    let mut json = gltf.to_string().into_bytes();
+    pad(&mut json, b' ');
+    pad(&mut bin, 0);
+
+    let mut chunks = vec![(CHUNK_JSON, json)];
+    if !model.faces.is_empty() {
+        chunks.push((CHUNK_BIN, bin));
+    }
+    let len = 12 + chunks.iter().map(|(_, c)| 8 + c.len()).sum::<usize>();
+
+    let mut glb = Vec::with_capacity(len);
+    glb.extend_from_slice(GLB_MAGIC);
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&(len as u32).to_le_bytes());
+    for (ty, data) in chunks {
+        glb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&ty.to_le_bytes());
+        glb.extend_from_slice(&data);
+    }
+    glb
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_glb() {
+        let mut model = Model::default();
+        model.add_cube([0, 0, 0].into());
+        let glb = to_glb(&model, [0f32; 3], 2f32);
+        let mut written = Vec::new();
+        write_glb(&model, &mut written, |idx| {
+            [0, 1, 2].map(|i| idx[i] as f32 * 2f32)
+        })
+        .unwrap();
+        assert_eq!(written, glb);
+
+        assert_eq!(&glb[..4], GLB_MAGIC);
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+        let json_len = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        let gltf: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+        assert_eq!(gltf["accessors"][0]["count"], 8);
+        assert_eq!(gltf["accessors"][1]["count"], 36);
+        // Z up becomes Y up
+        assert_eq!(gltf["accessors"][0]["max"], json!([2.0, 2.0, 0.0]));
+
+        let bin = &glb[20 + json_len..];
+        assert_eq!(u32::from_le_bytes(bin[4..8].try_into().unwrap()), CHUNK_BIN);
+        assert_eq!(bin.len() - 8, 8 * 12 + 36 * 4);
+    }
+}
@@ -0,0 +1,49 @@
+use nalgebra::Vector3;
+
+/// Millimeters per inch, for gcode in inches after G20.
+pub const MM_PER_INCH: f32 = 25.4;
+
+/// Drops moves to absurd coordinates, e.g. parser glitches or inch files read as
+/// millimeters, before they blow up the bounding box and memory. The limit applies to
+/// both signs, so delta and SCARA printers with the origin at the bed center pass.
+#[derive(Clone, Debug)]
+pub struct Guard {
+    /// in millimeters, on each axis
+    limit: f32,
+    dropped: usize,
+}
+
+impl Guard {
+    pub fn new(limit: f32) -> Self {
+        Self { limit, dropped: 0 }
+    }
+
+    /// Returns false, and counts the move as dropped, if `dst` is not finite or beyond
+    /// the limit on any axis.
+    pub fn check(&mut self, dst: Vector3<f32>) -> bool {
+        let ok = dst.iter().all(|v| v.is_finite() && v.abs() <= self.limit);
+        if !ok {
+            self.dropped += 1;
+        }
+        ok
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_guard() {
+        let mut guard = Guard::new(1000f32);
+        assert!(guard.check(Vector3::new(100.0, 100.0, 0.2)));
+        assert!(guard.check(Vector3::new(-150.0, -150.0, 0.2)));
+        assert!(!guard.check(Vector3::new(100.0, 1e6, 0.2)));
+        assert!(!guard.check(Vector3::new(f32::NAN, 100.0, 0.2)));
+        assert_eq!(guard.dropped(), 2);
+    }
+}
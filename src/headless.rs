@@ -0,0 +1,121 @@
+use super::overlay::Stats;
+use super::render::{self, Still};
+use super::{project::read_gcode, simulate_gcode, Estimate, MonotonicVoxel, Params};
+use super::{storage, Simulation, Voxel};
+use anyhow::Result;
+use log::*;
+
+/// Renders a frame of the simulation with `Stats` of its overlay into a png file, on a
+/// GPU without a window, like `view::headless` does.
+pub type Gpu<'a> = Box<dyn FnMut(&mut Simulation<MonotonicVoxel>, &Stats, &str) -> Result<()> + 'a>;
+
+/// Renders the model after each layer into `{outdir}/view_{layer}.png`, without a
+/// window, with the CPU ray tracer, so servers render frames without a GPU, a display or
+/// the `view` feature. The overlay of `still` counts layers and progress by extruded
+/// volume from a pre-scan of the gcode.
+pub fn headless(
+    gcode: &str,
+    layer: usize,
+    params: &Params,
+    outdir: &str,
+    still: &Still,
+) -> Result<()> {
+    let estimate = Estimate::scan(&read_gcode(gcode)?, layer);
+    frames(gcode, layer, params, outdir, still, &estimate, None)
+}
+
+/// Renders frames like `headless`, with `gpu` if any, from the pre-scan `estimate`.
+pub fn frames(
+    gcode: &str,
+    layer: usize,
+    params: &Params,
+    outdir: &str,
+    still: &Still,
+    estimate: &Estimate,
+    mut gpu: Option<Gpu>,
+) -> Result<()> {
+    storage::create_dir_all(outdir)?;
+    let total = estimate.volumes.iter().sum::<f32>();
+
+    let mut render = |sim: &mut Simulation<MonotonicVoxel>, layer_idx: usize| -> Result<()> {
+        if sim.voxel.bounding_box().count == 0 {
+            return Ok(());
+        }
+        let out = format!("{}/view_{:03}.png", outdir, layer_idx);
+        let done = estimate.volumes.iter().take(layer_idx).sum::<f32>();
+        let stats = Stats {
+            layer: layer_idx,
+            layers: Some(estimate.volumes.len()),
+            z: sim.nozzle[2],
+            time: sim.time,
+            progress: (total > 0f32).then(|| done / total),
+        };
+        match &mut gpu {
+            Some(gpu) => gpu(sim, &stats, &out)?,
+            None => {
+                let frame = sim.voxel.bounding_box();
+                let colors = sim.colors.as_ref();
+                render::render_frame(&sim.voxel, colors, still, frame, Some(&stats), &out)?
+            }
+        }
+        info!("headless: layer={}, out={}", layer_idx, out);
+        Ok(())
+    };
+
+    let mut last = 0;
+    let mut sim = simulate_gcode::<MonotonicVoxel, _>(gcode, layer, params, |sim, layer_idx| {
+        last = layer_idx;
+        render(sim, layer_idx)
+    })?;
+    render(&mut sim, last + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_headless() {
+        let dir = std::env::temp_dir().join(format!("tdp-tl-headless-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let gcode = dir.join("a.gcode");
+        std::fs::write(
+            &gcode,
+            ";LAYER:0\nG1 X10 Y10 Z0.2 F600\nG1 X12 Y10 E0.1\n;LAYER:1\nG1 Z0.4\nG1 X10 Y10 E0.2\n",
+        )
+        .unwrap();
+        let still = Still {
+            width: 16,
+            height: 12,
+            azimuth: -60.0,
+            elevation: 30.0,
+            ao_samples: 0,
+            camera: None,
+            overlay: None,
+            transparent: false,
+            stereo: None,
+            aovs: false,
+            ortho: None,
+        };
+        let outdir = dir.join("view");
+        let outdir = outdir.to_str().unwrap();
+        headless(
+            gcode.to_str().unwrap(),
+            usize::MAX,
+            &Params::default(),
+            outdir,
+            &still,
+        )
+        .unwrap();
+        let mut files = std::fs::read_dir(outdir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, ["view_001.png", "view_002.png"]);
+        let png = std::fs::read(format!("{}/view_001.png", outdir)).unwrap();
+        let reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (16, 12));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
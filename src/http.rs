@@ -0,0 +1,172 @@
+use anyhow::Result;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Connecting, sending and waiting for the response each time out after this.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Plain `http://` url, without a TLS client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Url {
+    /// `host:port`, to connect to
+    pub addr: String,
+    /// Host header, with the port if given
+    pub host: String,
+    /// from the first slash after the host
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            anyhow::bail!("{}: only http:// urls are supported", url);
+        };
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        anyhow::ensure!(!host.is_empty(), "{}: no host", url);
+        let addr = if host
+            .rsplit_once(':')
+            .is_some_and(|(_, p)| p.parse::<u16>().is_ok())
+        {
+            host.to_owned()
+        } else {
+            format!("{}:80", host)
+        };
+        Ok(Self {
+            addr,
+            host: host.to_owned(),
+            path: path.to_owned(),
+        })
+    }
+}
+
+/// Response of a request, after a 2xx status.
+#[derive(Debug, Default)]
+pub struct Response {
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Value of the first header `name`, case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Parses a response. Fails unless the status is 2xx, with the status line.
+    fn parse(data: &[u8]) -> Result<Self> {
+        let end = data
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .unwrap_or(data.len());
+        let head = String::from_utf8_lossy(&data[..end]);
+        let mut lines = head.lines();
+        let status = lines.next().unwrap_or("no response");
+        let code = status.split_whitespace().nth(1).unwrap_or_default();
+        anyhow::ensure!(code.starts_with('2'), "{}", status);
+
+        let mut response = Self {
+            headers: lines
+                .filter_map(|l| l.split_once(':'))
+                .map(|(n, v)| (n.trim().to_owned(), v.trim().to_owned()))
+                .collect(),
+            body: data.get(end + 4..).unwrap_or_default().to_vec(),
+        };
+        if response
+            .header("transfer-encoding")
+            .is_some_and(|e| e.eq_ignore_ascii_case("chunked"))
+        {
+            response.body = dechunk(&response.body)?;
+        }
+        Ok(response)
+    }
+}
+
+/// Body of a chunked transfer encoding.
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow::anyhow!("truncated chunk"))?;
+        let size = String::from_utf8_lossy(&data[..line]);
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)?;
+        if size == 0 {
+            return Ok(body);
+        }
+        let chunk = data
+            .get(line + 2..line + 2 + size)
+            .ok_or_else(|| anyhow::anyhow!("truncated chunk"))?;
+        body.extend_from_slice(chunk);
+        data = data.get(line + 4 + size..).unwrap_or_default();
+    }
+}
+
+/// Sends a request to `path` of `url`, with `headers` besides Host, Content-Length and
+/// Connection, and reads the whole response. Fails unless the status is 2xx.
+pub fn request(
+    url: &Url,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response> {
+    let addr = url
+        .addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no address of {}", url.addr))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, url.host);
+    for (name, value) in headers {
+        head += &format!("{}: {}\r\n", name, value);
+    }
+    head += &format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    // the server closes the connection after the response
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    Response::parse(&response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_url() {
+        let url = Url::parse("http://minio:9000/bucket/a b").unwrap();
+        assert_eq!(url.addr, "minio:9000");
+        assert_eq!(url.host, "minio:9000");
+        assert_eq!(url.path, "/bucket/a b");
+        assert_eq!(Url::parse("http://a").unwrap().addr, "a:80");
+        assert!(Url::parse("https://a/b").is_err());
+        assert!(Url::parse("http:///b").is_err());
+
+        let response = Response::parse(
+            b"HTTP/1.1 200 OK\r\nETag: \"abc\"\r\nTransfer-Encoding: chunked\r\n\r\n\
+            3\r\n<a>\r\n4;x=y\r\n</a>\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.header("etag"), Some("\"abc\""));
+        assert_eq!(response.body, b"<a></a>");
+        assert!(Response::parse(b"HTTP/1.1 403 Forbidden\r\n\r\n").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use super::{deterministic, Filament, Voxel, UNIT};
+use nalgebra::Matrix3;
+use rayon::prelude::*;
+use std::ops::Range;
+
+/// Sums over centers of voxels, in voxels.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Moments {
+    pub count: f64,
+    /// sum of p
+    pub first: [f64; 3],
+    /// sum of p p^T
+    pub second: [[f64; 3]; 3],
+}
+
+/// Sum of k and of k^2 for k in `r`.
+fn sums(r: &Range<i32>) -> (f64, f64) {
+    let s = |m: f64| m * (m + 1f64) * (2f64 * m + 1f64) / 6f64;
+    let (a, b) = (r.start as f64, r.end as f64 - 1f64);
+    let n = b - a + 1f64;
+    ((a + b) * n / 2f64, s(b) - s(a - 1f64))
+}
+
+impl Moments {
+    pub fn of<V: Voxel + Sync>(v: &V) -> Self {
+        let bb = v.bounding_box();
+        if bb.count == 0 {
+            return Self::default();
+        }
+        let (min, max) = (bb.bound_min, bb.bound_max);
+        let rows = (min[1]..=max[1]).into_par_iter().map(|y| {
+            let mut m = Self::default();
+            for x in min[0]..=max[0] {
+                for r in v.column(x, y) {
+                    m.add_range(x, y, &r);
+                }
+            }
+            m
+        });
+        deterministic::reduce(rows, Self::default, Self::merge)
+    }
+
+    /// Adds voxels of `r` in the column at (x, y).
+    fn add_range(&mut self, x: i32, y: i32, r: &Range<i32>) {
+        let n = (r.end - r.start) as f64;
+        let (x, y) = (x as f64 + 0.5, y as f64 + 0.5);
+        // centers are k + 0.5
+        let (sk, skk) = sums(r);
+        let (sz, szz) = (sk + n / 2f64, skk + sk + n / 4f64);
+
+        self.count += n;
+        for (sum, p) in self.first.iter_mut().zip([x * n, y * n, sz]) {
+            *sum += p;
+        }
+        let s = &mut self.second;
+        s[0][0] += x * x * n;
+        s[1][1] += y * y * n;
+        s[2][2] += szz;
+        s[0][1] += x * y * n;
+        s[0][2] += x * sz;
+        s[1][2] += y * sz;
+        s[1][0] = s[0][1];
+        s[2][0] = s[0][2];
+        s[2][1] = s[1][2];
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.count += other.count;
+        for i in 0..3 {
+            self.first[i] += other.first[i];
+            for j in 0..3 {
+                self.second[i][j] += other.second[i][j];
+            }
+        }
+        self
+    }
+
+    /// Center of mass in voxels.
+    pub fn center(&self) -> [f64; 3] {
+        self.first.map(|s| s / self.count.max(1f64))
+    }
+}
+
+/// Mass, center of mass and inertia of a part of uniform density.
+#[derive(Clone, Debug)]
+pub struct MassProperties {
+    /// in grams
+    pub mass: f32,
+    /// in millimeters, in bed coordinates
+    pub com: [f32; 3],
+    /// inertia tensor about the center of mass along bed axes, in g*mm^2
+    pub inertia: [[f32; 3]; 3],
+    /// principal moments in g*mm^2, ascending
+    pub principal: [f32; 3],
+    /// principal axes, as unit vectors in the order of `principal`
+    pub axes: [[f32; 3]; 3],
+}
+
+impl MassProperties {
+    pub fn of<V: Voxel + Sync>(v: &V, filament: &Filament) -> Self {
+        let m = Moments::of(v);
+        let c = m.center();
+        let n = m.count;
+
+        // second moments about the center, with each voxel as a unit cube
+        let central = Matrix3::from_fn(|i, j| {
+            let cube = if i == j { n / 12f64 } else { 0f64 };
+            m.second[i][j] - n * c[i] * c[j] + cube
+        });
+        // voxels to grams and millimeters^2
+        let voxel_mass = (UNIT as f64).powi(3) / 1000f64 * filament.density as f64;
+        let k = voxel_mass * (UNIT as f64).powi(2);
+        let inertia = (Matrix3::identity() * central.trace() - central) * k;
+
+        let eigen = inertia.symmetric_eigen();
+        let mut order = [0, 1, 2];
+        order.sort_by(|a, b| eigen.eigenvalues[*a].total_cmp(&eigen.eigenvalues[*b]));
+
+        Self {
+            mass: (n * voxel_mass) as f32,
+            com: c.map(|c| (c * UNIT as f64) as f32),
+            inertia: [0, 1, 2].map(|i| [0, 1, 2].map(|j| inertia[(i, j)] as f32)),
+            principal: order.map(|i| eigen.eigenvalues[i] as f32),
+            axes: order.map(|i| [0, 1, 2].map(|j| eigen.eigenvectors[(j, i)] as f32)),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "mass_g": self.mass,
+            "com_mm": self.com,
+            "inertia_g_mm2": self.inertia,
+            "principal_moments_g_mm2": self.principal,
+            "principal_axes": self.axes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_mass_properties() {
+        // 4x2x1mm box
+        let mut v = MonotonicVoxel::default();
+        for x in 0..100 {
+            for y in 0..50 {
+                for z in 0..25 {
+                    v.add([x, y, z].into());
+                }
+            }
+        }
+        let filament = Filament {
+            density: 1f32,
+            price: 0f32,
+        };
+        let p = MassProperties::of(&v, &filament);
+        assert!((p.mass - 0.008).abs() < 1e-6, "{}", p.mass);
+        for (com, expected) in p.com.iter().zip([2f32, 1f32, 0.5f32]) {
+            assert!((com - expected).abs() < 1e-4, "{:?}", p.com);
+        }
+
+        // box inertia: m (b^2 + c^2) / 12
+        let expected = [4f32 + 1f32, 16f32 + 1f32, 16f32 + 4f32].map(|s| p.mass * s / 12f32);
+        let diagonal = [0, 1, 2].map(|i| p.inertia[i][i]);
+        for (inertia, expected) in diagonal.iter().zip(expected) {
+            assert!((inertia - expected).abs() < 1e-6, "{:?}", p.inertia);
+        }
+        assert!(p.inertia[0][1].abs() < 1e-7);
+        assert!((p.principal[0] - expected[0]).abs() < 1e-6);
+        assert!((p.axes[0][0].abs() - 1f32).abs() < 1e-4, "{:?}", p.axes);
+    }
+}
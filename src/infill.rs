@@ -0,0 +1,259 @@
+use super::{backend, flood, storage, Tags, Voxel, UNIT, Z_OFFSET};
+use anyhow::Result;
+use rayon::prelude::*;
+use std::io::Write;
+use std::ops::Range;
+
+/// Interior regions smaller than this in square millimeters, like gaps between walls of
+/// embossed text, are left out.
+const MIN_AREA: f32 = 4f32;
+
+/// Sparse infill density from slicer settings comments anywhere in `gcode`, 0 to 1.
+pub fn declared(gcode: &str) -> Option<f32> {
+    let comments = gcode.lines().filter_map(|l| l.trim().strip_prefix(';'));
+    comments.rev().find_map(backend::infill_density)
+}
+
+/// Connected interior of the part within a band of layers: cells enclosed by walls which
+/// are neither walls nor skin, so sparse infill and the air between it.
+#[derive(Clone, Debug)]
+pub struct Interior {
+    pub layers: Range<usize>,
+    /// center of the region, in millimeters
+    pub center: [f32; 2],
+    /// in square millimeters
+    pub area: f32,
+    /// occupied fraction of the region over the band, 0 to 1
+    pub density: f32,
+}
+
+impl Interior {
+    /// Density off the declared one by more than `tolerance`, e.g. thin features where
+    /// infill collapses into gap fill.
+    pub fn deviates(&self, declared: f32, tolerance: f32) -> bool {
+        (self.density - declared).abs() > tolerance
+    }
+}
+
+/// Cell of a layer slice.
+#[derive(Clone, Copy, PartialEq)]
+enum Cell {
+    Empty,
+    Fill,
+    Wall,
+    /// skin, support, skirts and the like, excluded from the interior
+    Solid,
+}
+
+/// Interior regions of each band of `band` layers, with the density of voxels within.
+/// Voxels are classed by `features` tags, so they need `Params::features`; untagged
+/// voxels count as infill.
+pub fn interiors<V: Voxel + Sync>(v: &V, features: &Tags, band: usize) -> Vec<Interior> {
+    let bb = v.bounding_box();
+    if bb.count == 0 {
+        return Vec::new();
+    }
+    let (min, max) = (bb.bound_min, bb.bound_max);
+    let (width, height) = (
+        (max[0] - min[0] + 1) as usize,
+        (max[1] - min[1] + 1) as usize,
+    );
+    let columns = (min[1]..=max[1])
+        .into_par_iter()
+        .flat_map_iter(|y| (min[0]..=max[0]).map(move |x| v.column(x, y)))
+        .collect::<Vec<_>>();
+    let cell = |i: usize, z: i32| {
+        if !columns[i].iter().any(|r| r.contains(&z)) {
+            return Cell::Empty;
+        }
+        let coord = [min[0] + (i % width) as i32, min[1] + (i / width) as i32, z];
+        match features.tag_of(coord.into()) {
+            Some("infill") | None => Cell::Fill,
+            Some("wall-outer") | Some("wall-inner") => Cell::Wall,
+            _ => Cell::Solid,
+        }
+    };
+
+    let layers = (max[2] / Z_OFFSET + 1).max(0) as usize;
+    let mut interiors = Vec::new();
+    for start in (0..layers).step_by(band.max(1)) {
+        let end = (start + band.max(1)).min(layers);
+        // interior and occupied layers of each cell over the band
+        let mut inside = vec![0u32; columns.len()];
+        let mut filled = vec![0u32; columns.len()];
+        for layer in start..end {
+            let z = layer as i32 * Z_OFFSET + Z_OFFSET / 2;
+            let cells = (0..columns.len())
+                .into_par_iter()
+                .map(|i| cell(i, z))
+                .collect::<Vec<_>>();
+            // skirts around the part do not enclose it, only walls do
+            let mut outside = vec![false; cells.len()];
+            flood(
+                width,
+                height,
+                &mut outside,
+                |i| cells[i] != Cell::Wall,
+                border(width, height),
+            );
+            for (i, c) in cells.iter().enumerate() {
+                if !outside[i] && matches!(c, Cell::Empty | Cell::Fill) {
+                    inside[i] += 1;
+                    filled[i] += (*c == Cell::Fill) as u32;
+                }
+            }
+        }
+
+        let mut visited = vec![false; columns.len()];
+        for seed in 0..columns.len() {
+            if inside[seed] == 0 || visited[seed] {
+                continue;
+            }
+            // regions are disjoint, so they share the buffer of visited cells
+            let region = flood(width, height, &mut visited, |i| inside[i] > 0, [seed]);
+            let (cells, mut sum, mut total, mut occupied) = (region.len(), [0f64; 2], 0, 0);
+            for i in region {
+                sum[0] += (i % width) as f64;
+                sum[1] += (i / width) as f64;
+                total += inside[i];
+                occupied += filled[i];
+            }
+            let area = cells as f32 * UNIT * UNIT;
+            if area < MIN_AREA {
+                continue;
+            }
+            let center = [0, 1].map(|a| (sum[a] / cells as f64) as f32 + (min[a] as f32 + 0.5));
+            interiors.push(Interior {
+                layers: start..end,
+                center: center.map(|c| c * UNIT),
+                area,
+                density: occupied as f32 / total as f32,
+            });
+        }
+    }
+    interiors
+}
+
+/// Cells on the border of a `width` by `height` grid.
+fn border(width: usize, height: usize) -> impl Iterator<Item = usize> {
+    let rows = (0..width).flat_map(move |x| [x, (height - 1) * width + x]);
+    rows.chain((0..height).flat_map(move |y| [y * width, y * width + width - 1]))
+}
+
+/// Cells 4-connected to `seeds` through cells where `pass` holds and not `reached` yet,
+/// seeds included if they pass. Marks them in `reached`, and returns them, so floods of
+/// small regions do not scan the whole grid.
+fn flood(
+    width: usize,
+    height: usize,
+    reached: &mut [bool],
+    pass: impl Fn(usize) -> bool,
+    seeds: impl IntoIterator<Item = usize>,
+) -> Vec<usize> {
+    let mut cells = Vec::new();
+    flood::fill(
+        seeds,
+        |&i| {
+            if reached[i] || !pass(i) {
+                return false;
+            }
+            reached[i] = true;
+            cells.push(i);
+            true
+        },
+        |&i| {
+            let (x, y) = (i % width, i / width);
+            [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ]
+            .into_iter()
+            .flatten()
+        },
+    );
+    cells
+}
+
+pub fn write_csv(
+    interiors: &[Interior],
+    declared: Option<f32>,
+    tolerance: f32,
+    path: &str,
+) -> Result<()> {
+    let mut w = storage::create(path)?;
+    writeln!(
+        w,
+        "layer_start,layer_end,center_x,center_y,area,density,deviates"
+    )?;
+    for i in interiors {
+        writeln!(
+            w,
+            "{},{},{:.3},{:.3},{:.3},{:.3},{}",
+            i.layers.start,
+            i.layers.end,
+            i.center[0],
+            i.center[1],
+            i.area,
+            i.density,
+            declared.is_some_and(|d| i.deviates(d, tolerance))
+        )?;
+    }
+    w.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_interiors() {
+        let mut v = MonotonicVoxel::default();
+        let mut features = Tags::default();
+        let mut add = |x: i32, y: i32, z: i32, tag: &'static str| {
+            v.add([x, y, z].into());
+            features.add(tag, [x, y, z].into());
+        };
+        // two layers of a 120x60 box with 2 voxel walls, infill lines every 5 voxels
+        // along x in the left half, and solid gap fill in the right half
+        for z in 0..2 * Z_OFFSET {
+            for x in 0..120 {
+                for y in 0..60 {
+                    let wall = x < 2 || y < 2 || x >= 118 || y >= 58 || x == 60;
+                    if wall {
+                        add(x, y, z, "wall-inner");
+                    } else if x > 60 || y % 5 == 0 {
+                        add(x, y, z, "infill");
+                    }
+                }
+            }
+        }
+        // skirt around it
+        for x in -10..130 {
+            for y in [-10, 70] {
+                (0..Z_OFFSET).for_each(|z| add(x, y, z, "other"));
+            }
+        }
+        for y in -10..=70 {
+            for x in [-10, 130] {
+                (0..Z_OFFSET).for_each(|z| add(x, y, z, "other"));
+            }
+        }
+
+        let mut interiors = interiors(&v, &features, 10);
+        assert_eq!(interiors.len(), 2);
+        interiors.sort_by(|a, b| a.center[0].total_cmp(&b.center[0]));
+        let (sparse, solid) = (&interiors[0], &interiors[1]);
+        assert_eq!(sparse.layers, 0..2);
+        assert_eq!(sparse.area, (58 * 56) as f32 * UNIT * UNIT);
+        assert!((sparse.density - 11f32 / 56f32).abs() < 1e-4);
+        assert_eq!(solid.density, 1f32);
+        assert!(!sparse.deviates(0.2, 0.1) && solid.deviates(0.2, 0.1));
+
+        let gcode = ";LAYER:0\nG1 X1 E1\n;SETTING_3 {\\\\n\\\\ninfill_sparse_density = 20\\\\n";
+        assert_eq!(declared(gcode), Some(0.2));
+        assert_eq!(declared("; sparse_infill_density = 15%"), Some(0.15));
+    }
+}
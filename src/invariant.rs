@@ -0,0 +1,133 @@
+use super::Voxel;
+use anyhow::Result;
+
+/// Checks data structure invariants of `voxel`, for `Params::paranoid`: columns hold
+/// sorted, non-empty, non-overlapping ranges within the bounding box, the bounding box
+/// is tight, and blocks counted by columns match `blocks()` and the bounding box count.
+/// Visits every column of the bounding box, so it is slow.
+pub fn check<V: Voxel>(voxel: &V) -> Result<()> {
+    let bb = voxel.bounding_box();
+    let blocks = voxel.blocks();
+    if bb.count == 0 {
+        anyhow::ensure!(blocks == 0, "{} blocks without bounding box", blocks);
+        return Ok(());
+    }
+
+    let (lo, hi) = (bb.bound_min, bb.bound_max);
+    let mut min = [i32::MAX; 3];
+    let mut max = [i32::MIN; 3];
+    let mut counted = 0usize;
+    for x in lo[0]..=hi[0] {
+        for y in lo[1]..=hi[1] {
+            let column = voxel.column(x, y);
+            for (i, r) in column.iter().enumerate() {
+                anyhow::ensure!(r.start < r.end, "empty range {:?} at {}, {}", r, x, y);
+                if let Some(prev) = i.checked_sub(1).map(|i| &column[i]) {
+                    anyhow::ensure!(
+                        prev.end <= r.start,
+                        "unsorted or overlapping ranges {:?}, {:?} at {}, {}",
+                        prev,
+                        r,
+                        x,
+                        y
+                    );
+                }
+                anyhow::ensure!(
+                    lo[2] <= r.start && r.end - 1 <= hi[2],
+                    "range {:?} at {}, {} outside bounding box z {}..={}",
+                    r,
+                    x,
+                    y,
+                    lo[2],
+                    hi[2]
+                );
+                counted += (r.end - r.start) as usize;
+            }
+            if let (Some(first), Some(last)) = (column.first(), column.last()) {
+                for (i, v) in [x, y, first.start].into_iter().enumerate() {
+                    min[i] = min[i].min(v);
+                }
+                for (i, v) in [x, y, last.end - 1].into_iter().enumerate() {
+                    max[i] = max[i].max(v);
+                }
+            }
+        }
+    }
+
+    anyhow::ensure!(
+        counted == blocks,
+        "columns hold {} blocks, blocks() is {}, or blocks are outside the bounding box",
+        counted,
+        blocks
+    );
+    anyhow::ensure!(
+        counted == bb.count,
+        "columns hold {} blocks, bounding box counted {}",
+        counted,
+        bb.count
+    );
+    anyhow::ensure!(
+        min == lo.idx && max == hi.idx,
+        "bounding box {:?}..={:?} is not tight, voxels span {:?}..={:?}",
+        lo.idx,
+        hi.idx,
+        min,
+        max
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BoundingBox, ChunkId, Model, MonotonicVoxel, RangeSetVoxel, VoxelIdx};
+
+    /// Backend miscounting its blocks.
+    struct Miscounting(MonotonicVoxel);
+
+    impl Voxel for Miscounting {
+        fn blocks(&self) -> usize {
+            self.0.blocks() + 1
+        }
+        fn ranges(&self) -> usize {
+            self.0.ranges()
+        }
+        fn bounding_box(&self) -> &BoundingBox {
+            self.0.bounding_box()
+        }
+        fn occupied(&self, coord: VoxelIdx) -> bool {
+            self.0.occupied(coord)
+        }
+        fn add(&mut self, coord: VoxelIdx) -> bool {
+            self.0.add(coord)
+        }
+        fn to_model(&self) -> Model {
+            self.0.to_model()
+        }
+        fn take_dirty(&mut self) -> Vec<ChunkId> {
+            self.0.take_dirty()
+        }
+        fn column(&self, x: i32, y: i32) -> Vec<std::ops::Range<i32>> {
+            self.0.column(x, y)
+        }
+    }
+
+    #[test]
+    pub fn test_check() {
+        let mut v = MonotonicVoxel::default();
+        let mut r = RangeSetVoxel::default();
+        assert!(check(&v).is_ok());
+        // gaps filled from both sides, then in between
+        for z in [0, 2, 4, 1, 3, 3, 10, 8, 9] {
+            assert_eq!(v.add([1, 2, z].into()), r.add([1, 2, z].into()));
+        }
+        v.add([-3, 5, 7].into());
+        r.add([-3, 5, 7].into());
+        assert_eq!(v.column(1, 2), [0..5, 8..11]);
+        assert_eq!(v.column(1, 2), r.column(1, 2));
+        check(&v).unwrap();
+        check(&r).unwrap();
+
+        assert!(check(&Miscounting(v)).is_err());
+    }
+}
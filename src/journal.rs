@@ -0,0 +1,271 @@
+use super::{storage, surface, BoundingBox, ChunkId, Model, Segment, Voxel, VoxelIdx, UNIT};
+use anyhow::Result;
+use nalgebra::Vector3;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+
+/// Voxel added by a deposit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entry {
+    pub voxel: VoxelIdx,
+    /// index of the extrusion move in `Simulation::segments`
+    pub segment: usize,
+    /// nozzle of the deposition step, in millimeters
+    pub nozzle: Vector3<f32>,
+}
+
+impl Entry {
+    /// Distance from the nozzle to the voxel, in millimeters. Voxels far from the
+    /// nozzle are suspect, unless they sag on bridges or fall as spaghetti.
+    pub fn distance(&self) -> f32 {
+        let v = Vector3::new(
+            self.voxel[0] as f32,
+            self.voxel[1] as f32,
+            self.voxel[2] as f32,
+        ) * UNIT;
+        (v - self.nozzle).magnitude()
+    }
+}
+
+/// Bounded log of the last voxels added by deposits, for debugging voxels where no
+/// material should be, like floating blobs. Dumped to stderr if a panic unwinds the
+/// simulation which owns it.
+#[derive(Debug)]
+pub struct DepositLog {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl DepositLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Records an added voxel, forgetting the oldest if full.
+    pub fn push(&mut self, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Entries, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    fn write<W: std::io::Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(w, "x,y,z,segment,nozzle_x,nozzle_y,nozzle_z,distance")?;
+        for e in &self.entries {
+            writeln!(
+                w,
+                "{},{},{},{},{:.3},{:.3},{:.3},{:.3}",
+                e.voxel[0],
+                e.voxel[1],
+                e.voxel[2],
+                e.segment,
+                e.nozzle[0],
+                e.nozzle[1],
+                e.nozzle[2],
+                e.distance()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes entries as csv, oldest first, with voxel coordinates in voxels.
+    pub fn write_csv(&self, path: &str) -> Result<()> {
+        let mut w = storage::create(path)?;
+        self.write(&mut w)?;
+        w.finish()
+    }
+
+    /// Wraps `v`, so voxels added through the wrapper are logged if `log` is some, and
+    /// recorded in `order` if it is some, as deposited by `segment` with the nozzle at
+    /// `nozzle`.
+    pub fn logging<'a, V: Voxel>(
+        log: Option<&'a mut DepositLog>,
+        order: Option<&'a mut Order>,
+        v: &'a mut V,
+        segment: usize,
+        nozzle: Vector3<f32>,
+    ) -> Logging<'a, V> {
+        Logging {
+            voxel: v,
+            log,
+            order,
+            segment,
+            nozzle,
+        }
+    }
+}
+
+/// Runs of a column along Z, with the segment which deposited each.
+type Runs = Vec<(Range<i32>, u32)>;
+
+/// Segment which deposited each voxel, unlike `DepositLog` of all voxels, for exports
+/// colored by build order. Runs of voxels along Z deposited by a segment are kept
+/// together, so a column holds about a run per bead.
+#[derive(Clone, Debug, Default)]
+pub struct Order {
+    columns: HashMap<(i32, i32), Runs>,
+}
+
+impl Order {
+    /// Records a voxel added by `segment`, an index of `Simulation::segments`.
+    pub fn push(&mut self, coord: VoxelIdx, segment: usize) {
+        let column = self.columns.entry((coord[0], coord[1])).or_default();
+        let (z, segment) = (coord[2], segment as u32);
+        match column.last_mut() {
+            Some((run, s)) if *s == segment && run.end == z => run.end += 1,
+            Some((run, s)) if *s == segment && run.start == z + 1 => run.start -= 1,
+            _ => column.push((z..z + 1, segment)),
+        }
+    }
+
+    /// Segment which deposited `coord`, none if no segment did.
+    pub fn segment_of(&self, coord: VoxelIdx) -> Option<usize> {
+        let column = self.columns.get(&(coord[0], coord[1]))?;
+        let (_, segment) = column
+            .iter()
+            .rev()
+            .find(|(run, _)| run.contains(&coord[2]))?;
+        Some(*segment as usize)
+    }
+
+    /// Sets the `order` and `layer` attributes of vertices of `model`, of the earliest
+    /// segment which deposited voxels of faces around each vertex, 0 without any.
+    pub fn paint<V: Voxel>(&self, model: &mut Model, v: &V, segments: &[Segment]) {
+        let mut first = vec![u32::MAX; model.vertices.len()];
+        for face in &model.faces {
+            let (c0, c1, _) = surface::face_cells(model, face);
+            let coord = if v.occupied(c0) { c0 } else { c1 };
+            let Some(segment) = self.segment_of(coord) else {
+                continue;
+            };
+            for &i in face {
+                first[i] = first[i].min(segment as u32);
+            }
+        }
+        let layer = |s: &u32| segments.get(*s as usize).map_or(0, |s| s.layer as u32);
+        let layers = first.iter().map(layer).collect();
+        let order = first
+            .iter()
+            .map(|s| if *s == u32::MAX { 0 } else { *s })
+            .collect();
+        model.set_attribute("order", order);
+        model.set_attribute("layer", layers);
+    }
+}
+
+impl Drop for DepositLog {
+    fn drop(&mut self) {
+        if std::thread::panicking() && !self.entries.is_empty() {
+            eprintln!("deposit log, last {} voxels:", self.entries.len());
+            let _ = self.write(std::io::stderr().lock());
+        }
+    }
+}
+
+pub struct Logging<'a, V> {
+    voxel: &'a mut V,
+    log: Option<&'a mut DepositLog>,
+    order: Option<&'a mut Order>,
+    segment: usize,
+    nozzle: Vector3<f32>,
+}
+
+impl<V: Voxel> Voxel for Logging<'_, V> {
+    fn blocks(&self) -> usize {
+        self.voxel.blocks()
+    }
+
+    fn ranges(&self) -> usize {
+        self.voxel.ranges()
+    }
+
+    fn bounding_box(&self) -> &BoundingBox {
+        self.voxel.bounding_box()
+    }
+
+    fn occupied(&self, coord: VoxelIdx) -> bool {
+        self.voxel.occupied(coord)
+    }
+
+    fn add(&mut self, coord: VoxelIdx) -> bool {
+        if !self.voxel.add(coord) {
+            return false;
+        }
+        if let Some(log) = &mut self.log {
+            log.push(Entry {
+                voxel: coord,
+                segment: self.segment,
+                nozzle: self.nozzle,
+            });
+        }
+        if let Some(order) = &mut self.order {
+            order.push(coord, self.segment);
+        }
+        true
+    }
+
+    fn to_model(&self) -> Model {
+        self.voxel.to_model()
+    }
+
+    fn chunk_model(&self, chunk: ChunkId) -> Model {
+        self.voxel.chunk_model(chunk)
+    }
+
+    fn take_dirty(&mut self) -> Vec<ChunkId> {
+        self.voxel.take_dirty()
+    }
+
+    fn reserve(&mut self, bounds: &BoundingBox, blocks: usize) {
+        self.voxel.reserve(bounds, blocks)
+    }
+
+    fn column(&self, x: i32, y: i32) -> Vec<Range<i32>> {
+        self.voxel.column(x, y)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_deposit_log() {
+        let mut log = DepositLog::new(3);
+        let mut v = MonotonicVoxel::default();
+        let nozzle = Vector3::new(0.0, 0.0, 0.2);
+        let mut order = Order::default();
+        let mut lv = DepositLog::logging(Some(&mut log), Some(&mut order), &mut v, 7, nozzle);
+        for x in 0..5 {
+            lv.add([x, 0, 5].into());
+        }
+        // voxels already there are not logged
+        assert!(!lv.add([4, 0, 5].into()));
+        let mut lv = DepositLog::logging(None, Some(&mut order), &mut v, 9, nozzle);
+        (3..6).for_each(|z| assert!(lv.add([0, 1, z].into())));
+        lv.add([0, 0, 4].into());
+
+        let entries = log.entries().copied().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].voxel, [2, 0, 5].into());
+        assert_eq!(entries[2].segment, 7);
+        assert!((entries[2].distance() - 0.16).abs() < 1e-5);
+
+        assert_eq!(order.segment_of([4, 0, 5].into()), Some(7));
+        assert_eq!(order.segment_of([0, 0, 4].into()), Some(9));
+        assert_eq!(order.segment_of([0, 0, 3].into()), None);
+        assert_eq!(order.columns[&(0, 1)], vec![(3..6, 9)]);
+    }
+}
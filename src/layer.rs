@@ -0,0 +1,118 @@
+use super::LAYER_HEIGHT;
+
+/// Layer changes of gcode without `;LAYER:` comments, e.g. vase mode from slicers which
+/// mark a single layer while Z rises continuously. Extrusion moves are bucketed into
+/// Z bands of `LAYER_HEIGHT`, so a spiral gets a layer for each layer height it rises.
+#[derive(Clone, Debug)]
+pub struct Layers {
+    comments: bool,
+    band: Option<i32>,
+    current: usize,
+}
+
+impl Layers {
+    pub fn new(gcode: &str) -> Self {
+        Self {
+            comments: has_comments(gcode),
+            band: None,
+            current: 0,
+        }
+    }
+
+    /// Follows gcode appended after `new`, which may have the first layer comments.
+    pub fn extend(&mut self, gcode: &str) {
+        self.comments |= has_comments(gcode);
+    }
+
+    /// Follows an extrusion move ending at height `z`, returning the index of the new
+    /// layer if it enters a higher Z band. Always `None` with layer comments.
+    pub fn extrude(&mut self, z: f32) -> Option<usize> {
+        if self.comments {
+            return None;
+        }
+        // bead tops at exact multiples of the layer height stay in their band
+        let band = (z / LAYER_HEIGHT - 1e-3).ceil() as i32;
+        match self.band {
+            None => {
+                self.band = Some(band);
+                None
+            }
+            Some(prev) if band > prev => {
+                self.band = Some(band);
+                self.current += 1;
+                Some(self.current)
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+fn has_comments(gcode: &str) -> bool {
+    gcode.lines().any(|l| l.trim_start().starts_with(";LAYER:"))
+}
+
+/// Layers thicker than this are taken as jumps between objects, not layer heights.
+const MAX_LAYER_HEIGHT: f32 = 4f32 * LAYER_HEIGHT;
+
+/// Height of the layer being deposited, from Z of the first extrusion of each layer, so
+/// beads of variable layer height prints are as thick as their layers.
+#[derive(Clone, Debug, Default)]
+pub struct LayerHeight {
+    layer: Option<usize>,
+    /// Z of the first extrusion of the previous layer, 0 for the bed
+    base: f32,
+    /// Z of the first extrusion of the current layer
+    z: f32,
+}
+
+impl LayerHeight {
+    /// Follows an extrusion move at height `z` of `layer`, returning the height of the
+    /// layer in millimeters.
+    pub fn extrude(&mut self, layer: usize, z: f32) -> f32 {
+        if self.layer != Some(layer) {
+            if self.layer.is_some() {
+                self.base = self.z;
+            }
+            self.layer = Some(layer);
+            self.z = z;
+        }
+        match self.z - self.base {
+            h if h > 0f32 && h <= MAX_LAYER_HEIGHT => h,
+            _ => LAYER_HEIGHT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_layers() {
+        let mut layers = Layers::new("G1 X1 Y1 Z0.2 E1\n");
+        assert_eq!(layers.extrude(0.2), None);
+        // spiral rising from the first layer
+        assert_eq!(layers.extrude(0.25), Some(1));
+        assert_eq!(layers.extrude(0.39), None);
+        assert_eq!(layers.extrude(0.4), None);
+        assert_eq!(layers.extrude(0.41), Some(2));
+        // does not go back down
+        assert_eq!(layers.extrude(0.3), None);
+        assert_eq!(layers.extrude(1.0), Some(3));
+
+        let mut layers = Layers::new(";LAYER:0\nG1 X1 Y1 Z0.2 E1\n");
+        assert_eq!(layers.extrude(0.2), None);
+        assert_eq!(layers.extrude(0.6), None);
+
+        // thick first layer, then adaptive layers
+        let mut height = LayerHeight::default();
+        assert_eq!(height.extrude(0, 0.3), 0.3);
+        assert!((height.extrude(1, 0.4) - 0.1).abs() < 1e-6);
+        // z hops and spirals within a layer keep its height
+        assert!((height.extrude(1, 0.45) - 0.1).abs() < 1e-6);
+        assert!((height.extrude(2, 0.7) - 0.3).abs() < 1e-6);
+        // next object of a sequential print, or a layer below the last
+        assert_eq!(height.extrude(3, 20.0), LAYER_HEIGHT);
+        assert_eq!(height.extrude(4, 0.2), LAYER_HEIGHT);
+    }
+}
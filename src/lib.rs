@@ -0,0 +1,2262 @@
+//! Simulates FDM prints of gcode into voxels, and exports them as models. The `tdp-tl`
+//! binary is a command line over this library, which other tools embed without shelling
+//! out to it.
+//!
+//! ```
+//! use tdp_tl::{simulate, MonotonicVoxel, Params, Voxel, UNIT};
+//!
+//! let gcode = "G1 X10 Y10 Z0.2 F600\nG1 X20 Y10 E0.5\n";
+//! let sim = simulate::<MonotonicVoxel, _>(gcode, usize::MAX, &Params::default(), |_, _| {
+//!     Ok(())
+//! })?;
+//! assert!(sim.voxel.blocks() > 0);
+//!
+//! // quads of voxel corners, in millimeters with `UNIT`
+//! let model = sim.voxel.to_model();
+//! for face in model.faces() {
+//!     let corners = face.map(|i| model.vertex(i).idx.map(|c| c as f32 * UNIT));
+//!     assert!(corners.iter().all(|p| p[2] >= 0f32));
+//! }
+//! # anyhow::Ok(())
+//! ```
+
+use anyhow::Result;
+use log::*;
+use nalgebra::Vector3;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+mod timer;
+use timer::Stopwatch;
+
+pub mod obj;
+
+pub mod gltf;
+
+pub mod stl;
+
+pub mod ply;
+
+pub mod threemf;
+
+pub mod vox;
+
+pub mod srt;
+
+pub mod series;
+
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "python")]
+mod python;
+
+#[cfg(feature = "async")]
+pub mod progress;
+
+mod cancel;
+pub use cancel::Cancel;
+
+mod observer;
+pub use observer::Observer;
+use observer::OnLayer;
+
+pub mod voxelidx;
+pub use voxelidx::VoxelIdx;
+
+pub mod rangesetvoxel;
+pub use rangesetvoxel::RangeSetVoxel;
+
+pub mod monotonicvoxel;
+pub use monotonicvoxel::MonotonicVoxel;
+
+pub mod surface;
+pub use surface::ExportMode;
+
+pub mod firstlayer;
+
+pub mod tags;
+pub use tags::Tags;
+
+mod bridge;
+use bridge::Bridge;
+
+mod support;
+
+mod seam;
+use seam::LoopTracker;
+
+mod purge;
+
+mod layer;
+use layer::{LayerHeight, Layers};
+
+pub mod journal;
+use journal::{DepositLog, Order};
+
+pub mod invariant;
+
+pub mod plan;
+
+pub mod deterministic;
+
+mod http;
+
+pub mod webhook;
+
+mod sha256;
+
+pub mod s3;
+
+pub mod storage;
+
+pub mod eta;
+use eta::{Heaters, Motion};
+
+mod guard;
+use guard::Guard;
+
+mod ringing;
+use ringing::Ringing;
+
+mod fuzzy;
+use fuzzy::FuzzySkin;
+
+mod spaghetti;
+use spaghetti::Spaghetti;
+
+pub mod volume;
+
+pub mod distance;
+
+pub mod flood;
+
+pub mod schematic;
+
+pub mod stream;
+
+pub mod cache;
+use cache::FrameCache;
+
+pub mod explain;
+
+pub mod adhesion;
+
+pub mod infill;
+
+pub mod thickness;
+
+pub mod follow;
+
+pub mod gcode;
+use gcode::{Event, GcodeStream, Parser};
+
+mod zip;
+
+pub mod project;
+use project::read_gcode;
+
+pub mod filter;
+use filter::{Filter, FilterSpec};
+
+pub mod region;
+
+pub mod sweep;
+
+pub mod dataset;
+
+pub mod diff;
+
+pub mod align;
+
+pub mod balance;
+
+pub mod cost;
+
+pub mod inertia;
+
+pub mod section;
+pub use cost::Filament;
+
+pub mod shrink;
+pub use schematic::{Blocks, PaletteBy};
+pub use shrink::Shrinkage;
+
+#[cfg(feature = "vdb")]
+pub mod vdb;
+
+mod path;
+use path::Path;
+
+pub mod raster;
+
+pub mod deposit;
+pub use deposit::{Bead, Deposition, DepositionModel};
+
+pub mod units;
+pub use units::{Depth, Units};
+
+pub mod metadata;
+pub use metadata::{Frame, Manifest, Metadata};
+
+pub mod color;
+pub use color::{parse_color, Colors, Mixer, Palette, Rgb};
+
+pub mod render;
+
+pub mod overlay;
+
+pub mod anim;
+
+pub mod chunk;
+pub use chunk::ChunkId;
+
+pub mod backend;
+pub use backend::{AnyVoxel, Backend, ByteSize, Estimate, Registry};
+
+pub mod headless;
+pub mod profile;
+#[cfg(feature = "view")]
+pub mod view;
+
+impl std::ops::Index<usize> for VoxelIdx {
+    type Output = i32;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.idx[index]
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct BoundingBox {
+    bound_min: VoxelIdx,
+    bound_max: VoxelIdx,
+
+    count: usize,
+}
+
+impl BoundingBox {
+    /// Bounds of voxels between `lo` and `hi` in millimeters.
+    pub fn of_mm(lo: [f32; 3], hi: [f32; 3]) -> Self {
+        let mut bb = Self::default();
+        bb.add(to_intpos(lo));
+        bb.add(to_intpos(hi));
+        bb
+    }
+
+    fn add(&mut self, coord: VoxelIdx) {
+        // first block
+        if self.count == 0 {
+            self.bound_min = coord;
+            self.bound_max = coord;
+        } else {
+            self.bound_min = coord.bb_min(&self.bound_min);
+            self.bound_max = coord.bb_max(&self.bound_max);
+        }
+        self.count += 1;
+    }
+}
+
+// unit: 0.04mm, layer thickness: 0.2mm, nozzle size: 0.4mm
+// 20mm
+pub const UNIT: f32 = 0.04f32;
+
+// unit: millimeters
+// TODO: extract from gcode
+pub const LAYER_HEIGHT: f32 = 0.2f32;
+pub const Z_OFFSET: i32 = (LAYER_HEIGHT / UNIT) as i32;
+
+// unit: millimeters
+const FILAMENT_DIAMETER: f32 = 1.75f32;
+
+/// Voxels per millimeter of filament.
+fn blocks_per_filament(params: &Params) -> f32 {
+    let cross_section = 0.25f32 * std::f32::consts::PI * FILAMENT_DIAMETER * FILAMENT_DIAMETER;
+    cross_section / (UNIT * UNIT * UNIT) * params.flow
+}
+
+fn to_intpos(pos: [f32; 3]) -> VoxelIdx {
+    [
+        (pos[0] / UNIT).round() as i32,
+        (pos[1] / UNIT).round() as i32,
+        (pos[2] / UNIT).round() as i32,
+    ]
+    .into()
+}
+
+pub trait Voxel {
+    fn blocks(&self) -> usize;
+    fn ranges(&self) -> usize;
+    fn bounding_box(&self) -> &BoundingBox;
+    fn occupied(&self, coord: VoxelIdx) -> bool;
+    fn add(&mut self, coord: VoxelIdx) -> bool;
+    fn to_model(&self) -> Model;
+
+    /// Faces of `to_model` of the columns in `chunk`, so models are built and written
+    /// chunk by chunk. Faces of all chunks together are the faces of `to_model`, with
+    /// vertices of a face within the columns of its chunk and their far edges.
+    fn chunk_model(&self, chunk: ChunkId) -> Model {
+        // each face goes to the chunk of its lowest corner
+        let model = self.to_model();
+        model.retain_faces(|model, face| {
+            let lo = face.iter().fold(model.vertices[face[0]], |acc, i| {
+                acc.bb_min(&model.vertices[*i])
+            });
+            chunk::chunk_of(lo[0], lo[1]) == chunk
+        })
+    }
+
+    /// Chunks with voxels added since the last call, including neighbor chunks whose
+    /// exposed faces may have changed.
+    fn take_dirty(&mut self) -> Vec<ChunkId>;
+
+    /// Hints that about `blocks` voxels will be added within `bounds`, so storage can
+    /// be allocated up front instead of while depositing.
+    fn reserve(&mut self, _bounds: &BoundingBox, _blocks: usize) {}
+
+    /// Occupied z ranges of the column at (x, y), in ascending order.
+    fn column(&self, x: i32, y: i32) -> Vec<std::ops::Range<i32>> {
+        let bb = self.bounding_box();
+        let mut ranges: Vec<std::ops::Range<i32>> = Vec::new();
+        if bb.count == 0 {
+            return ranges;
+        }
+        for z in bb.bound_min[2]..=bb.bound_max[2] {
+            if !self.occupied([x, y, z].into()) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(r) if r.end == z => r.end += 1,
+                _ => ranges.push(z..z + 1),
+            }
+        }
+        ranges
+    }
+}
+
+#[derive(Default)]
+pub struct Model {
+    vertices: indexmap::IndexSet<VoxelIdx>,
+    faces: Vec<[usize; 4]>,
+    // named face groups, (index of the first face, name)
+    groups: Vec<(usize, String)>,
+    /// written as comments in exported files
+    pub metadata: Metadata,
+    // per-vertex colors, empty if not colored
+    colors: Vec<Rgb>,
+    // named per-vertex attributes, like build order, written by formats which carry them
+    attributes: Vec<(String, Vec<u32>)>,
+}
+
+impl Model {
+    /// Vertices in voxel units, by index of faces.
+    pub fn vertices(&self) -> impl ExactSizeIterator<Item = VoxelIdx> + '_ {
+        self.vertices.iter().copied()
+    }
+
+    pub fn vertex(&self, i: usize) -> VoxelIdx {
+        self.vertices[i]
+    }
+
+    /// Quads of vertex indices.
+    pub fn faces(&self) -> &[[usize; 4]] {
+        &self.faces
+    }
+
+    fn add_vert(&mut self, coord: VoxelIdx) -> usize {
+        let (idx, _) = self.vertices.insert_full(coord);
+        idx
+    }
+
+    fn add_face(&mut self, coord: VoxelIdx, dir: VoxelIdx) {
+        let (i0, i1, i2, i3) = if dir[0] == 0 {
+            let i0 = self.add_vert(coord);
+            let i1 = self.add_vert(coord + dir.y());
+            let i2 = self.add_vert(coord + dir.yz());
+            let i3 = self.add_vert(coord + dir.z());
+            (i0, i1, i2, i3)
+        } else if dir[1] == 0 {
+            let i0 = self.add_vert(coord);
+            let i1 = self.add_vert(coord + dir.x());
+            let i2 = self.add_vert(coord + dir.xz());
+            let i3 = self.add_vert(coord + dir.z());
+            (i0, i1, i2, i3)
+        } else {
+            let i0 = self.add_vert(coord);
+            let i1 = self.add_vert(coord + dir.x());
+            let i2 = self.add_vert(coord + dir.xy());
+            let i3 = self.add_vert(coord + dir.y());
+            (i0, i1, i2, i3)
+        };
+
+        self.faces.push([i0, i1, i2, i3]);
+    }
+
+    /// Sets the per-vertex attribute `name`, a value of each vertex so far. Vertices
+    /// added later have 0.
+    pub fn set_attribute(&mut self, name: &str, values: Vec<u32>) {
+        self.attributes.retain(|(n, _)| n != name);
+        self.attributes.push((name.to_owned(), values));
+    }
+
+    /// Adds a box from `min` to `max` as its own face group.
+    pub fn add_box(&mut self, min: VoxelIdx, max: VoxelIdx, group: &str) {
+        self.groups.push((self.faces.len(), group.to_owned()));
+        let size = max - min;
+        self.add_face(min, [size[0], size[1], 0].into());
+        self.add_face(min, [size[0], 0, size[2]].into());
+        self.add_face(min, [0, size[1], size[2]].into());
+        self.add_face(max, [-size[0], -size[1], 0].into());
+        self.add_face(max, [-size[0], 0, -size[2]].into());
+        self.add_face(max, [0, -size[1], -size[2]].into());
+    }
+
+    pub fn add_cube(&mut self, coord: VoxelIdx) {
+        self.add_face(coord, [1, 1, 0].into());
+        self.add_face(coord, [1, 0, 1].into());
+        self.add_face(coord, [0, 1, 1].into());
+
+        let coord = coord + VoxelIdx::unit();
+
+        self.add_face(coord, [-1, -1, 0].into());
+        self.add_face(coord, [-1, 0, -1].into());
+        self.add_face(coord, [0, -1, -1].into());
+    }
+
+    fn retain_faces<F>(&self, mut f: F) -> Self
+    where
+        F: FnMut(&Self, &[usize; 4]) -> bool,
+    {
+        let mut model = Self::default();
+        for face in &self.faces {
+            if !f(self, face) {
+                continue;
+            }
+            let [i0, i1, i2, i3] = face.map(|i| model.add_vert(self.vertices[i]));
+            model.faces.push([i0, i1, i2, i3]);
+        }
+        model
+    }
+
+    /// Reorders faces so faces with the same key are contiguous, and names each run of
+    /// faces with its key. Faces without key come first, without a group.
+    fn group_faces<'k, F>(&mut self, mut f: F)
+    where
+        F: FnMut(&Self, &[usize; 4]) -> Option<&'k str>,
+    {
+        let mut keyed = Vec::with_capacity(self.faces.len());
+        for face in &self.faces {
+            keyed.push((f(self, face), *face));
+        }
+        keyed.sort_by_key(|(key, _)| *key);
+
+        self.faces.clear();
+        self.groups.clear();
+        for (key, face) in keyed {
+            if let Some(key) = key {
+                if self.groups.last().map(|(_, name)| name.as_str()) != Some(key) {
+                    self.groups.push((self.faces.len(), key.to_owned()));
+                }
+            }
+            self.faces.push(face);
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        for [i0, i1, i2, i3] in other.faces {
+            let i0 = self.add_vert(other.vertices[i0]);
+            let i1 = self.add_vert(other.vertices[i1]);
+            let i2 = self.add_vert(other.vertices[i2]);
+            let i3 = self.add_vert(other.vertices[i3]);
+            self.faces.push([i0, i1, i2, i3]);
+        }
+    }
+
+    /// Writes model as obj, and returns hash of the written file.
+    pub fn serialize(
+        &self,
+        path: &str,
+        offset: [f32; 3],
+        scale: f32,
+        precision: usize,
+    ) -> Result<u64> {
+        self.serialize_with(path, precision, |idx| {
+            [0, 1, 2].map(|i| idx[i] as f32 * scale + offset[i])
+        })
+    }
+
+    /// Like `serialize`, with vertex positions from `position`.
+    pub fn serialize_with<F>(&self, path: &str, precision: usize, position: F) -> Result<u64>
+    where
+        F: Fn(VoxelIdx) -> [f32; 3],
+    {
+        self.serialize_as(path, Format::Obj, precision, position)
+    }
+
+    /// Like `serialize_with`, in `format`; `precision` is of text formats only.
+    pub fn serialize_as<F>(
+        &self,
+        path: &str,
+        format: Format,
+        precision: usize,
+        position: F,
+    ) -> Result<u64>
+    where
+        F: Fn(VoxelIdx) -> [f32; 3],
+    {
+        let mut w = metadata::HashWriter::new(storage::create(path)?);
+        match format {
+            Format::Obj => self.write_obj_with(&mut w, precision, position)?,
+            Format::Stl => stl::write_stl(self, &mut w, position)?,
+            Format::Ply => ply::write_ply(self, &mut w, position)?,
+            Format::Glb => gltf::write_glb(self, &mut w, position)?,
+            Format::ThreeMf => threemf::write_3mf(self, &mut w, position)?,
+        }
+        let hash = w.hash();
+        w.into_inner().finish()?;
+
+        Ok(hash)
+    }
+
+    /// Writes model as obj to `w`, with vertices scaled by `scale` and moved by `offset`.
+    pub fn write_obj<W: std::io::Write>(
+        &self,
+        w: W,
+        offset: [f32; 3],
+        scale: f32,
+        precision: usize,
+    ) -> Result<()> {
+        self.write_obj_with(w, precision, |idx| {
+            [0, 1, 2].map(|i| idx[i] as f32 * scale + offset[i])
+        })
+    }
+
+    fn write_obj_with<W, F>(&self, mut w: W, precision: usize, position: F) -> Result<()>
+    where
+        W: std::io::Write,
+        F: Fn(VoxelIdx) -> [f32; 3],
+    {
+        for (key, value) in self.metadata.entries() {
+            writeln!(&mut w, "# {}: {}", key, value)?;
+        }
+        for (i, idx) in self.vertices.iter().enumerate() {
+            let [x, y, z] = position(*idx);
+            write!(
+                &mut w,
+                "v {:.*} {:.*} {:.*}",
+                precision, x, precision, y, precision, z
+            )?;
+            // vertex colors, as an extension understood by most viewers
+            if let Some([r, g, b]) = self.colors.get(i) {
+                let k = 1f32 / 255f32;
+                write!(
+                    &mut w,
+                    " {:.3} {:.3} {:.3}",
+                    *r as f32 * k,
+                    *g as f32 * k,
+                    *b as f32 * k
+                )?;
+            }
+            writeln!(&mut w)?;
+        }
+        let mut groups = self.groups.iter().peekable();
+        for (idx, [i0, i1, i2, i3]) in self.faces.iter().enumerate() {
+            if let Some((_, name)) = groups.next_if(|(start, _)| *start == idx) {
+                writeln!(&mut w, "g {}", name)?;
+            }
+            write!(&mut w, "f {} {} {} {}\n", i0 + 1, i1 + 1, i2 + 1, i3 + 1)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn inject_at<V: Voxel>(v: &mut V, zlow: i32, zhigh: i32, pos0: VoxelIdx, n: usize) -> usize {
+    use std::collections::BinaryHeap;
+
+    if n == 0 {
+        return 0;
+    }
+
+    let mut injected = 0;
+
+    #[derive(Clone, Copy, Ord, PartialEq, Eq, Debug)]
+    struct HeapItem {
+        dist: usize,
+        depth: usize,
+        pos: VoxelIdx,
+    }
+    impl std::cmp::PartialOrd for HeapItem {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(other.dist.cmp(&self.dist))
+        }
+    }
+
+    // with unit = 0.04mm and nozzle diameter 0.4mm, limiting maximum depth to 15
+
+    let mut candidates = BinaryHeap::new();
+    // a plain set: visited positions need no chunks, snapshots or dirty tracking
+    let mut visited = std::collections::HashSet::new();
+    candidates.push(HeapItem {
+        dist: 0,
+        depth: 10,
+        pos: pos0,
+    });
+
+    while let Some(HeapItem {
+        dist: _dist,
+        depth,
+        pos,
+    }) = candidates.pop()
+    {
+        if depth == 0 {
+            continue;
+        }
+        if !visited.insert(pos) {
+            continue;
+        }
+
+        if v.add(pos) {
+            injected += 1;
+            if n == injected {
+                break;
+            }
+        }
+
+        let directions = [
+            [1, 0, 0],
+            [-1, 0, 0],
+            [0, 1, 0],
+            [0, -1, 0],
+            [0, 0, 1],
+            [0, 0, -1],
+        ];
+
+        for dir in directions {
+            let next: VoxelIdx = pos + dir.into();
+            if next[2] < zlow || next[2] > zhigh {
+                continue;
+            }
+            if visited.contains(&next) {
+                continue;
+            }
+
+            let delta = pos0 - next;
+            let dist = delta.magnitude_squared();
+            candidates.push(HeapItem {
+                dist,
+                depth: depth - 1,
+                pos: next,
+            });
+        }
+    }
+
+    injected
+}
+
+/// Simulation parameters.
+#[derive(Clone, Debug)]
+pub struct Params {
+    /// maximum distance between deposition points along a move, in millimeters
+    pub step_size: f32,
+    /// consecutive moves are merged into paths of this length, in millimeters
+    pub merge_length: f32,
+    /// moves to coordinates beyond this on any axis are dropped, in millimeters
+    pub coordinate_limit: f32,
+    /// largest distance of segments of G2 and G3 arcs from the arc, in millimeters
+    pub arc_tolerance: f32,
+    /// detect and tag bridges
+    pub bridges: bool,
+    /// bridge sag depth relative to span length
+    pub bridge_sag: f32,
+    /// detect and tag seams of perimeter loops
+    pub seams: bool,
+    /// extra material deposited at each seam, in cubic millimeters
+    pub seam_blob: f32,
+    /// resonance of the toolhead for emulated ringing in hertz, 0 to disable
+    pub ringing_frequency: f32,
+    /// damping ratio of emulated ringing
+    pub ringing_damping: f32,
+    /// random sideways perturbation of outer walls in millimeters, 0 to disable
+    pub fuzzy_skin: f32,
+    /// distance between random points of fuzzy skin, in millimeters
+    pub fuzzy_spacing: f32,
+    /// seed of random perturbations
+    pub seed: u32,
+    /// air gap kept above support material, in millimeters
+    pub support_z_gap: f32,
+    /// track filament colors across tool changes
+    pub colors: bool,
+    /// color of each tool, overrides slicer settings if not empty
+    pub palette: Vec<Rgb>,
+    /// purge volume if not set by the slicer, in cubic millimeters
+    pub purge_volume: f32,
+    /// tag voxels by the class of the feature which deposited them
+    pub features: bool,
+    /// stops the simulation early, keeping voxels deposited so far
+    pub cancel: Cancel,
+    /// extrusion multiplier, like flow of slicers
+    pub flow: f32,
+    /// depth of beads below the nozzle, in voxels, from `Depth::voxels`
+    pub spread_depth: i32,
+    /// bead model turning extruded filament into voxels
+    pub deposition: Deposition,
+    /// rewrite gcode events before they are simulated, in order
+    pub filters: Vec<FilterSpec>,
+    /// the print detaches from the bed at this layer, and later beads fall as spaghetti
+    pub detach_layer: Option<usize>,
+    /// leave purge lines, skirts and brims out, also ones found without `;TYPE:` comments
+    pub exclude_purge: bool,
+    /// acceleration of the toolhead for print time, in millimeters per second squared,
+    /// 0 to ignore it
+    pub acceleration: f32,
+    /// log the last voxels added by deposits with their moves, 0 to disable
+    pub deposit_log: usize,
+    /// check invariants of the voxels after each layer, panicking on violations so the
+    /// deposit log is dumped. Slow, for developing backends
+    pub paranoid: bool,
+    /// record the move which deposited each voxel, for exports colored by build order
+    pub build_order: bool,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            step_size: 0.1,
+            merge_length: 0.0,
+            coordinate_limit: 1000.0,
+            arc_tolerance: gcode::ARC_TOLERANCE,
+            bridges: false,
+            bridge_sag: 0.02,
+            seams: false,
+            seam_blob: 0.0,
+            ringing_frequency: 0.0,
+            ringing_damping: 0.05,
+            fuzzy_skin: 0.0,
+            fuzzy_spacing: 0.8,
+            seed: 0,
+            support_z_gap: 0.0,
+            colors: false,
+            palette: Vec::new(),
+            purge_volume: 0.0,
+            features: false,
+            cancel: Cancel::default(),
+            flow: 1.0,
+            spread_depth: Z_OFFSET,
+            deposition: Deposition::default(),
+            filters: Vec::new(),
+            detach_layer: None,
+            exclude_purge: false,
+            acceleration: 0.0,
+            deposit_log: 0,
+            paranoid: false,
+            build_order: false,
+        }
+    }
+}
+
+impl Params {
+    /// Sets a parameter by its field name, for `--set`. Only parameters read while
+    /// depositing are settable, so runs of a `Simulator` can change them between layers.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let parse = |value: &str| -> Result<f32> {
+            value
+                .parse()
+                .map_err(|e| anyhow::anyhow!("{}={}: {}", key, value, e))
+        };
+        let flag = |value: &str| -> Result<bool> {
+            value
+                .parse()
+                .map_err(|e| anyhow::anyhow!("{}={}: {}", key, value, e))
+        };
+        match key {
+            "step_size" => self.step_size = parse(value)?,
+            "merge_length" => self.merge_length = parse(value)?,
+            "bridges" => self.bridges = flag(value)?,
+            "bridge_sag" => self.bridge_sag = parse(value)?,
+            "seams" => self.seams = flag(value)?,
+            "seam_blob" => self.seam_blob = parse(value)?,
+            "support_z_gap" => self.support_z_gap = parse(value)?,
+            "flow" => self.flow = parse(value)?,
+            "deposit_model" => {
+                self.deposition = value
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("{}={}: {}", key, value, e))?
+            }
+            // bare integers are voxels, otherwise like `2layers` or `0.4mm`
+            "spread_depth" | "bond_depth" => {
+                self.spread_depth = match value.parse::<i32>() {
+                    Ok(voxels) => voxels,
+                    Err(_) => value
+                        .parse::<Depth>()
+                        .map_err(|e| anyhow::anyhow!("{}={}: {}", key, value, e))?
+                        .voxels(),
+                }
+            }
+            _ => anyhow::bail!(
+                "unknown parameter {}, expected one of step_size, merge_length, bridges, \
+                bridge_sag, seams, seam_blob, support_z_gap, flow, spread_depth, bond_depth, \
+                deposit_model",
+                key
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Extrusion move, in millimeters.
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub from: Vector3<f32>,
+    pub to: Vector3<f32>,
+    pub layer: usize,
+}
+
+/// Point drawn into exported models as a small cube, without voxels.
+#[derive(Clone, Debug)]
+pub struct Marker {
+    /// name of the face group
+    pub name: String,
+    /// in millimeters
+    pub pos: Vector3<f32>,
+}
+
+/// Deposited voxels, extrusion moves and tagged voxels.
+#[derive(Clone)]
+pub struct Simulation<V> {
+    pub voxel: V,
+    pub segments: Vec<Segment>,
+    pub tags: Tags,
+    /// filament colors, if tracked
+    pub colors: Option<Colors>,
+    /// print time from feedrates and `Params::acceleration`, in seconds
+    pub time: f32,
+    /// seconds of `time` without motion, in dwells and heat-up waits
+    pub idle: f32,
+    /// closing points of perimeter loops in millimeters, if detected
+    pub seams: Vec<Vector3<f32>>,
+    /// emulated toolhead resonance, if enabled
+    pub ringing: Option<Ringing>,
+    /// random perturbation of outer walls, if enabled
+    pub fuzzy: Option<FuzzySkin>,
+    /// detached print, if simulated
+    pub spaghetti: Option<Spaghetti>,
+    /// voxels by feature class, if tracked
+    pub features: Tags,
+    /// voxels added by each `;TYPE:` feature of the slicer, and `seam` blobs
+    pub deposited: BTreeMap<String, usize>,
+    /// annotations of exported models
+    pub markers: Vec<Marker>,
+    /// nozzle in millimeters, at layer changes, extrusion segments and the end of runs
+    pub nozzle: Vector3<f32>,
+    /// last voxels added by deposits, if logged. Shared by checkpoints
+    pub deposit_log: Option<Arc<Mutex<DepositLog>>>,
+    /// move which deposited each voxel, if `Params::build_order`
+    pub order: Option<Order>,
+    /// stopped by `Params::cancel` before the end of the gcode
+    pub cancelled: bool,
+    /// height of the layer being deposited in millimeters, which may vary by layer
+    pub layer_height: f32,
+    /// gcode line of the last simulated event, from 1
+    pub line: usize,
+}
+
+impl<V> Simulation<V> {
+    /// Depth of beads below the nozzle in voxels, `Params::spread_depth` scaled from
+    /// the nominal layer height to the current one.
+    fn bead_depth(&self, params: &Params) -> i32 {
+        let depth = params.spread_depth as f32 * self.layer_height / LAYER_HEIGHT;
+        (depth.round() as i32).max(1)
+    }
+}
+
+/// Deposits material along `path`, and clears it. `budget` carries fractional blocks
+/// over between steps and paths, so low-flow moves (ironing, thin walls) still deposit.
+fn deposit<V: Voxel>(
+    sim: &mut Simulation<V>,
+    path: &mut Path,
+    params: &Params,
+    feature: &str,
+    budget: &mut f32,
+) {
+    if path.is_empty() {
+        path.clear();
+        return;
+    }
+
+    let len = path.len();
+    let total_blocks = path.total_blocks();
+
+    // split the path into equal steps no longer than step_size, so short moves still
+    // deposit along their length
+    let steps = ((len / params.step_size).ceil() as usize).max(1);
+    let step_size = len / steps as f32;
+
+    debug!(
+        "{:?} -> {:?}, len={}, blocks={}",
+        path.start(),
+        path.end(),
+        len,
+        total_blocks
+    );
+
+    let depth = sim.bead_depth(params);
+    let bridge = if params.bridges {
+        Bridge::detect(&sim.voxel, path, step_size, depth)
+    } else {
+        None
+    };
+    let support_gap = (params.support_z_gap / UNIT).round() as i32;
+    let is_support = support_gap > 0 && support::is_support(feature);
+    let detached = sim.spaghetti.as_ref().is_some_and(Spaghetti::is_detached);
+    let tag = if detached {
+        Some("spaghetti")
+    } else if is_support {
+        Some("support")
+    } else {
+        bridge.as_ref().map(|_| "bridge")
+    };
+    let class = params.features.then(|| schematic::feature_class(feature));
+    let mut fv = sim.features.tagging(&mut sim.voxel, class);
+    let mut mv = sim.tags.tagging(&mut fv, tag);
+    let fuzzy_wall = fuzzy::is_outer_wall(feature);
+    let per_filament = blocks_per_filament(params);
+    let mut log = sim.deposit_log.as_ref().map(|log| log.lock().unwrap());
+    // the path holds the last moves of segments
+    let first_segment = sim.segments.len().saturating_sub(path.moves());
+
+    let mut deposited = 0f32;
+    let mut added = 0;
+    let mut prev = path.start();
+    for step in 1..=steps {
+        let d = if step == steps {
+            len
+        } else {
+            step as f32 * step_size
+        };
+        // Z is interpolated too, so beads of non-planar moves follow the slope instead of
+        // the Z of move ends
+        let mut next = match &mut sim.ringing {
+            Some(ringing) => ringing.follow(path, d - step_size, d),
+            None => path.at(d),
+        };
+        if let (Some(fuzzy), true) = (&sim.fuzzy, fuzzy_wall) {
+            next += fuzzy.offset(path, d);
+        }
+        let mut next_pos = to_intpos([next[0], next[1], next[2]]);
+        if let Some(bridge) = &bridge {
+            let sag = bridge.sag(d, params.bridge_sag);
+            next_pos.idx[2] -= (sag / UNIT).round() as i32;
+        }
+        if let (false, Some(support)) = (is_support, mv.tags().get("support")) {
+            next_pos.idx[2] += support::support_offset(support, next_pos, depth, support_gap);
+        }
+        if let (Some(spaghetti), true) = (&mut sim.spaghetti, detached) {
+            next_pos = spaghetti.land(&mv, next_pos, depth);
+        }
+        let z = next_pos[2];
+
+        // blocks follow the flow of each move along the path. last step takes whatever
+        // is left, so the path deposits exactly total_blocks
+        let target = if step == steps {
+            total_blocks
+        } else {
+            path.blocks_at(d)
+        };
+        let e = (target - deposited) / per_filament;
+        *budget += target - deposited;
+        deposited = target;
+        let blocks = *budget as usize;
+        *budget -= blocks as f32;
+
+        let bead = Bead {
+            from: prev,
+            to: next,
+            pos: next_pos,
+            zlow: z - depth,
+            zhigh: z,
+            blocks,
+            e,
+            speed: path.speed_at(d),
+        };
+        prev = next;
+        let segment = first_segment + path.move_at(d);
+        let order = sim.order.as_mut();
+        let mut lv = DepositLog::logging(log.as_deref_mut(), order, &mut mv, segment, next);
+        let injected = match &mut sim.colors {
+            Some(colors) => {
+                let color = colors.mixer.extrude(blocks as f32 * UNIT * UNIT * UNIT);
+                let mut mv = colors.coloring(&mut lv, color);
+                params.deposition.deposit(&mut mv, &bead)
+            }
+            None => params.deposition.deposit(&mut lv, &bead),
+        };
+        if injected != blocks {
+            debug!("injected != blocks_per_step, skipping");
+        }
+        added += injected;
+    }
+    drop(log);
+    count_deposited(sim, feature, added);
+
+    if let (Some(fuzzy), true) = (&mut sim.fuzzy, fuzzy_wall) {
+        fuzzy.finish(path);
+    }
+    path.clear();
+}
+
+fn count_deposited<V>(sim: &mut Simulation<V>, feature: &str, blocks: usize) {
+    match sim.deposited.get_mut(feature) {
+        Some(count) => *count += blocks,
+        None => {
+            sim.deposited.insert(feature.to_owned(), blocks);
+        }
+    }
+}
+
+/// Records the seam of a perimeter loop closing at `pos`, after the loop is deposited.
+/// Deposits the seam blob, and tags the bead around the seam.
+fn seam<V: Voxel>(sim: &mut Simulation<V>, pos: Vector3<f32>, params: &Params) {
+    sim.seams.push(pos);
+
+    let blocks = (params.seam_blob / (UNIT * UNIT * UNIT)).round() as usize;
+    let c = to_intpos([pos[0], pos[1], pos[2]]);
+    let depth = sim.bead_depth(params);
+    let bead = Bead {
+        from: pos,
+        to: pos,
+        pos: c,
+        zlow: c[2] - depth,
+        zhigh: c[2],
+        blocks,
+        e: params.seam_blob / (UNIT * UNIT * UNIT) / blocks_per_filament(params),
+        speed: 0f32,
+    };
+    let mut mv = sim.tags.tagging(&mut sim.voxel, Some("seam"));
+    let mut log = sim.deposit_log.as_ref().map(|log| log.lock().unwrap());
+    let segment = sim.segments.len().saturating_sub(1);
+    let order = sim.order.as_mut();
+    let mut lv = DepositLog::logging(log.as_deref_mut(), order, &mut mv, segment, pos);
+    let injected = params.deposition.deposit(&mut lv, &bead);
+    if injected != blocks {
+        debug!("seam: injected={} != blocks={}", injected, blocks);
+    }
+    drop(log);
+    count_deposited(sim, "seam", injected);
+
+    seam::tag_seam(&mut sim.tags, &sim.voxel, pos, depth);
+}
+
+/// Simulates gcode of `filename` until `layer`, calling `on_layer` with the simulation
+/// state before each layer change.
+pub fn simulate_gcode<V, F>(
+    filename: &str,
+    layer: usize,
+    params: &Params,
+    on_layer: F,
+) -> Result<Simulation<V>>
+where
+    V: Voxel + Default + Clone,
+    F: FnMut(&mut Simulation<V>, usize) -> Result<()>,
+{
+    let gcode = read_gcode(filename)?;
+    simulate(&gcode, layer, params, on_layer)
+}
+
+/// Simulates `gcode` text, like `simulate_gcode`, without touching the file system.
+pub fn simulate<V, F>(
+    gcode: &str,
+    layer: usize,
+    params: &Params,
+    on_layer: F,
+) -> Result<Simulation<V>>
+where
+    V: Voxel + Default + Clone,
+    F: FnMut(&mut Simulation<V>, usize) -> Result<()>,
+{
+    simulate_with(gcode, layer, params, &mut OnLayer(on_layer))
+}
+
+/// Simulates `gcode` text until `layer`, calling hooks of `observer` along the way.
+pub fn simulate_with<V, O>(
+    gcode: &str,
+    layer: usize,
+    params: &Params,
+    observer: &mut O,
+) -> Result<Simulation<V>>
+where
+    V: Voxel + Default + Clone,
+    O: Observer<V>,
+{
+    simulate_into(V::default(), gcode, layer, params, observer)
+}
+
+/// Like `simulate_with`, depositing into `voxel`, e.g. a backend from a `Registry`.
+pub fn simulate_into<V, O>(
+    voxel: V,
+    gcode: &str,
+    layer: usize,
+    params: &Params,
+    observer: &mut O,
+) -> Result<Simulation<V>>
+where
+    V: Voxel + Clone,
+    O: Observer<V>,
+{
+    let mut simulator = Simulator::with_voxel(voxel, gcode, params)?;
+    simulator.run(layer, params, observer)?;
+    Ok(simulator.into_simulation())
+}
+
+/// Parser state between lines of gcode, kept in checkpoints with the simulation.
+#[derive(Clone)]
+struct Cursor {
+    /// index of the next event
+    event: usize,
+    pos: Vector3<f32>,
+    e: f64,
+    // mm/min, until the first F word
+    feedrate: f32,
+    current_layer: usize,
+    feature: String,
+    // fractional blocks carried over between steps and moves, so low-flow moves
+    // (ironing, thin walls) still deposit
+    budget: f32,
+    path: Path,
+    perimeter: LoopTracker,
+    layers: Layers,
+    layer_height: LayerHeight,
+    guard: Guard,
+    motion: Motion,
+    heaters: Heaters,
+    /// layer change found, but not passed to observers yet
+    pending: Option<usize>,
+    /// the layer change was found by the extrusion move at `event`, which continues
+    /// after the layer change
+    in_move: bool,
+}
+
+/// Events of `stream`, passed through `filters`.
+fn filter_events(
+    stream: &mut GcodeStream,
+    filters: &mut [Box<dyn Filter>],
+) -> Result<Vec<(usize, Event)>> {
+    let mut events = Vec::new();
+    for event in stream {
+        let (line, event) = event?;
+        if filters.is_empty() {
+            events.push((line, event));
+        } else {
+            events.extend(filter::apply(filters, line, event));
+        }
+    }
+    Ok(events)
+}
+
+/// Resumable simulation of gcode. Runs stop before a layer, and continue from there
+/// with other parameters. Checkpoints at layer changes allow rewinding to earlier
+/// layers, without simulating layers before them again.
+pub struct Simulator<V> {
+    /// events of the gcode, with their line numbers
+    events: Vec<(usize, Event)>,
+    /// parser state after the last line, for gcode appended later
+    parser: Parser,
+    filters: Vec<Box<dyn Filter>>,
+    sim: Simulation<V>,
+    cursor: Cursor,
+    /// checkpoints before layer changes, oldest first
+    checkpoints: VecDeque<(Simulation<V>, Cursor)>,
+    keep: usize,
+    /// hash of what is read from the whole gcode, see `whole_file_hash`
+    whole_file: u64,
+}
+
+impl<V: Voxel + Default + Clone> Simulator<V> {
+    /// Parses `gcode`. Filament colors, ringing, fuzzy skin and filters are set up from
+    /// `params`, and keep their setup across runs.
+    pub fn new(gcode: &str, params: &Params) -> Result<Self> {
+        Self::with_voxel(V::default(), gcode, params)
+    }
+}
+
+impl<V: Voxel + Clone> Simulator<V> {
+    /// Like `new`, depositing into empty `voxel` of a backend chosen at runtime.
+    pub fn with_voxel(voxel: V, gcode: &str, params: &Params) -> Result<Self> {
+        anyhow::ensure!(params.step_size > 0f32, "step size must be positive");
+
+        let colors = if params.colors {
+            let mut palette = Palette::from_gcode(gcode);
+            if !params.palette.is_empty() {
+                palette = palette.with_colors(&params.palette);
+            }
+            Some(Colors::new(Mixer::new(palette, params.purge_volume)))
+        } else {
+            None
+        };
+        let mut sim = Simulation {
+            voxel,
+            segments: Vec::new(),
+            tags: Tags::default(),
+            colors,
+            time: 0f32,
+            idle: 0f32,
+            seams: Vec::new(),
+            ringing: (params.ringing_frequency > 0f32)
+                .then(|| Ringing::new(params.ringing_frequency, params.ringing_damping)),
+            fuzzy: (params.fuzzy_skin > 0f32)
+                .then(|| FuzzySkin::new(params.fuzzy_skin, params.fuzzy_spacing, params.seed)),
+            spaghetti: params
+                .detach_layer
+                .map(|layer| Spaghetti::new(layer, params.seed)),
+            features: Tags::default(),
+            deposited: BTreeMap::new(),
+            markers: Vec::new(),
+            nozzle: Vector3::default(),
+            deposit_log: (params.deposit_log > 0)
+                .then(|| Arc::new(Mutex::new(DepositLog::new(params.deposit_log)))),
+            order: params.build_order.then(Order::default),
+            cancelled: false,
+            layer_height: LAYER_HEIGHT,
+            line: 0,
+        };
+        let cursor = Cursor {
+            event: 0,
+            pos: Vector3::default(),
+            e: 0f64,
+            feedrate: 1500f32,
+            current_layer: 0,
+            feature: String::new(),
+            budget: 0f32,
+            path: Path::default(),
+            perimeter: LoopTracker::default(),
+            layers: Layers::new(gcode),
+            layer_height: LayerHeight::default(),
+            guard: Guard::new(params.coordinate_limit),
+            motion: Motion::default(),
+            heaters: Heaters::default(),
+            pending: None,
+            in_move: false,
+        };
+
+        let mut filters: Vec<_> = params.filters.iter().map(FilterSpec::build).collect();
+        let mut stream = GcodeStream::new(gcode)
+            .with_arc_tolerance(params.arc_tolerance)
+            .with_cancel(&params.cancel);
+        let mut events = filter_events(&mut stream, &mut filters)?;
+        purge::tag(&mut events);
+        let parser = stream.parser().clone();
+
+        // purge lines and skirts are found from the whole first layer, and their ends
+        let purge = events
+            .iter()
+            .filter(|(_, e)| matches!(e, Event::Feature(f) if f.is_empty() || purge::is_purge_or_skirt(f)))
+            .map(|(line, _)| *line)
+            .collect::<Vec<_>>();
+        let whole_file = format!(
+            "{:?} {:?} {:?}",
+            cursor.layers,
+            sim.colors.as_ref().map(|c| &c.mixer),
+            purge
+        );
+
+        let estimate = Estimate::scan(gcode, usize::MAX);
+        if let Some([lo, hi]) = estimate.bounds {
+            let blocks = estimate.volume() / (UNIT * UNIT * UNIT);
+            sim.voxel
+                .reserve(&BoundingBox::of_mm(lo, hi), blocks as usize);
+        }
+
+        Ok(Self {
+            events,
+            parser,
+            filters,
+            sim,
+            cursor,
+            checkpoints: VecDeque::new(),
+            keep: 0,
+            whole_file: metadata::hash_bytes(whole_file.as_bytes()),
+        })
+    }
+
+    /// Hash of what the simulation reads from the whole gcode, rather than from lines
+    /// before each event: whether it has layer comments, the filament palette, and purge
+    /// lines and skirts found without comments. Models after the same lines are the same
+    /// only if this is too.
+    pub fn whole_file_hash(&self) -> u64 {
+        self.whole_file
+    }
+
+    /// Keeps checkpoints of the last `keep` layer changes, none by default. Voxels of
+    /// checkpoints share chunks until they change, but each layer usually changes every
+    /// chunk under it, so each checkpoint costs up to a copy of the model.
+    pub fn keep_checkpoints(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Layers of checkpoints, oldest first.
+    pub fn checkpoints(&self) -> Vec<usize> {
+        self.checkpoints
+            .iter()
+            .filter_map(|(_, cursor)| cursor.pending)
+            .collect()
+    }
+
+    /// Restores the checkpoint before `layer`, and drops checkpoints after it.
+    pub fn rewind(&mut self, layer: usize) -> Result<()> {
+        let Some(i) = self
+            .checkpoints
+            .iter()
+            .position(|(_, cursor)| cursor.pending == Some(layer))
+        else {
+            anyhow::bail!(
+                "no checkpoint before layer {}, checkpoints: {:?}",
+                layer,
+                self.checkpoints()
+            );
+        };
+        self.checkpoints.truncate(i + 1);
+        let (sim, cursor) = self.checkpoints[i].clone();
+        self.sim = sim;
+        self.cursor = cursor;
+        Ok(())
+    }
+
+    /// Takes a checkpoint, if the last run stopped before a layer. Checkpoints taken
+    /// here are evicted like others, oldest first.
+    pub fn checkpoint(&mut self) {
+        if self.cursor.pending.is_some() {
+            self.checkpoints
+                .push_back((self.sim.clone(), self.cursor.clone()));
+        }
+    }
+
+    /// Appends lines to the gcode, for files which are still being written. Runs
+    /// which stopped at the end of the gcode continue from there with the next call.
+    pub fn extend(&mut self, gcode: &str) -> Result<()> {
+        let mut stream = GcodeStream::resume(gcode, self.parser.clone());
+        let events = filter_events(&mut stream, &mut self.filters)?;
+        self.events.extend(events);
+        self.parser = stream.parser().clone();
+        self.cursor.layers.extend(gcode);
+        Ok(())
+    }
+
+    /// Index of the layer being simulated.
+    pub fn layer(&self) -> usize {
+        self.cursor.current_layer
+    }
+
+    pub fn simulation(&self) -> &Simulation<V> {
+        &self.sim
+    }
+
+    pub fn simulation_mut(&mut self) -> &mut Simulation<V> {
+        &mut self.sim
+    }
+
+    pub fn into_simulation(self) -> Simulation<V> {
+        self.sim
+    }
+
+    /// Simulates until `layer`, or the end of gcode. Stopped runs continue with the
+    /// next call, with `params` of that call.
+    pub fn run<O: Observer<V>>(
+        &mut self,
+        layer: usize,
+        params: &Params,
+        observer: &mut O,
+    ) -> Result<()> {
+        anyhow::ensure!(params.step_size > 0f32, "step size must be positive");
+
+        let sw = Stopwatch::start_new();
+        let sim = &mut self.sim;
+        let c = &mut self.cursor;
+
+        loop {
+            if let Some(layer_idx) = c.pending {
+                if layer_idx == layer {
+                    break;
+                }
+                // cloned in place, so borrows of the simulation and the cursor end here
+                if self.keep > 0 {
+                    while self.checkpoints.len() >= self.keep {
+                        self.checkpoints.pop_front();
+                    }
+                    self.checkpoints.push_back((sim.clone(), c.clone()));
+                }
+                c.pending = None;
+                sim.nozzle = c.pos;
+                if params.paranoid {
+                    if let Err(e) = invariant::check(&sim.voxel) {
+                        panic!("invariant violated after layer {}: {}", layer_idx, e);
+                    }
+                }
+                observer.on_layer_complete(sim, layer_idx)?;
+            }
+            if params.cancel.is_cancelled() {
+                sim.cancelled = true;
+                break;
+            }
+
+            let Some((line, event)) = self.events.get(c.event) else {
+                // the toolhead stops at the end, unless gcode is appended later
+                sim.time += c.motion.stop(params.acceleration);
+                break;
+            };
+            c.event += 1;
+            sim.line = *line;
+            match event {
+                Event::Comment(_) | Event::Object(_) => (),
+                Event::Feature(ty) => {
+                    deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+                    c.feature = ty.clone();
+                    c.perimeter.reset();
+                }
+                Event::LayerChange(layer_idx) => {
+                    deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+                    c.perimeter.reset();
+                    c.current_layer = *layer_idx;
+                    if let Some(spaghetti) = &mut sim.spaghetti {
+                        spaghetti.set_layer(c.current_layer);
+                    }
+                    if *layer_idx > 0 {
+                        c.pending = Some(*layer_idx);
+                    }
+                }
+                Event::ToolChange(tool) => {
+                    deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+                    if let Some(colors) = &mut sim.colors {
+                        colors.mixer.tool_change(*tool);
+                    }
+                }
+                Event::Dwell(seconds) => {
+                    sim.time += c.motion.stop(params.acceleration) + seconds;
+                    sim.idle += seconds;
+                }
+                Event::Temperature { bed, target, wait } => {
+                    if *wait {
+                        sim.time += c.motion.stop(params.acceleration);
+                        let seconds = c.heaters.wait(*bed, *target, sim.time);
+                        sim.time += seconds;
+                        sim.idle += seconds;
+                    } else {
+                        c.heaters.set(*bed, *target, sim.time);
+                    }
+                }
+                Event::Travel(words) => {
+                    deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+                    c.perimeter.reset();
+                    let dst = words.apply(c.pos);
+                    if let Some(f) = words.f {
+                        c.feedrate = f;
+                    }
+                    if !c.guard.check(dst) {
+                        debug!("dropped travel to {:?}", dst);
+                        continue;
+                    }
+                    let speed = c.feedrate / 60f32;
+                    sim.time += c.motion.advance(c.pos, dst, speed, params.acceleration);
+                    c.pos = dst;
+                }
+                Event::Move(words) => {
+                    let dst = words.apply(c.pos);
+                    let dst_e = words.e.unwrap_or(c.e);
+                    if let Some(f) = words.f {
+                        c.feedrate = f;
+                    }
+                    // the move was read up to its layer change before
+                    let resumed = std::mem::take(&mut c.in_move);
+                    if !resumed {
+                        if !c.guard.check(dst) || !dst_e.is_finite() {
+                            // filament is still used, but nothing is deposited
+                            debug!("dropped move to {:?}", dst);
+                            deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+                            c.perimeter.reset();
+                            if dst_e.is_finite() {
+                                c.e = dst_e;
+                            }
+                            continue;
+                        }
+                        let speed = c.feedrate / 60f32;
+                        sim.time += c.motion.advance(c.pos, dst, speed, params.acceleration);
+                        if dst_e > c.e {
+                            if let Some(layer_idx) = c.layers.extrude(dst[2]) {
+                                deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+                                c.perimeter.reset();
+                                c.current_layer = layer_idx;
+                                if let Some(spaghetti) = &mut sim.spaghetti {
+                                    spaghetti.set_layer(c.current_layer);
+                                }
+                                c.pending = Some(layer_idx);
+                                c.in_move = true;
+                                c.event -= 1;
+                                continue;
+                            }
+                        }
+                    }
+                    if params.exclude_purge && purge::is_purge_or_skirt(&c.feature) {
+                        // filament is used, but nothing is deposited
+                        deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+                        c.perimeter.reset();
+                        c.pos = dst;
+                        c.e = dst_e;
+                        continue;
+                    }
+                    if dst_e <= c.e {
+                        deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+                        if dst.xy() != c.pos.xy() {
+                            c.perimeter.reset();
+                        }
+                        c.pos = dst;
+                        continue;
+                    }
+
+                    let height = c.layer_height.extrude(c.current_layer, dst[2]);
+                    if height != sim.layer_height {
+                        deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+                        sim.layer_height = height;
+                    }
+                    let segment = Segment {
+                        from: c.pos,
+                        to: dst,
+                        layer: c.current_layer,
+                    };
+                    sim.nozzle = c.pos;
+                    observer.on_segment(sim, &segment)?;
+                    sim.segments.push(segment);
+
+                    // in centimeters
+                    let delta_e = (dst_e - c.e) as f32;
+
+                    // flow rate calculation
+                    // block volume in cubic millimeters
+                    let block_volume = UNIT * UNIT * UNIT;
+
+                    // calculate filament volume, in millimeters
+                    let filament_cross_section =
+                        0.25f32 * std::f32::consts::PI * FILAMENT_DIAMETER * FILAMENT_DIAMETER;
+                    let filament_volume = delta_e * filament_cross_section;
+
+                    // TODO: accurate volume calculation
+                    let total_blocks = filament_volume / block_volume * params.flow;
+
+                    if let Some(ringing) = &mut sim.ringing {
+                        ringing.set_speed(c.feedrate / 60f32);
+                    }
+                    c.path.push(c.pos, dst, total_blocks, c.feedrate / 60f32);
+                    let closed = if params.seams && seam::is_perimeter(&c.feature) {
+                        c.perimeter.extrude(c.pos, dst)
+                    } else {
+                        None
+                    };
+                    // moves shorter than a step are merged, so dense tiny segments (fuzzy
+                    // skin, arcs split by the slicer) deposit like a single move
+                    let merge_length = params.merge_length.max(params.step_size);
+                    if c.path.len() >= merge_length || closed.is_some() {
+                        deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+                    }
+                    if let Some(closed) = closed {
+                        seam(sim, closed, params);
+                    }
+
+                    c.pos = dst;
+                    c.e = dst_e;
+                }
+            }
+        }
+        deposit(sim, &mut c.path, params, &c.feature, &mut c.budget);
+        sim.nozzle = c.pos;
+        if params.paranoid {
+            if let Err(e) = invariant::check(&sim.voxel) {
+                panic!("invariant violated at the end: {}", e);
+            }
+        }
+
+        let blocks = sim.voxel.blocks();
+        info!(
+            "voxel construction: took={}ms, blocks={}/{}, bps={}",
+            sw.elapsed_ms(),
+            blocks,
+            sim.voxel.ranges(),
+            blocks * 1000 / sw.elapsed_ms().max(1) as usize
+        );
+
+        info!("bounding box: {:?}", sim.voxel.bounding_box());
+        if c.guard.dropped() > 0 {
+            warn!(
+                "dropped {} moves beyond coordinate limit {}mm",
+                c.guard.dropped(),
+                params.coordinate_limit
+            );
+        }
+        if params.seams {
+            info!("seams: {}", sim.seams.len());
+        }
+        if sim.cancelled {
+            warn!("cancelled, at {:.1}s of print time", sim.time);
+        }
+
+        Ok(())
+    }
+}
+
+/// File format of exported models.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Format {
+    #[default]
+    Obj,
+    /// binary stl, without groups and colors
+    Stl,
+    /// binary ply, with colors and per-vertex attributes like build order
+    Ply,
+    /// binary gltf of indexed triangles, with colors, for web viewers
+    Glb,
+    /// 3MF with units, for slicers, without groups and colors
+    ThreeMf,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "obj" => Ok(Self::Obj),
+            "stl" => Ok(Self::Stl),
+            "ply" => Ok(Self::Ply),
+            "glb" => Ok(Self::Glb),
+            "3mf" => Ok(Self::ThreeMf),
+            _ => Err(format!(
+                "unknown format: {}, expected one of obj, stl, ply, glb, 3mf",
+                s
+            )),
+        }
+    }
+}
+
+impl Format {
+    /// File name extension of exports, also the name of the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Obj => "obj",
+            Self::Stl => "stl",
+            Self::Ply => "ply",
+            Self::Glb => "glb",
+            Self::ThreeMf => "3mf",
+        }
+    }
+}
+
+/// Export options of gcode subcommands.
+#[derive(Clone, Debug)]
+pub struct Output {
+    pub mode: ExportMode,
+    pub format: Format,
+    pub units: Units,
+    pub precision: usize,
+    pub shrinkage: Shrinkage,
+    /// toolhead drawn at the nozzle, as the `toolhead` group
+    pub toolhead: bool,
+}
+
+/// Frames written within layers by `generate_gcode`, besides the frame of each layer.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SubFrames {
+    /// after every number of extrusion segments
+    Segments(usize),
+    /// after every number of seconds of print time
+    Seconds(f32),
+}
+
+impl std::str::FromStr for SubFrames {
+    type Err = anyhow::Error;
+
+    /// `50` for segments, `10s` for seconds.
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(seconds) = s.strip_suffix('s') {
+            let seconds = seconds
+                .parse::<f32>()
+                .map_err(|e| anyhow::anyhow!("sub-frames {}: {}", s, e))?;
+            anyhow::ensure!(seconds > 0f32, "sub-frames {}: must be positive", s);
+            return Ok(Self::Seconds(seconds));
+        }
+        let segments = s.parse::<usize>().map_err(|e| {
+            anyhow::anyhow!("sub-frames {}: expected segments or seconds, {}", s, e)
+        })?;
+        anyhow::ensure!(segments > 0, "sub-frames {}: must be positive", s);
+        Ok(Self::Segments(segments))
+    }
+}
+
+/// Idle periods without motion, like heat-up waits and dwells, in frames paced by print
+/// time.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Idle {
+    /// frame clocks run through idle periods, so frames paced by them show the model
+    /// unchanged for as long
+    #[default]
+    Keep,
+    /// idle periods count as at most this many seconds in frame clocks
+    Compress(f32),
+    /// frames of the model before idle periods repeat through them, every
+    /// `SubFrames::Seconds`; the model is written once, and listed again
+    Hold,
+}
+
+impl std::str::FromStr for Idle {
+    type Err = anyhow::Error;
+
+    /// `keep`, `hold`, or `compress:5` to keep at most 5 seconds of idle periods.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "keep" => Ok(Self::Keep),
+            None if s == "hold" => Ok(Self::Hold),
+            None if s == "compress" => Ok(Self::Compress(0f32)),
+            Some(("compress", seconds)) => {
+                let seconds = seconds
+                    .trim_end_matches('s')
+                    .parse::<f32>()
+                    .map_err(|e| anyhow::anyhow!("idle {}: {}", s, e))?;
+                anyhow::ensure!(seconds >= 0f32, "idle {}: must not be negative", s);
+                Ok(Self::Compress(seconds))
+            }
+            _ => anyhow::bail!("idle {}: expected keep, hold or compress:seconds", s),
+        }
+    }
+}
+
+/// When `generate_gcode` writes frames besides the frame of each layer.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Pacing {
+    pub sub_frames: Option<SubFrames>,
+    pub idle: Idle,
+}
+
+/// Edge of marker cubes, in voxels.
+const MARKER_SIZE: i32 = 24;
+
+/// Boxes of the toolhead drawn into exported models, in millimeters from the nozzle tip:
+/// nozzle, heater block, and heat sink with its fan.
+const TOOLHEAD: [([f32; 3], [f32; 3]); 4] = [
+    ([-0.5, -0.5, 0.0], [0.5, 0.5, 1.5]),
+    ([-3.5, -3.5, 1.5], [3.5, 3.5, 4.5]),
+    ([-8.0, -6.0, 4.5], [12.0, 6.0, 14.5]),
+    ([-15.0, -15.0, 14.5], [15.0, 15.0, 45.0]),
+];
+
+/// Metadata embedded in models exported by `export_model`.
+fn export_metadata(mut meta: Metadata, output: &Output) -> Metadata {
+    if !output.shrinkage.is_none() {
+        meta.set("shrinkage", output.shrinkage.describe());
+    }
+    meta
+}
+
+/// Writes the model of `sim` to `filename`, with the bed center at the origin. Returns
+/// the hash of the written model. Full obj models without colors, boxes or build order
+/// are streamed by `obj::write_obj_streamed`, so large prints fit in memory.
+pub fn export_model<V: Voxel>(
+    sim: &Simulation<V>,
+    output: &Output,
+    meta: Metadata,
+    filename: &str,
+) -> Result<u64> {
+    // bed center at origin
+    let k = output.units.per_mm();
+    let offset = [-90f32 * k, -90f32 * k, 0f32];
+    let bb = sim.voxel.bounding_box();
+    let position = |idx: VoxelIdx| {
+        let p = output.shrinkage.apply([0, 1, 2].map(|i| idx[i] as f32), bb);
+        [0, 1, 2].map(|i| p[i] * UNIT * k + offset[i])
+    };
+
+    let streamed = output.format == Format::Obj
+        && output.mode == ExportMode::Full
+        && sim.colors.is_none()
+        && sim.markers.is_empty()
+        && !output.toolhead
+        && sim.order.is_none();
+    if streamed {
+        let sw = Stopwatch::start_new();
+        let meta = export_metadata(meta, output);
+        let mut w = metadata::HashWriter::new(storage::create(filename)?);
+        let faces = obj::write_obj_streamed(
+            &sim.voxel,
+            &sim.tags,
+            &meta,
+            &mut w,
+            output.precision,
+            position,
+        )?;
+        let hash = w.hash();
+        w.into_inner().finish()?;
+        info!(
+            "obj::write_obj_streamed: took={}ms, filename={}, faces={}, units={}",
+            sw.elapsed_ms(),
+            filename,
+            faces,
+            output.units.name()
+        );
+        return Ok(hash);
+    }
+
+    let sw = Stopwatch::start_new();
+    let mut model = surface::to_model(&sim.voxel, output.mode);
+    sim.tags.group(&mut model, &sim.voxel);
+    if let Some(colors) = &sim.colors {
+        colors.paint(&mut model, &sim.voxel);
+    }
+    for marker in &sim.markers {
+        let c = to_intpos([marker.pos[0], marker.pos[1], marker.pos[2]]);
+        let half = VoxelIdx::new([MARKER_SIZE / 2; 3]);
+        model.add_box(c - half, c + half, &marker.name);
+    }
+    if output.toolhead {
+        for (lo, hi) in TOOLHEAD {
+            let corner = |d: [f32; 3]| to_intpos([0, 1, 2].map(|i| sim.nozzle[i] + d[i]));
+            model.add_box(corner(lo), corner(hi), "toolhead");
+        }
+    }
+    if let Some(order) = &sim.order {
+        order.paint(&mut model, &sim.voxel, &sim.segments);
+    }
+    if output.format != Format::Obj {
+        surface::orient(&mut model, |c| sim.voxel.occupied(c));
+    }
+    model.metadata = export_metadata(meta, output);
+    info!("to_model: took={}ms", sw.elapsed_ms());
+
+    let sw = Stopwatch::start_new();
+    let hash = model.serialize_as(filename, output.format, output.precision, position)?;
+    info!(
+        "Model::serialize: took={}ms, filename={}, units={}",
+        sw.elapsed_ms(),
+        filename,
+        output.units.name()
+    );
+    Ok(hash)
+}
+
+/// Simulates gcode of `filename` into `voxel` until `layer`, and writes the model to `out_filename`,
+/// or models before each layer change into the `out_filename` directory if `out_layers`.
+/// With `Pacing::sub_frames`, models within layers are written between them too, as
+/// `gcode_{layer}_{n}.obj`, with the extension of `Output::format`; each is a full
+/// export, so frequent ones are slow without a `cache` of earlier runs. Frames are reused from the `cache` directory, if given.
+/// Returns the simulation, for reports.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_gcode<V, O>(
+    voxel: V,
+    filename: &str,
+    out_filename: &str,
+    layer: usize,
+    out_layers: bool,
+    pacing: Pacing,
+    output: &Output,
+    params: &Params,
+    cache: Option<&str>,
+    observer: &mut O,
+) -> Result<Simulation<V>>
+where
+    V: Voxel + Clone,
+    O: Observer<V>,
+{
+    struct Frames<'a, O> {
+        observer: &'a mut O,
+        out_filename: &'a str,
+        out_layers: bool,
+        output: &'a Output,
+        meta: &'a Metadata,
+        manifest: Manifest,
+        cache: Option<FrameCache>,
+        pacing: Pacing,
+        /// sub-frames written within the current layer, and segments and frame clock since
+        /// the last frame
+        sub_frame: usize,
+        segments: usize,
+        since: f32,
+        /// idle seconds of the simulation seen so far, and those left out of frame clocks
+        idle: f32,
+        compressed: f32,
+        /// blocks and print time of the last frame, for the flow since
+        last: (usize, f32),
+    }
+
+    impl<O> Frames<'_, O> {
+        fn write<V: Voxel>(
+            &mut self,
+            sim: &Simulation<V>,
+            name: String,
+            layer: usize,
+            meta: Metadata,
+        ) -> Result<Frame> {
+            let out_path = format!("{}/{}", self.out_filename, name);
+            let hash = match &mut self.cache {
+                Some(cache) => cache.export(sim, self.output, meta, &out_path)?,
+                None => export_model(sim, self.output, meta, &out_path)?,
+            };
+            self.segments = 0;
+            self.since = sim.time - self.compressed;
+            let blocks = sim.voxel.blocks();
+            let (last_blocks, last_time) = std::mem::replace(&mut self.last, (blocks, sim.time));
+            let flow = match sim.time - last_time {
+                dt if dt > 0f32 => blocks.saturating_sub(last_blocks) as f32 * UNIT.powi(3) / dt,
+                _ => 0f32,
+            };
+            Ok(Frame {
+                file: name,
+                layer,
+                time: sim.time,
+                clock: self.since,
+                z: sim.nozzle[2],
+                flow,
+                blocks,
+                hash,
+            })
+        }
+
+        /// Writes the model of `sim` as the next sub-frame of `layer`.
+        fn write_sub_frame<V: Voxel>(
+            &mut self,
+            sim: &Simulation<V>,
+            layer: usize,
+        ) -> Result<Frame> {
+            let n = self.sub_frame + 1;
+            let name = format!(
+                "gcode_{:03}_{:03}.{}",
+                layer,
+                n,
+                self.output.format.extension()
+            );
+            let meta = self.meta.with("layer", layer).with("sub_frame", n);
+            let frame = self.write(sim, name, layer, meta)?;
+            self.sub_frame = n;
+            Ok(frame)
+        }
+
+        /// Follows idle time of `sim` since the last segment or layer change, by `Idle`.
+        fn follow_idle<V: Voxel>(&mut self, sim: &Simulation<V>, layer: usize) -> Result<()>
+        where
+            O: Observer<V>,
+        {
+            let idle = sim.idle - self.idle;
+            self.idle = sim.idle;
+            if idle <= 0f32 {
+                return Ok(());
+            }
+            match (self.pacing.idle, self.pacing.sub_frames) {
+                (Idle::Compress(max), _) => self.compressed += (idle - max).max(0f32),
+                (Idle::Hold, Some(SubFrames::Seconds(seconds))) => {
+                    // frames due until now, all of the model before the idle period
+                    let mut held: Option<Frame> = None;
+                    while self.since + seconds <= sim.time - self.compressed {
+                        let clock = self.since + seconds;
+                        let frame = match held.take() {
+                            Some(frame) => frame,
+                            None => self.write_sub_frame(sim, layer)?,
+                        };
+                        let frame = Frame {
+                            time: clock + self.compressed,
+                            clock,
+                            ..frame
+                        };
+                        self.since = clock;
+                        self.push(sim, frame.clone())?;
+                        held = Some(frame);
+                    }
+                }
+                _ => (),
+            }
+            Ok(())
+        }
+
+        fn push<V: Voxel>(&mut self, sim: &Simulation<V>, frame: Frame) -> Result<()>
+        where
+            O: Observer<V>,
+        {
+            self.observer.on_frame(sim, &frame)?;
+            self.manifest.push(frame);
+            // rewritten on every frame, so interrupted runs still list their frames
+            self.manifest
+                .write(&format!("{}/manifest.json", self.out_filename))
+        }
+    }
+
+    impl<V: Voxel, O: Observer<V>> Observer<V> for Frames<'_, O> {
+        fn on_segment(&mut self, sim: &Simulation<V>, segment: &Segment) -> Result<()> {
+            self.observer.on_segment(sim, segment)?;
+            if !self.out_layers {
+                return Ok(());
+            }
+            self.follow_idle(sim, segment.layer)?;
+            let Some(sub_frames) = self.pacing.sub_frames else {
+                return Ok(());
+            };
+            self.segments += 1;
+            let due = match sub_frames {
+                SubFrames::Segments(n) => self.segments >= n,
+                SubFrames::Seconds(seconds) => sim.time - self.compressed - self.since >= seconds,
+            };
+            if !due {
+                return Ok(());
+            }
+            let frame = self.write_sub_frame(sim, segment.layer)?;
+            self.push(sim, frame)
+        }
+
+        fn on_layer_complete(&mut self, sim: &mut Simulation<V>, layer: usize) -> Result<()> {
+            self.observer.on_layer_complete(sim, layer)?;
+            if !self.out_layers {
+                return Ok(());
+            }
+            self.follow_idle(sim, layer)?;
+            let name = format!("gcode_{:03}.{}", layer, self.output.format.extension());
+            let meta = self.meta.with("layer", layer);
+            let frame = self.write(sim, name, layer, meta)?;
+            self.sub_frame = 0;
+            self.push(sim, frame)
+        }
+    }
+
+    let meta = Metadata::gcode(filename, params, output)?;
+    let gcode = read_gcode(filename)?;
+    let mut simulator = Simulator::with_voxel(voxel, &gcode, params)?;
+    let cache = match cache {
+        Some(dir) if out_layers => Some(FrameCache::new(
+            dir,
+            &gcode,
+            simulator.whole_file_hash(),
+            params,
+            output,
+        )?),
+        _ => None,
+    };
+    let mut frames = Frames {
+        observer,
+        out_filename,
+        out_layers,
+        output,
+        meta: &meta,
+        manifest: Manifest::new(&meta),
+        cache,
+        pacing,
+        sub_frame: 0,
+        segments: 0,
+        since: 0f32,
+        idle: 0f32,
+        compressed: 0f32,
+        last: (0, 0f32),
+    };
+    simulator.run(layer, params, &mut frames)?;
+    let sim = simulator.into_simulation();
+    if let Some(cache) = &frames.cache {
+        info!("frame cache: {} frames reused", cache.hits());
+    }
+
+    if !out_layers {
+        export_model(&sim, output, meta, out_filename)?;
+    }
+
+    Ok(sim)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_simulator_rewind() {
+        // layers from Z, changed in the middle of extrusion moves
+        let gcode = "G1 X10 Y10 Z0.2\nG1 X20 Y10 E0.1\nG1 X20 Y20 Z0.4 E0.2\n\
+            G1 X10 Y20 Z0.6 E0.3\nG1 X10 Y10 Z0.8 E0.4\n";
+        let params = Params::default();
+        let full =
+            simulate::<MonotonicVoxel, _>(gcode, usize::MAX, &params, |_, _| Ok(())).unwrap();
+
+        let mut simulator = Simulator::<MonotonicVoxel>::new(gcode, &params)
+            .unwrap()
+            .keep_checkpoints(2);
+        simulator.run(2, &params, &mut ()).unwrap();
+        simulator.run(usize::MAX, &params, &mut ()).unwrap();
+        let sim = simulator.simulation();
+        assert_eq!(sim.voxel.blocks(), full.voxel.blocks());
+        assert_eq!(sim.segments.len(), full.segments.len());
+        assert_eq!(sim.nozzle, Vector3::new(10.0, 10.0, 0.8));
+        assert_eq!(simulator.checkpoints(), vec![2, 3]);
+        assert!(simulator.rewind(1).is_err());
+
+        simulator.rewind(2).unwrap();
+        assert_eq!(simulator.checkpoints(), vec![2]);
+        assert_eq!(simulator.simulation().segments.len(), 2);
+        simulator.run(usize::MAX, &params, &mut ()).unwrap();
+        assert_eq!(simulator.simulation().voxel.blocks(), full.voxel.blocks());
+
+        let mut bridges = params.clone();
+        bridges.set("bridges", "true").unwrap();
+        assert!(bridges.set("nozzle", "0.6").is_err());
+        simulator.rewind(2).unwrap();
+        simulator.run(usize::MAX, &bridges, &mut ()).unwrap();
+        assert_eq!(simulator.simulation().segments.len(), full.segments.len());
+
+        // the rest of the gcode appended later, like a file which is still written
+        let (head, tail) = gcode.split_at(gcode.find("G1 X10 Y20").unwrap());
+        let mut simulator = Simulator::<MonotonicVoxel>::new(head, &params).unwrap();
+        simulator.run(usize::MAX, &params, &mut ()).unwrap();
+        assert_eq!(simulator.layer(), 1);
+        simulator.extend(tail).unwrap();
+        simulator.run(usize::MAX, &params, &mut ()).unwrap();
+        assert_eq!(simulator.simulation().voxel.blocks(), full.voxel.blocks());
+        assert_eq!(simulator.layer(), 3);
+    }
+
+    #[test]
+    pub fn test_budget_carry() {
+        let params = Params::default();
+        // moves along X of the given blocks each, with absolute E
+        let blocks = |moves: &[f32]| {
+            let mut gcode = "G1 X10 Y10 Z0.2 F600\n".to_owned();
+            let mut e = 0f32;
+            for (i, blocks) in moves.iter().enumerate() {
+                e += blocks / blocks_per_filament(&params);
+                gcode += &format!("G1 X{} E{:.9}\n", 11 + i, e);
+            }
+            let sim = simulate::<MonotonicVoxel, _>(&gcode, usize::MAX, &params, |_, _| Ok(()));
+            sim.unwrap().voxel.blocks()
+        };
+        // 10 steps of 0.35 blocks, none of which deposits a whole block alone
+        assert_eq!(blocks(&[3.5]), 3);
+        // moves of less than a block, until the carry adds up to one
+        assert_eq!(blocks(&[0.45]), 0);
+        assert_eq!(blocks(&[0.45; 2]), 0);
+        assert_eq!(blocks(&[0.45; 3]), 1);
+        assert_eq!(blocks(&[0.45; 5]), 2);
+    }
+
+    #[test]
+    pub fn test_deposited_volume() {
+        let params = Params::default();
+        // 0.8mm of filament along a 20mm line, in one move or in moves shorter than a step
+        let expected = 0.8f32 * blocks_per_filament(&params);
+        let one = "G1 X10 Y10 Z0.2 F600\nG1 X30 E0.8\n".to_owned();
+        let mut short = "G1 X10 Y10 Z0.2 F600\n".to_owned();
+        for i in 1..=400 {
+            short += &format!(
+                "G1 X{:.2} E{:.4}\n",
+                10f32 + i as f32 * 0.05,
+                i as f32 * 0.002
+            );
+        }
+        for gcode in [one, short] {
+            let sim = simulate::<MonotonicVoxel, _>(&gcode, usize::MAX, &params, |_, _| Ok(()));
+            let blocks = sim.unwrap().voxel.blocks() as f32;
+            assert!(
+                (blocks - expected).abs() <= 1f32,
+                "{} != {}",
+                blocks,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_sub_frames() {
+        assert_eq!("50".parse::<SubFrames>().unwrap(), SubFrames::Segments(50));
+        assert_eq!(
+            "2.5s".parse::<SubFrames>().unwrap(),
+            SubFrames::Seconds(2.5)
+        );
+        assert!("0".parse::<SubFrames>().is_err() && "s".parse::<SubFrames>().is_err());
+
+        let dir = std::env::temp_dir().join(format!("tdp-tl-frames-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let output = Output {
+            mode: ExportMode::Full,
+            format: Format::Obj,
+            units: Units::Millimeter,
+            precision: 2,
+            shrinkage: Shrinkage::default(),
+            toolhead: false,
+        };
+        let run_as = |name: &str, gcode: &str, pacing: Pacing, format: Format| {
+            let out = format!("{}/{}", dir, name);
+            std::fs::create_dir_all(&out).unwrap();
+            let input = format!("{}/{}.gcode", dir, name);
+            std::fs::write(&input, gcode).unwrap();
+            generate_gcode(
+                MonotonicVoxel::default(),
+                &input,
+                &out,
+                usize::MAX,
+                true,
+                pacing,
+                &Output {
+                    format,
+                    ..output.clone()
+                },
+                &Params::default(),
+                None,
+                &mut (),
+            )
+            .unwrap();
+            let mut files = std::fs::read_dir(&out)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .filter(|f| !f.ends_with(".json"))
+                .collect::<Vec<_>>();
+            files.sort();
+            let manifest = std::fs::read_to_string(format!("{}/manifest.json", out)).unwrap();
+            let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+            (files, manifest["frames"].as_array().unwrap().clone())
+        };
+        let run =
+            |name: &str, gcode: &str, pacing: Pacing| run_as(name, gcode, pacing, Format::Obj);
+        let square = ";LAYER:0\nG1 X10 Y10 Z0.2 F600\nG1 X20 Y10 E0.1\nG1 X20 Y20 E0.2\n\
+            G1 X10 Y20 E0.3\nG1 X10 Y10 E0.4\n;LAYER:1\nG1 Z0.4\nG1 X20 Y10 E0.5\n";
+        let pacing = |sub_frames: &str, idle: Idle| Pacing {
+            sub_frames: Some(sub_frames.parse().unwrap()),
+            idle,
+        };
+
+        // after every second segment of the first layer, and in order with layer frames
+        let (files, frames) = run("segments", square, pacing("2", Idle::Keep));
+        let listed = frames.iter().map(|f| f["file"].as_str().unwrap());
+        assert_eq!(files, listed.collect::<Vec<_>>());
+        assert_eq!(
+            files,
+            ["gcode_000_001.obj", "gcode_000_002.obj", "gcode_001.obj"]
+        );
+        let blocks = frames.iter().map(|f| f["blocks"].as_u64().unwrap());
+        let blocks = blocks.collect::<Vec<_>>();
+        assert!(blocks[0] > 0 && blocks[0] < blocks[1] && blocks[1] <= blocks[2]);
+
+        // over a minute of heating the bed and the hotend before the first layer
+        let heated = format!("M140 S60\nM104 S205\nM190 S60\nM109 S205\n{}", square);
+        let clock = |f: &serde_json::Value| f["clock"].as_f64().unwrap();
+        let (files, frames) = run("hold", &heated, pacing("10s", Idle::Hold));
+        let held = frames
+            .iter()
+            .take_while(|f| f["file"] == "gcode_000_001.obj");
+        let held = held.map(clock).collect::<Vec<_>>();
+        assert!(held.len() >= 7 && files.len() <= 3);
+        assert!(held.windows(2).all(|w| (w[1] - w[0] - 10.0).abs() < 1e-3));
+
+        let (_, frames) = run("compress", &heated, pacing("10s", Idle::Compress(5.0)));
+        let time = frames[0]["time"].as_f64().unwrap();
+        assert!(time > 70.0 && clock(&frames[0]) < 15.0);
+        assert!(frames
+            .iter()
+            .all(|f| f["time"].as_f64().unwrap() - clock(f) > 60.0));
+
+        // frames in the export format
+        let (files, _) = run_as("stl", square, pacing("2", Idle::Keep), Format::Stl);
+        assert_eq!(
+            files,
+            ["gcode_000_001.stl", "gcode_000_002.stl", "gcode_001.stl"]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!("compress:5".parse::<Idle>().unwrap(), Idle::Compress(5.0));
+        assert!("hold:5".parse::<Idle>().is_err());
+    }
+}
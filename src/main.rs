@@ -1,21 +1,51 @@
 use anyhow::Result;
 use argh::FromArgs;
 use log::*;
-use std::fs::File;
 use stopwatch::Stopwatch;
 
-mod voxelidx;
-use voxelidx::VoxelIdx;
-
-mod rangesetvoxel;
-use rangesetvoxel::RangeSetVoxel;
-
-mod monotonicvoxel;
-use monotonicvoxel::MonotonicVoxel;
+use tdp_tl::align::{self, Deviation};
+use tdp_tl::backend::{Backend, ByteSize, Estimate};
+use tdp_tl::balance::Balance;
+use tdp_tl::color::{parse_color, Rgb};
+use tdp_tl::diff::Diff;
+use tdp_tl::filter::FilterSpec;
+use tdp_tl::follow::Tail;
+use tdp_tl::inertia::MassProperties;
+use tdp_tl::metadata::Metadata;
+use tdp_tl::overlay::Overlay;
+use tdp_tl::project::read_gcode;
+use tdp_tl::schematic::{Blocks, PaletteBy};
+use tdp_tl::series::Series;
+use tdp_tl::srt::Subtitles;
+use tdp_tl::storage;
+use tdp_tl::surface::{self, ExportMode};
+use tdp_tl::sweep::{self, Axis};
+use tdp_tl::units::Units;
+#[cfg(feature = "vdb")]
+use tdp_tl::vdb;
+#[cfg(feature = "view")]
+use tdp_tl::view;
+use tdp_tl::vox::Vox;
+use tdp_tl::webhook::Webhook;
+use tdp_tl::{adhesion, infill, thickness};
+use tdp_tl::{anim, dataset, eta, firstlayer, profile, render, schematic, section, stream, volume};
+use tdp_tl::{export_model, generate_gcode, inject_at, simulate_gcode, simulate_into};
+use tdp_tl::{AnyVoxel, Idle, MonotonicVoxel, Pacing, Registry, Simulator, SubFrames, Voxel};
+use tdp_tl::{Cancel, Deposition, Depth, Filament, Format, Model, Output, Params};
+use tdp_tl::{Shrinkage, Simulation};
 
 #[derive(FromArgs)]
 /// toplevel
 struct TopLevel {
+    /// merge results of parallel loops in a fixed order, so outputs are identical to
+    /// single-threaded runs
+    #[argh(switch)]
+    deterministic: bool,
+
+    /// endpoint url of s3:// outputs, like http://minio:9000, instead of AWS_ENDPOINT_URL
+    #[argh(option)]
+    s3_endpoint: Option<String>,
+
     #[argh(subcommand)]
     nested: SubCommandEnum,
 }
@@ -28,6 +58,24 @@ enum SubCommandEnum {
     DemoInject(DemoInject),
     Gcode(SubCommandGcode),
     GcodeLayers(SubCommandGcodeLayers),
+    FirstLayer(SubCommandFirstLayer),
+    RenderStill(SubCommandRenderStill),
+    Preview(SubCommandPreview),
+    ColumnStats(SubCommandColumnStats),
+    Eta(SubCommandEta),
+    Volume(SubCommandVolume),
+    VoxelExport(SubCommandVoxelExport),
+    Schematic(SubCommandSchematic),
+    Stream(SubCommandStream),
+    Sweep(SubCommandSweep),
+    Dataset(SubCommandDataset),
+    Diff(SubCommandDiff),
+    Align(SubCommandAlign),
+    ExplainParams(SubCommandExplainParams),
+    Adhesion(SubCommandAdhesion),
+    Infill(SubCommandInfill),
+    Thickness(SubCommandThickness),
+    View(SubCommandView),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -58,6 +106,10 @@ struct DemoSphere {
     /// output filename
     #[argh(option)]
     out: String,
+
+    /// output format: obj, stl, ply, glb, 3mf
+    #[argh(option, default = "Format::Obj")]
+    format: Format,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -69,165 +121,961 @@ struct DemoInject {
     out: String,
 }
 
+/// Declares a gcode subcommand with its own options, followed by the simulation and
+/// export options shared by gcode subcommands, and `params` and `output` from them.
+/// argh has no flattened structs, so the shared options are spliced into each.
+macro_rules! gcode_options {
+    ($(#[$attr:meta])* struct $name:ident { $($field:tt)* }) => {
+        $(#[$attr])*
+        struct $name {
+            $($field)*
+
+            /// voxel memory budget like 8G, the backend is chosen to fit in it
+            #[argh(option)]
+            memory_limit: Option<ByteSize>,
+
+            /// exported faces: full, top, silhouette
+            #[argh(option, default = "ExportMode::Full")]
+            mode: ExportMode,
+
+            /// exported file format: obj, stl, glb, 3mf, or ply with the layer and order in which
+            /// each vertex was deposited
+            #[argh(option, default = "Format::Obj")]
+            format: Format,
+
+            /// exported length units: mm, cm, m, inch
+            #[argh(option, default = "Units::Millimeter")]
+            units: Units,
+
+            /// decimal places of exported coordinates
+            #[argh(option, default = "2")]
+            precision: usize,
+
+            /// shrinkage after cooling of exported models in percent, p or x,y,z
+            #[argh(option, default = "Shrinkage::default()")]
+            shrink: Shrinkage,
+
+            /// extra XY shrinkage at the top of exported models in percent, for warp
+            #[argh(option, default = "0.0")]
+            warp: f32,
+
+            /// draw a simple toolhead at the nozzle into exported models, as its own group
+            #[argh(switch)]
+            toolhead: bool,
+
+            /// detect bridges, and tag them in exports
+            #[argh(switch)]
+            bridges: bool,
+
+            /// bridge sag depth relative to span length
+            #[argh(option, default = "0.02")]
+            bridge_sag: f32,
+
+            /// detect seams of perimeter loops, and tag them in exports
+            #[argh(switch)]
+            seams: bool,
+
+            /// emulate ringing after direction changes, with the toolhead resonating at this
+            /// frequency in hertz, 0 to disable
+            #[argh(option, default = "0.0")]
+            ringing_frequency: f32,
+
+            /// damping ratio of emulated ringing
+            #[argh(option, default = "0.05")]
+            ringing_damping: f32,
+
+            /// random sideways perturbation of outer walls like fuzzy skin, in millimeters,
+            /// 0 to disable
+            #[argh(option, default = "0.0")]
+            fuzzy_skin: f32,
+
+            /// distance between random points of fuzzy skin in millimeters
+            #[argh(option, default = "0.8")]
+            fuzzy_spacing: f32,
+
+            /// seed of random perturbations
+            #[argh(option, default = "0")]
+            seed: u32,
+
+            /// extra material deposited at each seam in cubic millimeters, implies --seams
+            #[argh(option, default = "0.0")]
+            seam_blob: f32,
+
+            /// air gap kept above support material in millimeters, 0 to disable
+            #[argh(option, default = "0.0")]
+            support_z_gap: f32,
+
+            /// depth of beads below the nozzle where voxels spread, like 1layer or 0.2mm
+            #[argh(option, default = "Depth::default()")]
+            bond_depth: Depth,
+
+            /// bead model, inject (material spreads to the nearest empty voxels, default),
+            /// ellipse (elliptical beads along moves), or a bead preset: oval-0.4x0.2,
+            /// oval-0.6x0.3, flat-0.4x0.2, flat-0.6x0.3, with :n=exponent for another
+            /// super-ellipse
+            #[argh(option, default = "Deposition::default()")]
+            deposit_model: Deposition,
+
+            /// rewrite gcode before simulating it, repeated to chain filters: flow=0.9 (scale
+            /// extrusion), drop-tool=1 (no extrusion from a tool), clog=onset[:flow[:ramp]] (flow
+            /// falls to 0 or flow over ramp seconds from onset, like 120s or layer20),
+            /// shift=onset:dx,dy[:every] (layer shift in millimeters, repeated every seconds or
+            /// layers), exclude=x,y;x,y;x,y[:layerN-M] (no extrusion inside the polygon, also
+            /// from a .geojson file)
+            #[argh(option)]
+            filter: Vec<FilterSpec>,
+
+            /// object of EXCLUDE_OBJECT_START labels cancelled from a layer, as name@layer,
+            /// repeated
+            #[argh(option, from_str_fn(parse_cancel_object))]
+            cancel_object: Vec<FilterSpec>,
+
+            /// the print detaches from the bed at this layer: later beads fall onto whatever is
+            /// below them and tangle, tagged as `spaghetti`
+            #[argh(option)]
+            detach_layer: Option<usize>,
+
+            /// leave purge lines, skirts and brims out of the simulation, also ones found
+            /// without ;TYPE: comments
+            #[argh(switch)]
+            exclude_purge: bool,
+
+            /// acceleration of the toolhead in mm/s^2 for print time, 0 to ignore it
+            #[argh(option, default = "0.0")]
+            acceleration: f32,
+
+            /// log the last N voxels added with their moves, dumped to stderr on panics
+            #[argh(option, default = "0")]
+            deposit_log: usize,
+
+            /// write the deposit log as csv to this file at the end
+            #[argh(option)]
+            deposit_log_out: Option<String>,
+
+            /// check invariants of the voxels after each layer, slow
+            #[argh(switch)]
+            paranoid: bool,
+
+            /// post JSON progress events to this http:// url: started, layer, finished, failed
+            #[argh(option)]
+            webhook: Option<String>,
+
+            /// maximum distance between deposition points along a move in millimeters
+            #[argh(option, default = "0.1")]
+            step_size: f32,
+
+            /// moves to coordinates beyond this distance from the origin in millimeters are
+            /// dropped
+            #[argh(option, default = "1000.0")]
+            coordinate_limit: f32,
+
+            /// largest distance of segments of G2 and G3 arcs from the arc in millimeters
+            #[argh(option, default = "0.01")]
+            arc_tolerance: f32,
+
+            /// merge consecutive moves into paths of this length in millimeters, 0 to disable
+            #[argh(option, default = "0.0")]
+            merge_length: f32,
+
+            /// track filament colors across tool changes, and export vertex colors
+            #[argh(switch)]
+            colors: bool,
+
+            /// color of each tool as #rrggbb, repeated in tool order, overrides slicer settings
+            #[argh(option, from_str_fn(parse_color))]
+            palette: Vec<Rgb>,
+
+            /// mixed filament after a tool change in cubic millimeters, if not set by the slicer
+            #[argh(option, default = "0.0")]
+            purge_volume: f32,
+        }
+
+        impl $name {
+            fn params(&self) -> Params {
+                Params {
+                    step_size: self.step_size,
+                    merge_length: self.merge_length,
+                    coordinate_limit: self.coordinate_limit,
+                    arc_tolerance: self.arc_tolerance,
+                    bridges: self.bridges,
+                    bridge_sag: self.bridge_sag,
+                    seams: self.seams || self.seam_blob > 0f32,
+                    seam_blob: self.seam_blob,
+                    ringing_frequency: self.ringing_frequency,
+                    ringing_damping: self.ringing_damping,
+                    fuzzy_skin: self.fuzzy_skin,
+                    fuzzy_spacing: self.fuzzy_spacing,
+                    seed: self.seed,
+                    support_z_gap: self.support_z_gap,
+                    colors: self.colors,
+                    palette: self.palette.clone(),
+                    purge_volume: self.purge_volume,
+                    features: false,
+                    cancel: Cancel::default(),
+                    flow: 1.0,
+                    spread_depth: self.bond_depth.voxels(),
+                    deposition: self.deposit_model.clone(),
+                    filters: self
+                        .filter
+                        .iter()
+                        .chain(&self.cancel_object)
+                        .cloned()
+                        .collect(),
+                    detach_layer: self.detach_layer,
+                    exclude_purge: self.exclude_purge,
+                    acceleration: self.acceleration,
+                    deposit_log: self.deposit_log,
+                    paranoid: self.paranoid,
+                    build_order: self.format == Format::Ply,
+                }
+            }
+
+            fn output(&self) -> Output {
+                Output {
+                    mode: self.mode,
+                    format: self.format,
+                    units: self.units,
+                    precision: self.precision,
+                    shrinkage: Shrinkage {
+                        warp: self.warp,
+                        ..self.shrink
+                    },
+                    toolhead: self.toolhead,
+                }
+            }
+        }
+    };
+}
+
+gcode_options! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    /// gcode to obj
+    #[argh(subcommand, name = "gcode")]
+    struct SubCommandGcode {
+        /// input filename
+        #[argh(option)]
+        gcode: String,
+
+        /// output filename
+        #[argh(option)]
+        out: String,
+
+        /// target number of layers
+        #[argh(option)]
+        layer: Option<usize>,
+
+        /// filament density in g/cm^3, for the mass report
+        #[argh(option, default = "1.24")]
+        density: f32,
+
+        /// filament price per kilogram, for the cost report
+        #[argh(option, default = "20.0")]
+        price: f32,
+
+        /// leave skirts and brims out of the mass report
+        #[argh(switch)]
+        no_skirt: bool,
+
+        /// leave supports out of the mass report
+        #[argh(switch)]
+        no_support: bool,
+
+        /// output json of mass, center of mass and inertia tensor of the whole part
+        #[argh(option)]
+        mass_properties: Option<String>,
+
+        /// output csv of bonded area between each layer and the one below
+        #[argh(option)]
+        sections: Option<String>,
+
+        /// simulate again from this layer with --set parameters, into --rewind-out
+        #[argh(option)]
+        rewind_to: Option<usize>,
+
+        /// parameter changed after --rewind-to as name=value, repeated, e.g. bridge_sag=0.05
+        #[argh(option, from_str_fn(parse_set))]
+        set: Vec<(String, String)>,
+
+        /// output filename of the simulation rewound with --rewind-to
+        #[argh(option)]
+        rewind_out: Option<String>,
+    }
+}
+
+fn parse_cancel_object(value: &str) -> Result<FilterSpec, String> {
+    format!("cancel-object={}", value)
+        .parse()
+        .map_err(|e: anyhow::Error| e.to_string())
+}
+
+fn parse_set(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) => Ok((key.trim().to_owned(), value.trim().to_owned())),
+        None => Err(format!("expected name=value, got {}", value)),
+    }
+}
+
+gcode_options! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    /// gcode layers to obj
+    #[argh(subcommand, name = "gcode-layers")]
+    struct SubCommandGcodeLayers {
+        /// input filename
+        #[argh(option)]
+        gcode: String,
+
+        /// output directory
+        #[argh(option)]
+        outdir: String,
+
+        /// use rangeset data structure, instead of choosing one from the gcode
+        #[argh(switch)]
+        rangeset: bool,
+
+        /// output csv of the center of mass of each layer, which is also marked in frames
+        #[argh(option)]
+        balance: Option<String>,
+
+        /// directory of cached obj frames, reused by reruns for layers before the first
+        /// changed layer of the gcode; those layers are still simulated, only not exported
+        #[argh(option)]
+        frame_cache: Option<String>,
+
+        /// also write frames within layers, after every number of extrusion segments like
+        /// `50`, or of seconds of print time like `10s`
+        #[argh(option)]
+        sub_frames: Option<SubFrames>,
+
+        /// idle periods like heat-up waits in frames paced by seconds: keep, hold to repeat
+        /// frames through them, or compress:5 to shorten them to 5 seconds of frame clocks
+        #[argh(option, default = "Idle::Keep")]
+        idle: Idle,
+
+        /// write an srt subtitle track of frame statistics to this file: layer, z height,
+        /// print time and flow, one frame after another at --fps
+        #[argh(option)]
+        subtitles: Option<String>,
+
+        /// write block count, bounding box and surface area after every frame to this file,
+        /// as json if it ends with .json, csv otherwise
+        #[argh(option)]
+        series: Option<String>,
+
+        /// frames per second of videos of the frames, for --subtitles
+        #[argh(option, default = "24.0")]
+        fps: f32,
+    }
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
-/// gcode to obj
-#[argh(subcommand, name = "gcode")]
-struct SubCommandGcode {
+/// first layer inspection: heightmap and coverage
+#[argh(subcommand, name = "first-layer")]
+struct SubCommandFirstLayer {
     /// input filename
     #[argh(option)]
     gcode: String,
 
-    /// output filename
+    /// output heightmap filename, 16-bit png in micrometers
+    #[argh(option)]
+    out: String,
+
+    /// nominal line width in millimeters
+    #[argh(option, default = "0.4")]
+    line_width: f32,
+
+    /// minimum reported gap area in square millimeters
+    #[argh(option, default = "0.04")]
+    min_gap: f32,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// ray traced still image of the simulated model
+#[argh(subcommand, name = "render-still")]
+struct SubCommandRenderStill {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// output png filename
     #[argh(option)]
     out: String,
 
     /// target number of layers
     #[argh(option)]
     layer: Option<usize>,
+
+    /// image width in pixels
+    #[argh(option, default = "1280")]
+    width: u32,
+
+    /// image height in pixels
+    #[argh(option, default = "960")]
+    height: u32,
+
+    /// camera angle around the model in degrees, counterclockwise from +X
+    #[argh(option, default = "-60.0")]
+    azimuth: f32,
+
+    /// camera angle above the bed in degrees
+    #[argh(option, default = "30.0")]
+    elevation: f32,
+
+    /// ambient occlusion rays per pixel, 0 to disable
+    #[argh(option, default = "16")]
+    ao_samples: usize,
+
+    /// fixed camera as x,y,z:x,y,z[:fov] in millimeters, its position and the point it
+    /// looks at, like a printer camera, instead of the orbit
+    #[argh(option)]
+    camera: Option<render::Camera>,
+
+    /// image region hidden by the toolhead in front of the fixed camera, as x0,y0,x1,y1
+    /// fractions of the image from the top left, repeated for more regions
+    #[argh(option)]
+    occlude: Vec<render::Region>,
+
+    /// transparent background instead of the sky and ground, for compositing
+    #[argh(switch)]
+    transparent: bool,
+
+    /// two views for 3D playback: sbs side by side, or anaglyph for red/cyan glasses
+    #[argh(option)]
+    stereo: Option<render::Stereo>,
+
+    /// also write 16-bit depth and normal pngs next to the image, as _depth.png in
+    /// hundredths of millimeters from the camera, and _normal.png
+    #[argh(switch)]
+    aovs: bool,
+
+    /// top-down view of the bed at true scale instead of the orbit, as millimeters per
+    /// pixel and optionally the bed position at the bottom left, like 0.2:10,10
+    #[argh(option)]
+    ortho: Option<render::Ortho>,
+
+    /// color voxels by filament colors across tool changes
+    #[argh(switch)]
+    colors: bool,
+
+    /// color of each tool as #rrggbb, repeated in tool order, overrides slicer settings
+    #[argh(option, from_str_fn(parse_color))]
+    palette: Vec<Rgb>,
+
+    /// emulate ringing after direction changes, with the toolhead resonating at this
+    /// frequency in hertz, 0 to disable
+    #[argh(option, default = "0.0")]
+    ringing_frequency: f32,
+
+    /// damping ratio of emulated ringing
+    #[argh(option, default = "0.05")]
+    ringing_damping: f32,
+
+    /// random sideways perturbation of outer walls like fuzzy skin, in millimeters,
+    /// 0 to disable
+    #[argh(option, default = "0.0")]
+    fuzzy_skin: f32,
+
+    /// distance between random points of fuzzy skin in millimeters
+    #[argh(option, default = "0.8")]
+    fuzzy_spacing: f32,
+
+    /// seed of random perturbations
+    #[argh(option, default = "0")]
+    seed: u32,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
-/// gcode layers to obj
-#[argh(subcommand, name = "gcode-layers")]
-struct SubCommandGcodeLayers {
+/// animated gif or apng preview of the print, a low resolution frame per layer
+#[argh(subcommand, name = "preview")]
+struct SubCommandPreview {
     /// input filename
     #[argh(option)]
     gcode: String,
 
-    /// output directory
+    /// output filename, .gif, or .png for apng
     #[argh(option)]
-    outdir: String,
+    out: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// frame width in pixels
+    #[argh(option, default = "320")]
+    width: u32,
+
+    /// frame height in pixels
+    #[argh(option, default = "240")]
+    height: u32,
+
+    /// frames per second
+    #[argh(option, default = "10.0")]
+    fps: f32,
+
+    /// layers per frame
+    #[argh(option, default = "1")]
+    every: usize,
+
+    /// ambient occlusion rays per pixel, 0 to disable
+    #[argh(option, default = "4")]
+    ao_samples: usize,
+
+    /// statistics burnt into frames, separated by commas: layer, z, time and progress
+    #[argh(option)]
+    overlay: Option<Overlay>,
+
+    /// color voxels by filament colors across tool changes
+    #[argh(switch)]
+    colors: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// print time estimated by the slicer against simulated time, by layer
+#[argh(subcommand, name = "eta")]
+struct SubCommandEta {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// acceleration of the toolhead in mm/s^2, like the printer config, 0 to ignore it
+    #[argh(option, default = "0.0")]
+    acceleration: f32,
+
+    /// output csv of the time of each layer, estimated and simulated
+    #[argh(option)]
+    csv: Option<String>,
+
+    /// number of layers with the largest differences to print
+    #[argh(option, default = "5")]
+    top: usize,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// ranges per column distribution, and worst columns
+#[argh(subcommand, name = "column-stats")]
+struct SubCommandColumnStats {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// number of worst columns to report
+    #[argh(option, default = "10")]
+    top: usize,
+
+    /// output heatmap filename, png of ranges per column
+    #[argh(option)]
+    heatmap: Option<String>,
 
     /// use rangeset data structure
     #[argh(switch)]
     rangeset: bool,
 }
 
-impl std::ops::Index<usize> for VoxelIdx {
-    type Output = i32;
+#[derive(FromArgs, PartialEq, Debug)]
+/// occupancy volume for external volume renderers
+#[argh(subcommand, name = "volume")]
+struct SubCommandVolume {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.idx[index]
-    }
+    /// output filename, nrrd for .nrrd, openvdb for .vdb with the vdb feature, otherwise
+    /// a raw bitmask with a json header
+    #[argh(option)]
+    out: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// occupied voxels as magicavoxel .vox models, without meshing
+#[argh(subcommand, name = "voxel-export")]
+struct SubCommandVoxelExport {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// output .vox filename
+    #[argh(option)]
+    out: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// use rangeset data structure
+    #[argh(switch)]
+    rangeset: bool,
+
+    /// color voxels by filament colors across tool changes, as palette entries
+    #[argh(switch)]
+    colors: bool,
 }
 
-#[derive(Default, Debug)]
-pub struct BoundingBox {
-    bound_min: VoxelIdx,
-    bound_max: VoxelIdx,
+#[derive(FromArgs, PartialEq, Debug)]
+/// minecraft schematic, a block per voxel
+#[argh(subcommand, name = "schematic")]
+struct SubCommandSchematic {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// output filename, litematica for .litematic, otherwise a sponge schematic for
+    /// worldedit
+    #[argh(option)]
+    out: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// blocks by: single, layer, or feature
+    #[argh(option, default = "PaletteBy::Feature")]
+    palette_by: PaletteBy,
 
-    count: usize,
+    /// block id, repeated for cycling layers or feature classes (wall-outer,
+    /// wall-inner, skin, infill, support, other)
+    #[argh(option)]
+    block: Vec<String>,
 }
 
-impl BoundingBox {
-    fn add(&mut self, coord: VoxelIdx) {
-        // first block
-        if self.count == 0 {
-            self.bound_min = coord;
-            self.bound_max = coord;
-        } else {
-            self.bound_min = coord.bb_min(&self.bound_min);
-            self.bound_max = coord.bb_max(&self.bound_max);
-        }
-        self.count += 1;
-    }
+#[derive(FromArgs, PartialEq, Debug)]
+/// progressive mesh stream for web viewers, appending changed chunks every layer
+#[argh(subcommand, name = "stream")]
+struct SubCommandStream {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// output directory, for stream.json and chunks.bin
+    #[argh(option)]
+    outdir: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// keep reading lines appended to the gcode, for files which are still written
+    #[argh(switch)]
+    follow: bool,
+
+    /// with --follow, stop after this many seconds without new lines
+    #[argh(option, default = "30.0")]
+    idle: f32,
 }
 
-// unit: 0.04mm, layer thickness: 0.2mm, nozzle size: 0.4mm
-// 20mm
-const UNIT: f32 = 0.04f32;
+#[derive(FromArgs, PartialEq, Debug)]
+/// runs gcode with every combination of parameter values, with a summary of metrics
+#[argh(subcommand, name = "sweep")]
+struct SubCommandSweep {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// output directory, for models and summary.csv
+    #[argh(option)]
+    outdir: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// values of a parameter as name=v1,v2,..., repeated for each axis of the grid, e.g.
+    /// step_size (resolution of deposition), flow, bond_depth (bead depth like 1layer)
+    #[argh(option)]
+    vary: Vec<Axis>,
 
-pub trait Voxel {
-    fn blocks(&self) -> usize;
-    fn ranges(&self) -> usize;
-    fn bounding_box(&self) -> &BoundingBox;
-    fn occupied(&self, coord: VoxelIdx) -> bool;
-    fn add(&mut self, coord: VoxelIdx) -> bool;
-    fn to_model(&self) -> Model;
+    /// write the summary only, without models
+    #[argh(switch)]
+    no_models: bool,
 }
 
-#[derive(Default)]
-pub struct Model {
-    vertices: indexmap::IndexSet<VoxelIdx>,
-    faces: Vec<[usize; 4]>,
+#[derive(FromArgs, PartialEq, Debug)]
+/// labeled frame sequences of every gcode file with every failure scenario, for training
+#[argh(subcommand, name = "dataset")]
+struct SubCommandDataset {
+    /// directory of input gcode files
+    #[argh(option)]
+    gcode_dir: String,
+
+    /// failure scenarios, one per line as name: failures, where failures are --filter
+    /// values or detach=layer, and a scenario without failures is a clean print
+    #[argh(option)]
+    spec: String,
+
+    /// output directory, with a directory of frames and sample.json per sample
+    #[argh(option)]
+    outdir: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// a frame every this many layers
+    #[argh(option, default = "1")]
+    every: usize,
+
+    /// rendered png frames instead of obj meshes
+    #[argh(switch)]
+    images: bool,
+
+    /// draw a simple toolhead at the nozzle into mesh frames, as its own group
+    #[argh(switch)]
+    toolhead: bool,
+
+    /// image width in pixels
+    #[argh(option, default = "640")]
+    width: u32,
+
+    /// image height in pixels
+    #[argh(option, default = "480")]
+    height: u32,
+
+    /// fixed camera of images as x,y,z:x,y,z[:fov] in millimeters, like a printer
+    /// camera, instead of the orbit
+    #[argh(option)]
+    camera: Option<render::Camera>,
+
+    /// image region hidden by the toolhead in front of the fixed camera, as x0,y0,x1,y1
+    /// fractions of the image from the top left, repeated for more regions
+    #[argh(option)]
+    occlude: Vec<render::Region>,
+
+    /// also write 16-bit depth and normal pngs of images, for training on them
+    #[argh(switch)]
+    aovs: bool,
+
+    /// seed of random perturbations, like spaghetti strands
+    #[argh(option, default = "0")]
+    seed: u32,
 }
 
-impl Model {
-    fn add_vert(&mut self, coord: VoxelIdx) -> usize {
-        let (idx, _) = self.vertices.insert_full(coord);
-        idx
-    }
+#[derive(FromArgs, PartialEq, Debug)]
+/// renders two runs from the same camera, and a heatmap of where deposition differs
+#[argh(subcommand, name = "diff")]
+struct SubCommandDiff {
+    /// input filename of run A
+    #[argh(option)]
+    gcode: String,
 
-    fn add_face(&mut self, coord: VoxelIdx, dir: VoxelIdx) {
-        let (i0, i1, i2, i3) = if dir[0] == 0 {
-            let i0 = self.add_vert(coord);
-            let i1 = self.add_vert(coord + dir.y());
-            let i2 = self.add_vert(coord + dir.yz());
-            let i3 = self.add_vert(coord + dir.z());
-            (i0, i1, i2, i3)
-        } else if dir[1] == 0 {
-            let i0 = self.add_vert(coord);
-            let i1 = self.add_vert(coord + dir.x());
-            let i2 = self.add_vert(coord + dir.xz());
-            let i3 = self.add_vert(coord + dir.z());
-            (i0, i1, i2, i3)
-        } else {
-            let i0 = self.add_vert(coord);
-            let i1 = self.add_vert(coord + dir.x());
-            let i2 = self.add_vert(coord + dir.xy());
-            let i3 = self.add_vert(coord + dir.y());
-            (i0, i1, i2, i3)
-        };
+    /// input filename of run B, the gcode of run A if not given
+    #[argh(option)]
+    gcode_b: Option<String>,
 
-        self.faces.push([i0, i1, i2, i3]);
-    }
+    /// parameter of run A as name=value, repeated
+    #[argh(option, from_str_fn(parse_set))]
+    set_a: Vec<(String, String)>,
 
-    fn add_cube(&mut self, coord: VoxelIdx) {
-        self.add_face(coord, [1, 1, 0].into());
-        self.add_face(coord, [1, 0, 1].into());
-        self.add_face(coord, [0, 1, 1].into());
+    /// parameter of run B as name=value, repeated
+    #[argh(option, from_str_fn(parse_set))]
+    set_b: Vec<(String, String)>,
 
-        let coord = coord + VoxelIdx::unit();
+    /// output directory, for a.png, b.png and diff.png
+    #[argh(option)]
+    outdir: String,
 
-        self.add_face(coord, [-1, -1, 0].into());
-        self.add_face(coord, [-1, 0, -1].into());
-        self.add_face(coord, [0, -1, -1].into());
-    }
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
 
-    fn merge(&mut self, other: Self) {
-        for [i0, i1, i2, i3] in other.faces {
-            let i0 = self.add_vert(other.vertices[i0]);
-            let i1 = self.add_vert(other.vertices[i1]);
-            let i2 = self.add_vert(other.vertices[i2]);
-            let i3 = self.add_vert(other.vertices[i3]);
-            self.faces.push([i0, i1, i2, i3]);
-        }
-    }
+    /// image width in pixels
+    #[argh(option, default = "1280")]
+    width: u32,
 
-    fn serialize(&self, path: &str, offset: [f32; 3], scale: f32) -> Result<()> {
-        use std::io::Write;
-
-        let w = File::create(path)?;
-        let mut w = std::io::BufWriter::new(w);
-
-        for idx in &self.vertices {
-            let x = idx[0];
-            let y = idx[1];
-            let z = idx[2];
-            write!(
-                &mut w,
-                "v {:.2} {:.2} {:.2}\n",
-                x as f32 * scale + offset[0],
-                y as f32 * scale + offset[1],
-                z as f32 * scale + offset[2]
-            )?;
-        }
-        for [i0, i1, i2, i3] in &self.faces {
-            write!(&mut w, "f {} {} {} {}\n", i0 + 1, i1 + 1, i2 + 1, i3 + 1)?;
-        }
+    /// image height in pixels
+    #[argh(option, default = "960")]
+    height: u32,
 
-        Ok(())
-    }
+    /// camera angle around the model in degrees, counterclockwise from +X
+    #[argh(option, default = "-60.0")]
+    azimuth: f32,
+
+    /// camera angle above the bed in degrees
+    #[argh(option, default = "30.0")]
+    elevation: f32,
+
+    /// ambient occlusion rays per pixel, 0 to disable
+    #[argh(option, default = "16")]
+    ao_samples: usize,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// aligns a 3d scan of the printed part to the simulation, and reports deviations
+#[argh(subcommand, name = "align")]
+struct SubCommandAlign {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// scan of the printed part in millimeters: obj, stl, ascii ply, or xyz points
+    #[argh(option)]
+    scan: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// cell size in millimeters, scan and model points are reduced to one per cell
+    #[argh(option, default = "0.2")]
+    cell: f32,
+
+    /// maximum number of alignment steps
+    #[argh(option, default = "50")]
+    iterations: usize,
+
+    /// output csv of aligned scan points and their deviation
+    #[argh(option)]
+    out: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// bed contact area of each part, with brims and rafts, and its adhesion risk
+#[argh(subcommand, name = "adhesion")]
+struct SubCommandAdhesion {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// output csv of contacts
+    #[argh(option)]
+    out: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// effective infill density of interior regions per band of layers, against the
+/// density declared by the slicer
+#[argh(subcommand, name = "infill")]
+struct SubCommandInfill {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// layers per band
+    #[argh(option, default = "10")]
+    band: usize,
+
+    /// declared infill density from 0 to 1, overrides slicer settings
+    #[argh(option)]
+    density: Option<f32>,
+
+    /// largest difference from the declared density before a region is flagged
+    #[argh(option, default = "0.1")]
+    tolerance: f32,
+
+    /// output csv of interior regions
+    #[argh(option)]
+    out: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// local wall thickness from the distance transform of each layer, with the thinnest
+/// spot of each part per band of layers
+#[argh(subcommand, name = "thickness")]
+struct SubCommandThickness {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// layers per band
+    #[argh(option, default = "10")]
+    band: usize,
+
+    /// walls thinner than this in millimeters are flagged, and red in the mesh
+    #[argh(option, default = "0.8")]
+    thin: f32,
+
+    /// output csv of parts per band
+    #[argh(option)]
+    out: Option<String>,
+
+    /// output obj colored by thickness, from red at --thin to green at twice that
+    #[argh(option)]
+    obj: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// prints every tunable parameter as json: value, unit, and the modules which read it
+#[argh(subcommand, name = "explain-params")]
+struct SubCommandExplainParams {
+    /// parameter as name=value, repeated, shown with its effective value
+    #[argh(option, from_str_fn(parse_set))]
+    set: Vec<(String, String)>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// interactive viewer, showing the model while it is simulated, or png frames of each
+/// layer without a window
+#[argh(subcommand, name = "view")]
+struct SubCommandView {
+    /// input filename
+    #[argh(option)]
+    gcode: String,
+
+    /// target number of layers
+    #[argh(option)]
+    layer: Option<usize>,
+
+    /// maximum distance between deposition points along a move in millimeters
+    #[argh(option, default = "0.1")]
+    step_size: f32,
+
+    /// render each layer into png files instead of a window, default if no display or
+    /// without the view feature
+    #[argh(switch)]
+    headless: bool,
+
+    /// output directory of headless frames
+    #[argh(option, default = "String::from(\"view\")")]
+    outdir: String,
+
+    /// headless frame width in pixels
+    #[argh(option, default = "1280")]
+    width: u32,
+
+    /// headless frame height in pixels
+    #[argh(option, default = "960")]
+    height: u32,
+
+    /// statistics burnt into headless frames, separated by commas: layer, z, time and
+    /// progress
+    #[argh(option)]
+    overlay: Option<Overlay>,
+
+    /// headless frames with a transparent background, for compositing
+    #[argh(switch)]
+    transparent: bool,
+
+    /// headless frames of two views for 3D playback, with the cpu ray tracer: sbs side
+    /// by side, or anaglyph for red/cyan glasses
+    #[argh(option)]
+    stereo: Option<render::Stereo>,
+
+    /// also write 16-bit depth and normal pngs of headless frames, with the cpu ray
+    /// tracer
+    #[argh(switch)]
+    aovs: bool,
+
+    /// headless frames of the bed from the top at true scale, with the cpu ray tracer,
+    /// as millimeters per pixel and optionally the bed position at the bottom left
+    #[argh(option)]
+    ortho: Option<render::Ortho>,
 }
 
 const SIZE: i32 = 100i32;
@@ -320,94 +1168,12 @@ fn generate_frames_constz(outdir: &String) -> Result<()> {
 
         let model = mv.to_model();
         let filename = format!("{}/out_{:03}.obj", outdir, idx);
-        model.serialize(&filename, [0f32; 3], 1f32)?;
+        model.serialize(&filename, [0f32; 3], 1f32, 2)?;
         idx += 1;
     }
     Ok(())
 }
 
-fn inject_at<V: Voxel>(v: &mut V, zlow: i32, zhigh: i32, pos0: VoxelIdx, n: usize) -> usize {
-    use std::collections::BinaryHeap;
-
-    if n == 0 {
-        return 0;
-    }
-
-    let mut injected = 0;
-
-    #[derive(Clone, Copy, Ord, PartialEq, Eq, Debug)]
-    struct HeapItem {
-        dist: usize,
-        depth: usize,
-        pos: VoxelIdx,
-    }
-    impl std::cmp::PartialOrd for HeapItem {
-        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-            Some(other.dist.cmp(&self.dist))
-        }
-    }
-
-    // with unit = 0.04mm and nozzle diameter 0.4mm, limiting maximum depth to 15
-
-    let mut candidates = BinaryHeap::new();
-    let mut visited = MonotonicVoxel::default();
-    candidates.push(HeapItem {
-        dist: 0,
-        depth: 10,
-        pos: pos0,
-    });
-
-    while let Some(HeapItem {
-        dist: _dist,
-        depth,
-        pos,
-    }) = candidates.pop()
-    {
-        if depth == 0 {
-            continue;
-        }
-        if !visited.add(pos) {
-            continue;
-        }
-
-        if v.add(pos) {
-            injected += 1;
-            if n == injected {
-                break;
-            }
-        }
-
-        let directions = [
-            [1, 0, 0],
-            [-1, 0, 0],
-            [0, 1, 0],
-            [0, -1, 0],
-            [0, 0, 1],
-            [0, 0, -1],
-        ];
-
-        for dir in directions {
-            let next: VoxelIdx = pos + dir.into();
-            if next[2] < zlow || next[2] > zhigh {
-                continue;
-            }
-            if visited.occupied(next) {
-                continue;
-            }
-
-            let delta = pos0 - next;
-            let dist = delta.magnitude_squared();
-            candidates.push(HeapItem {
-                dist,
-                depth: depth - 1,
-                pos: next,
-            });
-        }
-    }
-
-    injected
-}
-
 fn generate_inject(out: &str) -> Result<()> {
     let mut mv = MonotonicVoxel::default();
 
@@ -428,7 +1194,8 @@ fn generate_inject(out: &str) -> Result<()> {
     }
 
     let model = mv.to_model();
-    model.serialize(out, [0f32; 3], 1f32)
+    model.serialize(out, [0f32; 3], 1f32, 2)?;
+    Ok(())
 }
 
 fn generate_frames(outdir: &str) -> Result<()> {
@@ -448,7 +1215,7 @@ fn generate_frames(outdir: &str) -> Result<()> {
                         info!("render={:?}", (x, y, z));
                         let model = mv.to_model();
                         let filename = format!("{}/out_{:03}.obj", outdir, idx);
-                        model.serialize(&filename, [0f32; 3], 1f32)?;
+                        model.serialize(&filename, [0f32; 3], 1f32, 2)?;
                         idx += 1;
                     }
                 }
@@ -458,211 +1225,264 @@ fn generate_frames(outdir: &str) -> Result<()> {
     Ok(())
 }
 
-fn generate_gcode<V: Voxel + Default>(
-    filename: &str,
-    out_filename: &str,
-    layer: usize,
-    out_layers: bool,
-) -> Result<()> {
-    use nalgebra::Vector3;
-    use nom_gcode::{GCodeLine::*, Mnemonic};
-
-    let mut mv = V::default();
-
-    // unit: millimeters
-    // TODO: extract from gcode
-    const LAYER_HEIGHT: f32 = 0.2f32;
-    const Z_OFFSET: i32 = (LAYER_HEIGHT / UNIT) as i32;
-
-    let gcode = std::fs::read_to_string(filename)?;
-
-    fn to_intpos(pos: [f32; 3]) -> VoxelIdx {
-        return [
-            (pos[0] / UNIT).round() as i32,
-            (pos[1] / UNIT).round() as i32,
-            (pos[2] / UNIT).round() as i32,
-        ]
-        .into();
+/// Picks the voxel backend from a pre-scan of the gcode.
+fn choose_backend(filename: &str, layer: usize, limit: Option<ByteSize>) -> Result<Backend> {
+    let estimate = Estimate::scan(&read_gcode(filename)?, layer);
+    if let Some([lo, hi]) = estimate.bounds {
+        info!(
+            "plan: layers={}, bounds={:.1},{:.1},{:.1}..{:.1},{:.1},{:.1}mm",
+            estimate.layers(),
+            lo[0],
+            lo[1],
+            lo[2],
+            hi[0],
+            hi[1],
+            hi[2]
+        );
     }
+    let backend = estimate.choose(limit)?;
+    info!(
+        "backend: {}, volume={:.1}mm^3, infill={:?}, monotonic={}, rangeset={}",
+        backend.name(),
+        estimate.volume(),
+        estimate.infill_density,
+        estimate.memory(Backend::Monotonic),
+        estimate.memory(Backend::RangeSet)
+    );
+    Ok(backend)
+}
 
-    let sw = Stopwatch::start_new();
+/// Fixed camera with the toolhead occlusions, which need one.
+fn fixed_camera(
+    camera: &Option<render::Camera>,
+    occlude: &[render::Region],
+) -> Result<Option<render::Camera>> {
+    let Some(camera) = camera else {
+        anyhow::ensure!(occlude.is_empty(), "--occlude needs a fixed --camera");
+        return Ok(None);
+    };
+    Ok(Some(render::Camera {
+        occlusions: occlude.to_vec(),
+        ..camera.clone()
+    }))
+}
 
-    let mut pos = Vector3::default();
-    let mut e = 0f32;
+/// Prints mass and cost of the simulated part, and writes its mass properties and layer
+/// cross-sections if asked.
+fn report<V: Voxel + Sync>(sim: &Simulation<V>, opt: &SubCommandGcode) -> Result<()> {
+    let filament = Filament {
+        density: opt.density,
+        price: opt.price,
+    };
+    let usage = filament.usage(sim, opt.no_skirt, opt.no_support);
+    println!(
+        "material: volume={:.1}mm^3, mass={:.2}g, cost={:.2}",
+        usage.volume, usage.mass, usage.cost
+    );
 
-    let mut parsed = Vec::new();
-    for line in gcode.lines() {
-        let item = nom_gcode::parse_gcode(&line)?;
-        parsed.push(item);
+    if let Some(path) = &opt.mass_properties {
+        let props = MassProperties::of(&sim.voxel, &filament);
+        let [x, y, z] = props.com;
+        println!("center of mass: {:.3}, {:.3}, {:.3}mm", x, y, z);
+        storage::write(path, serde_json::to_string_pretty(&props.to_json())?)?;
     }
 
-    for item in parsed {
-        match item {
-            (_, Some(Comment(comment))) => {
-                let prefix = "LAYER:";
-                if !comment.0.starts_with(prefix) {
-                    continue;
-                }
-                let layer_idx = comment.0[prefix.len()..].parse::<usize>()?;
-                if layer_idx == 0 {
-                    continue;
-                }
+    if let Some(path) = &opt.sections {
+        let sections = section::sections(&sim.voxel);
+        section::write_csv(&sections, path)?;
+        if let Some(neck) = section::weakest(&sections) {
+            let [x, y] = neck.centroid;
+            println!(
+                "weakest layer boundary: layer {} at z={:.2}mm, area={:.2}mm^2 around {:.2}, {:.2}",
+                neck.layer, neck.z, neck.area, x, y
+            );
+        }
+    }
+    Ok(())
+}
 
-                if layer_idx == layer {
-                    break;
-                }
+/// Runs a conversion `job` of `gcode`, posting its progress to the webhook of `url` if
+/// any. The job returns fields of the `finished` event.
+fn with_webhook<F>(url: &Option<String>, gcode: &str, job: F) -> Result<()>
+where
+    F: FnOnce(&mut Option<Webhook>) -> Result<serde_json::Value>,
+{
+    let mut webhook = match url {
+        Some(url) => Some(Webhook::new(url, gcode)?),
+        None => None,
+    };
+    if let Some(webhook) = &webhook {
+        webhook.started();
+    }
+    let result = job(&mut webhook);
+    if let Some(webhook) = webhook {
+        webhook.finish(&result);
+    }
+    result.map(|_| ())
+}
 
-                if out_layers {
-                    let sw = Stopwatch::start_new();
-                    let model = mv.to_model();
-                    info!("to_model: took={}ms", sw.elapsed_ms());
-
-                    let sw = Stopwatch::start_new();
-                    let out_filename = format!("{}/gcode_{:03}.obj", out_filename, layer_idx);
-                    model.serialize(&out_filename, [-90f32, -90f32, 0f32], UNIT)?;
-                    info!(
-                        "Model::serialize: took={}ms, filename={}",
-                        sw.elapsed_ms(),
-                        out_filename
-                    );
-                }
-            }
-            (_, Some(GCode(code))) => {
-                if code.mnemonic != Mnemonic::General {
-                    continue;
-                }
-                if code.major == 0 {
-                    for (letter, value) in code.arguments() {
-                        let letter = *letter;
-                        let v = match value {
-                            Some(v) => *v,
-                            None => continue,
-                        };
-
-                        if letter == 'X' {
-                            pos[0] = v;
-                        }
-                        if letter == 'Y' {
-                            pos[1] = v;
-                        }
-                        if letter == 'Z' {
-                            pos[2] = v;
-                        }
-                    }
-                } else if code.major == 1 {
-                    let mut dst = pos;
-                    let mut dst_e = e;
-                    for (letter, value) in code.arguments() {
-                        let letter = *letter;
-                        let v = match value {
-                            Some(v) => *v,
-                            None => continue,
-                        };
-
-                        if letter == 'X' {
-                            dst[0] = v;
-                        }
-                        if letter == 'Y' {
-                            dst[1] = v;
-                        }
-                        if letter == 'Z' {
-                            dst[2] = v;
-                        }
-                        if letter == 'E' {
-                            dst_e = v;
-                        }
-                    }
-                    if dst_e <= e {
-                        pos = dst;
-                        continue;
-                    }
+/// Fields of `finished` webhook events.
+fn summary<V: Voxel>(sim: &Simulation<V>) -> serde_json::Value {
+    serde_json::json!({
+        "time": sim.time,
+        "blocks": sim.voxel.blocks(),
+        "cancelled": sim.cancelled,
+    })
+}
 
-                    let dir = (dst - pos).normalize();
-                    let len = (dst - pos).magnitude();
-
-                    // in centimeters
-                    let delta_e = dst_e - e;
-
-                    // flow rate calculation
-                    // block volume in cubic millimeters
-                    let block_volume = UNIT * UNIT * UNIT;
-
-                    // with 1.75mm filament, calculate volume, in millimeters
-                    let filament_diameter = 1.75f32;
-                    let filament_cross_section =
-                        0.25f32 * std::f32::consts::PI * filament_diameter * filament_diameter;
-                    let filament_volume = delta_e * filament_cross_section;
-
-                    // TODO: accurate volume calculation
-                    let total_blocks = filament_volume / block_volume;
-                    let mut blocks = total_blocks as usize;
-                    let step_size = 0.1;
-                    let blocks_per_step = (total_blocks * step_size / len) as usize;
-
-                    debug!(
-                        "{:?} -> {:?}, len={}, e={:?}, blocks={}",
-                        pos,
-                        dst,
-                        len,
-                        dst_e - e,
-                        total_blocks
-                    );
-
-                    let mut cursor = pos;
-                    while (cursor - dst).magnitude() > step_size {
-                        let next = cursor + dir * step_size;
-                        let next_pos = to_intpos([next[0], next[1], next[2]]);
-                        let z = next_pos[2];
-                        let injected =
-                            inject_at(&mut mv, z - Z_OFFSET, z, next_pos, blocks_per_step);
-                        if injected != blocks_per_step {
-                            debug!("injected != blocks_per_step, skipping");
-                        }
-                        cursor = next;
-                        blocks -= blocks_per_step;
-                    }
-                    {
-                        let next_pos = to_intpos([dst[0], dst[1], dst[2]]);
-                        let z = next_pos[2];
-                        let injected = inject_at(&mut mv, z - Z_OFFSET, z, next_pos, blocks);
-                        if injected != blocks {
-                            debug!("injected != blocks_per_step, skipping");
-                        }
-                    }
+fn write_deposit_log<V>(sim: &Simulation<V>, path: &Option<String>) -> Result<()> {
+    if let (Some(path), Some(log)) = (path, &sim.deposit_log) {
+        log.lock().unwrap().write_csv(path)?;
+    }
+    Ok(())
+}
 
-                    pos = dst;
-                    e = dst_e;
-                }
+/// Simulates lines as they are appended to the gcode, with a frame after each batch of
+/// lines, until the target layer or `--idle` seconds without new lines.
+fn follow_stream(
+    opt: &SubCommandStream,
+    layer: usize,
+    params: &Params,
+    stream: &mut stream::Stream,
+) -> Result<()> {
+    let poll = std::time::Duration::from_millis(200);
+    let idle = std::time::Duration::from_secs_f32(opt.idle);
+
+    let mut tail = Tail::open(&opt.gcode)?;
+    let mut simulator = Simulator::<MonotonicVoxel>::new("", params)?;
+    let mut last = std::time::Instant::now();
+    loop {
+        let lines = match tail.read()? {
+            Some(lines) => Some(lines),
+            None if last.elapsed() >= idle => tail.rest(),
+            None => {
+                std::thread::sleep(poll);
+                continue;
             }
-            (_, _) => (),
+        };
+        let Some(lines) = lines else {
+            info!("follow: no new lines for {}s, done", opt.idle);
+            break;
+        };
+        last = std::time::Instant::now();
+        simulator.extend(&lines)?;
+        simulator.run(layer, params, stream)?;
+        if simulator.layer() >= layer {
+            break;
         }
+        let current = simulator.layer();
+        let sim = simulator.simulation_mut();
+        stream.frame(&mut sim.voxel, current, sim.time)?;
+        info!("follow: layer={}, time={:.1}s", current, sim.time);
     }
+    let sim = simulator.simulation_mut();
+    stream.finish(&mut sim.voxel, sim.time)
+}
 
-    let blocks = mv.blocks();
-    info!(
-        "voxel construction: took={}ms, blocks={}/{}, bps={}",
-        sw.elapsed_ms(),
-        blocks,
-        mv.ranges(),
-        blocks * 1000 / sw.elapsed_ms() as usize
-    );
+/// Simulates gcode until `--rewind-to` once, then the rest into `--out`, and again with
+/// `--set` parameters from the checkpoint into `--rewind-out`.
+fn rewind_gcode(
+    voxel: AnyVoxel,
+    opt: &SubCommandGcode,
+    layer: usize,
+    output: &Output,
+    params: &Params,
+    rewound: &Params,
+) -> Result<()> {
+    let Some(rewind_to) = opt.rewind_to else {
+        anyhow::bail!("--rewind-to is required");
+    };
+    let Some(rewind_out) = &opt.rewind_out else {
+        anyhow::bail!("--rewind-out is required with --rewind-to");
+    };
+    anyhow::ensure!(rewind_to < layer, "--rewind-to must be before --layer");
+
+    let gcode = read_gcode(&opt.gcode)?;
+    let mut simulator = Simulator::with_voxel(voxel, &gcode, params)?;
+    simulator.run(rewind_to, params, &mut ())?;
+    simulator.checkpoint();
+    simulator.run(layer, params, &mut ())?;
+    let meta = Metadata::gcode(&opt.gcode, params, output)?;
+    export_model(simulator.simulation(), output, meta, &opt.out)?;
+
+    simulator.rewind(rewind_to)?;
+    simulator.run(layer, rewound, &mut ())?;
+    let meta = Metadata::gcode(&opt.gcode, rewound, output)?.with("rewind_to", rewind_to);
+    export_model(simulator.simulation(), output, meta, rewind_out)?;
+    Ok(())
+}
 
-    info!("bounding box: {:?}", mv.bounding_box());
+/// Simulates gcode with each point of the `--vary` grid, writing models and a summary.
+fn sweep_gcode(backend: &str, opt: &SubCommandSweep, layer: usize) -> Result<()> {
+    anyhow::ensure!(
+        !opt.vary.is_empty(),
+        "nothing to sweep, add --vary name=v1,v2"
+    );
+    storage::create_dir_all(&opt.outdir)?;
+
+    let output = Output {
+        mode: ExportMode::Full,
+        format: Format::Obj,
+        units: Units::Millimeter,
+        precision: 2,
+        shrinkage: Shrinkage::default(),
+        toolhead: false,
+    };
+    let gcode = read_gcode(&opt.gcode)?;
+    let registry = Registry::default();
+    let grid = sweep::grid(&opt.vary);
+    let mut summary = sweep::Summary::new(&opt.vary);
+    for (i, point) in grid.iter().enumerate() {
+        let params = sweep::params_at(&Params::default(), &opt.vary, point)?;
+        let voxel = registry.create(backend)?;
+        let sim = simulate_into(voxel, &gcode, layer, &params, &mut ())?;
+
+        let file = format!("sweep_{:03}.obj", i);
+        if !opt.no_models {
+            let meta = Metadata::gcode(&opt.gcode, &params, &output)?.with("sweep", i);
+            export_model(&sim, &output, meta, &format!("{}/{}", opt.outdir, file))?;
+        }
+        info!("sweep: {}/{}, params={:?}", i + 1, grid.len(), point);
+        summary.push(&file, point, sweep::Metrics::of(&sim));
+    }
 
-    if !out_layers {
-        let sw = Stopwatch::start_new();
-        let model = mv.to_model();
-        info!("to_model: took={}ms", sw.elapsed_ms());
+    summary.write_csv(&format!("{}/summary.csv", opt.outdir))?;
+    print!("{}", summary.to_table());
+    Ok(())
+}
 
-        let sw = Stopwatch::start_new();
-        model.serialize(&out_filename, [-90f32, -90f32, 0f32], UNIT)?;
-        info!(
-            "Model::Serialize: took={}ms, filename={}",
-            sw.elapsed_ms(),
-            out_filename
+fn column_stats(
+    voxel: AnyVoxel,
+    filename: &str,
+    layer: usize,
+    top: usize,
+    heatmap: Option<&str>,
+) -> Result<()> {
+    let gcode = read_gcode(filename)?;
+    let sim = simulate_into(voxel, &gcode, layer, &Params::default(), &mut ())?;
+
+    let stats = profile::ColumnStats::build(&sim.voxel, top);
+    println!(
+        "columns: {}, ranges: {}, ranges per column: {:.2}",
+        stats.columns(),
+        sim.voxel.ranges(),
+        stats.mean()
+    );
+    for (n, count) in &stats.histogram {
+        println!(
+            "{:>4} ranges: {:>8} columns ({:.2}%)",
+            n,
+            count,
+            *count as f32 * 100f32 / stats.columns() as f32
         );
     }
+    for ([x, y], n) in &stats.worst {
+        println!("worst: x={:.2} y={:.2} ranges={}", x, y, n);
+    }
 
+    if let Some(heatmap) = heatmap {
+        stats.write_heatmap(heatmap)?;
+    }
     Ok(())
 }
 
@@ -670,6 +1490,10 @@ fn main() -> Result<()> {
     env_logger::init();
 
     let opt: TopLevel = argh::from_env();
+    tdp_tl::deterministic::enable(opt.deterministic);
+    if let Some(endpoint) = &opt.s3_endpoint {
+        tdp_tl::s3::set_endpoint(endpoint);
+    }
 
     match opt.nested {
         SubCommandEnum::DemoSphereFrames(opt) => {
@@ -681,15 +1505,19 @@ fn main() -> Result<()> {
         }
 
         SubCommandEnum::DemoSphere(opt) => {
-            let model = if opt.bruteforce {
+            let mut model = if opt.bruteforce {
                 generate_brute_force()
             } else if opt.shell {
                 generate_shell()
             } else {
                 generate_face_only()
             };
-
-            model.serialize(&opt.out, [0f32; 3], 1f32)?;
+            if opt.format != Format::Obj {
+                surface::orient(&mut model, |c| test(c[0], c[1], c[2]));
+            }
+            model.serialize_as(&opt.out, opt.format, 2, |idx| {
+                [0, 1, 2].map(|i| idx[i] as f32)
+            })?;
             Ok(())
         }
 
@@ -697,16 +1525,603 @@ fn main() -> Result<()> {
 
         SubCommandEnum::Gcode(opt) => {
             let layer = opt.layer.unwrap_or(std::usize::MAX);
-            generate_gcode::<MonotonicVoxel>(&opt.gcode, &opt.out, layer, false)
+            let params = opt.params();
+            let output = opt.output();
+            with_webhook(&opt.webhook, &opt.gcode, |webhook| {
+                let backend = choose_backend(&opt.gcode, layer, opt.memory_limit)?;
+                let voxel = Registry::default().create(backend.name())?;
+                if opt.rewind_to.is_some() || !opt.set.is_empty() {
+                    let mut rewound = params.clone();
+                    for (key, value) in &opt.set {
+                        rewound.set(key, value)?;
+                    }
+                    rewind_gcode(voxel, &opt, layer, &output, &params, &rewound)?;
+                    return Ok(serde_json::json!({}));
+                }
+                let sim = generate_gcode(
+                    voxel,
+                    &opt.gcode,
+                    &opt.out,
+                    layer,
+                    false,
+                    Pacing::default(),
+                    &output,
+                    &params,
+                    None,
+                    webhook,
+                )?;
+                write_deposit_log(&sim, &opt.deposit_log_out)?;
+                report(&sim, &opt)?;
+                Ok(summary(&sim))
+            })
         }
 
         SubCommandEnum::GcodeLayers(opt) => {
             let layer = std::usize::MAX;
-            if opt.rangeset {
-                generate_gcode::<RangeSetVoxel>(&opt.gcode, &opt.outdir, layer, true)
+            let params = opt.params();
+            let output = opt.output();
+            with_webhook(&opt.webhook, &opt.gcode, |webhook| {
+                let backend = if opt.rangeset {
+                    Backend::RangeSet
+                } else {
+                    choose_backend(&opt.gcode, layer, opt.memory_limit)?
+                };
+                let mut balance = opt.balance.as_ref().map(|_| Balance::new());
+                anyhow::ensure!(opt.fps > 0f32, "--fps must be positive: {}", opt.fps);
+                let mut subtitles = opt.subtitles.as_ref().map(|_| Subtitles::new(opt.fps));
+                let mut series = opt.series.as_ref().map(|_| Series::new());
+                let sim = generate_gcode(
+                    Registry::default().create(backend.name())?,
+                    &opt.gcode,
+                    &opt.outdir,
+                    layer,
+                    true,
+                    Pacing {
+                        sub_frames: opt.sub_frames,
+                        idle: opt.idle,
+                    },
+                    &output,
+                    &params,
+                    opt.frame_cache.as_deref(),
+                    &mut (((&mut balance, webhook), &mut subtitles), &mut series),
+                )?;
+                if let (Some(subtitles), Some(path)) = (&subtitles, &opt.subtitles) {
+                    subtitles.write_srt(path)?;
+                }
+                if let (Some(series), Some(path)) = (&series, &opt.series) {
+                    series.write(path)?;
+                }
+                if let Some(balance) = &mut balance {
+                    balance.finish(&sim.voxel);
+                }
+                write_deposit_log(&sim, &opt.deposit_log_out)?;
+
+                if let (Some(balance), Some(path)) = (&balance, &opt.balance) {
+                    balance.write_csv(path)?;
+                    match balance.tipping().next() {
+                        Some(l) => println!(
+                            "tipping from layer {}: center of mass {:.2}mm outside the footprint",
+                            l.layer, -l.margin
+                        ),
+                        None => println!("no tipping"),
+                    }
+                }
+                Ok(summary(&sim))
+            })
+        }
+
+        SubCommandEnum::RenderStill(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let params = Params {
+                colors: opt.colors,
+                palette: opt.palette,
+                ringing_frequency: opt.ringing_frequency,
+                ringing_damping: opt.ringing_damping,
+                fuzzy_skin: opt.fuzzy_skin,
+                fuzzy_spacing: opt.fuzzy_spacing,
+                seed: opt.seed,
+                ..Params::default()
+            };
+            let sim =
+                simulate_gcode::<MonotonicVoxel, _>(&opt.gcode, layer, &params, |_, _| Ok(()))?;
+
+            let still = render::Still {
+                width: opt.width,
+                height: opt.height,
+                azimuth: opt.azimuth,
+                elevation: opt.elevation,
+                ao_samples: opt.ao_samples,
+                camera: fixed_camera(&opt.camera, &opt.occlude)?,
+                overlay: None,
+                transparent: opt.transparent,
+                stereo: opt.stereo,
+                aovs: opt.aovs,
+                ortho: opt.ortho,
+            };
+            let sw = Stopwatch::start_new();
+            render::render_still(&sim.voxel, sim.colors.as_ref(), &still, &opt.out)?;
+            info!("render_still: took={}ms", sw.elapsed_ms());
+            Ok(())
+        }
+
+        SubCommandEnum::Preview(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let params = Params {
+                colors: opt.colors,
+                ..Params::default()
+            };
+            let still = render::Still {
+                width: opt.width,
+                height: opt.height,
+                azimuth: -60.0,
+                elevation: 30.0,
+                ao_samples: opt.ao_samples,
+                camera: None,
+                overlay: opt.overlay,
+                transparent: false,
+                stereo: None,
+                aovs: false,
+                ortho: None,
+            };
+            let sw = Stopwatch::start_new();
+            anim::preview(
+                &opt.gcode, layer, &params, &still, opt.every, opt.fps, &opt.out,
+            )?;
+            info!("preview: took={}ms", sw.elapsed_ms());
+            Ok(())
+        }
+
+        SubCommandEnum::View(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let params = Params {
+                step_size: opt.step_size,
+                ..Params::default()
+            };
+            #[cfg(feature = "view")]
+            if !opt.headless && view::has_display() {
+                return view::view(&opt.gcode, layer, &params);
+            }
+            // the gpu, if any, with the view feature, and the cpu ray tracer otherwise
+            #[cfg(feature = "view")]
+            let frames = view::headless;
+            #[cfg(not(feature = "view"))]
+            let frames = tdp_tl::headless::headless;
+            let still = render::Still {
+                width: opt.width,
+                height: opt.height,
+                azimuth: -60.0,
+                elevation: 30.0,
+                ao_samples: 16,
+                camera: None,
+                overlay: opt.overlay.clone(),
+                transparent: opt.transparent,
+                stereo: opt.stereo,
+                aovs: opt.aovs,
+                ortho: opt.ortho,
+            };
+            frames(&opt.gcode, layer, &params, &opt.outdir, &still)
+        }
+
+        SubCommandEnum::ColumnStats(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let backend = if opt.rangeset {
+                Backend::RangeSet
+            } else {
+                Backend::Monotonic
+            };
+            let voxel = Registry::default().create(backend.name())?;
+            column_stats(voxel, &opt.gcode, layer, opt.top, opt.heatmap.as_deref())
+        }
+
+        SubCommandEnum::Eta(opt) => {
+            let cmp = eta::compare(&read_gcode(&opt.gcode)?, opt.acceleration)?;
+            anyhow::ensure!(
+                cmp.slicer_total.is_some() || !cmp.layers.is_empty(),
+                "no slicer time estimates in {}",
+                opt.gcode
+            );
+            let total = cmp.simulated_total;
+            match cmp.slicer_total {
+                Some(slicer) => println!(
+                    "total: slicer={:.0}s, simulated={:.0}s ({:+.1}%)",
+                    slicer,
+                    total,
+                    (total - slicer) / slicer * 100f32
+                ),
+                None => println!("total: simulated={:.0}s", total),
+            }
+
+            let mut layers = cmp.durations();
+            layers.sort_by(|a, b| (b.2 - b.1).abs().total_cmp(&(a.2 - a.1).abs()));
+            for (layer, slicer, simulated) in layers.iter().take(opt.top) {
+                println!(
+                    "layer {}: slicer={:.1}s, simulated={:.1}s ({:+.1}s)",
+                    layer,
+                    slicer,
+                    simulated,
+                    simulated - slicer
+                );
+            }
+            if let Some(path) = &opt.csv {
+                cmp.write_csv(path)?;
+            }
+            Ok(())
+        }
+
+        SubCommandEnum::Volume(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let params = Params::default();
+            let sim =
+                simulate_gcode::<MonotonicVoxel, _>(&opt.gcode, layer, &params, |_, _| Ok(()))?;
+
+            let meta = Metadata::new()
+                .with("input", &opt.gcode)
+                .with("layer", layer);
+            let sw = Stopwatch::start_new();
+            if opt.out.ends_with(".vdb") {
+                #[cfg(feature = "vdb")]
+                vdb::write_vdb(&sim.voxel, &opt.out, &meta)?;
+                #[cfg(not(feature = "vdb"))]
+                anyhow::bail!("openvdb output requires the vdb feature");
             } else {
-                generate_gcode::<MonotonicVoxel>(&opt.gcode, &opt.outdir, layer, true)
+                volume::Volume::new(&sim.voxel).write(&opt.out, &meta)?;
             }
+            info!("volume: took={}ms, filename={}", sw.elapsed_ms(), opt.out);
+            Ok(())
+        }
+
+        SubCommandEnum::VoxelExport(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let backend = if opt.rangeset {
+                Backend::RangeSet
+            } else {
+                Backend::Monotonic
+            };
+            let params = Params {
+                colors: opt.colors,
+                ..Params::default()
+            };
+            let voxel = Registry::default().create(backend.name())?;
+            let gcode = read_gcode(&opt.gcode)?;
+            let sim = simulate_into(voxel, &gcode, layer, &params, &mut ())?;
+
+            let sw = Stopwatch::start_new();
+            let vox = Vox::new(&sim.voxel, sim.colors.as_ref());
+            vox.write(&opt.out)?;
+            info!(
+                "voxel-export: took={}ms, models={}, filename={}",
+                sw.elapsed_ms(),
+                vox.models(),
+                opt.out
+            );
+            Ok(())
+        }
+
+        SubCommandEnum::Schematic(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let params = Params {
+                features: opt.palette_by == PaletteBy::Feature,
+                ..Params::default()
+            };
+            let sim =
+                simulate_gcode::<MonotonicVoxel, _>(&opt.gcode, layer, &params, |_, _| Ok(()))?;
+
+            let meta = Metadata::new()
+                .with("input", &opt.gcode)
+                .with("layer", layer);
+            let sw = Stopwatch::start_new();
+            let blocks = Blocks::new(opt.palette_by, &opt.block);
+            schematic::Schematic::new(&sim.voxel, &sim.features, &blocks).write(&opt.out, &meta)?;
+            info!(
+                "schematic: took={}ms, filename={}",
+                sw.elapsed_ms(),
+                opt.out
+            );
+            Ok(())
+        }
+
+        SubCommandEnum::Stream(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let params = Params::default();
+            let meta = Metadata::new().with("input", &opt.gcode);
+            let mut stream = stream::Stream::new(&opt.outdir, &meta)?;
+            if opt.follow {
+                return follow_stream(&opt, layer, &params, &mut stream);
+            }
+            let mut sim = simulate_gcode::<MonotonicVoxel, _>(
+                &opt.gcode,
+                layer,
+                &params,
+                |sim, layer_idx| stream.frame(&mut sim.voxel, layer_idx, sim.time),
+            )?;
+            stream.finish(&mut sim.voxel, sim.time)
+        }
+
+        SubCommandEnum::Sweep(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let backend = choose_backend(&opt.gcode, layer, None)?;
+            sweep_gcode(backend.name(), &opt, layer)
+        }
+
+        SubCommandEnum::Dataset(opt) => {
+            let scenarios = dataset::parse_spec(&std::fs::read_to_string(&opt.spec)?)?;
+            let files = dataset::gcode_files(&opt.gcode_dir)?;
+            let camera = fixed_camera(&opt.camera, &opt.occlude)?;
+            let options = dataset::Options {
+                layer: opt.layer.unwrap_or(usize::MAX),
+                every: opt.every,
+                output: Output {
+                    mode: ExportMode::Full,
+                    format: Format::Obj,
+                    units: Units::Millimeter,
+                    precision: 2,
+                    shrinkage: Shrinkage::default(),
+                    toolhead: opt.toolhead,
+                },
+                still: opt.images.then(|| render::Still {
+                    width: opt.width,
+                    height: opt.height,
+                    azimuth: -60.0,
+                    elevation: 30.0,
+                    ao_samples: 16,
+                    camera,
+                    overlay: None,
+                    transparent: false,
+                    stereo: None,
+                    aovs: opt.aovs,
+                    ortho: None,
+                }),
+            };
+            let params = Params {
+                seed: opt.seed,
+                ..Params::default()
+            };
+
+            let registry = Registry::default();
+            let mut samples = Vec::new();
+            for file in &files {
+                let gcode = file.to_str().unwrap_or_default();
+                let stem = file.file_stem().and_then(|s| s.to_str()).unwrap_or("gcode");
+                let backend = choose_backend(gcode, options.layer, None)?;
+                for scenario in &scenarios {
+                    let name = format!("{}-{}", stem, scenario.name);
+                    let dir = format!("{}/{}", opt.outdir, name);
+                    let voxel = registry.create(backend.name())?;
+                    dataset::sample(voxel, gcode, scenario, &params, &options, &dir)?;
+                    samples.push(serde_json::json!({
+                        "dir": name,
+                        "gcode": gcode,
+                        "scenario": scenario.name,
+                    }));
+                }
+            }
+            let json = serde_json::to_vec_pretty(&samples)?;
+            storage::write(&format!("{}/dataset.json", opt.outdir), json)
+        }
+
+        SubCommandEnum::Diff(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let mut runs = Vec::new();
+            for (gcode, set) in [
+                (&opt.gcode, &opt.set_a),
+                (opt.gcode_b.as_ref().unwrap_or(&opt.gcode), &opt.set_b),
+            ] {
+                let mut params = Params::default();
+                for (key, value) in set {
+                    params.set(key, value)?;
+                }
+                runs.push(simulate_gcode::<MonotonicVoxel, _>(
+                    gcode,
+                    layer,
+                    &params,
+                    |_, _| Ok(()),
+                )?);
+            }
+            let (a, b) = (&runs[0].voxel, &runs[1].voxel);
+
+            storage::create_dir_all(&opt.outdir)?;
+            let still = render::Still {
+                width: opt.width,
+                height: opt.height,
+                azimuth: opt.azimuth,
+                elevation: opt.elevation,
+                ao_samples: opt.ao_samples,
+                camera: None,
+                overlay: None,
+                transparent: false,
+                stereo: None,
+                aovs: false,
+                ortho: None,
+            };
+            let frame = a.bounding_box().union(b.bounding_box());
+            for (v, name) in [(a, "a.png"), (b, "b.png")] {
+                let out = format!("{}/{}", opt.outdir, name);
+                render::render_still_in(v, None, &still, &frame, &out)?;
+            }
+
+            let diff = Diff::build(a, b);
+            diff.write_heatmap(&format!("{}/diff.png", opt.outdir))?;
+            let [only_a, only_b, both] = diff.totals();
+            println!(
+                "only in a: {}, only in b: {}, both: {}, iou: {:.4}",
+                only_a,
+                only_b,
+                both,
+                both as f64 / (only_a + only_b + both).max(1) as f64
+            );
+            Ok(())
+        }
+
+        SubCommandEnum::Align(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let params = Params::default();
+            let sim =
+                simulate_gcode::<MonotonicVoxel, _>(&opt.gcode, layer, &params, |_, _| Ok(()))?;
+
+            let sw = Stopwatch::start_new();
+            let scan = align::voxelize(&align::read_points(&opt.scan)?, opt.cell);
+            let model = align::Surface::of(&sim.voxel, opt.cell);
+            anyhow::ensure!(!model.points.is_empty(), "nothing simulated");
+            let alignment = align::align(&scan, &model, opt.cell, opt.iterations);
+            let deviation = Deviation::measure(&sim.voxel, &scan, &model, opt.cell, &alignment);
+            info!(
+                "align: took={}ms, scan_points={}, model_points={}",
+                sw.elapsed_ms(),
+                scan.len(),
+                model.points.len()
+            );
+
+            let r = alignment.rotation;
+            for i in 0..3 {
+                println!(
+                    "{:>9.5} {:>9.5} {:>9.5} {:>9.3}",
+                    r[(i, 0)],
+                    r[(i, 1)],
+                    r[(i, 2)],
+                    alignment.translation[i]
+                );
+            }
+            println!(
+                "deviation (mm): mean={:.3}, rms={:.3}, p95={:.3}, max={:.3}",
+                deviation.mean, deviation.rms, deviation.p95, deviation.max
+            );
+            if let Some(out) = &opt.out {
+                deviation.write_csv(out)?;
+            }
+            Ok(())
+        }
+
+        SubCommandEnum::FirstLayer(opt) => {
+            let params = Params::default();
+            let sim = simulate_gcode::<MonotonicVoxel, _>(&opt.gcode, 1, &params, |_, _| Ok(()))?;
+            let report = firstlayer::first_layer(
+                &sim.voxel,
+                &sim.segments,
+                &opt.out,
+                opt.line_width,
+                opt.min_gap,
+            )?;
+
+            println!(
+                "coverage: {:.2}% ({:.2}/{:.2}mm^2)",
+                report.coverage() * 100f32,
+                report.covered,
+                report.intended
+            );
+            for gap in &report.gaps {
+                warn!("gap: center={:?}, area={:.3}mm^2", gap.center, gap.area);
+                println!(
+                    "gap: x={:.2} y={:.2} area={:.3}mm^2",
+                    gap.center[0], gap.center[1], gap.area
+                );
+            }
+            Ok(())
+        }
+
+        SubCommandEnum::Adhesion(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let params = Params::default();
+            let sim =
+                simulate_gcode::<MonotonicVoxel, _>(&opt.gcode, layer, &params, |_, _| Ok(()))?;
+            let contacts = adhesion::contacts(&sim.voxel);
+            for c in &contacts {
+                println!(
+                    "contact: x={:.2} y={:.2} area={:.2}mm^2 height={:.2}mm ratio={:.2} risk={}",
+                    c.center[0],
+                    c.center[1],
+                    c.area,
+                    c.height,
+                    c.ratio(),
+                    c.risk().name()
+                );
+            }
+            if let Some(out) = &opt.out {
+                adhesion::write_csv(&contacts, out)?;
+            }
+            Ok(())
+        }
+
+        SubCommandEnum::Infill(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let params = Params {
+                features: true,
+                ..Params::default()
+            };
+            let sim =
+                simulate_gcode::<MonotonicVoxel, _>(&opt.gcode, layer, &params, |_, _| Ok(()))?;
+            let declared = match opt.density {
+                Some(density) => Some(density),
+                None => infill::declared(&read_gcode(&opt.gcode)?),
+            };
+            match declared {
+                Some(d) => info!("declared infill density: {:.1}%", d * 100f32),
+                None => warn!("infill density not declared, use --density to flag regions"),
+            }
+            let interiors = infill::interiors(&sim.voxel, &sim.features, opt.band);
+            for i in &interiors {
+                let deviates = declared.is_some_and(|d| i.deviates(d, opt.tolerance));
+                println!(
+                    "interior: layers={}..{} x={:.2} y={:.2} area={:.2}mm^2 density={:.1}%{}",
+                    i.layers.start,
+                    i.layers.end,
+                    i.center[0],
+                    i.center[1],
+                    i.area,
+                    i.density * 100f32,
+                    if deviates { " deviates" } else { "" }
+                );
+            }
+            if let Some(out) = &opt.out {
+                infill::write_csv(&interiors, declared, opt.tolerance, out)?;
+            }
+            Ok(())
+        }
+
+        SubCommandEnum::Thickness(opt) => {
+            let layer = opt.layer.unwrap_or(usize::MAX);
+            let params = Params::default();
+            let sim =
+                simulate_gcode::<MonotonicVoxel, _>(&opt.gcode, layer, &params, |_, _| Ok(()))?;
+            let sw = Stopwatch::start_new();
+            let thickness = thickness::Thickness::new(&sim.voxel);
+            info!("thickness: took={}ms", sw.elapsed_ms());
+            let walls = thickness.walls(opt.band);
+            for w in &walls {
+                println!(
+                    "part: layers={}..{} x={:.2} y={:.2} area={:.2}mm^2 min={:.2}mm at x={:.2} y={:.2} z={:.2}{}",
+                    w.layers.start,
+                    w.layers.end,
+                    w.center[0],
+                    w.center[1],
+                    w.area,
+                    w.min,
+                    w.thinnest[0],
+                    w.thinnest[1],
+                    w.thinnest[2],
+                    if w.min < opt.thin { " thin" } else { "" }
+                );
+            }
+            if let Some(out) = &opt.out {
+                thickness::write_csv(&walls, opt.thin, out)?;
+            }
+            if let Some(obj) = &opt.obj {
+                let meta = Metadata::new()
+                    .with("input", &opt.gcode)
+                    .with("layer", layer)
+                    .with("thin", opt.thin);
+                thickness.write_obj(&sim.voxel, opt.thin, obj, meta)?;
+            }
+            Ok(())
+        }
+
+        SubCommandEnum::ExplainParams(opt) => {
+            let mut params = Params::default();
+            for (key, value) in &opt.set {
+                params.set(key, value)?;
+            }
+            let explained = tdp_tl::explain::explain(&params);
+            println!("{}", serde_json::to_string_pretty(&explained)?);
+            Ok(())
         }
     }
 }
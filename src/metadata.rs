@@ -0,0 +1,229 @@
+use super::{explain, project::read_gcode, storage, Output, Params, UNIT};
+use anyhow::Result;
+use std::io::Write;
+
+/// FNV-1a, 64-bit. Identifies inputs and outputs, not cryptographic.
+pub fn hash_bytes(data: &[u8]) -> u64 {
+    hash_continue(0xcbf29ce484222325, data)
+}
+
+pub(crate) fn hash_continue(mut h: u64, data: &[u8]) -> u64 {
+    for b in data {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+/// Writer which hashes written bytes with `hash_bytes`.
+pub struct HashWriter<W> {
+    inner: W,
+    hash: u64,
+}
+
+impl<W: Write> HashWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hash: hash_bytes(&[]),
+        }
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hash = hash_continue(self.hash, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Generator metadata embedded in exports, as ordered key-value pairs.
+#[derive(Clone, Debug, Default)]
+pub struct Metadata {
+    entries: Vec<(String, String)>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        let mut meta = Self::default();
+        meta.set("generator", env!("CARGO_PKG_NAME"));
+        meta.set("version", env!("CARGO_PKG_VERSION"));
+        meta
+    }
+
+    /// Metadata of a gcode simulation: input, resolution and parameters.
+    pub fn gcode(filename: &str, params: &Params, output: &Output) -> Result<Self> {
+        let data = read_gcode(filename)?;
+
+        let mut meta = Self::new();
+        meta.set("input", filename);
+        meta.set(
+            "input_hash",
+            format!("{:016x}", hash_bytes(data.as_bytes())),
+        );
+        meta.set("resolution_mm", UNIT);
+        meta.set("units", output.units.name());
+        meta.set("mode", format!("{:?}", output.mode));
+        meta.set("params", explain::model(params));
+        Ok(meta)
+    }
+
+    /// Sets `key`, replacing previous value.
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        let value = value.to_string();
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.entries.push((key.to_owned(), value)),
+        }
+    }
+
+    pub fn with(&self, key: &str, value: impl ToString) -> Self {
+        let mut meta = self.clone();
+        meta.set(key, value);
+        meta
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        self.entries
+            .iter()
+            .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+/// Output file of a multi-frame job.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// filename, relative to the manifest
+    pub file: String,
+    pub layer: usize,
+    /// simulated print time, in seconds
+    pub time: f32,
+    /// seconds into the timelapse: print time with idle periods compressed, see `Idle`
+    pub clock: f32,
+    /// height of the nozzle, in millimeters
+    pub z: f32,
+    /// volume deposited per second of print time since the previous frame, in cubic
+    /// millimeters
+    pub flow: f32,
+    pub blocks: usize,
+    pub hash: u64,
+}
+
+/// `manifest.json` listing every frame of a job, for downstream assembly scripts.
+pub struct Manifest {
+    meta: Metadata,
+    frames: Vec<Frame>,
+}
+
+impl Manifest {
+    pub fn new(meta: &Metadata) -> Self {
+        Self {
+            meta: meta.clone(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, frame: Frame) {
+        self.frames.push(frame);
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        let frames = self
+            .frames
+            .iter()
+            .map(|f| {
+                json!({
+                    "file": f.file,
+                    "layer": f.layer,
+                    "time": f.time,
+                    "clock": f.clock,
+                    "z": f.z,
+                    "flow": f.flow,
+                    "blocks": f.blocks,
+                    "hash": format!("{:016x}", f.hash),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "metadata": self.meta.to_json(),
+            "frames": frames,
+        })
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        let mut w = storage::create(path)?;
+        serde_json::to_writer_pretty(&mut w, &self.to_json())?;
+        w.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_metadata() {
+        assert_eq!(hash_bytes(b""), 0xcbf29ce484222325);
+        assert_eq!(hash_bytes(b"a"), 0xaf63dc4c8601ec8c);
+
+        let meta = Metadata::new().with("layer", 3).with("layer", 4);
+        assert_eq!(
+            meta.entries()[0],
+            ("generator".to_owned(), "tdp-tl".to_owned())
+        );
+        assert_eq!(meta.entries().last().unwrap().1, "4");
+
+        let mut w = HashWriter::new(Vec::new());
+        w.write_all(b"a").unwrap();
+        assert_eq!(w.hash(), hash_bytes(b"a"));
+    }
+
+    #[test]
+    pub fn test_manifest() {
+        let mut manifest = Manifest::new(&Metadata::new());
+        manifest.push(Frame {
+            file: "gcode_001.obj".to_owned(),
+            layer: 1,
+            time: 1.5,
+            clock: 1.5,
+            z: 0.4,
+            flow: 2.0,
+            blocks: 10,
+            hash: 0xff,
+        });
+
+        let json = manifest.to_json();
+        assert_eq!(json["metadata"]["generator"], "tdp-tl");
+        assert_eq!(json["frames"][0]["layer"], 1);
+        assert_eq!(json["frames"][0]["hash"], "00000000000000ff");
+    }
+}
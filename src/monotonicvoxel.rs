@@ -1,19 +1,80 @@
-use super::{BoundingBox, Model, Voxel, VoxelIdx};
+use super::chunk::{chunk_of, chunks_in, Dirty};
+use super::{BoundingBox, ChunkId, Model, Voxel, VoxelIdx};
 use rayon::prelude::*;
+use smallvec::{smallvec, SmallVec};
 use std::collections::BTreeMap;
 use std::ops::Range;
+use std::sync::Arc;
 
-// RLE, over Z axis,
-#[derive(Default)]
+/// Ranges of a column. Most columns have a few ranges, which are stored inline in the
+/// map node without a separate allocation. Ranges of all columns of a chunk in one slab
+/// were measured as no faster to build, and slower to mesh, by `test_slab_timing`.
+type Ranges = SmallVec<[Range<i32>; 4]>;
+type Columns = BTreeMap<[i32; 2], Ranges>;
+
+// RLE, over Z axis, grouped by chunks. Clones are cheap snapshots: chunks are shared,
+// and copied on the first write after a clone.
+#[derive(Default, Clone)]
 pub struct MonotonicVoxel {
-    ranges: BTreeMap<[i32; 2], Vec<Range<i32>>>,
+    chunks: BTreeMap<ChunkId, Arc<Columns>>,
     bb: BoundingBox,
+    dirty: Dirty,
+}
+
+impl MonotonicVoxel {
+    fn columns(&self) -> impl Iterator<Item = (&[i32; 2], &Ranges)> {
+        self.chunks.values().flat_map(|columns| columns.iter())
+    }
+
+    fn get(&self, x: i32, y: i32) -> Option<&Ranges> {
+        self.chunks.get(&chunk_of(x, y))?.get(&[x, y])
+    }
+}
+
+/// Adds faces of the column of `ranges` at `coord` of `v` to `model`: tops, and sides
+/// toward +X and +Y.
+fn column_faces<V: Voxel>(v: &V, model: &mut Model, coord: [i32; 2], ranges: &[Range<i32>]) {
+    for range in ranges {
+        if Range::is_empty(range) {
+            continue;
+        }
+
+        let x = coord[0];
+        let y = coord[1];
+
+        /*
+        if !v.occupied([x, y, range.start - 1].into()) {
+            model.add_face([x, y, range.start].into(), up);
+        }
+        */
+        if !v.occupied([x, y, range.end].into()) {
+            model.add_face([x + 1, y + 1, range.end].into(), [-1, -1, 0].into());
+        }
+
+        let faces = [
+            ([1, 0], [1, 1, 1], [0, -1, -1]),
+            // ([-1, 0], [0, 0, 0], [0, 1, 1]),
+            ([0, 1], [1, 1, 1], [-1, 0, -1]),
+            // ([0, -1], [0, 0, 0], [1, 0, 1]),
+        ];
+
+        for ([dx, dy], offset, dir) in faces {
+            for z in range.clone() {
+                if !v.occupied([x + dx, y + dy, z].into()) {
+                    model.add_face(
+                        [x + offset[0], y + offset[1], z + offset[2]].into(),
+                        dir.into(),
+                    );
+                }
+            }
+        }
+    }
 }
 
 impl Voxel for MonotonicVoxel {
     fn blocks(&self) -> usize {
         let mut count = 0;
-        for ranges in self.ranges.values() {
+        for (_, ranges) in self.columns() {
             for range in ranges {
                 assert!(range.start < range.end);
                 count += (range.end - range.start) as usize;
@@ -24,7 +85,7 @@ impl Voxel for MonotonicVoxel {
 
     fn ranges(&self) -> usize {
         let mut count = 0;
-        for ranges in self.ranges.values() {
+        for (_, ranges) in self.columns() {
             count += ranges.len();
         }
         count
@@ -35,7 +96,7 @@ impl Voxel for MonotonicVoxel {
     }
 
     fn occupied(&self, coord: VoxelIdx) -> bool {
-        if let Some(ranges) = self.ranges.get(&[coord[0], coord[1]]) {
+        if let Some(ranges) = self.get(coord[0], coord[1]) {
             for range in ranges {
                 if range.contains(&coord[2]) {
                     return true;
@@ -50,80 +111,71 @@ impl Voxel for MonotonicVoxel {
         let z = coord[2];
         use std::collections::btree_map::Entry;
 
-        match self.ranges.entry([coord[0], coord[1]]) {
+        let chunk = self.chunks.entry(chunk_of(coord[0], coord[1])).or_default();
+        match Arc::make_mut(chunk).entry([coord[0], coord[1]]) {
             Entry::Vacant(v) => {
-                v.insert(vec![z..z + 1]);
+                v.insert(smallvec![z..z + 1]);
             }
             Entry::Occupied(mut v) => {
-                let mut updated = false;
-                for r in v.get_mut() {
-                    if r.contains(&z) {
-                        return false;
-                    }
-                    if r.start == z + 1 {
-                        r.start -= 1;
-                        updated = true;
-                        break;
-                    } else if r.end == z {
-                        r.end += 1;
-                        updated = true;
-                        break;
-                    }
+                let r = v.get_mut();
+                // ranges before idx start at or below z
+                let idx = r.upper_bound_by(|r| r.start.cmp(&z));
+                let prev = idx.checked_sub(1);
+                if prev.is_some_and(|i| r[i].contains(&z)) {
+                    return false;
                 }
-
-                if !updated {
-                    let r = v.get_mut();
-                    let idx = r.upper_bound_by(|r| z.cmp(&r.start));
-                    r.insert(idx, z..(z + 1));
+                let joins_prev = prev.is_some_and(|i| r[i].end == z);
+                let joins_next = r.get(idx).is_some_and(|next| next.start == z + 1);
+                match (joins_prev, joins_next) {
+                    // filling a gap of one voxel merges both ranges
+                    (true, true) => {
+                        let next = r.remove(idx);
+                        r[idx - 1].end = next.end;
+                    }
+                    (true, false) => r[idx - 1].end += 1,
+                    (false, true) => r[idx].start -= 1,
+                    (false, false) => r.insert(idx, z..(z + 1)),
                 }
             }
         };
 
         self.bb.add(coord);
+        self.dirty.add(coord);
         true
     }
 
-    fn to_model(&self) -> Model {
-        self.ranges
-            .par_iter()
-            .map(|(coord, ranges)| {
-                let mut model = Model::default();
+    fn column(&self, x: i32, y: i32) -> Vec<Range<i32>> {
+        self.get(x, y).map(|r| r.to_vec()).unwrap_or_default()
+    }
 
-                for range in ranges {
-                    if Range::is_empty(range) {
-                        continue;
-                    }
+    fn take_dirty(&mut self) -> Vec<ChunkId> {
+        self.dirty.take()
+    }
 
-                    let x = coord[0];
-                    let y = coord[1];
+    /// Creates empty chunks of `bounds`, so chunks are not allocated while depositing.
+    fn reserve(&mut self, bounds: &BoundingBox, _blocks: usize) {
+        for id in chunks_in(bounds) {
+            self.chunks.entry(id).or_default();
+        }
+    }
 
-                    /*
-                    if !self.occupied([x, y, range.start - 1].into()) {
-                        model.add_face([x, y, range.start].into(), up);
-                    }
-                    */
-                    if !self.occupied([x, y, range.end].into()) {
-                        model.add_face([x + 1, y + 1, range.end].into(), [-1, -1, 0].into());
-                    }
+    fn chunk_model(&self, chunk: ChunkId) -> Model {
+        let mut model = Model::default();
+        if let Some(columns) = self.chunks.get(&chunk) {
+            for (coord, ranges) in columns.iter() {
+                column_faces(self, &mut model, *coord, ranges);
+            }
+        }
+        model
+    }
 
-                    let faces = [
-                        ([1, 0], [1, 1, 1], [0, -1, -1]),
-                        // ([-1, 0], [0, 0, 0], [0, 1, 1]),
-                        ([0, 1], [1, 1, 1], [-1, 0, -1]),
-                        // ([0, -1], [0, 0, 0], [1, 0, 1]),
-                    ];
-
-                    for ([dx, dy], offset, dir) in faces {
-                        for z in range.clone() {
-                            if !self.occupied([x + dx, y + dy, z].into()) {
-                                model.add_face(
-                                    [x + offset[0], y + offset[1], z + offset[2]].into(),
-                                    dir.into(),
-                                );
-                            }
-                        }
-                    }
-                }
+    fn to_model(&self) -> Model {
+        self.chunks
+            .par_iter()
+            .flat_map_iter(|(_, columns)| columns.iter())
+            .map(|(coord, ranges)| {
+                let mut model = Model::default();
+                column_faces(self, &mut model, *coord, ranges);
                 model
             })
             .reduce(
@@ -135,3 +187,277 @@ impl Voxel for MonotonicVoxel {
             )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{simulate_gcode, Params};
+    use std::time::{Duration, Instant};
+
+    /// Ranges of a column in the slab of its chunk.
+    #[derive(Clone, Copy)]
+    struct Span {
+        start: usize,
+        len: usize,
+        cap: usize,
+    }
+
+    /// Ranges of all columns of a chunk in one Vec. Columns grow in place at the end of
+    /// the slab, or move to the end with twice the room, and the slab is compacted in
+    /// column order once holes reach half of it.
+    #[derive(Default, Clone)]
+    struct Slab {
+        ranges: Vec<Range<i32>>,
+        spans: BTreeMap<[i32; 2], Span>,
+        holes: usize,
+    }
+
+    impl Slab {
+        fn get(&self, coord: [i32; 2]) -> Option<&[Range<i32>]> {
+            let span = self.spans.get(&coord)?;
+            Some(&self.ranges[span.start..span.start + span.len])
+        }
+
+        /// Makes room for one more range of the column at `coord`.
+        fn grow(&mut self, coord: [i32; 2]) -> Span {
+            let span = self.spans[&coord];
+            if span.len < span.cap {
+                return span;
+            }
+            let span = if span.start + span.cap == self.ranges.len() {
+                self.ranges.push(0..0);
+                Span {
+                    cap: span.cap + 1,
+                    ..span
+                }
+            } else {
+                let start = self.ranges.len();
+                self.ranges
+                    .extend_from_within(span.start..span.start + span.len);
+                self.ranges.resize(start + span.cap * 2, 0..0);
+                self.holes += span.cap;
+                Span {
+                    start,
+                    cap: span.cap * 2,
+                    ..span
+                }
+            };
+            self.spans.insert(coord, span);
+            if self.holes * 2 >= self.ranges.len() {
+                self.compact();
+                return self.grow(coord);
+            }
+            span
+        }
+
+        fn compact(&mut self) {
+            let mut ranges = Vec::with_capacity(self.ranges.len() - self.holes);
+            for span in self.spans.values_mut() {
+                let start = ranges.len();
+                ranges.extend_from_slice(&self.ranges[span.start..span.start + span.len]);
+                *span = Span {
+                    start,
+                    len: span.len,
+                    cap: span.len,
+                };
+            }
+            self.ranges = ranges;
+            self.holes = 0;
+        }
+
+        fn add(&mut self, coord: [i32; 2], z: i32) -> bool {
+            use ordslice::Ext;
+            let Some(r) = self.get(coord) else {
+                self.spans.insert(
+                    coord,
+                    Span {
+                        start: self.ranges.len(),
+                        len: 1,
+                        cap: 1,
+                    },
+                );
+                self.ranges.push(z..z + 1);
+                return true;
+            };
+            let idx = r.upper_bound_by(|r| r.start.cmp(&z));
+            let prev = idx.checked_sub(1);
+            if prev.is_some_and(|i| r[i].contains(&z)) {
+                return false;
+            }
+            let joins_prev = prev.is_some_and(|i| r[i].end == z);
+            let joins_next = r.get(idx).is_some_and(|next| next.start == z + 1);
+            let span = match (joins_prev, joins_next) {
+                (false, false) => self.grow(coord),
+                _ => self.spans[&coord],
+            };
+            let r = &mut self.ranges[span.start..span.start + span.cap];
+            let len = match (joins_prev, joins_next) {
+                (true, true) => {
+                    r[idx - 1].end = r[idx].end;
+                    r[idx..span.len].rotate_left(1);
+                    span.len - 1
+                }
+                (true, false) => {
+                    r[idx - 1].end += 1;
+                    span.len
+                }
+                (false, true) => {
+                    r[idx].start -= 1;
+                    span.len
+                }
+                (false, false) => {
+                    r[idx..=span.len].rotate_right(1);
+                    r[idx] = z..z + 1;
+                    span.len + 1
+                }
+            };
+            self.spans.insert(coord, Span { len, ..span });
+            true
+        }
+    }
+
+    /// `MonotonicVoxel` with ranges in a slab per chunk, instead of inline in the map.
+    #[derive(Default, Clone)]
+    struct SlabVoxel {
+        chunks: BTreeMap<ChunkId, Arc<Slab>>,
+        bb: BoundingBox,
+        dirty: Dirty,
+    }
+
+    impl Voxel for SlabVoxel {
+        fn blocks(&self) -> usize {
+            let ranges = self
+                .chunks
+                .values()
+                .flat_map(|slab| slab.spans.keys().map(|c| slab.get(*c).unwrap()));
+            ranges.flatten().map(|r| r.len()).sum()
+        }
+
+        fn ranges(&self) -> usize {
+            let spans = self.chunks.values().flat_map(|slab| slab.spans.values());
+            spans.map(|span| span.len).sum()
+        }
+
+        fn bounding_box(&self) -> &BoundingBox {
+            &self.bb
+        }
+
+        fn occupied(&self, coord: VoxelIdx) -> bool {
+            let Some(slab) = self.chunks.get(&chunk_of(coord[0], coord[1])) else {
+                return false;
+            };
+            let ranges = slab.get([coord[0], coord[1]]).unwrap_or_default();
+            ranges.iter().any(|r| r.contains(&coord[2]))
+        }
+
+        fn add(&mut self, coord: VoxelIdx) -> bool {
+            let slab = self.chunks.entry(chunk_of(coord[0], coord[1])).or_default();
+            if !Arc::make_mut(slab).add([coord[0], coord[1]], coord[2]) {
+                return false;
+            }
+            self.bb.add(coord);
+            self.dirty.add(coord);
+            true
+        }
+
+        fn take_dirty(&mut self) -> Vec<ChunkId> {
+            self.dirty.take()
+        }
+
+        fn reserve(&mut self, bounds: &BoundingBox, _blocks: usize) {
+            for id in chunks_in(bounds) {
+                self.chunks.entry(id).or_default();
+            }
+        }
+
+        fn to_model(&self) -> Model {
+            self.chunks
+                .par_iter()
+                .flat_map_iter(|(_, slab)| slab.spans.keys().map(|c| (*c, slab.get(*c).unwrap())))
+                .map(|(coord, ranges)| {
+                    let mut model = Model::default();
+                    column_faces(self, &mut model, coord, ranges);
+                    model
+                })
+                .reduce(
+                    || Model::default(),
+                    |mut a, b| {
+                        a.merge(b);
+                        a
+                    },
+                )
+        }
+    }
+
+    /// Builds and meshes the demo print up to layer 20 with `V`, returning the time of
+    /// each and the mesh.
+    fn time<V: Voxel + Default + Clone>() -> (Duration, Duration, V, Model) {
+        let t = Instant::now();
+        let gcode = "demo/KK_xyzCalibration_cube.gcode";
+        let sim = simulate_gcode::<V, _>(gcode, 20, &Params::default(), |_, _| Ok(())).unwrap();
+        let build = t.elapsed();
+        let t = Instant::now();
+        let model = sim.voxel.to_model();
+        (build, t.elapsed(), sim.voxel, model)
+    }
+
+    #[test]
+    pub fn test_snapshot() {
+        let mut v = MonotonicVoxel::default();
+        v.add([0, 0, 0].into());
+        v.add([100, 0, 0].into());
+
+        let snapshot = v.clone();
+        v.add([0, 0, 1].into());
+        v.add([1, 0, 0].into());
+
+        assert_eq!(snapshot.blocks(), 2);
+        assert!(!snapshot.occupied([0, 0, 1].into()));
+        assert_eq!(v.blocks(), 4);
+        // untouched chunks stay shared
+        assert!(Arc::ptr_eq(&v.chunks[&[1, 0]], &snapshot.chunks[&[1, 0]]));
+
+        let mut v = MonotonicVoxel::default();
+        v.reserve(&BoundingBox::of_mm([0.; 3], [5.; 3]), 100);
+        assert_eq!(v.chunks.len(), 4);
+        assert_eq!(v.blocks(), 0);
+        assert_eq!(v.bounding_box().count, 0);
+        v.add([0, 0, 0].into());
+        assert_eq!(v.chunks.len(), 4);
+        assert_eq!(v.blocks(), 1);
+    }
+
+    #[test]
+    pub fn test_slab() {
+        let mut v = SlabVoxel::default();
+        let mut m = MonotonicVoxel::default();
+        // columns of ranges filled in out of order, with gaps filled later
+        for i in 0..2000 {
+            let coord = [i % 7, i % 5, (i * 37) % 61].into();
+            assert_eq!(v.add(coord), m.add(coord));
+        }
+        assert_eq!((v.blocks(), v.ranges()), (m.blocks(), m.ranges()));
+        for x in 0..7 {
+            for y in 0..5 {
+                assert_eq!(v.column(x, y), m.column(x, y));
+            }
+        }
+    }
+
+    /// Compares `MonotonicVoxel` with ranges in a slab per chunk, on the demo print. Run
+    /// with `cargo test --release -- --ignored test_slab_timing --nocapture`.
+    #[test]
+    #[ignore]
+    pub fn test_slab_timing() {
+        // interleaved, so both see the same load
+        for _ in 0..2 {
+            let (build, mesh, v, model) = time::<MonotonicVoxel>();
+            eprintln!("smallvec: build={:?}, mesh={:?}", build, mesh);
+            let (slab_build, slab_mesh, slab, slab_model) = time::<SlabVoxel>();
+            eprintln!("slab: build={:?}, mesh={:?}", slab_build, slab_mesh);
+
+            assert_eq!((v.blocks(), v.ranges()), (slab.blocks(), slab.ranges()));
+            assert_eq!(model.faces.len(), slab_model.faces.len());
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use super::chunk::CHUNK_SIZE;
+use super::{Metadata, Tags, Voxel, VoxelIdx};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Writes the faces of `v.to_model()` as obj to `w`, with vertex positions from
+/// `position`, without building the whole `Model`. Chunks are modeled and written one at a
+/// time, by rows along Y. Vertices are written before the first face using them, and
+/// dropped from the index cache once no later row can share them. Faces are grouped by
+/// tag like `Tags::group`, though groups may repeat, once in every chunk. Returns the
+/// number of faces.
+pub fn write_obj_streamed<V, W, F>(
+    v: &V,
+    tags: &Tags,
+    metadata: &Metadata,
+    mut w: W,
+    precision: usize,
+    position: F,
+) -> Result<usize>
+where
+    V: Voxel,
+    W: Write,
+    F: Fn(VoxelIdx) -> [f32; 3],
+{
+    for (key, value) in metadata.entries() {
+        writeln!(&mut w, "# {}: {}", key, value)?;
+    }
+    let bb = v.bounding_box();
+    if bb.count == 0 {
+        return Ok(0);
+    }
+
+    // obj indices of cached vertices, from 1
+    let mut indices = HashMap::<VoxelIdx, usize>::new();
+    let (mut vertices, mut faces) = (0, 0);
+    let mut group: Option<String> = None;
+    let [x0, y0] = [0, 1].map(|i| bb.bound_min[i].div_euclid(CHUNK_SIZE));
+    let [x1, y1] = [0, 1].map(|i| bb.bound_max[i].div_euclid(CHUNK_SIZE));
+    for cy in y0..=y1 {
+        for cx in x0..=x1 {
+            let mut model = v.chunk_model([cx, cy]);
+            tags.group(&mut model, v);
+            let mut groups = model.groups.iter().peekable();
+            let mut tag = None;
+            for (i, face) in model.faces.iter().enumerate() {
+                if let Some((_, name)) = groups.next_if(|(start, _)| *start == i) {
+                    tag = Some(name);
+                }
+                if tag != group.as_ref() {
+                    // faces without tag go back to the default group
+                    writeln!(&mut w, "g {}", tag.map_or("default", |t| t.as_str()))?;
+                    group = tag.cloned();
+                }
+                let mut indexed = [0; 4];
+                for (corner, vertex) in face.iter().enumerate() {
+                    let idx = model.vertices[*vertex];
+                    indexed[corner] = match indices.get(&idx) {
+                        Some(index) => *index,
+                        None => {
+                            let [x, y, z] = position(idx);
+                            writeln!(
+                                &mut w,
+                                "v {:.*} {:.*} {:.*}",
+                                precision, x, precision, y, precision, z
+                            )?;
+                            vertices += 1;
+                            indices.insert(idx, vertices);
+                            vertices
+                        }
+                    };
+                }
+                let [i0, i1, i2, i3] = indexed;
+                writeln!(&mut w, "f {} {} {} {}", i0, i1, i2, i3)?;
+                faces += 1;
+            }
+        }
+        // later rows only share vertices on the edge to the next row
+        let edge = (cy + 1) * CHUNK_SIZE;
+        indices.retain(|idx, _| idx[1] >= edge);
+    }
+    Ok(faces)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Model, MonotonicVoxel, RangeSetVoxel};
+
+    /// Faces of obj text as corners, with their group.
+    fn faces_of(text: &str) -> Vec<(Option<String>, Vec<String>)> {
+        let (mut vertices, mut faces, mut group) = (Vec::new(), Vec::new(), None);
+        for line in text.lines() {
+            if let Some(v) = line.strip_prefix("v ") {
+                vertices.push(v.to_owned());
+            } else if let Some(g) = line.strip_prefix("g ") {
+                group = Some(g.to_owned()).filter(|g| g != "default");
+            } else if let Some(f) = line.strip_prefix("f ") {
+                let corners = f
+                    .split(' ')
+                    .map(|i| vertices[i.parse::<usize>().unwrap() - 1].clone());
+                faces.push((group.clone(), corners.collect()));
+            }
+        }
+        faces.sort();
+        faces
+    }
+
+    fn check<V: Voxel>(v: &V, tags: &Tags) -> usize {
+        let position = |idx: VoxelIdx| [0, 1, 2].map(|i| idx[i] as f32);
+        let mut streamed = Vec::new();
+        let faces =
+            write_obj_streamed(v, tags, &Metadata::new(), &mut streamed, 0, position).unwrap();
+        let streamed = String::from_utf8(streamed).unwrap();
+        assert!(streamed.starts_with("# generator: tdp-tl"));
+
+        let mut model: Model = v.to_model();
+        tags.group(&mut model, v);
+        let mut whole = Vec::new();
+        model.write_obj_with(&mut whole, 0, position).unwrap();
+        let whole = String::from_utf8(whole).unwrap();
+
+        // the same faces with the same winding and tags, and every vertex written once
+        assert_eq!(faces, model.faces().len());
+        assert_eq!(faces_of(&streamed), faces_of(&whole));
+        let lines = |prefix: &str| streamed.lines().filter(|l| l.starts_with(prefix)).count();
+        assert_eq!(lines("v "), model.vertices().len());
+        let first = |prefix: &str| streamed.lines().position(|l| l.starts_with(prefix));
+        assert!(first("v ") < first("f "));
+        faces
+    }
+
+    #[test]
+    pub fn test_obj_streamed() {
+        let (mut m, mut r) = (MonotonicVoxel::default(), RangeSetVoxel::default());
+        // blocks across the edges of chunks along X and Y, with a hole in one column
+        for x in 62..66 {
+            for y in 62..66 {
+                for z in 0..3 {
+                    if [x, y, z] != [63, 64, 1] {
+                        m.add([x, y, z].into());
+                        r.add([x, y, z].into());
+                    }
+                }
+            }
+        }
+        let mut tags = Tags::default();
+        tags.add("bridge", [64, 64, 2].into());
+        tags.add("bridge", [63, 63, 2].into());
+        assert!(check(&m, &tags) > 0);
+        assert!(check(&r, &tags) > check(&m, &tags));
+        assert_eq!(check(&r, &Tags::default()), check(&r, &tags));
+
+        let empty = MonotonicVoxel::default();
+        assert_eq!(
+            write_obj_streamed(
+                &empty,
+                &tags,
+                &Metadata::new(),
+                std::io::sink(),
+                2,
+                |_| [0f32; 3]
+            )
+            .unwrap(),
+            0
+        );
+    }
+}
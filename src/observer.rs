@@ -0,0 +1,139 @@
+use super::{Frame, Segment, Simulation};
+use anyhow::Result;
+
+/// Hooks into a running simulation, for GUIs, servers and bindings. Every hook does
+/// nothing by default, and an error from any hook stops the simulation with it.
+pub trait Observer<V> {
+    /// After an extrusion move is parsed, before it is deposited.
+    fn on_segment(&mut self, _sim: &Simulation<V>, _segment: &Segment) -> Result<()> {
+        Ok(())
+    }
+
+    /// Before each layer change, after the moves of previous layers are deposited.
+    /// `layer` is the index of the next layer, as in frame names.
+    fn on_layer_complete(&mut self, _sim: &mut Simulation<V>, _layer: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// After the model of a layer is written by `generate_gcode`.
+    fn on_frame(&mut self, _sim: &Simulation<V>, _frame: &Frame) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<V> Observer<V> for () {}
+
+/// Optional observers, enabled by command line flags.
+impl<V, O: Observer<V>> Observer<V> for Option<O> {
+    fn on_segment(&mut self, sim: &Simulation<V>, segment: &Segment) -> Result<()> {
+        match self {
+            Some(o) => o.on_segment(sim, segment),
+            None => Ok(()),
+        }
+    }
+
+    fn on_layer_complete(&mut self, sim: &mut Simulation<V>, layer: usize) -> Result<()> {
+        match self {
+            Some(o) => o.on_layer_complete(sim, layer),
+            None => Ok(()),
+        }
+    }
+
+    fn on_frame(&mut self, sim: &Simulation<V>, frame: &Frame) -> Result<()> {
+        match self {
+            Some(o) => o.on_frame(sim, frame),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<V, O: Observer<V> + ?Sized> Observer<V> for &mut O {
+    fn on_segment(&mut self, sim: &Simulation<V>, segment: &Segment) -> Result<()> {
+        (**self).on_segment(sim, segment)
+    }
+
+    fn on_layer_complete(&mut self, sim: &mut Simulation<V>, layer: usize) -> Result<()> {
+        (**self).on_layer_complete(sim, layer)
+    }
+
+    fn on_frame(&mut self, sim: &Simulation<V>, frame: &Frame) -> Result<()> {
+        (**self).on_frame(sim, frame)
+    }
+}
+
+/// Both observers, the first one first.
+impl<V, A: Observer<V>, B: Observer<V>> Observer<V> for (A, B) {
+    fn on_segment(&mut self, sim: &Simulation<V>, segment: &Segment) -> Result<()> {
+        self.0.on_segment(sim, segment)?;
+        self.1.on_segment(sim, segment)
+    }
+
+    fn on_layer_complete(&mut self, sim: &mut Simulation<V>, layer: usize) -> Result<()> {
+        self.0.on_layer_complete(sim, layer)?;
+        self.1.on_layer_complete(sim, layer)
+    }
+
+    fn on_frame(&mut self, sim: &Simulation<V>, frame: &Frame) -> Result<()> {
+        self.0.on_frame(sim, frame)?;
+        self.1.on_frame(sim, frame)
+    }
+}
+
+/// Calls the closure of `simulate` before each layer change.
+pub(crate) struct OnLayer<F>(pub F);
+
+impl<V, F> Observer<V> for OnLayer<F>
+where
+    F: FnMut(&mut Simulation<V>, usize) -> Result<()>,
+{
+    fn on_layer_complete(&mut self, sim: &mut Simulation<V>, layer: usize) -> Result<()> {
+        (self.0)(sim, layer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{simulate_with, MonotonicVoxel, Params};
+
+    #[derive(Default)]
+    struct Counter {
+        segments: usize,
+        layers: Vec<usize>,
+    }
+
+    impl Observer<MonotonicVoxel> for Counter {
+        fn on_segment(&mut self, _sim: &Simulation<MonotonicVoxel>, s: &Segment) -> Result<()> {
+            assert_eq!(s.layer, self.layers.len());
+            self.segments += 1;
+            Ok(())
+        }
+
+        fn on_layer_complete(
+            &mut self,
+            _sim: &mut Simulation<MonotonicVoxel>,
+            layer: usize,
+        ) -> Result<()> {
+            self.layers.push(layer);
+            anyhow::ensure!(layer < 2, "stop");
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_observer() {
+        let gcode = "G1 X10 Y10 Z0.2\n;LAYER:0\nG1 X20 Y10 E0.1\nG1 X20 Y20 E0.2\n\
+            ;LAYER:1\nG1 X10 Y20 Z0.4 E0.3\n;LAYER:2\nG1 X10 Y10 Z0.6 E0.4\n";
+        let params = Params::default();
+
+        let mut counter = Counter::default();
+        let err = simulate_with::<MonotonicVoxel, _>(gcode, usize::MAX, &params, &mut counter);
+        assert!(err.is_err());
+        assert_eq!(counter.segments, 3);
+        assert_eq!(counter.layers, vec![1, 2]);
+
+        let mut counter = Counter::default();
+        simulate_with::<MonotonicVoxel, _>(gcode, 2, &params, &mut counter).unwrap();
+        assert_eq!(counter.layers, vec![1]);
+    }
+}
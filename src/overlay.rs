@@ -0,0 +1,204 @@
+use super::eta::clock;
+use anyhow::Result;
+
+/// Rows of 5x7 glyphs, the high bit of 5 on the left.
+const GLYPHS: &[(char, [u8; 7])] = &[
+    ('0', [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e]),
+    ('1', [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e]),
+    ('2', [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f]),
+    ('3', [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e]),
+    ('4', [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02]),
+    ('5', [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e]),
+    ('6', [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e]),
+    ('7', [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e]),
+    ('9', [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c]),
+    ('A', [0x0e, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11]),
+    ('E', [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f]),
+    ('M', [0x11, 0x1b, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('R', [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11]),
+    ('Y', [0x11, 0x11, 0x0a, 0x04, 0x04, 0x04, 0x04]),
+    ('Z', [0x1f, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1f]),
+    (':', [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c]),
+    ('%', [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03]),
+    ('/', [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00]),
+    ('-', [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00]),
+];
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+/// Space between glyphs, and around text, in glyph pixels.
+const SPACING: usize = 1;
+
+/// Statistic shown in overlays.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Field {
+    Layer,
+    Z,
+    Time,
+    Progress,
+}
+
+impl std::str::FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "layer" => Ok(Self::Layer),
+            "z" => Ok(Self::Z),
+            "time" => Ok(Self::Time),
+            "progress" => Ok(Self::Progress),
+            _ => anyhow::bail!(
+                "unknown overlay field {}, expected layer, z, time or progress",
+                s
+            ),
+        }
+    }
+}
+
+/// Statistics of a frame, for overlays.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub layer: usize,
+    /// layers of the print, none if unknown
+    pub layers: Option<usize>,
+    /// height of the nozzle in millimeters
+    pub z: f32,
+    /// seconds of print time
+    pub time: f32,
+    /// completed fraction of the print, 0 to 1, none if unknown
+    pub progress: Option<f32>,
+}
+
+/// Line of statistics burnt into the top left corner of rendered frames, in white over a
+/// darkened band, so frames make a timelapse without compositing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overlay {
+    pub fields: Vec<Field>,
+}
+
+impl std::str::FromStr for Overlay {
+    type Err = anyhow::Error;
+
+    /// Fields separated by commas like `layer,z,time,progress`.
+    fn from_str(s: &str) -> Result<Self> {
+        let fields = s
+            .split(',')
+            .map(|f| f.trim().parse())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { fields })
+    }
+}
+
+impl Overlay {
+    pub fn text(&self, stats: &Stats) -> String {
+        self.fields
+            .iter()
+            .filter_map(|f| match f {
+                Field::Layer => Some(match stats.layers {
+                    Some(layers) => format!("LAYER {}/{}", stats.layer, layers),
+                    None => format!("LAYER {}", stats.layer),
+                }),
+                Field::Z => Some(format!("Z {:.2}MM", stats.z)),
+                Field::Time => Some(clock(stats.time)),
+                Field::Progress => stats
+                    .progress
+                    .map(|p| format!("{:.0}%", (p * 100f32).clamp(0f32, 100f32))),
+            })
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+    /// Draws the overlay into rows of `width` pixels of `channels` bytes, rgb first then
+    /// alpha if any, scaled with the height of the image.
+    pub fn draw(&self, pixels: &mut [u8], width: usize, channels: usize, stats: &Stats) {
+        let height = pixels.len() / (width * channels);
+        let scale = (height / 240).max(1);
+        draw_text(pixels, width, channels, scale, &self.text(stats));
+    }
+}
+
+/// Draws `text` at the top left corner, over a darkened band, which stays visible over
+/// transparent pixels. Glyphs without a shape are left blank; lowercase letters are
+/// drawn as uppercase.
+fn draw_text(pixels: &mut [u8], width: usize, channels: usize, scale: usize, text: &str) {
+    let height = pixels.len() / (width * channels);
+    let chars = text.chars().count();
+    let band_w = ((GLYPH_WIDTH + SPACING) * chars + SPACING) * scale;
+    let band_h = (GLYPH_HEIGHT + 2 * SPACING) * scale;
+    let mut set = |x: usize, y: usize, f: &dyn Fn(u8) -> u8, alpha: u8| {
+        if x < width && y < height {
+            let at = (y * width + x) * channels;
+            for c in &mut pixels[at..at + 3] {
+                *c = f(*c);
+            }
+            if channels == 4 {
+                pixels[at + 3] = pixels[at + 3].max(alpha);
+            }
+        }
+    };
+    for y in 0..band_h {
+        for x in 0..band_w {
+            set(x, y, &|c| c / 3, 160);
+        }
+    }
+    for (i, ch) in text.chars().enumerate() {
+        let ch = ch.to_ascii_uppercase();
+        let Some((_, rows)) = GLYPHS.iter().find(|(c, _)| *c == ch) else {
+            continue;
+        };
+        let left = (SPACING + i * (GLYPH_WIDTH + SPACING)) * scale;
+        for (gy, row) in rows.iter().enumerate() {
+            for gx in 0..GLYPH_WIDTH {
+                if row & (0x10 >> gx) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let (x, y) = (left + gx * scale + dx, (SPACING + gy) * scale + dy);
+                        set(x, y, &|_| 255, 255);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_overlay() {
+        let overlay = "layer,z,time,progress".parse::<Overlay>().unwrap();
+        let mut stats = Stats {
+            layer: 12,
+            layers: Some(120),
+            z: 2.4,
+            time: 754.0,
+            progress: Some(0.456),
+        };
+        assert_eq!(overlay.text(&stats), "LAYER 12/120  Z 2.40MM  0:12:34  46%");
+        stats.progress = None;
+        assert_eq!(overlay.text(&stats), "LAYER 12/120  Z 2.40MM  0:12:34");
+        assert!("layer,speed".parse::<Overlay>().is_err());
+
+        // text is white over a darkened band, the rest is left alone
+        let (w, h) = (200, 20);
+        let mut pixels = vec![90u8; w * h * 3];
+        let overlay = Overlay {
+            fields: vec![Field::Layer],
+        };
+        overlay.draw(&mut pixels, w, 3, &stats);
+        let at = |x: usize, y: usize| pixels[(y * w + x) * 3];
+        // top left pixel of the L
+        assert_eq!(at(1, 1), 255);
+        assert_eq!(at(0, 0), 30);
+        assert_eq!(at(199, 19), 90);
+        // the band of "LAYER 12/120" is 73 pixels wide
+        assert_eq!(at(72, 0), 30);
+        assert_eq!(at(73, 0), 90);
+    }
+}
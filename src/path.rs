@@ -0,0 +1,169 @@
+use nalgebra::Vector3;
+
+/// Polyline of consecutive extrusion moves, parameterized by arc length.
+#[derive(Clone, Default, Debug)]
+pub struct Path {
+    points: Vec<Vector3<f32>>,
+    // cumulative length at each point, in millimeters
+    dist: Vec<f32>,
+    // cumulative blocks deposited until each point
+    blocks: Vec<f32>,
+    // speed of the move ending at each point, in millimeters per second
+    speeds: Vec<f32>,
+}
+
+impl Path {
+    /// Appends a move at `speed` in millimeters per second, depositing `blocks` evenly
+    /// along it.
+    pub fn push(&mut self, from: Vector3<f32>, to: Vector3<f32>, blocks: f32, speed: f32) {
+        if self.points.is_empty() {
+            self.points.push(from);
+            self.dist.push(0f32);
+            self.blocks.push(0f32);
+            self.speeds.push(speed);
+        }
+
+        let last = self.points.len() - 1;
+        let len = (to - self.points[last]).magnitude();
+        self.points.push(to);
+        self.dist.push(self.dist[last] + len);
+        self.blocks.push(self.blocks[last] + blocks);
+        self.speeds.push(speed);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.len() < 2
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.dist.clear();
+        self.blocks.clear();
+        self.speeds.clear();
+    }
+
+    /// Number of moves.
+    pub fn moves(&self) -> usize {
+        self.points.len().saturating_sub(1)
+    }
+
+    /// Index of the move containing arc length `d`.
+    pub fn move_at(&self, d: f32) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        self.locate(d).0 - 1
+    }
+
+    pub fn len(&self) -> f32 {
+        self.dist.last().copied().unwrap_or(0f32)
+    }
+
+    pub fn total_blocks(&self) -> f32 {
+        self.blocks.last().copied().unwrap_or(0f32)
+    }
+
+    pub fn start(&self) -> Vector3<f32> {
+        self.points.first().copied().unwrap_or_default()
+    }
+
+    pub fn end(&self) -> Vector3<f32> {
+        self.points.last().copied().unwrap_or_default()
+    }
+
+    /// Returns the move containing arc length `d`, and the position within the move.
+    fn locate(&self, d: f32) -> (usize, f32) {
+        let i = self
+            .dist
+            .partition_point(|&x| x < d)
+            .clamp(1, self.dist.len() - 1);
+        let len = self.dist[i] - self.dist[i - 1];
+        if len <= 0f32 {
+            return (i, 1f32);
+        }
+        (i, ((d - self.dist[i - 1]) / len).clamp(0f32, 1f32))
+    }
+
+    /// Position at arc length `d`.
+    pub fn at(&self, d: f32) -> Vector3<f32> {
+        if self.is_empty() {
+            return self.start();
+        }
+        let (i, t) = self.locate(d);
+        self.points[i - 1] + (self.points[i] - self.points[i - 1]) * t
+    }
+
+    /// Unit direction of the move containing arc length `d`.
+    pub fn direction(&self, d: f32) -> Vector3<f32> {
+        if self.is_empty() {
+            return Vector3::zeros();
+        }
+        let (i, _) = self.locate(d);
+        (self.points[i] - self.points[i - 1])
+            .try_normalize(1e-6)
+            .unwrap_or_default()
+    }
+
+    /// Blocks deposited from the start until arc length `d`.
+    pub fn blocks_at(&self, d: f32) -> f32 {
+        if self.is_empty() {
+            return 0f32;
+        }
+        let (i, t) = self.locate(d);
+        self.blocks[i - 1] + (self.blocks[i] - self.blocks[i - 1]) * t
+    }
+
+    /// Speed of the move containing arc length `d`, in millimeters per second.
+    pub fn speed_at(&self, d: f32) -> f32 {
+        if self.is_empty() {
+            return 0f32;
+        }
+        self.speeds[self.locate(d).0]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_path() {
+        let mut path = Path::default();
+        assert!(path.is_empty());
+
+        path.push(
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            10.0,
+            20.0,
+        );
+        path.push(
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(1.0, 3.0, 0.0),
+            60.0,
+            40.0,
+        );
+
+        assert_eq!(path.len(), 4.0);
+        assert_eq!(path.total_blocks(), 70.0);
+        assert_eq!(path.at(2.5), Vector3::new(1.0, 1.5, 0.0));
+        assert_eq!(path.blocks_at(0.5), 5.0);
+        assert_eq!(path.blocks_at(2.5), 40.0);
+        assert_eq!(path.blocks_at(10.0), 70.0);
+        assert_eq!(path.end(), Vector3::new(1.0, 3.0, 0.0));
+        assert_eq!(path.direction(2.5), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!((path.speed_at(0.5), path.speed_at(2.5)), (20.0, 40.0));
+
+        // non-planar move, Z rises along the move
+        let mut path = Path::default();
+        path.push(
+            Vector3::new(0.0, 0.0, 0.2),
+            Vector3::new(0.0, 4.0, 3.2),
+            50.0,
+            20.0,
+        );
+        assert_eq!(path.len(), 5.0);
+        assert_eq!(path.at(2.5), Vector3::new(0.0, 2.0, 1.7));
+        assert_eq!(path.blocks_at(2.5), 25.0);
+    }
+}
@@ -0,0 +1,150 @@
+use super::gcode::{Event, Parser};
+use super::guard::Guard;
+use super::layer::Layers;
+use super::Params;
+use nalgebra::Vector3;
+
+/// Toolhead move planned from gcode, before any voxels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentEvent {
+    /// line number of the move, from 1
+    pub line: usize,
+    pub from: Vector3<f32>,
+    pub to: Vector3<f32>,
+    /// filament extruded along the move in millimeters, 0 for travels and retractions
+    pub e: f32,
+    /// in millimeters per second
+    pub speed: f32,
+    pub layer: usize,
+    /// `;TYPE:` feature of the move, empty if none
+    pub feature: String,
+    pub tool: usize,
+}
+
+impl SegmentEvent {
+    pub fn is_extrusion(&self) -> bool {
+        self.e > 0f32
+    }
+}
+
+/// Plans moves of raw gcode bytes like the simulation does, without I/O, voxels or
+/// errors: invalid UTF-8 is replaced, lines which fail to parse are skipped, and moves
+/// to absurd or non-finite coordinates are dropped. For fuzz targets and property
+/// tests, so returned segments always have finite coordinates, extrusion and speeds.
+pub fn parse_and_plan(gcode: &[u8]) -> Vec<SegmentEvent> {
+    let gcode = String::from_utf8_lossy(gcode);
+    let mut parser = Parser::default();
+    let mut layers = Layers::new(&gcode);
+    let mut guard = Guard::new(Params::default().coordinate_limit);
+    let mut segments = Vec::new();
+
+    let (mut pos, mut e, mut feedrate) = (Vector3::<f32>::zeros(), 0f64, 1500f32);
+    let (mut layer, mut feature, mut tool) = (0usize, String::new(), 0usize);
+    let events = gcode
+        .lines()
+        .flat_map(|line| parser.parse(line).unwrap_or_default());
+    for (line, event) in events {
+        let (words, travel) = match event {
+            Event::LayerChange(l) => {
+                layer = l;
+                continue;
+            }
+            Event::Feature(f) => {
+                feature = f;
+                continue;
+            }
+            Event::ToolChange(t) => {
+                tool = t;
+                continue;
+            }
+            Event::Travel(words) => (words, true),
+            Event::Move(words) => (words, false),
+            Event::Dwell(_) | Event::Temperature { .. } | Event::Comment(_) | Event::Object(_) => {
+                continue
+            }
+        };
+
+        if let Some(f) = words.f.filter(|f| f.is_finite()) {
+            feedrate = f;
+        }
+        let dst = words.apply(pos);
+        let dst_e = match words.e {
+            Some(v) if !travel && v.is_finite() => v,
+            _ => e,
+        };
+        if !guard.check(dst) {
+            e = dst_e;
+            continue;
+        }
+        // differences of huge E values overflow
+        let extruded = Some((dst_e - e) as f32)
+            .filter(|d| d.is_finite())
+            .map_or(0f32, |d| d.max(0f32));
+        e = dst_e;
+        if extruded > 0f32 {
+            if let Some(l) = layers.extrude(dst[2]) {
+                layer = l;
+            }
+        }
+        if dst == pos && extruded == 0f32 {
+            continue;
+        }
+        segments.push(SegmentEvent {
+            line,
+            from: pos,
+            to: dst,
+            e: extruded,
+            speed: feedrate / 60f32,
+            layer,
+            feature: feature.clone(),
+            tool,
+        });
+        pos = dst;
+    }
+    segments
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_and_plan() {
+        let gcode = b"G1 F600\n;LAYER:0\n;TYPE:WALL-OUTER\nG1 X10 E1\nG1 X1e9 E2\n\
+            G1 E1.5\nG0 X10 Y10\nT1\n;LAYER:x\nG1 Y20 E3\n";
+        let segments = parse_and_plan(gcode);
+        let ends = segments
+            .iter()
+            .map(|s| (s.line, s.to, s.e))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ends,
+            [
+                (4, Vector3::new(10.0, 0.0, 0.0), 1.0),
+                (7, Vector3::new(10.0, 10.0, 0.0), 0.0),
+                (10, Vector3::new(10.0, 20.0, 0.0), 1.5),
+            ]
+        );
+        assert_eq!(segments[2].tool, 1);
+        assert_eq!(segments[2].feature, "WALL-OUTER");
+        assert_eq!(segments[0].speed, 10.0);
+
+        // garbage in, finite segments out
+        let mut seed = 1u32;
+        for _ in 0..200 {
+            let alphabet = b"G01XYZEF.-; \nT:LAYER9e\xff";
+            let bytes = (0..256)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    alphabet[seed as usize % alphabet.len()]
+                })
+                .collect::<Vec<_>>();
+            for s in parse_and_plan(&bytes) {
+                assert!(s.from.iter().chain(&s.to).all(|v| v.is_finite()));
+                assert!(s.e.is_finite() && s.speed.is_finite() && s.speed > 0f32);
+            }
+        }
+    }
+}
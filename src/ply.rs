@@ -0,0 +1,92 @@
+use super::{Model, VoxelIdx};
+use anyhow::Result;
+use std::io::Write;
+
+/// Writes `model` as binary little-endian ply to `w`, with vertex positions from
+/// `position`. Vertices carry colors and attributes of the model if it has them, as
+/// `red`, `green` and `blue`, and a `uint` property per attribute; faces stay quads, and
+/// groups are lost. Metadata goes to comments.
+pub fn write_ply<W, F>(model: &Model, w: W, position: F) -> Result<()>
+where
+    W: Write,
+    F: Fn(VoxelIdx) -> [f32; 3],
+{
+    let mut w = std::io::BufWriter::new(w);
+    writeln!(w, "ply")?;
+    writeln!(w, "format binary_little_endian 1.0")?;
+    for (key, value) in model.metadata.entries() {
+        // comments end at newlines, which values may have
+        writeln!(w, "comment {}: {}", key, value.replace(['\r', '\n'], " "))?;
+    }
+    writeln!(w, "element vertex {}", model.vertices.len())?;
+    for axis in ["x", "y", "z"] {
+        writeln!(w, "property float {}", axis)?;
+    }
+    let colored = !model.colors.is_empty();
+    if colored {
+        for channel in ["red", "green", "blue"] {
+            writeln!(w, "property uchar {}", channel)?;
+        }
+    }
+    for (name, _) in &model.attributes {
+        writeln!(w, "property uint {}", name)?;
+    }
+    writeln!(w, "element face {}", model.faces.len())?;
+    writeln!(w, "property list uchar uint vertex_indices")?;
+    writeln!(w, "end_header")?;
+
+    for (i, idx) in model.vertices.iter().enumerate() {
+        for p in position(*idx) {
+            w.write_all(&p.to_le_bytes())?;
+        }
+        if colored {
+            // markers and the toolhead are added after painting, left white
+            w.write_all(&model.colors.get(i).copied().unwrap_or([255; 3]))?;
+        }
+        for (_, values) in &model.attributes {
+            let value = values.get(i).copied().unwrap_or(0);
+            w.write_all(&value.to_le_bytes())?;
+        }
+    }
+    for face in &model.faces {
+        w.write_all(&[4u8])?;
+        for i in face {
+            w.write_all(&(*i as u32).to_le_bytes())?;
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Metadata;
+
+    #[test]
+    pub fn test_ply() {
+        let mut model = Model::default();
+        model.add_cube([0, 0, 0].into());
+        model.metadata = Metadata::new();
+        model.set_attribute("order", (0..8).collect());
+        model.set_attribute("order", (10..18).collect());
+
+        let mut buf = Vec::new();
+        write_ply(&model, &mut buf, |idx| [0, 1, 2].map(|i| idx[i] as f32)).unwrap();
+        let end = b"end_header\n";
+        let body = buf.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        let header = std::str::from_utf8(&buf[..body]).unwrap();
+        assert!(header.contains("comment generator: tdp-tl\n"));
+        assert!(header.contains("element vertex 8\n"));
+        assert!(header.ends_with(
+            "property uint order\nelement face 6\n\
+            property list uchar uint vertex_indices\nend_header\n"
+        ));
+        assert!(!header.contains("red"));
+
+        // 16 bytes per vertex, 17 per quad
+        assert_eq!(buf.len() - body, 8 * 16 + 6 * 17);
+        let vertex = &buf[body + 16..body + 32];
+        assert_eq!(u32::from_le_bytes(vertex[12..].try_into().unwrap()), 11);
+    }
+}
@@ -0,0 +1,123 @@
+use super::{render, Voxel, UNIT};
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// Distribution of ranges per column, a proxy for backend memory use and meshing cost.
+#[derive(Debug)]
+pub struct ColumnStats {
+    /// number of columns by the number of ranges in the column
+    pub histogram: BTreeMap<usize, usize>,
+    /// columns with the most ranges, as (center in millimeters, ranges), worst first
+    pub worst: Vec<([f32; 2], usize)>,
+    /// ranges per column, rows from bound_min, for the heatmap
+    counts: Vec<Vec<usize>>,
+}
+
+impl ColumnStats {
+    /// Scans every column in the bounding box of `v`, keeping `top` worst columns.
+    pub fn build<V: Voxel>(v: &V, top: usize) -> Self {
+        let bb = v.bounding_box();
+        let mut histogram = BTreeMap::new();
+        let mut worst: Vec<([i32; 2], usize)> = Vec::new();
+        let mut counts = Vec::new();
+        if bb.count == 0 {
+            return Self {
+                histogram,
+                worst: Vec::new(),
+                counts,
+            };
+        }
+
+        for y in bb.bound_min[1]..=bb.bound_max[1] {
+            let mut row = Vec::new();
+            for x in bb.bound_min[0]..=bb.bound_max[0] {
+                let n = v.column(x, y).len();
+                row.push(n);
+                if n == 0 {
+                    continue;
+                }
+                *histogram.entry(n).or_insert(0) += 1;
+                worst.push(([x, y], n));
+            }
+            counts.push(row);
+        }
+
+        worst.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        worst.truncate(top);
+        let worst = worst
+            .into_iter()
+            .map(|([x, y], n)| ([x as f32 * UNIT, y as f32 * UNIT], n))
+            .collect();
+
+        Self {
+            histogram,
+            worst,
+            counts,
+        }
+    }
+
+    pub fn columns(&self) -> usize {
+        self.histogram.values().sum()
+    }
+
+    pub fn mean(&self) -> f32 {
+        let ranges: usize = self.histogram.iter().map(|(n, count)| n * count).sum();
+        ranges as f32 / self.columns().max(1) as f32
+    }
+
+    /// Writes ranges per column as png, black for empty columns and brighter for more
+    /// ranges. Rows are flipped so +Y is up.
+    pub fn write_heatmap(&self, path: &str) -> Result<()> {
+        let h = self.counts.len();
+        let w = self.counts.first().map(|row| row.len()).unwrap_or(0);
+        anyhow::ensure!(w > 0 && h > 0, "no columns");
+        let max = self.histogram.keys().last().copied().unwrap_or(1).max(1);
+
+        let mut data = Vec::with_capacity(w * h * 3);
+        for row in self.counts.iter().rev() {
+            for &n in row {
+                data.extend_from_slice(&heat(n, max));
+            }
+        }
+        render::write_png(path, w as u32, h as u32, png::ColorType::Rgb, false, &data)
+    }
+}
+
+/// Black, through red and yellow, to white.
+fn heat(n: usize, max: usize) -> [u8; 3] {
+    if n == 0 {
+        return [0, 0, 0];
+    }
+    let t = n as f32 / max as f32;
+    let ramp = |lo: f32| ((t - lo) * 3f32).clamp(0f32, 1f32);
+    // dim but visible for a single range
+    let r = 0.25 + 0.75 * ramp(0f32);
+    [r, ramp(1f32 / 3f32), ramp(2f32 / 3f32)].map(|c| (c * 255f32).round() as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_column_stats() {
+        let mut v = MonotonicVoxel::default();
+        // column at (0, 0) with 3 ranges, two columns with a single range
+        for z in [0, 2, 4] {
+            v.add([0, 0, z].into());
+        }
+        v.add([1, 0, 0].into());
+        v.add([2, 1, 0].into());
+
+        let stats = ColumnStats::build(&v, 2);
+        assert_eq!(stats.columns(), 3);
+        assert_eq!(stats.histogram[&1], 2);
+        assert_eq!(stats.histogram[&3], 1);
+        assert_eq!(stats.worst[0], ([0f32, 0f32], 3));
+        assert_eq!(stats.worst.len(), 2);
+        assert!((stats.mean() - 5f32 / 3f32).abs() < 1e-6);
+        assert_eq!(heat(0, 3), [0, 0, 0]);
+        assert_eq!(heat(3, 3), [255, 255, 255]);
+    }
+}
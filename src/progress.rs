@@ -0,0 +1,117 @@
+use super::{simulate, Params, Simulation, Voxel};
+use anyhow::Result;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+/// Layers queued ahead of a slow consumer, before the simulation waits for it.
+const QUEUE: usize = 16;
+
+/// State after a layer is completed, for progress reports.
+#[derive(Clone, Debug)]
+pub struct LayerEvent {
+    /// index of the next layer, as in frame names
+    pub layer: usize,
+    /// simulated print time, in seconds
+    pub time: f32,
+    pub blocks: usize,
+}
+
+impl LayerEvent {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "layer": self.layer,
+            "time": self.time,
+            "blocks": self.blocks,
+        })
+    }
+}
+
+pub enum Event<V> {
+    Layer(LayerEvent),
+    /// last event, with the whole simulation
+    Done(Box<Simulation<V>>),
+}
+
+/// Events of a simulation running on the blocking pool of tokio. Dropping the stream
+/// cancels the simulation.
+pub struct SimulationStream<V> {
+    rx: mpsc::Receiver<Result<Event<V>>>,
+    params: Params,
+}
+
+impl<V> Stream for SimulationStream<V> {
+    type Item = Result<Event<V>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl<V> Drop for SimulationStream<V> {
+    fn drop(&mut self) {
+        self.params.cancel.cancel();
+    }
+}
+
+/// Simulates `gcode` like `simulate`, with `tokio::task::spawn_blocking`, so async
+/// services can forward progress without threads of their own. Must be called within a
+/// tokio runtime. Errors end the stream.
+pub fn simulate_async<V>(gcode: String, layer: usize, params: Params) -> SimulationStream<V>
+where
+    V: Voxel + Default + Clone + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(QUEUE);
+    let stream = SimulationStream {
+        rx,
+        params: params.clone(),
+    };
+
+    tokio::task::spawn_blocking(move || {
+        let on_layer = |sim: &mut Simulation<V>, layer| {
+            let event = LayerEvent {
+                layer,
+                time: sim.time,
+                blocks: sim.voxel.blocks(),
+            };
+            tx.blocking_send(Ok(Event::Layer(event)))
+                .map_err(|_| anyhow::anyhow!("stream dropped"))
+        };
+        let result = simulate(&gcode, layer, &params, on_layer);
+        // the receiver is gone if the stream was dropped, and nobody waits for the result
+        let _ = tx.blocking_send(result.map(|sim| Event::Done(Box::new(sim))));
+    });
+    stream
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+    use std::future::poll_fn;
+
+    #[test]
+    pub fn test_simulate_async() {
+        let gcode = "G1 X10 Y10 Z0.2\n;LAYER:0\nG1 X20 Y10 E0.1\n\
+            ;LAYER:1\nG1 X20 Y20 Z0.4 E0.2\n;LAYER:2\nG1 X10 Y20 Z0.6 E0.3\n";
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let (layers, blocks) = rt.block_on(async {
+            let mut stream =
+                simulate_async::<MonotonicVoxel>(gcode.to_owned(), usize::MAX, Params::default());
+            let mut layers = Vec::new();
+            while let Some(event) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+                match event.unwrap() {
+                    Event::Layer(e) => layers.push(e.layer),
+                    Event::Done(sim) => return (layers, sim.voxel.blocks()),
+                }
+            }
+            unreachable!("stream ended without a result");
+        });
+        assert_eq!(layers, vec![1, 2]);
+        assert!(blocks > 0);
+    }
+}
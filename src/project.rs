@@ -0,0 +1,76 @@
+use super::zip::Archive;
+use anyhow::Result;
+
+/// Plates with sliced gcode in a Bambu Studio or OrcaSlicer project, from its
+/// `Metadata/plate_N.gcode` files, in plate order.
+pub fn plates(archive: &Archive) -> Vec<usize> {
+    let mut plates = archive
+        .names()
+        .filter_map(|name| {
+            name.strip_prefix("Metadata/plate_")?
+                .strip_suffix(".gcode")?
+                .parse()
+                .ok()
+        })
+        .collect::<Vec<usize>>();
+    plates.sort();
+    plates
+}
+
+/// Splits `project.3mf:2` into the project and plate 2. Other filenames have no plate.
+fn split_plate(filename: &str) -> (&str, Option<usize>) {
+    if let Some((path, plate)) = filename.rsplit_once(':') {
+        if path.to_ascii_lowercase().ends_with(".3mf") {
+            if let Ok(plate) = plate.parse() {
+                return (path, Some(plate));
+            }
+        }
+    }
+    (filename, None)
+}
+
+/// Gcode of `filename`: a gcode file, or a plate of a 3MF project with sliced gcode,
+/// like `project.3mf:2` for plate 2. Projects with a single sliced plate need no plate.
+pub fn read_gcode(filename: &str) -> Result<String> {
+    let (path, plate) = split_plate(filename);
+    if !path.to_ascii_lowercase().ends_with(".3mf") {
+        return Ok(std::fs::read_to_string(path)?);
+    }
+
+    let archive = Archive::new(std::fs::read(path)?)?;
+    let plates = plates(&archive);
+    let plate = match (plate, plates.as_slice()) {
+        (_, []) => anyhow::bail!("{}: no sliced plates, slice and save the project", path),
+        (Some(plate), _) => plate,
+        (None, [plate]) => *plate,
+        (None, _) => anyhow::bail!(
+            "{}: select one of plates {:?}, like {}:{}",
+            path,
+            plates,
+            path,
+            plates[0]
+        ),
+    };
+    anyhow::ensure!(
+        plates.contains(&plate),
+        "{}: no sliced plate {}, plates are {:?}",
+        path,
+        plate,
+        plates
+    );
+    let data = archive.read(&format!("Metadata/plate_{}.gcode", plate))?;
+    Ok(String::from_utf8(data)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_split_plate() {
+        assert_eq!(split_plate("a/p.3mf:2"), ("a/p.3mf", Some(2)));
+        assert_eq!(split_plate("p.3MF"), ("p.3MF", None));
+        assert_eq!(split_plate("C:\\p.3mf"), ("C:\\p.3mf", None));
+        assert_eq!(split_plate("p.gcode:2"), ("p.gcode:2", None));
+    }
+}
@@ -0,0 +1,188 @@
+use super::cost::is_skirt;
+use super::gcode::Event;
+use nalgebra::Vector3;
+
+/// Feature of purge lines found in gcode without `;TYPE:` comments.
+pub const PURGE: &str = "PURGE";
+/// Feature of skirts found in gcode without `;TYPE:` comments, named like Cura.
+pub const SKIRT: &str = "SKIRT";
+
+/// Purge lines and skirts are this far from the part, in millimeters. Brims touch the
+/// part, so they stay with it.
+const GAP: f32 = 1.0;
+/// Strokes ending this close to their start are skirt loops, in millimeters.
+const CLOSE_DISTANCE: f32 = 1.0;
+/// First layer extrusion is within this height of the lowest one, in millimeters.
+const FIRST_LAYER: f32 = 0.1;
+
+/// Returns true for purge lines and skirts, commented or detected.
+pub fn is_purge_or_skirt(feature: &str) -> bool {
+    feature == PURGE || is_skirt(feature)
+}
+
+/// Continuous extrusion, from the first to the last of its events.
+struct Stroke {
+    events: std::ops::Range<usize>,
+    start: Vector3<f32>,
+    end: Vector3<f32>,
+    /// XY bounds of each move
+    moves: Vec<([f32; 2], [f32; 2])>,
+}
+
+impl Stroke {
+    /// Returns true if no move of the stroke comes within `GAP` of the XY box.
+    fn is_clear_of(&self, bounds: &Option<([f32; 2], [f32; 2])>) -> bool {
+        let Some((lo, hi)) = bounds else {
+            return true;
+        };
+        self.moves
+            .iter()
+            .all(|(min, max)| (0..2).any(|i| max[i] < lo[i] - GAP || min[i] > hi[i] + GAP))
+    }
+}
+
+fn extend(bounds: &mut Option<([f32; 2], [f32; 2])>, min: [f32; 2], max: [f32; 2]) {
+    let (lo, hi) = bounds.get_or_insert((min, max));
+    for i in 0..2 {
+        lo[i] = lo[i].min(min[i]);
+        hi[i] = hi[i].max(max[i]);
+    }
+}
+
+/// Tags purge lines and skirts of gcode without `;TYPE:` comments, like gcode of
+/// minimal post-processors, with `PURGE` and `SKIRT` features.
+///
+/// They are the first strokes of the first layer, printed before the part and clear of
+/// it: clear of the bounds of the rest of the first layer, and of the layers above.
+/// Skirts close into loops, purge lines don't. Bounds are boxes, so strokes within the
+/// box of a part, like between parts, stay with it.
+pub fn tag(events: &mut Vec<(usize, Event)>) {
+    if events.iter().any(|(_, e)| matches!(e, Event::Feature(_))) {
+        return;
+    }
+
+    let mut strokes: Vec<Stroke> = Vec::new();
+    let mut above = None;
+    let mut pos = Vector3::<f32>::zeros();
+    let mut e = 0f64;
+    let mut lowest = f32::MAX;
+    let mut extruding = false;
+    for (i, (_, event)) in events.iter().enumerate() {
+        let (to, extrudes) = match event {
+            Event::Travel(words) => (words.apply(pos), false),
+            Event::Move(words) => {
+                let to = words.apply(pos);
+                let dst_e = words.e.unwrap_or(e);
+                let extrudes = dst_e > e;
+                e = dst_e;
+                (to, extrudes)
+            }
+            _ => continue,
+        };
+        let from = std::mem::replace(&mut pos, to);
+        if !extrudes {
+            extruding = false;
+            continue;
+        }
+        let min = [from[0].min(to[0]), from[1].min(to[1])];
+        let max = [from[0].max(to[0]), from[1].max(to[1])];
+        if to[2] > lowest + FIRST_LAYER {
+            extend(&mut above, min, max);
+            continue;
+        }
+        lowest = lowest.min(to[2]);
+        match strokes.last_mut() {
+            Some(stroke) if extruding => {
+                stroke.events.end = i + 1;
+                stroke.end = to;
+                stroke.moves.push((min, max));
+            }
+            _ => strokes.push(Stroke {
+                events: i..i + 1,
+                start: from,
+                end: to,
+                moves: vec![(min, max)],
+            }),
+        }
+        extruding = true;
+    }
+    if above.is_none() {
+        return;
+    }
+
+    // bounds of the first layer after each stroke
+    let mut rest = vec![None; strokes.len() + 1];
+    for i in (0..strokes.len()).rev() {
+        rest[i] = rest[i + 1];
+        for (min, max) in &strokes[i].moves {
+            extend(&mut rest[i], *min, *max);
+        }
+    }
+    // the longest run of first strokes, clear of the part
+    let count = (1..strokes.len())
+        .rev()
+        .find(|&n| {
+            strokes[..n]
+                .iter()
+                .all(|s| s.is_clear_of(&rest[n]) && s.is_clear_of(&above))
+        })
+        .unwrap_or(0);
+
+    // features from the last stroke, so indices of earlier strokes stay
+    for stroke in strokes[..count].iter().rev() {
+        let closed = (stroke.end - stroke.start).xy().magnitude() <= CLOSE_DISTANCE;
+        let feature = if closed { SKIRT } else { PURGE };
+        let line = events[stroke.events.start].0;
+        let end = events[stroke.events.end - 1].0;
+        events.insert(stroke.events.end, (end, Event::Feature(String::new())));
+        events.insert(
+            stroke.events.start,
+            (line, Event::Feature(feature.to_owned())),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::gcode::GcodeStream;
+
+    fn features(gcode: &str) -> Vec<String> {
+        let mut events = GcodeStream::new(gcode)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        tag(&mut events);
+        events
+            .into_iter()
+            .filter_map(|(_, e)| match e {
+                Event::Feature(f) => Some(f),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_purge() {
+        // purge line at the front of the bed, a skirt loop, then a 10mm square part
+        let purge = "G1 X10 Y-3 Z0.2\nG1 X60 E5\nG1 X100 E10\nG0 X80 Y80\n";
+        let skirt = "G1 X120 Y80 E12\nG1 X120 Y120 E14\nG1 X80 Y120 E16\nG1 X80 Y80 E18\n";
+        let part = "G0 X95 Y95\nG1 X105 Y95 E19\nG1 X105 Y105 E20\nG1 X95 Y105 E21\n\
+            G1 X95 Y95 E22\nG1 Z0.4\nG1 X105 Y95 E23\nG1 X105 Y105 E24\n";
+        let gcode = format!("{}{}{}", purge, skirt, part);
+        assert_eq!(features(&gcode), [PURGE, "", SKIRT, ""]);
+
+        // brims touch the part
+        let brim = "G1 X94.5 Y94.5 Z0.2 E11\nG1 X105.5 Y94.5 E12\n\
+            G1 X105.5 Y105.5 E13\nG1 X94.5 Y105.5 E14\nG1 X94.5 Y94.5 E15\n";
+        assert_eq!(features(&format!("{}{}{}", purge, brim, part)), [PURGE, ""]);
+
+        // nothing before the part, or without layers above
+        assert!(features(part).is_empty());
+        assert!(features(&format!("{}{}", purge, skirt)).is_empty());
+
+        // comments win
+        let commented = format!(";TYPE:SKIRT\n{}{}", skirt, part);
+        assert_eq!(features(&commented), ["SKIRT"]);
+        assert!(is_purge_or_skirt(PURGE) && is_purge_or_skirt("Skirt/Brim"));
+    }
+}
@@ -0,0 +1,116 @@
+use super::{simulate, MonotonicVoxel, Params, Simulation, Voxel, UNIT};
+use numpy::ndarray::Array3;
+use numpy::{IntoPyArray, PyArray3};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// Gcode simulation, run on construction.
+#[pyclass(unsendable)]
+pub struct GcodeSimulator {
+    sim: Simulation<MonotonicVoxel>,
+}
+
+#[pymethods]
+impl GcodeSimulator {
+    /// Simulates `gcode` text, or the file at `path`, before `layer` if given.
+    /// `on_layer(layer, blocks)` is called before each layer change.
+    #[new]
+    #[pyo3(signature = (gcode=None, path=None, layer=None, step_size=0.1, on_layer=None))]
+    fn new(
+        py: Python<'_>,
+        gcode: Option<String>,
+        path: Option<String>,
+        layer: Option<usize>,
+        step_size: f32,
+        on_layer: Option<PyObject>,
+    ) -> PyResult<Self> {
+        let gcode = match (gcode, path) {
+            (Some(gcode), None) => gcode,
+            (None, Some(path)) => crate::project::read_gcode(&path)?,
+            _ => return Err(PyValueError::new_err("either gcode or path is required")),
+        };
+        let params = Params {
+            step_size,
+            ..Params::default()
+        };
+        let sim = simulate(
+            &gcode,
+            layer.unwrap_or(usize::MAX),
+            &params,
+            |sim: &mut Simulation<MonotonicVoxel>, layer| {
+                if let Some(on_layer) = &on_layer {
+                    on_layer.call1(py, (layer, sim.voxel.blocks()))?;
+                }
+                Ok(())
+            },
+        )?;
+        Ok(Self { sim })
+    }
+
+    /// Size of voxels, in millimeters.
+    #[classattr]
+    fn unit() -> f32 {
+        UNIT
+    }
+
+    /// Number of occupied voxels.
+    #[getter]
+    fn blocks(&self) -> usize {
+        self.sim.voxel.blocks()
+    }
+
+    /// Simulated print time, in seconds.
+    #[getter]
+    fn time(&self) -> f32 {
+        self.sim.time
+    }
+
+    /// Inclusive voxel index bounds, `((x0, y0, z0), (x1, y1, z1))`, or None if empty.
+    fn bounding_box(&self) -> Option<([i32; 3], [i32; 3])> {
+        let bb = self.sim.voxel.bounding_box();
+        let idx = |v: super::VoxelIdx| [v[0], v[1], v[2]];
+        (bb.count > 0).then(|| (idx(bb.bound_min), idx(bb.bound_max)))
+    }
+
+    fn occupied(&self, x: i32, y: i32, z: i32) -> bool {
+        self.sim.voxel.occupied([x, y, z].into())
+    }
+
+    /// Occupied z ranges of a column, as `(start, end)` with `end` excluded.
+    fn column(&self, x: i32, y: i32) -> Vec<(i32, i32)> {
+        let column = self.sim.voxel.column(x, y);
+        column.into_iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    /// Occupancy of voxels from `z0` up to `z1`, excluded, over the bounding box, as a
+    /// bool array indexed `[z, y, x]` from the bounding box minimum.
+    fn slab<'py>(&self, py: Python<'py>, z0: i32, z1: i32) -> Bound<'py, PyArray3<bool>> {
+        let bb = self.sim.voxel.bounding_box();
+        let (min, max) = (bb.bound_min, bb.bound_max);
+        let [nx, ny] = if bb.count > 0 {
+            [0, 1].map(|i| (max[i] - min[i] + 1) as usize)
+        } else {
+            [0, 0]
+        };
+        let nz = (z1 - z0).max(0) as usize;
+
+        let mut slab = Array3::from_elem((nz, ny, nx), false);
+        for y in 0..ny {
+            for x in 0..nx {
+                let column = self.sim.voxel.column(min[0] + x as i32, min[1] + y as i32);
+                for r in column {
+                    for z in r.start.max(z0)..r.end.min(z1) {
+                        slab[((z - z0) as usize, y, x)] = true;
+                    }
+                }
+            }
+        }
+        slab.into_pyarray_bound(py)
+    }
+}
+
+#[pymodule]
+fn tdp_tl(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<GcodeSimulator>()?;
+    Ok(())
+}
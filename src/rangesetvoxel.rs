@@ -1,23 +1,65 @@
-use super::{BoundingBox, Model, Voxel, VoxelIdx};
+use super::chunk::{chunk_of, chunks_in, Dirty};
+use super::{BoundingBox, ChunkId, Model, Voxel, VoxelIdx};
 use rangemap::RangeSet;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
-#[derive(Default)]
+// grouped by chunks, clones are cheap snapshots like MonotonicVoxel
+#[derive(Default, Clone)]
 pub struct RangeSetVoxel {
-    ranges: RangeSet<VoxelIdx>,
+    chunks: BTreeMap<ChunkId, Arc<RangeSet<VoxelIdx>>>,
     bb: BoundingBox,
+    dirty: Dirty,
+}
+
+impl RangeSetVoxel {
+    fn iter_ranges(&self) -> impl Iterator<Item = &std::ops::Range<VoxelIdx>> {
+        self.chunks.values().flat_map(|ranges| ranges.iter())
+    }
+
+    /// Adds faces of `range` of a column to `model`, on every side.
+    fn range_faces(&self, model: &mut Model, range: &std::ops::Range<VoxelIdx>) {
+        assert_eq!(range.start.xy(), range.end.xy());
+        let x = range.start[0];
+        let y = range.start[1];
+
+        let range_z = range.start[2]..range.end[2];
+
+        let up = VoxelIdx::from([1, 1, 0]);
+        model.add_face([x, y, range_z.start].into(), up);
+        model.add_face([x, y, range_z.end].into(), up);
+
+        let faces = [
+            ([1, 0], [1, 1, 1], [0, -1, -1]),
+            ([-1, 0], [0, 0, 0], [0, 1, 1]),
+            ([0, 1], [1, 1, 1], [-1, 0, -1]),
+            ([0, -1], [0, 0, 0], [1, 0, 1]),
+        ];
+
+        for ([dx, dy], offset, dir) in faces {
+            for z in range_z.clone() {
+                if !self.occupied([x + dx, y + dy, z].into()) {
+                    model.add_face(
+                        [x + offset[0], y + offset[1], z + offset[2]].into(),
+                        dir.into(),
+                    );
+                }
+            }
+        }
+    }
 }
 
 impl Voxel for RangeSetVoxel {
     fn blocks(&self) -> usize {
         let mut count = 0usize;
-        for r in self.ranges.iter() {
+        for r in self.iter_ranges() {
             count += (r.end[2] - r.start[2]) as usize;
         }
         count
     }
 
     fn ranges(&self) -> usize {
-        self.ranges.iter().count()
+        self.iter_ranges().count()
     }
 
     fn bounding_box(&self) -> &BoundingBox {
@@ -25,7 +67,10 @@ impl Voxel for RangeSetVoxel {
     }
 
     fn occupied(&self, coord: VoxelIdx) -> bool {
-        self.ranges.contains(&coord)
+        match self.chunks.get(&chunk_of(coord[0], coord[1])) {
+            Some(ranges) => ranges.contains(&coord),
+            None => false,
+        }
     }
 
     fn add(&mut self, coord: VoxelIdx) -> bool {
@@ -34,42 +79,51 @@ impl Voxel for RangeSetVoxel {
         }
 
         let end = coord + VoxelIdx::new([0, 0, 1]);
-        self.ranges.insert(coord..end);
+        let chunk = self.chunks.entry(chunk_of(coord[0], coord[1])).or_default();
+        Arc::make_mut(chunk).insert(coord..end);
         self.bb.add(coord);
+        self.dirty.add(coord);
         true
     }
 
+    fn column(&self, x: i32, y: i32) -> Vec<std::ops::Range<i32>> {
+        let ranges = match self.chunks.get(&chunk_of(x, y)) {
+            Some(ranges) => ranges,
+            None => return Vec::new(),
+        };
+        let column = VoxelIdx::new([x, y, i32::MIN])..VoxelIdx::new([x, y, i32::MAX]);
+        ranges
+            .overlapping(&column)
+            .map(|r| r.start[2]..r.end[2])
+            .collect()
+    }
+
+    fn take_dirty(&mut self) -> Vec<ChunkId> {
+        self.dirty.take()
+    }
+
+    /// Creates empty chunks of `bounds`, so chunks are not allocated while depositing.
+    fn reserve(&mut self, bounds: &BoundingBox, _blocks: usize) {
+        for id in chunks_in(bounds) {
+            self.chunks.entry(id).or_default();
+        }
+    }
+
+    fn chunk_model(&self, chunk: ChunkId) -> Model {
+        let mut model = Model::default();
+        if let Some(ranges) = self.chunks.get(&chunk) {
+            for range in ranges.iter() {
+                self.range_faces(&mut model, range);
+            }
+        }
+        model
+    }
+
     fn to_model(&self) -> Model {
         let mut model = Model::default();
 
-        for range in self.ranges.iter() {
-            assert_eq!(range.start.xy(), range.end.xy());
-            let x = range.start[0];
-            let y = range.start[1];
-
-            let range_z = range.start[2]..range.end[2];
-
-            let up = VoxelIdx::from([1, 1, 0]);
-            model.add_face([x, y, range_z.start].into(), up);
-            model.add_face([x, y, range_z.end].into(), up);
-
-            let faces = [
-                ([1, 0], [1, 1, 1], [0, -1, -1]),
-                ([-1, 0], [0, 0, 0], [0, 1, 1]),
-                ([0, 1], [1, 1, 1], [-1, 0, -1]),
-                ([0, -1], [0, 0, 0], [1, 0, 1]),
-            ];
-
-            for ([dx, dy], offset, dir) in faces {
-                for z in range_z.clone() {
-                    if !self.occupied([x + dx, y + dy, z].into()) {
-                        model.add_face(
-                            [x + offset[0], y + offset[1], z + offset[2]].into(),
-                            dir.into(),
-                        );
-                    }
-                }
-            }
+        for range in self.iter_ranges() {
+            self.range_faces(&mut model, range);
         }
 
         model
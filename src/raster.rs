@@ -0,0 +1,237 @@
+use super::{Voxel, VoxelIdx};
+use nalgebra::Vector3;
+
+/// Solid in voxel units, rasterized by the voxels whose centers it contains. Voxel centers
+/// are at integer coordinates, like positions rounded to voxels.
+pub trait Shape {
+    /// Voxels which may be inside, lowest and highest.
+    fn bounds(&self) -> (VoxelIdx, VoxelIdx);
+    fn contains(&self, p: Vector3<f32>) -> bool;
+}
+
+/// Bounds of points within `margin` of any of `points`.
+fn bounds_of(points: &[Vector3<f32>], margin: [f32; 3]) -> (VoxelIdx, VoxelIdx) {
+    let lo = [0, 1, 2].map(|i| {
+        let min = points.iter().map(|p| p[i]).fold(f32::INFINITY, f32::min);
+        (min - margin[i]).floor() as i32
+    });
+    let hi = [0, 1, 2].map(|i| {
+        let max = points
+            .iter()
+            .map(|p| p[i])
+            .fold(f32::NEG_INFINITY, f32::max);
+        (max + margin[i]).ceil() as i32
+    });
+    (lo.into(), hi.into())
+}
+
+/// Sphere of `radius` swept from `a` to `b`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capsule {
+    pub a: Vector3<f32>,
+    pub b: Vector3<f32>,
+    pub radius: f32,
+}
+
+impl Capsule {
+    /// Squared distance of `p` from the segment.
+    fn distance_squared(&self, p: Vector3<f32>) -> f32 {
+        let d = self.b - self.a;
+        let len2 = d.norm_squared();
+        let t = if len2 > 0f32 {
+            ((p - self.a).dot(&d) / len2).clamp(0f32, 1f32)
+        } else {
+            0f32
+        };
+        (p - (self.a + d * t)).norm_squared()
+    }
+}
+
+impl Shape for Capsule {
+    fn bounds(&self) -> (VoxelIdx, VoxelIdx) {
+        bounds_of(&[self.a, self.b], [self.radius; 3])
+    }
+
+    fn contains(&self, p: Vector3<f32>) -> bool {
+        self.distance_squared(p) <= self.radius * self.radius
+    }
+}
+
+/// Box of `half` extents along orthonormal `axes`, around `center`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrientedBox {
+    pub center: Vector3<f32>,
+    pub axes: [Vector3<f32>; 3],
+    pub half: [f32; 3],
+}
+
+impl OrientedBox {
+    /// Box from `a` to `b` with a `width` by `height` cross-section, level across the
+    /// segment, like a bead of rectangular section or the toolhead sweeping along a move.
+    /// Vertical segments are boxes aligned to X and Y.
+    pub fn along(a: Vector3<f32>, b: Vector3<f32>, width: f32, height: f32) -> Self {
+        let d = b - a;
+        let len = d.norm();
+        let forward = if len > 0f32 { d / len } else { Vector3::x() };
+        let side = Vector3::z().cross(&forward);
+        let side = if side.norm() > 1e-6 {
+            side.normalize()
+        } else {
+            Vector3::y()
+        };
+        let up = forward.cross(&side);
+        Self {
+            center: (a + b) / 2f32,
+            axes: [forward, side, up],
+            half: [len / 2f32, width / 2f32, height / 2f32],
+        }
+    }
+}
+
+impl Shape for OrientedBox {
+    fn bounds(&self) -> (VoxelIdx, VoxelIdx) {
+        // extent along each world axis, from the projections of the box axes
+        let margin =
+            [0, 1, 2].map(|i| (0..3).map(|a| (self.axes[a][i] * self.half[a]).abs()).sum());
+        bounds_of(&[self.center], margin)
+    }
+
+    fn contains(&self, p: Vector3<f32>) -> bool {
+        let d = p - self.center;
+        (0..3).all(|a| d.dot(&self.axes[a]).abs() <= self.half[a])
+    }
+}
+
+/// Level cross-section of `half_width` and `half_height` swept from `a` to `b`, bounded
+/// by the super-ellipse of `exponent`: 2 is an ellipse, higher is flatter at the sides
+/// and top. Its ends are rounded in XY, like beads at the ends of moves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sweep {
+    pub a: Vector3<f32>,
+    pub b: Vector3<f32>,
+    pub half_width: f32,
+    pub half_height: f32,
+    pub exponent: f32,
+}
+
+impl Shape for Sweep {
+    fn bounds(&self) -> (VoxelIdx, VoxelIdx) {
+        bounds_of(
+            &[self.a, self.b],
+            [self.half_width, self.half_width, self.half_height],
+        )
+    }
+
+    fn contains(&self, p: Vector3<f32>) -> bool {
+        // distance in XY from the segment, and height from it along Z
+        let d = (self.b - self.a).xy();
+        let len2 = d.norm_squared();
+        let t = if len2 > 0f32 {
+            ((p - self.a).xy().dot(&d) / len2).clamp(0f32, 1f32)
+        } else {
+            0f32
+        };
+        let c = self.a + (self.b - self.a) * t;
+        let across = (p - c).xy().norm() / self.half_width;
+        let up = (p[2] - c[2]).abs() / self.half_height;
+        across.powf(self.exponent) + up.powf(self.exponent) <= 1f32
+    }
+}
+
+/// Calls `f` with each voxel of `shape`, z slowest, so layers fill from the bottom.
+pub fn rasterize(shape: &impl Shape, mut f: impl FnMut(VoxelIdx) -> bool) {
+    let (lo, hi) = shape.bounds();
+    for z in lo[2]..=hi[2] {
+        for y in lo[1]..=hi[1] {
+            for x in lo[0]..=hi[0] {
+                let p = Vector3::new(x as f32, y as f32, z as f32);
+                if shape.contains(p) && !f([x, y, z].into()) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Voxels of `shape`, z slowest.
+pub fn voxels(shape: &impl Shape) -> Vec<VoxelIdx> {
+    let mut voxels = Vec::new();
+    rasterize(shape, |c| {
+        voxels.push(c);
+        true
+    });
+    voxels
+}
+
+/// Adds voxels of `shape` to `v` from the bottom up, until `limit` are added. Returns how
+/// many were added, without those already occupied.
+pub fn fill<V: Voxel + ?Sized>(v: &mut V, shape: &impl Shape, limit: usize) -> usize {
+    let mut added = 0;
+    if limit == 0 {
+        return 0;
+    }
+    rasterize(shape, |c| {
+        if v.add(c) {
+            added += 1;
+        }
+        added < limit
+    });
+    added
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_raster() {
+        // a sphere of radius 1 covers the center and its 6 neighbors
+        let point = Capsule {
+            a: Vector3::zeros(),
+            b: Vector3::zeros(),
+            radius: 1.0,
+        };
+        assert_eq!(voxels(&point).len(), 7);
+
+        let capsule = Capsule {
+            a: Vector3::new(0.0, 0.0, 0.0),
+            b: Vector3::new(10.0, 0.0, 0.0),
+            radius: 2.0,
+        };
+        let cells = voxels(&capsule);
+        assert!(cells.contains(&[12, 0, 0].into()) && !cells.contains(&[13, 0, 0].into()));
+        assert!(cells.contains(&[5, 0, 2].into()) && !cells.contains(&[5, 2, 2].into()));
+        // layers fill from the bottom
+        assert_eq!(cells[0][2], -2);
+
+        // a diagonal box along the XY diagonal, 1.2 voxels wide and a voxel high
+        let b = OrientedBox::along(
+            Vector3::new(-1.5, -1.5, 0.0),
+            Vector3::new(1.5, 1.5, 0.0),
+            1.2,
+            1.0,
+        );
+        let cells = voxels(&b);
+        assert!(cells.contains(&[1, 1, 0].into()) && !cells.contains(&[2, 2, 0].into()));
+        assert!(!cells.contains(&[1, -1, 0].into()));
+        assert!(cells.iter().all(|c| c[2] == 0));
+
+        // flatter sections cover the corners of their bounds
+        let sweep = |exponent| Sweep {
+            a: Vector3::zeros(),
+            b: Vector3::new(10.0, 0.0, 0.0),
+            half_width: 5.0,
+            half_height: 1.5,
+            exponent,
+        };
+        let (oval, flat) = (voxels(&sweep(2.0)), voxels(&sweep(6.0)));
+        assert!(!oval.contains(&[5, 4, 1].into()) && flat.contains(&[5, 4, 1].into()));
+        assert!(oval.contains(&[5, 5, 0].into()) && !oval.contains(&[5, 0, 3].into()));
+
+        let mut v = MonotonicVoxel::default();
+        assert_eq!(fill(&mut v, &capsule, 10), 10);
+        assert_eq!(fill(&mut v, &point, 100), 7);
+        assert_eq!(fill(&mut v, &point, 100), 0);
+    }
+}
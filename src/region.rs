@@ -0,0 +1,202 @@
+use anyhow::Result;
+use serde_json::Value;
+
+/// Area of the bed in millimeters, bounded by polygon rings with the even-odd rule, so
+/// rings inside rings are holes, like holes of GeoJSON polygons.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    rings: Vec<Vec<[f32; 2]>>,
+}
+
+impl Region {
+    pub fn new(rings: Vec<Vec<[f32; 2]>>) -> Result<Self> {
+        anyhow::ensure!(!rings.is_empty(), "region without polygons");
+        for ring in &rings {
+            anyhow::ensure!(ring.len() >= 3, "polygon with less than 3 points");
+            anyhow::ensure!(
+                ring.iter().flatten().all(|v| v.is_finite()),
+                "polygon with non-finite coordinates"
+            );
+        }
+        Ok(Self { rings })
+    }
+
+    /// Rings of Polygon and MultiPolygon geometries of GeoJSON, also within features,
+    /// feature collections and geometry collections. Coordinates are in millimeters.
+    pub fn from_geojson(json: &Value) -> Result<Self> {
+        fn ring(points: &Value) -> Option<Vec<[f32; 2]>> {
+            let points = points.as_array()?.iter().map(|p| {
+                let p = p.as_array()?;
+                Some([p.first()?.as_f64()? as f32, p.get(1)?.as_f64()? as f32])
+            });
+            points.collect()
+        }
+        fn polygon(rings: &Value, out: &mut Vec<Vec<[f32; 2]>>) -> Option<()> {
+            for r in rings.as_array()? {
+                out.push(ring(r)?);
+            }
+            Some(())
+        }
+        fn walk(json: &Value, out: &mut Vec<Vec<[f32; 2]>>) -> Result<()> {
+            let invalid = || anyhow::anyhow!("invalid geojson coordinates");
+            match json["type"].as_str() {
+                Some("FeatureCollection") => {
+                    for feature in json["features"].as_array().into_iter().flatten() {
+                        walk(feature, out)?;
+                    }
+                }
+                Some("Feature") => walk(&json["geometry"], out)?,
+                Some("GeometryCollection") => {
+                    for geometry in json["geometries"].as_array().into_iter().flatten() {
+                        walk(geometry, out)?;
+                    }
+                }
+                Some("Polygon") => polygon(&json["coordinates"], out).ok_or_else(invalid)?,
+                Some("MultiPolygon") => {
+                    let polygons = json["coordinates"].as_array().ok_or_else(invalid)?;
+                    for rings in polygons {
+                        polygon(rings, out).ok_or_else(invalid)?;
+                    }
+                }
+                _ => (),
+            }
+            Ok(())
+        }
+
+        let mut rings = Vec::new();
+        walk(json, &mut rings)?;
+        Self::new(rings)
+    }
+
+    /// Whether `p` is inside, by the number of rings crossed by a ray along +X.
+    pub fn contains(&self, p: [f32; 2]) -> bool {
+        let mut inside = false;
+        for ring in &self.rings {
+            let mut j = ring.len() - 1;
+            for i in 0..ring.len() {
+                let (a, b) = (ring[i], ring[j]);
+                if (a[1] > p[1]) != (b[1] > p[1])
+                    && p[0] < a[0] + (p[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0])
+                {
+                    inside = !inside;
+                }
+                j = i;
+            }
+        }
+        inside
+    }
+
+    /// Fractions of the way from `a` to `b` where edges are crossed, ascending, without
+    /// the ends.
+    pub fn crossings(&self, a: [f32; 2], b: [f32; 2]) -> Vec<f32> {
+        let d = [b[0] - a[0], b[1] - a[1]];
+        let mut ts = Vec::new();
+        for ring in &self.rings {
+            let mut j = ring.len() - 1;
+            for i in 0..ring.len() {
+                let (p, q) = (ring[j], ring[i]);
+                let e = [q[0] - p[0], q[1] - p[1]];
+                let denom = d[0] * e[1] - d[1] * e[0];
+                j = i;
+                if denom == 0f32 {
+                    continue;
+                }
+                let w = [p[0] - a[0], p[1] - a[1]];
+                let t = (w[0] * e[1] - w[1] * e[0]) / denom;
+                let s = (w[0] * d[1] - w[1] * d[0]) / denom;
+                if t > 0f32 && t < 1f32 && (0f32..=1f32).contains(&s) {
+                    ts.push(t);
+                }
+            }
+        }
+        ts.sort_by(f32::total_cmp);
+        ts.dedup();
+        ts
+    }
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, ring) in self.rings.iter().enumerate() {
+            if i > 0 {
+                write!(f, "|")?;
+            }
+            for (j, [x, y]) in ring.iter().enumerate() {
+                if j > 0 {
+                    write!(f, ";")?;
+                }
+                write!(f, "{},{}", x, y)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Region {
+    type Err = anyhow::Error;
+
+    /// GeoJSON of a `.json` or `.geojson` file, or rings as `x,y;x,y;x,y`, separated by
+    /// `|`, like `10,10;50,10;50,50;10,50`.
+    fn from_str(s: &str) -> Result<Self> {
+        if s.ends_with(".json") || s.ends_with(".geojson") {
+            let json = serde_json::from_slice(&std::fs::read(s)?)?;
+            return Self::from_geojson(&json).map_err(|e| e.context(s.to_owned()));
+        }
+        let point = |p: &str| -> Result<[f32; 2]> {
+            let parse = |v: &str| {
+                v.trim()
+                    .parse::<f32>()
+                    .map_err(|e| anyhow::anyhow!("point {}: {}", p, e))
+            };
+            match p.split_once(',') {
+                Some((x, y)) => Ok([parse(x)?, parse(y)?]),
+                None => anyhow::bail!("expected point as x,y, got {}", p),
+            }
+        };
+        let rings = s
+            .split('|')
+            .map(|ring| ring.split(';').map(point).collect())
+            .collect::<Result<_>>()?;
+        Self::new(rings)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_region() {
+        // square with a square hole
+        let region: Region = "0,0;10,0;10,10;0,10|4,4;6,4;6,6;4,6".parse().unwrap();
+        assert!(region.contains([1.0, 1.0]));
+        assert!(!region.contains([5.0, 5.0]));
+        assert!(!region.contains([11.0, 5.0]));
+        assert_eq!(
+            region.crossings([-5.0, 5.0], [15.0, 5.0]),
+            vec![0.25, 0.45, 0.55, 0.75]
+        );
+        assert_eq!(region.to_string().parse::<Region>().unwrap(), region);
+
+        let json = serde_json::json!({
+            "type": "FeatureCollection",
+            "features": [{
+                "type": "Feature",
+                "geometry": {
+                    "type": "MultiPolygon",
+                    "coordinates": [
+                        [[[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]]],
+                        [[[20, 0], [30, 0], [30, 10], [20, 0]]],
+                    ],
+                },
+            }],
+        });
+        let region = Region::from_geojson(&json).unwrap();
+        assert!(region.contains([5.0, 5.0]) && region.contains([28.0, 2.0]));
+        assert!(!region.contains([15.0, 5.0]));
+
+        assert!("0,0;1,1".parse::<Region>().is_err());
+        assert!("0,0;1;1,1".parse::<Region>().is_err());
+        assert!(Region::from_geojson(&serde_json::json!({"type": "Point"})).is_err());
+    }
+}
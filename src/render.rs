@@ -0,0 +1,882 @@
+use super::overlay::{Overlay, Stats};
+use super::voxelidx::Traversal;
+use super::{color::Colors, storage, BoundingBox, Voxel, VoxelIdx, UNIT};
+use anyhow::Result;
+use nalgebra::Vector3;
+use rayon::prelude::*;
+
+/// Grids larger than this many cells are downsampled.
+const MAX_CELLS: usize = 1 << 28;
+
+/// Base color of voxels without a filament color.
+const ALBEDO: [f32; 3] = [0.8, 0.8, 0.78];
+const GROUND: [f32; 3] = [0.45, 0.47, 0.5];
+/// Flat color of image regions hidden by the toolhead.
+const OCCLUDED: [u8; 3] = [24, 24, 26];
+
+/// Camera and shading options.
+#[derive(Clone, Debug)]
+pub struct Still {
+    pub width: u32,
+    pub height: u32,
+    /// camera orbit around the model, in degrees
+    pub azimuth: f32,
+    pub elevation: f32,
+    /// ambient occlusion rays per pixel, 0 to disable
+    pub ao_samples: usize,
+    /// fixed camera instead of the orbit
+    pub camera: Option<Camera>,
+    /// text burnt into frames rendered with statistics
+    pub overlay: Option<Overlay>,
+    /// rgba with the sky and ground left transparent, so only the model covers frames
+    /// composited over other backgrounds
+    pub transparent: bool,
+    /// two views for 3D playback instead of one
+    pub stereo: Option<Stereo>,
+    /// also writes depth and normal buffers next to frames, see `render_frame`
+    pub aovs: bool,
+    /// top-down view of the bed at true scale instead of the orbit
+    pub ortho: Option<Ortho>,
+}
+
+impl Still {
+    /// Bytes per pixel of renders.
+    fn channels(&self) -> usize {
+        if self.transparent {
+            4
+        } else {
+            3
+        }
+    }
+
+    /// Width and height of renders, twice as wide with views side by side.
+    pub fn size(&self) -> (u32, u32) {
+        match self.stereo {
+            Some(Stereo::SideBySide) => (self.width * 2, self.height),
+            _ => (self.width, self.height),
+        }
+    }
+}
+
+/// Stereo views, for the eyes on either side of the camera, converging on its target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stereo {
+    /// left and right views next to each other, each of the full width
+    SideBySide,
+    /// red of the left view over green and blue of the right, for red/cyan glasses
+    Anaglyph,
+}
+
+impl std::str::FromStr for Stereo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sbs" | "side-by-side" => Ok(Self::SideBySide),
+            "anaglyph" => Ok(Self::Anaglyph),
+            _ => anyhow::bail!("unknown stereo mode {}, expected sbs or anaglyph", s),
+        }
+    }
+}
+
+/// Rows of `l` and `r` next to each other, of `row` elements each.
+fn side_by_side<T: Copy>(l: &[T], r: &[T], row: usize) -> Vec<T> {
+    l.chunks(row)
+        .zip(r.chunks(row))
+        .flat_map(|(a, b)| a.iter().chain(b))
+        .copied()
+        .collect()
+}
+
+impl Stereo {
+    /// Image of the `l` and `r` views, of `width` pixels of `channels` bytes per row.
+    fn combine(self, l: &[u8], r: &[u8], width: usize, channels: usize) -> Vec<u8> {
+        match self {
+            Self::SideBySide => side_by_side(l, r, width * channels),
+            Self::Anaglyph => l
+                .chunks(channels)
+                .zip(r.chunks(channels))
+                .flat_map(|(a, b)| {
+                    // covered by either view, if transparent
+                    let alpha = (channels == 4).then(|| a[3].max(b[3]));
+                    [a[0], b[1], b[2]].into_iter().chain(alpha)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Orthographic view straight down onto the bed, so pixels map to fixed bed positions
+/// like in overhead printer camera captures, whatever the model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ortho {
+    /// millimeters per pixel
+    pub scale: f32,
+    /// bed position at the bottom left corner of the image, in millimeters
+    pub origin: [f32; 2],
+}
+
+impl std::str::FromStr for Ortho {
+    type Err = anyhow::Error;
+
+    /// `mm[:x,y]`, millimeters per pixel and the bed position at the bottom left of the
+    /// image, the bed origin by default.
+    fn from_str(s: &str) -> Result<Self> {
+        let (scale, origin) = match s.split_once(':') {
+            Some((scale, origin)) => (scale, Some(origin)),
+            None => (s, None),
+        };
+        let scale = scale
+            .parse::<f32>()
+            .map_err(|e| anyhow::anyhow!("ortho {}: {}", s, e))?;
+        anyhow::ensure!(scale > 0f32, "ortho {}: scale must be positive", s);
+        let origin = match origin {
+            Some(origin) => {
+                let v = origin
+                    .split(',')
+                    .map(|c| c.trim().parse::<f32>())
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(|e| anyhow::anyhow!("ortho {}: {}", s, e))?;
+                v.try_into()
+                    .map_err(|_| anyhow::anyhow!("ortho {}: expected x,y, got {}", s, origin))?
+            }
+            None => [0f32; 2],
+        };
+        Ok(Self { scale, origin })
+    }
+}
+
+/// Fixed camera pose in bed coordinates, matching a real printer camera, so renders are
+/// comparable with its footage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Camera {
+    /// camera position and the point it looks at, in millimeters
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    /// vertical field of view in degrees
+    pub fov: f32,
+    /// parts of the image hidden by the nozzle and toolhead
+    pub occlusions: Vec<Region>,
+}
+
+impl std::str::FromStr for Camera {
+    type Err = anyhow::Error;
+
+    /// `x,y,z:x,y,z[:fov]`, the camera position, the point it looks at, and the vertical
+    /// field of view in degrees, 40 by default.
+    fn from_str(s: &str) -> Result<Self> {
+        let point = |p: &str| -> Result<[f32; 3]> {
+            let v = p
+                .split(',')
+                .map(|c| c.trim().parse::<f32>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| anyhow::anyhow!("camera {}: {}", s, e))?;
+            v.try_into()
+                .map_err(|_| anyhow::anyhow!("camera {}: expected x,y,z, got {}", s, p))
+        };
+        let parts = s.split(':').collect::<Vec<_>>();
+        anyhow::ensure!(
+            matches!(parts.len(), 2 | 3),
+            "camera {}: expected eye:target[:fov]",
+            s
+        );
+        let fov = match parts.get(2) {
+            Some(fov) => fov
+                .parse::<f32>()
+                .map_err(|e| anyhow::anyhow!("camera {}: {}", s, e))?,
+            None => 40f32,
+        };
+        anyhow::ensure!(
+            fov > 0f32 && fov < 180f32,
+            "camera {}: field of view must be within 0 to 180 degrees",
+            s
+        );
+        let (eye, target) = (point(parts[0])?, point(parts[1])?);
+        anyhow::ensure!(eye != target, "camera {}: looks at itself", s);
+        Ok(Self {
+            eye,
+            target,
+            fov,
+            occlusions: Vec::new(),
+        })
+    }
+}
+
+/// Rectangle of an image in fractions of its size, from the top left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl Region {
+    fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.min[0] && x < self.max[0] && y >= self.min[1] && y < self.max[1]
+    }
+}
+
+impl std::str::FromStr for Region {
+    type Err = anyhow::Error;
+
+    /// `x0,y0,x1,y1`, like `0.4,0,0.6,0.35` for the top middle of the image.
+    fn from_str(s: &str) -> Result<Self> {
+        let v = s
+            .split(',')
+            .map(|c| c.trim().parse::<f32>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!("region {}: {}", s, e))?;
+        let [x0, y0, x1, y1] = v[..] else {
+            anyhow::bail!("region {}: expected x0,y0,x1,y1", s);
+        };
+        anyhow::ensure!(
+            x0 < x1 && y0 < y1,
+            "region {}: expected the top left corner first",
+            s
+        );
+        Ok(Self {
+            min: [x0, y0],
+            max: [x1, y1],
+        })
+    }
+}
+
+/// Dense occupancy of the bounding box, with `scale` voxels per cell along each axis.
+struct Grid {
+    size: [i32; 3],
+    scale: i32,
+    bits: Vec<u64>,
+    colors: Vec<[f32; 3]>,
+}
+
+impl Grid {
+    fn build<V: Voxel + Sync>(v: &V, colors: Option<&Colors>, bb: &BoundingBox) -> Self {
+        let extent = bb.bound_max - bb.bound_min + VoxelIdx::unit();
+        let volume = |s: i32| {
+            (0..3)
+                .map(|i| (extent[i] / s + 1) as usize)
+                .product::<usize>()
+        };
+        let mut scale = 1;
+        while volume(scale) > MAX_CELLS {
+            scale += 1;
+        }
+        let size = [0, 1, 2].map(|i| (extent[i] + scale - 1) / scale);
+        let min = bb.bound_min;
+
+        // cells of a coarse grid sample the voxel at their center
+        let sample = if scale == 1 { 0 } else { scale / 2 };
+        let occupied = (0..size[2])
+            .into_par_iter()
+            .map(|z| {
+                let mut cells = Vec::new();
+                for y in 0..size[1] {
+                    for x in 0..size[0] {
+                        let coord = min + VoxelIdx::new([x, y, z].map(|c| c * scale + sample));
+                        if v.occupied(coord) {
+                            cells.push([x, y, z]);
+                        }
+                    }
+                }
+                cells
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+
+        let len = size[0] as usize * size[1] as usize * size[2] as usize;
+        let mut grid = Self {
+            size,
+            scale,
+            bits: vec![0u64; len.div_ceil(64)],
+            colors: Vec::new(),
+        };
+        if colors.is_some() {
+            grid.colors = vec![ALBEDO; len];
+        }
+        for cell in occupied {
+            let i = grid.index(cell).unwrap();
+            grid.bits[i / 64] |= 1 << (i % 64);
+            if let Some(c) = colors.and_then(|colors| {
+                colors.color_of(min + VoxelIdx::new(cell.map(|c| c * scale + sample)))
+            }) {
+                grid.colors[i] = srgb_to_linear(c);
+            }
+        }
+        grid
+    }
+
+    fn index(&self, cell: [i32; 3]) -> Option<usize> {
+        if (0..3).any(|axis| cell[axis] < 0 || cell[axis] >= self.size[axis]) {
+            return None;
+        }
+        let [sx, sy, _] = self.size;
+        Some(((cell[2] * sy + cell[1]) * sx + cell[0]) as usize)
+    }
+
+    fn occupied(&self, cell: [i32; 3]) -> bool {
+        match self.index(cell) {
+            Some(i) => self.bits[i / 64] & (1 << (i % 64)) != 0,
+            None => false,
+        }
+    }
+
+    fn albedo(&self, cell: [i32; 3]) -> [f32; 3] {
+        match self.index(cell) {
+            Some(i) if !self.colors.is_empty() => self.colors[i],
+            _ => ALBEDO,
+        }
+    }
+
+    /// Traverses cells along the ray with DDA, in cell units, up to `max_t`. Returns
+    /// distance, cell and normal of the first occupied cell.
+    fn trace(
+        &self,
+        origin: Vector3<f32>,
+        dir: Vector3<f32>,
+        max_t: f32,
+    ) -> Option<(f32, [i32; 3], Vector3<f32>)> {
+        let size = Vector3::new(
+            self.size[0] as f32,
+            self.size[1] as f32,
+            self.size[2] as f32,
+        );
+
+        // clip the ray to the grid
+        let (mut t0, mut t1) = (0f32, max_t);
+        for axis in 0..3 {
+            let inv = 1f32 / dir[axis];
+            let (a, b) = (
+                (0f32 - origin[axis]) * inv,
+                (size[axis] - origin[axis]) * inv,
+            );
+            let (a, b) = if a < b { (a, b) } else { (b, a) };
+            t0 = t0.max(a);
+            t1 = t1.min(b);
+        }
+        if t0 > t1 {
+            return None;
+        }
+
+        let (start, end) = (origin + dir * t0, origin + dir * t1);
+        // normal of the entry face of the first cell, from the axis with the latest entry
+        let first = (0..3)
+            .max_by(|&a, &b| entry(origin, dir, size, a).total_cmp(&entry(origin, dir, size, b)))
+            .unwrap();
+        let sign = |v: f32| if v < 0f32 { -1 } else { 1 };
+        let cells = Traversal::new([start.x, start.y, start.z], [end.x, end.y, end.z]);
+        for c in cells {
+            let cell = <[i32; 3]>::from(c.voxel);
+            if !self.occupied(cell) {
+                continue;
+            }
+            let (axis, step) = c.step.unwrap_or((first, sign(dir[first])));
+            let mut normal = Vector3::zeros();
+            normal[axis] = -step as f32;
+            return Some((t0 + c.t * (t1 - t0), cell, normal));
+        }
+        None
+    }
+}
+
+/// Distance where the ray enters the slab of `axis`.
+fn entry(origin: Vector3<f32>, dir: Vector3<f32>, size: Vector3<f32>, axis: usize) -> f32 {
+    let inv = 1f32 / dir[axis];
+    let (a, b) = (
+        (0f32 - origin[axis]) * inv,
+        (size[axis] - origin[axis]) * inv,
+    );
+    a.min(b)
+}
+
+fn srgb_to_linear(c: [u8; 3]) -> [f32; 3] {
+    c.map(|c| (c as f32 / 255f32).powf(2.2))
+}
+
+fn linear_to_srgb(c: f32) -> u8 {
+    (c.clamp(0f32, 1f32).powf(1f32 / 2.2) * 255f32).round() as u8
+}
+
+/// Cheap hash based random numbers, so renders are deterministic.
+pub fn random(seed: u32) -> f32 {
+    let mut x = seed.wrapping_mul(0x9e3779b9);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x85ebca6b);
+    x ^= x >> 13;
+    x = x.wrapping_mul(0xc2b2ae35);
+    x ^= x >> 16;
+    (x >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// Cosine weighted direction around `normal`.
+fn hemisphere(normal: Vector3<f32>, u: f32, v: f32) -> Vector3<f32> {
+    let helper = if normal[0].abs() < 0.5 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    let tangent = normal.cross(&helper).normalize();
+    let bitangent = normal.cross(&tangent);
+
+    let r = u.sqrt();
+    let phi = 2f32 * std::f32::consts::PI * v;
+    (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * (1f32 - u).sqrt())
+        .normalize()
+}
+
+/// Renders `v` with shadows and ambient occlusion, and writes it as png.
+pub fn render_still<V: Voxel + Sync>(
+    v: &V,
+    colors: Option<&Colors>,
+    still: &Still,
+    out: &str,
+) -> Result<()> {
+    render_still_in(v, colors, still, v.bounding_box(), out)
+}
+
+/// Renders like `render_still`, with the camera framing `frame` instead of the bounding
+/// box of `v`, so renders of different models with the same frame line up.
+pub fn render_still_in<V: Voxel + Sync>(
+    v: &V,
+    colors: Option<&Colors>,
+    still: &Still,
+    frame: &BoundingBox,
+    out: &str,
+) -> Result<()> {
+    render_frame(v, colors, still, frame, None, out)
+}
+
+/// Path of the `aov` buffer of frames written to `out`, like `frame_depth.png`.
+fn aov_path(out: &str, aov: &str) -> String {
+    let stem = out.strip_suffix(".png").unwrap_or(out);
+    format!("{}_{}.png", stem, aov)
+}
+
+/// Writes `data` as a png image of `w` by `h` pixels to `out`, local or
+/// `s3://bucket/key`.
+pub fn write_png(
+    out: &str,
+    w: u32,
+    h: u32,
+    color: png::ColorType,
+    bit16: bool,
+    data: &[u8],
+) -> Result<()> {
+    let mut sink = storage::create(out)?;
+    let mut encoder = png::Encoder::new(&mut sink, w, h);
+    encoder.set_color(color);
+    encoder.set_depth(match bit16 {
+        true => png::BitDepth::Sixteen,
+        false => png::BitDepth::Eight,
+    });
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(data)?;
+    writer.finish()?;
+    sink.finish()
+}
+
+/// Renders like `render_still_in`, with the overlay of `still` showing `stats`, if any.
+/// With aovs, also writes 16-bit buffers next to `out`: `_depth.png`, the distance of
+/// surfaces from the camera plane in hundredths of millimeters, 0 for the sky, and
+/// `_normal.png`, their normals in bed coordinates mapped from [-1, 1].
+pub fn render_frame<V: Voxel + Sync>(
+    v: &V,
+    colors: Option<&Colors>,
+    still: &Still,
+    frame: &BoundingBox,
+    stats: Option<&Stats>,
+    out: &str,
+) -> Result<()> {
+    let (w, h) = still.size();
+    let (rows, aovs) = render_overlaid(v, colors, still, frame, stats)?;
+    let color = match still.transparent {
+        true => png::ColorType::Rgba,
+        false => png::ColorType::Rgb,
+    };
+    write_png(out, w, h, color, false, &rows)?;
+
+    if still.aovs {
+        // 16-bit pngs are big endian
+        let depth = aovs
+            .iter()
+            .flat_map(|a| a[0].to_be_bytes())
+            .collect::<Vec<_>>();
+        let normal = aovs
+            .iter()
+            .flat_map(|a| a[1..].iter().flat_map(|c| c.to_be_bytes()))
+            .collect::<Vec<_>>();
+        let depth_out = aov_path(out, "depth");
+        write_png(&depth_out, w, h, png::ColorType::Grayscale, true, &depth)?;
+        write_png(
+            &aov_path(out, "normal"),
+            w,
+            h,
+            png::ColorType::Rgb,
+            true,
+            &normal,
+        )?;
+    }
+    Ok(())
+}
+
+/// Pixels of `render_frame` without writing them, as rgb rows, or rgba if transparent.
+pub fn render_pixels<V: Voxel + Sync>(
+    v: &V,
+    colors: Option<&Colors>,
+    still: &Still,
+    frame: &BoundingBox,
+    stats: Option<&Stats>,
+) -> Result<Vec<u8>> {
+    Ok(render_overlaid(v, colors, still, frame, stats)?.0)
+}
+
+fn render_overlaid<V: Voxel + Sync>(
+    v: &V,
+    colors: Option<&Colors>,
+    still: &Still,
+    frame: &BoundingBox,
+    stats: Option<&Stats>,
+) -> Result<(Vec<u8>, Vec<Aov>)> {
+    anyhow::ensure!(frame.count > 0, "nothing to render");
+    anyhow::ensure!(
+        still.ortho.is_none() || (still.camera.is_none() && still.stereo.is_none()),
+        "ortho views have no camera, and no stereo"
+    );
+    let (mut rows, aovs) = render(v, colors, still, frame);
+    if let (Some(overlay), Some(stats)) = (&still.overlay, stats) {
+        overlay.draw(&mut rows, still.size().0 as usize, still.channels(), stats);
+    }
+    Ok((rows, aovs))
+}
+
+/// Depth in hundredths of millimeters, 0 without a surface, and the normal mapped from
+/// [-1, 1], of a pixel.
+type Aov = [u16; 4];
+
+fn aov(depth: f32, normal: Vector3<f32>) -> Aov {
+    let n = normal.map(|c| ((c + 1f32) * 0.5 * 65535f32).round() as u16);
+    let depth = (depth * 100f32).round().clamp(1f32, 65535f32) as u16;
+    [depth, n[0], n[1], n[2]]
+}
+
+/// Pixels of a still as rgb rows, or rgba if transparent, and depth and normals of
+/// surfaces under them.
+fn render<V: Voxel + Sync>(
+    v: &V,
+    colors: Option<&Colors>,
+    still: &Still,
+    frame: &BoundingBox,
+) -> (Vec<u8>, Vec<Aov>) {
+    let grid = Grid::build(v, colors, frame);
+
+    // camera orbits the center of the grid, in cell units
+    let size = Vector3::new(
+        grid.size[0] as f32,
+        grid.size[1] as f32,
+        grid.size[2] as f32,
+    );
+    let center = size * 0.5;
+    let radius = size.magnitude() * 0.5;
+    // millimeters to cell units
+    let cell = |p: [f32; 3]| {
+        Vector3::from_fn(|i, _| (p[i] / UNIT - frame.bound_min[i] as f32) / grid.scale as f32)
+    };
+    let (eye, forward, fov, focus) = match (&still.camera, &still.ortho) {
+        // rays start above the grid, from the pixels
+        (_, Some(_)) => {
+            let eye = Vector3::new(center.x, center.y, size.z + 1f32);
+            (eye, -Vector3::z(), 0f32, size.z + 1f32)
+        }
+        (Some(camera), None) => {
+            let eye = cell(camera.eye);
+            let to_target = cell(camera.target) - eye;
+            let forward = to_target.normalize();
+            (eye, forward, camera.fov.to_radians(), to_target.magnitude())
+        }
+        (None, None) => {
+            let (az, el) = (still.azimuth.to_radians(), still.elevation.to_radians());
+            let back = Vector3::new(el.cos() * az.cos(), el.cos() * az.sin(), el.sin());
+            let fov = 30f32.to_radians();
+            let distance = radius / (fov * 0.5).sin();
+            (center + back * distance, -back, fov, distance)
+        }
+    };
+
+    // cameras looking straight down keep +Y up
+    let right_of = |forward: Vector3<f32>| {
+        forward
+            .cross(&Vector3::z())
+            .try_normalize(1e-6)
+            .unwrap_or_else(Vector3::x)
+    };
+    let right = right_of(forward);
+    let aspect = still.width as f32 / still.height as f32;
+    let half_h = (fov * 0.5).tan();
+
+    // key light from above, over the left shoulder of the camera
+    let light = (Vector3::z() * 2f32 - forward - right).normalize();
+    let max_t = radius * 4f32 + (eye - center).magnitude() + focus / 30f32;
+    // occlusion within about 1mm
+    let ao_range = (32f32 / grid.scale as f32).max(4f32);
+
+    let shade = |albedo: [f32; 3], pos: Vector3<f32>, normal: Vector3<f32>, seed: u32| {
+        let origin = pos + normal * 0.05;
+        let lit = grid.trace(origin, light, max_t).is_none();
+        let diffuse = if lit {
+            normal.dot(&light).max(0f32)
+        } else {
+            0f32
+        };
+
+        let mut ambient = 1f32;
+        if still.ao_samples > 0 {
+            let mut open = 0;
+            for i in 0..still.ao_samples {
+                let s = seed.wrapping_mul(64).wrapping_add(i as u32 * 2);
+                let d = hemisphere(normal, random(s), random(s + 1));
+                if grid.trace(origin, d, ao_range).is_none() {
+                    open += 1;
+                }
+            }
+            ambient = open as f32 / still.ao_samples as f32;
+        }
+
+        albedo.map(|c| c * (0.75 * diffuse + 0.35 * ambient))
+    };
+
+    let (w, h) = (still.width, still.height);
+    let occlusions = still
+        .camera
+        .as_ref()
+        .map_or(&[][..], |c| c.occlusions.as_slice());
+    let channels = still.channels();
+    // millimeters of cells
+    let mm = grid.scale as f32 * UNIT;
+    let view = |eye: Vector3<f32>, forward: Vector3<f32>| -> (Vec<u8>, Vec<Aov>) {
+        let right = right_of(forward);
+        let up = right.cross(&forward);
+        let rows = (0..h)
+            .into_par_iter()
+            .map(|py| {
+                let mut row = Vec::with_capacity(w as usize * channels);
+                let mut aovs = Vec::with_capacity(w as usize);
+                let mut push = |rgb: [u8; 3], covered: bool| {
+                    row.extend(rgb);
+                    if still.transparent {
+                        row.push(if covered { 255 } else { 0 });
+                    }
+                };
+                for px in 0..w {
+                    let (fx, fy) = ((px as f32 + 0.5) / w as f32, (py as f32 + 0.5) / h as f32);
+                    if occlusions.iter().any(|r| r.contains(fx, fy)) {
+                        push(OCCLUDED, true);
+                        aovs.push([0; 4]);
+                        continue;
+                    }
+                    let (eye, dir) = match &still.ortho {
+                        Some(ortho) => {
+                            // +Y up in the image
+                            let x = ortho.origin[0] + (px as f32 + 0.5) * ortho.scale;
+                            let y = ortho.origin[1] + ((h - py) as f32 - 0.5) * ortho.scale;
+                            let c = cell([x, y, 0f32]);
+                            (Vector3::new(c.x, c.y, eye.z), forward)
+                        }
+                        None => {
+                            let sx = (fx * 2f32 - 1f32) * half_h * aspect;
+                            let sy = (1f32 - fy * 2f32) * half_h;
+                            (eye, (forward + right * sx + up * sy).normalize())
+                        }
+                    };
+                    let seed = py * w + px;
+
+                    let hit = grid.trace(eye, dir, max_t);
+                    if still.transparent && hit.is_none() {
+                        push([0; 3], false);
+                        aovs.push([0; 4]);
+                        continue;
+                    }
+                    let surface = match hit {
+                        Some((t, cell, normal)) => Some((t, grid.albedo(cell), normal)),
+                        // ground plane under the model
+                        None if dir[2] < 0f32 => Some((-eye[2] / dir[2], GROUND, Vector3::z())),
+                        None => None,
+                    };
+                    let rgb = match surface {
+                        Some((t, albedo, normal)) => {
+                            aovs.push(aov(t * dir.dot(&forward) * mm, normal));
+                            shade(albedo, eye + dir * t, normal, seed)
+                        }
+                        None => {
+                            aovs.push([0; 4]);
+                            let k = 0.6 + 0.4 * dir[2];
+                            [0.55 * k, 0.6 * k, 0.7 * k]
+                        }
+                    };
+                    push(rgb.map(linear_to_srgb), true);
+                }
+                (row, aovs)
+            })
+            .collect::<Vec<_>>();
+        let mut image = (Vec::new(), Vec::new());
+        for (row, aovs) in rows {
+            image.0.extend(row);
+            image.1.extend(aovs);
+        }
+        image
+    };
+
+    match still.stereo {
+        None => view(eye, forward),
+        Some(stereo) => {
+            // eyes apart by 1/30 of the distance to the target, converging on it
+            let target = eye + forward * focus;
+            let offset = right * (focus / 60f32);
+            let [l, r] = [eye - offset, eye + offset].map(|e| view(e, (target - e).normalize()));
+            let aovs = match stereo {
+                Stereo::SideBySide => side_by_side(&l.1, &r.1, w as usize),
+                // surfaces of the left view, as colors do not add up
+                Stereo::Anaglyph => l.1,
+            };
+            (stereo.combine(&l.0, &r.0, w as usize, channels), aovs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_trace() {
+        let mut v = MonotonicVoxel::default();
+        for z in 0..4 {
+            v.add([0, 0, z].into());
+            v.add([3, 3, z].into());
+        }
+        let grid = Grid::build(&v, None, v.bounding_box());
+        assert_eq!(grid.size, [4, 4, 4]);
+
+        // straight down onto the first column
+        let hit = grid.trace(Vector3::new(0.5, 0.5, 10.0), -Vector3::z(), 100f32);
+        let (t, cell, normal) = hit.unwrap();
+        assert!((t - 6f32).abs() < 1e-4);
+        assert_eq!(cell, [0, 0, 3]);
+        assert_eq!(normal, Vector3::z());
+
+        // along the diagonal, between columns
+        let dir = Vector3::new(1.0, -1.0, 0.0).normalize();
+        assert!(grid
+            .trace(Vector3::new(0.5, 3.5, 1.5), dir, 100f32)
+            .is_none());
+
+        // from the side, hitting the far column
+        let hit = grid.trace(Vector3::new(-5.0, 3.5, 1.5), Vector3::x(), 100f32);
+        let (_, cell, normal) = hit.unwrap();
+        assert_eq!(cell, [3, 3, 1]);
+        assert_eq!(normal, -Vector3::x());
+    }
+
+    #[test]
+    pub fn test_camera() {
+        let mut v = MonotonicVoxel::default();
+        for x in 0..50 {
+            for y in 0..50 {
+                for z in 0..25 {
+                    v.add([x, y, z].into());
+                }
+            }
+        }
+        // a camera on the side of the 2x2x1mm block, with the toolhead at the top left
+        let mut camera = "1,-10,5:1,1,0.5:30".parse::<Camera>().unwrap();
+        camera.occlusions.push("0,0,0.5,0.25".parse().unwrap());
+        let still = Still {
+            width: 32,
+            height: 32,
+            azimuth: 0.0,
+            elevation: 0.0,
+            ao_samples: 0,
+            camera: Some(camera),
+            overlay: None,
+            transparent: false,
+            stereo: None,
+            aovs: false,
+            ortho: None,
+        };
+        let (rgb, aovs) = render(&v, None, &still, v.bounding_box());
+        let pixel = |x: usize, y: usize| &rgb[(y * 32 + x) * 3..][..3];
+        assert_eq!(pixel(0, 0), OCCLUDED);
+        assert_eq!(pixel(15, 7), OCCLUDED);
+        // the block is in the middle, with sky above it
+        let sky = pixel(31, 0);
+        assert_ne!(pixel(16, 16), sky);
+        assert_ne!(pixel(16, 16), OCCLUDED);
+        // the front of the block faces the camera, about 10mm away, before the ground
+        let [depth, nx, ny, nz] = aovs[16 * 32 + 16];
+        assert!((900..1100).contains(&depth), "{}", depth);
+        assert_eq!([nx, ny, nz], [32768, 0, 32768]);
+        assert!(aovs[31][0] > depth);
+        assert_eq!(aovs[31][1..], [32768, 32768, 65535]);
+        assert_eq!(aovs[0], [0; 4]);
+
+        // transparent stills cover only the block and the occluded region
+        let still = Still {
+            transparent: true,
+            ..still
+        };
+        let (rgba, _) = render(&v, None, &still, v.bounding_box());
+        let alpha = |x: usize, y: usize| rgba[(y * 32 + x) * 4 + 3];
+        assert_eq!(rgba.len(), 32 * 32 * 4);
+        assert_eq!((alpha(0, 0), alpha(16, 16), alpha(31, 0)), (255, 255, 0));
+        assert_eq!(&rgba[(16 * 32 + 16) * 4..][..3], pixel(16, 16));
+
+        // stereo views side by side see the block from either side of the camera
+        let still = Still {
+            transparent: false,
+            stereo: Some(Stereo::SideBySide),
+            ..still
+        };
+        assert_eq!(still.size(), (64, 32));
+        let (sbs, _) = render(&v, None, &still, v.bounding_box());
+        assert_eq!(sbs.len(), 64 * 32 * 3);
+        let half = |y: usize, x: usize| &sbs[(y * 64 + x) * 3..][..32 * 3];
+        assert_ne!(half(16, 0), half(16, 32));
+        let still = Still {
+            stereo: Some(Stereo::Anaglyph),
+            ..still
+        };
+        let (anaglyph, _) = render(&v, None, &still, v.bounding_box());
+        assert_eq!(anaglyph.len(), 32 * 32 * 3);
+        // red of the left view, green and blue of the right
+        let at = (20 * 32 + 16) * 3;
+        assert_eq!(anaglyph[at], sbs[20 * 64 * 3 + 16 * 3]);
+        assert_eq!(
+            anaglyph[at + 1..at + 3],
+            sbs[(20 * 64 + 32 + 16) * 3 + 1..][..2]
+        );
+        assert!("sbs".parse::<Stereo>().is_ok() && "3d".parse::<Stereo>().is_err());
+
+        // from the top at 0.1mm per pixel from the bed origin, the 2x2mm block covers the
+        // bottom left 20x20 pixels, at the same height
+        let still = Still {
+            camera: None,
+            stereo: None,
+            ortho: Some("0.1".parse().unwrap()),
+            ..still
+        };
+        let (top, aovs) = render(&v, None, &still, v.bounding_box());
+        let pixel = |x: usize, y: usize| &top[(y * 32 + x) * 3..][..3];
+        let depth = |x: usize, y: usize| aovs[y * 32 + x][0];
+        assert_eq!(pixel(0, 31), pixel(19, 12));
+        assert_ne!(pixel(0, 31), pixel(20, 31));
+        assert_ne!(pixel(0, 31), pixel(0, 11));
+        assert_eq!(depth(0, 31), depth(19, 12));
+        assert!(depth(20, 31) > depth(0, 31));
+        assert_eq!(aovs[31 * 32][1..], [32768, 32768, 65535]);
+        let ortho = "0.2:10,-5".parse::<Ortho>().unwrap();
+        assert_eq!((ortho.scale, ortho.origin), (0.2, [10.0, -5.0]));
+        assert!("0".parse::<Ortho>().is_err() && "0.1:1".parse::<Ortho>().is_err());
+
+        assert!("1,2,3:1,2,3".parse::<Camera>().is_err());
+        assert!("1,2:1,2,3".parse::<Camera>().is_err());
+        assert!("1,0,0,0.5".parse::<Region>().is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use super::Path;
+use nalgebra::{Vector2, Vector3};
+
+/// Toolhead as a damped spring pulled by the commanded position, emulating ringing
+/// (ghosting) after direction changes. Velocity changes instantly at corners, without
+/// acceleration limits, so ringing is exaggerated.
+#[derive(Clone, Debug)]
+pub struct Ringing {
+    /// natural angular frequency, in radians per second
+    omega: f32,
+    /// damping ratio, 1 for critical damping
+    damping: f32,
+    /// commanded speed, in millimeters per second
+    speed: f32,
+    /// actual position and velocity of the toolhead, in the XY plane
+    pos: Vector2<f32>,
+    vel: Vector2<f32>,
+    /// last commanded position, to detect travels between paths
+    last: Option<Vector3<f32>>,
+}
+
+impl Ringing {
+    pub fn new(frequency: f32, damping: f32) -> Self {
+        Self {
+            omega: 2f32 * std::f32::consts::PI * frequency,
+            damping,
+            speed: 25f32,
+            pos: Vector2::zeros(),
+            vel: Vector2::zeros(),
+            last: None,
+        }
+    }
+
+    /// Sets the commanded speed of following moves, in millimeters per second.
+    pub fn set_speed(&mut self, speed: f32) {
+        if speed > 0f32 {
+            self.speed = speed;
+        }
+    }
+
+    /// Follows `path` from arc length `d0` to `d1` at the commanded speed, returning
+    /// the actual position at `d1`. Paths not continuing from the last one start at
+    /// rest, as after a travel.
+    pub fn follow(&mut self, path: &Path, d0: f32, d1: f32) -> Vector3<f32> {
+        let start = path.at(d0);
+        if self.last.map(|last| (last - start).magnitude() > 1e-4) != Some(false) {
+            self.pos = start.xy();
+            self.vel = Vector2::zeros();
+        }
+
+        // substeps of a twentieth of the period keep the integration stable
+        let dt = (d1 - d0) / self.speed;
+        let substeps = ((dt * self.omega / (std::f32::consts::PI * 0.1)).ceil() as usize).max(1);
+        let h = dt / substeps as f32;
+        for i in 1..=substeps {
+            let cmd = path.at(d0 + (d1 - d0) * i as f32 / substeps as f32).xy();
+            // semi-implicit euler
+            let acc = (cmd - self.pos) * (self.omega * self.omega)
+                - self.vel * (2f32 * self.damping * self.omega);
+            self.vel += acc * h;
+            self.pos += self.vel * h;
+        }
+
+        let cmd = path.at(d1);
+        self.last = Some(cmd);
+        Vector3::new(self.pos[0], self.pos[1], cmd[2])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_ringing() {
+        let mut path = Path::default();
+        path.push(Vector3::zeros(), Vector3::new(20.0, 0.0, 0.0), 1.0, 20.0);
+        path.push(
+            Vector3::new(20.0, 0.0, 0.0),
+            Vector3::new(20.0, 20.0, 0.0),
+            1.0,
+            20.0,
+        );
+
+        let mut ringing = Ringing::new(40f32, 0.05f32);
+        ringing.set_speed(30f32);
+        let mut xs = Vec::new();
+        let step = 0.05f32;
+        for i in 0..800 {
+            let d = (i + 1) as f32 * step;
+            let pos = ringing.follow(&path, d - step, d);
+            if i == 300 {
+                // follows the straight move closely
+                assert!((pos - path.at(d)).magnitude() < 0.1);
+            }
+            xs.push(pos[0] - 20f32);
+        }
+
+        // overshoots the corner, and settles
+        let peak = xs[400..].iter().copied().fold(0f32, f32::max);
+        assert!(peak > 0.05);
+        assert!(xs[790].abs() < peak * 0.2);
+        // oscillates around the wall
+        assert!(xs[400..500].iter().any(|x| *x < 0f32));
+    }
+}
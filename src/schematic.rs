@@ -0,0 +1,453 @@
+use super::{
+    fuzzy, seam, storage, support, volume::Volume, Metadata, Tags, Voxel, VoxelIdx, Z_OFFSET,
+};
+use anyhow::Result;
+use flate2::{write::GzEncoder, Compression};
+use std::io::Write;
+
+// Minecraft 1.20.1
+const DATA_VERSION: i32 = 3465;
+const SPONGE_VERSION: i32 = 2;
+const LITEMATIC_VERSION: i32 = 6;
+const AIR: &str = "minecraft:air";
+
+/// Feature classes voxels are tagged with, in the order of block palettes.
+pub const FEATURES: [&str; 6] = [
+    "wall-outer",
+    "wall-inner",
+    "skin",
+    "infill",
+    "support",
+    "other",
+];
+
+/// Class of a `;TYPE:` comment, from Cura and PrusaSlicer names.
+pub fn feature_class(feature: &str) -> &'static str {
+    let lower = feature.to_ascii_lowercase();
+    if fuzzy::is_outer_wall(feature) || lower == "overhang perimeter" {
+        "wall-outer"
+    } else if seam::is_perimeter(feature) {
+        "wall-inner"
+    } else if support::is_support(feature) {
+        "support"
+    } else if lower == "skin" || lower.contains("solid infill") || lower == "bridge infill" {
+        "skin"
+    } else if lower == "fill" || lower == "internal infill" || lower == "gap fill" {
+        "infill"
+    } else {
+        "other"
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PaletteBy {
+    /// a single block
+    Single,
+    /// blocks cycle by layer
+    Layer,
+    /// a block per feature class, in `FEATURES` order
+    Feature,
+}
+
+impl std::str::FromStr for PaletteBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "single" => Ok(Self::Single),
+            "layer" => Ok(Self::Layer),
+            "feature" => Ok(Self::Feature),
+            _ => anyhow::bail!(
+                "unknown palette mode: {}, expected single, layer, or feature",
+                s
+            ),
+        }
+    }
+}
+
+/// Blocks of occupied voxels.
+#[derive(Debug)]
+pub struct Blocks {
+    by: PaletteBy,
+    ids: Vec<String>,
+}
+
+impl Blocks {
+    /// Uses `ids` if not empty, concrete of a few colors otherwise. Ids without a
+    /// namespace are in `minecraft`.
+    pub fn new(by: PaletteBy, ids: &[String]) -> Self {
+        let defaults: &[&str] = match by {
+            PaletteBy::Single => &["white_concrete"],
+            PaletteBy::Layer => &[
+                "white_concrete",
+                "orange_concrete",
+                "magenta_concrete",
+                "light_blue_concrete",
+                "yellow_concrete",
+                "lime_concrete",
+                "pink_concrete",
+                "cyan_concrete",
+            ],
+            PaletteBy::Feature => &[
+                "white_concrete",
+                "light_gray_concrete",
+                "orange_concrete",
+                "yellow_concrete",
+                "brown_concrete",
+                "gray_concrete",
+            ],
+        };
+        let ids = if ids.is_empty() {
+            defaults.iter().map(|id| id.to_string()).collect()
+        } else {
+            ids.to_vec()
+        };
+        let ids = ids
+            .into_iter()
+            .map(|id| {
+                if id.contains(':') {
+                    id
+                } else {
+                    format!("minecraft:{}", id)
+                }
+            })
+            .collect();
+        Self { by, ids }
+    }
+
+    /// Index into the palette of the voxel at `coord`. Feature palettes shorter than
+    /// `FEATURES` use their last block for the remaining classes.
+    fn index(&self, coord: VoxelIdx, features: &Tags) -> usize {
+        let i = match self.by {
+            PaletteBy::Single => 0,
+            // layer tops are at multiples of Z_OFFSET, filled downwards. the first layer
+            // may reach the bed at 0
+            PaletteBy::Layer => ((coord[2] - 1).max(0) / Z_OFFSET) as usize,
+            PaletteBy::Feature => {
+                let class = features.tag_of(coord).unwrap_or("other");
+                FEATURES.iter().position(|f| *f == class).unwrap_or(0)
+            }
+        };
+        match self.by {
+            PaletteBy::Layer => i % self.ids.len(),
+            _ => i.min(self.ids.len() - 1),
+        }
+    }
+}
+
+/// Voxels as Minecraft blocks, a block per voxel. Minecraft Y is up, so voxel z is
+/// block Y and voxel y is block Z.
+pub struct Schematic {
+    /// width, height and length: along x, up, and along z in Minecraft
+    size: [usize; 3],
+    /// air, then the block ids
+    palette: Vec<String>,
+    /// palette index of each block, x fastest, then z, then y
+    data: Vec<u16>,
+}
+
+impl Schematic {
+    pub fn new<V: Voxel>(v: &V, features: &Tags, blocks: &Blocks) -> Self {
+        let volume = Volume::new(v);
+        let [x, y, z] = volume.dims();
+        let mut data = Vec::with_capacity(x * y * z);
+        // volume samples are x fastest, then y, then z, the same order
+        volume.for_each(|coord, occupied| {
+            let i = if occupied {
+                blocks.index(coord, features) + 1
+            } else {
+                0
+            };
+            data.push(i as u16);
+        });
+
+        let mut palette = vec![AIR.to_owned()];
+        palette.extend(blocks.ids.iter().cloned());
+        Self {
+            size: [x, z, y],
+            palette,
+            data,
+        }
+    }
+
+    fn blocks(&self) -> usize {
+        self.data.iter().filter(|i| **i != 0).count()
+    }
+
+    /// Sponge schematic version 2, for WorldEdit.
+    pub fn to_schem(&self, meta: &Metadata) -> Result<Vec<u8>> {
+        anyhow::ensure!(
+            self.size.iter().all(|s| *s <= u16::MAX as usize),
+            "schematic too large: {:?}",
+            self.size
+        );
+
+        let mut w = Nbt::default();
+        w.compound("Schematic");
+        w.int("Version", SPONGE_VERSION);
+        w.int("DataVersion", DATA_VERSION);
+        // unsigned shorts
+        w.short("Width", self.size[0] as i16);
+        w.short("Height", self.size[1] as i16);
+        w.short("Length", self.size[2] as i16);
+        w.int_array("Offset", &[0, 0, 0]);
+
+        w.compound("Metadata");
+        for (k, v) in meta.entries() {
+            w.string(k, v);
+        }
+        w.end();
+
+        w.int("PaletteMax", self.palette.len() as i32);
+        w.compound("Palette");
+        for (i, id) in self.palette.iter().enumerate() {
+            w.int(id, i as i32);
+        }
+        w.end();
+
+        let mut data = Vec::with_capacity(self.data.len());
+        for i in &self.data {
+            varint(&mut data, *i as u32);
+        }
+        w.byte_array("BlockData", &data);
+        w.list("BlockEntities", TAG_COMPOUND, 0);
+        w.end();
+        gzip(&w.buf)
+    }
+
+    /// Litematica schematic with a single region, created at `time` in milliseconds
+    /// since the epoch.
+    pub fn to_litematic(&self, name: &str, meta: &Metadata, time: i64) -> Result<Vec<u8>> {
+        let [x, y, z] = self.size.map(|s| s as i32);
+        let description = meta
+            .entries()
+            .iter()
+            .map(|(k, v)| format!("{}: {}", k, v))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut w = Nbt::default();
+        w.compound("");
+        w.int("MinecraftDataVersion", DATA_VERSION);
+        w.int("Version", LITEMATIC_VERSION);
+
+        w.compound("Metadata");
+        w.string("Name", name);
+        w.string("Author", "tdp-tl");
+        w.string("Description", &description);
+        w.int("RegionCount", 1);
+        w.int("TotalBlocks", self.blocks() as i32);
+        w.int("TotalVolume", x * y * z);
+        w.xyz("EnclosingSize", [x, y, z]);
+        w.long("TimeCreated", time);
+        w.long("TimeModified", time);
+        w.end();
+
+        w.compound("Regions");
+        w.compound(name);
+        w.xyz("Position", [0, 0, 0]);
+        w.xyz("Size", [x, y, z]);
+        w.list("BlockStatePalette", TAG_COMPOUND, self.palette.len());
+        for id in &self.palette {
+            w.string("Name", id);
+            w.end();
+        }
+        w.long_array("BlockStates", &pack(&self.data, self.palette.len()));
+        for list in [
+            "TileEntities",
+            "Entities",
+            "PendingBlockTicks",
+            "PendingFluidTicks",
+        ] {
+            w.list(list, TAG_COMPOUND, 0);
+        }
+        w.end();
+        w.end();
+
+        w.end();
+        gzip(&w.buf)
+    }
+
+    /// Writes a litematic for `.litematic` files, a Sponge schematic otherwise.
+    pub fn write(&self, path: &str, meta: &Metadata) -> Result<()> {
+        let data = if path.ends_with(".litematic") {
+            let name = std::path::Path::new(path)
+                .file_stem()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_millis() as i64;
+            self.to_litematic(&name, meta, now)?
+        } else {
+            self.to_schem(meta)?
+        };
+        storage::write(path, data)
+    }
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut e = GzEncoder::new(Vec::new(), Compression::default());
+    e.write_all(data)?;
+    Ok(e.finish()?)
+}
+
+fn varint(buf: &mut Vec<u8>, mut v: u32) {
+    while v >= 0x80 {
+        buf.push((v & 0x7f) as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+/// Packs palette indices like Litematica, entries spanning across longs.
+fn pack(data: &[u16], palette: usize) -> Vec<i64> {
+    let bits = (usize::BITS - (palette.max(2) - 1).leading_zeros()).max(2) as usize;
+    let mut longs = vec![0u64; (data.len() * bits).div_ceil(64)];
+    for (i, v) in data.iter().enumerate() {
+        let (start, offset) = (i * bits / 64, i * bits % 64);
+        let v = *v as u64;
+        longs[start] |= v << offset;
+        if offset + bits > 64 {
+            longs[start + 1] |= v >> (64 - offset);
+        }
+    }
+    longs.into_iter().map(|l| l as i64).collect()
+}
+
+const TAG_END: u8 = 0;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+/// Uncompressed NBT, big endian. Compounds are closed with `end`.
+#[derive(Default)]
+struct Nbt {
+    buf: Vec<u8>,
+}
+
+impl Nbt {
+    fn name(&mut self, tag: u8, name: &str) {
+        self.buf.push(tag);
+        self.buf
+            .extend_from_slice(&(name.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(name.as_bytes());
+    }
+
+    fn compound(&mut self, name: &str) {
+        self.name(TAG_COMPOUND, name);
+    }
+
+    fn end(&mut self) {
+        self.buf.push(TAG_END);
+    }
+
+    fn short(&mut self, name: &str, v: i16) {
+        self.name(TAG_SHORT, name);
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn int(&mut self, name: &str, v: i32) {
+        self.name(TAG_INT, name);
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn long(&mut self, name: &str, v: i64) {
+        self.name(TAG_LONG, name);
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn string(&mut self, name: &str, v: &str) {
+        self.name(TAG_STRING, name);
+        self.buf.extend_from_slice(&(v.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(v.as_bytes());
+    }
+
+    fn byte_array(&mut self, name: &str, v: &[u8]) {
+        self.name(TAG_BYTE_ARRAY, name);
+        self.buf.extend_from_slice(&(v.len() as i32).to_be_bytes());
+        self.buf.extend_from_slice(v);
+    }
+
+    fn int_array(&mut self, name: &str, v: &[i32]) {
+        self.name(TAG_INT_ARRAY, name);
+        self.buf.extend_from_slice(&(v.len() as i32).to_be_bytes());
+        for i in v {
+            self.buf.extend_from_slice(&i.to_be_bytes());
+        }
+    }
+
+    fn long_array(&mut self, name: &str, v: &[i64]) {
+        self.name(TAG_LONG_ARRAY, name);
+        self.buf.extend_from_slice(&(v.len() as i32).to_be_bytes());
+        for l in v {
+            self.buf.extend_from_slice(&l.to_be_bytes());
+        }
+    }
+
+    /// List header, followed by `len` unnamed payloads of `tag`.
+    fn list(&mut self, name: &str, tag: u8, len: usize) {
+        self.name(TAG_LIST, name);
+        self.buf.push(if len == 0 { TAG_END } else { tag });
+        self.buf.extend_from_slice(&(len as i32).to_be_bytes());
+    }
+
+    /// Compound of `x`, `y` and `z` ints, as used by Litematica.
+    fn xyz(&mut self, name: &str, v: [i32; 3]) {
+        self.compound(name);
+        self.int("x", v[0]);
+        self.int("y", v[1]);
+        self.int("z", v[2]);
+        self.end();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_schematic() {
+        assert_eq!(feature_class("WALL-OUTER"), "wall-outer");
+        assert_eq!(feature_class("Perimeter"), "wall-inner");
+        assert_eq!(feature_class("Top solid infill"), "skin");
+        assert_eq!(feature_class("FILL"), "infill");
+        assert_eq!(feature_class("SKIRT"), "other");
+
+        let mut v = MonotonicVoxel::default();
+        let mut features = Tags::default();
+        for (c, class) in [([0, 0, 1], "wall-outer"), ([1, 2, 6], "infill")] {
+            v.add(c.into());
+            features.add(class, c.into());
+        }
+
+        let blocks = Blocks::new(PaletteBy::Feature, &[]);
+        let s = Schematic::new(&v, &features, &blocks);
+        // x, then voxel z up, then voxel y
+        assert_eq!(s.size, [2, 6, 3]);
+        assert_eq!(s.data[0], 1);
+        assert_eq!(s.data[5 * 6 + 2 * 2 + 1], 4);
+        assert_eq!(s.blocks(), 2);
+
+        let blocks = Blocks::new(PaletteBy::Layer, &["stone".to_owned()]);
+        assert_eq!(blocks.ids, vec!["minecraft:stone"]);
+        assert_eq!(blocks.index([0, 0, 6].into(), &features), 0);
+
+        // 3 bits per entry with 6 palette entries, the 22nd entry spans two longs
+        let packed = pack(&[0b101; 22], 6);
+        assert_eq!(packed.len(), 2);
+        assert_eq!(packed[0] as u64 >> 63, 1);
+        assert_eq!(packed[1], 0b10);
+
+        let mut buf = Vec::new();
+        varint(&mut buf, 300);
+        assert_eq!(buf, vec![0xac, 0x02]);
+    }
+}
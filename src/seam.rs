@@ -0,0 +1,111 @@
+use super::{to_intpos, Tags, Voxel};
+use nalgebra::Vector3;
+
+/// Loops close if extrusion comes back within this distance of its start, in
+/// millimeters.
+const CLOSE_DISTANCE: f32 = 0.1;
+/// Shorter loops are ignored, e.g. back and forth moves of thin walls, in millimeters.
+const MIN_LOOP: f32 = 1.0;
+/// Voxels within this distance of a seam are tagged, about a bead radius, in voxels.
+const SEAM_RADIUS: i32 = 5;
+
+/// Returns true for `;TYPE:` comments of perimeters, from Cura (`WALL-OUTER`,
+/// `WALL-INNER`) and PrusaSlicer (`External perimeter`, `Perimeter`, ...).
+pub fn is_perimeter(feature: &str) -> bool {
+    let feature = feature.to_ascii_lowercase();
+    feature.starts_with("wall") || feature.contains("perimeter")
+}
+
+/// Follows continuous extrusion of a perimeter, to find where the loop closes.
+#[derive(Clone, Default, Debug)]
+pub struct LoopTracker {
+    start: Option<Vector3<f32>>,
+    len: f32,
+}
+
+impl LoopTracker {
+    /// Forgets the current loop, on travels and feature changes.
+    pub fn reset(&mut self) {
+        self.start = None;
+        self.len = 0f32;
+    }
+
+    /// Follows an extrusion move, returning the seam if the move closes the loop. The
+    /// next loop starts at the seam, for perimeters printed without travels between.
+    pub fn extrude(&mut self, from: Vector3<f32>, to: Vector3<f32>) -> Option<Vector3<f32>> {
+        let start = *self.start.get_or_insert(from);
+        self.len += (to - from).xy().magnitude();
+        if self.len < MIN_LOOP || (to - start).xy().magnitude() > CLOSE_DISTANCE {
+            return None;
+        }
+        self.start = Some(to);
+        self.len = 0f32;
+        Some(to)
+    }
+}
+
+/// Tags voxels of the bead of `depth` voxels around the seam at `pos`.
+pub fn tag_seam<V: Voxel>(tags: &mut Tags, v: &V, pos: Vector3<f32>, depth: i32) {
+    let c = to_intpos([pos[0], pos[1], pos[2]]);
+    for dy in -SEAM_RADIUS..=SEAM_RADIUS {
+        for dx in -SEAM_RADIUS..=SEAM_RADIUS {
+            if dx * dx + dy * dy > SEAM_RADIUS * SEAM_RADIUS {
+                continue;
+            }
+            for z in c[2] - depth..=c[2] {
+                let coord = [c[0] + dx, c[1] + dy, z].into();
+                if v.occupied(coord) {
+                    tags.add("seam", coord);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{MonotonicVoxel, Z_OFFSET};
+
+    #[test]
+    pub fn test_loop_tracker() {
+        let corners = [[0.0, 0.0], [2.0, 0.0], [2.0, 2.0], [0.0, 2.0], [0.0, 0.0]]
+            .map(|[x, y]| Vector3::new(x, y, 0.2));
+
+        let mut tracker = LoopTracker::default();
+        let seams = corners
+            .windows(2)
+            .filter_map(|w| tracker.extrude(w[0], w[1]))
+            .collect::<Vec<_>>();
+        assert_eq!(seams, vec![corners[0]]);
+
+        // interrupted by a travel
+        tracker.reset();
+        for w in corners[..3].windows(2) {
+            assert!(tracker.extrude(w[0], w[1]).is_none());
+        }
+        tracker.reset();
+        assert!(tracker.extrude(corners[3], corners[4]).is_none());
+
+        assert!(is_perimeter("WALL-OUTER"));
+        assert!(is_perimeter("External perimeter"));
+        assert!(!is_perimeter("FILL"));
+
+        let mut v = MonotonicVoxel::default();
+        v.add([0, 0, 5].into());
+        v.add([20, 0, 5].into());
+        let mut tags = Tags::default();
+        tag_seam(&mut tags, &v, Vector3::new(0.0, 0.0, 0.2), Z_OFFSET);
+        assert_eq!(tags.tag_of([0, 0, 5].into()), Some("seam"));
+        assert_eq!(tags.tag_of([20, 0, 5].into()), None);
+
+        // thin layers tag thin beads
+        v.add([0, 0, 1].into());
+        let mut tags = Tags::default();
+        tag_seam(&mut tags, &v, Vector3::new(0.0, 0.0, 0.2), Z_OFFSET);
+        assert_eq!(tags.tag_of([0, 0, 1].into()), Some("seam"));
+        let mut tags = Tags::default();
+        tag_seam(&mut tags, &v, Vector3::new(0.0, 0.0, 0.2), 2);
+        assert_eq!(tags.tag_of([0, 0, 1].into()), None);
+    }
+}
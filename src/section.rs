@@ -0,0 +1,202 @@
+use super::{deterministic, storage, Voxel, LAYER_HEIGHT, UNIT};
+use anyhow::Result;
+use rayon::prelude::*;
+use std::io::Write;
+
+/// Bonded cross-section between a layer and the one below, a crude indicator of layer
+/// adhesion strength.
+#[derive(Clone, Debug, Default)]
+pub struct Section {
+    /// layer above the boundary, the first layer being 0
+    pub layer: usize,
+    /// height of the boundary in millimeters
+    pub z: f32,
+    /// bonded area in mm^2, where voxels are deposited on both sides of the boundary
+    pub area: f32,
+    /// centroid of the bonded area in millimeters
+    pub centroid: [f32; 2],
+    /// principal second moments of area about the centroid in mm^4, ascending; the
+    /// first is the bending stiffness about the weak axis
+    pub moments: [f32; 2],
+    /// direction of the weak axis in degrees from +X
+    pub weak_axis: f32,
+}
+
+/// Sums over bonded columns of a boundary, in voxels.
+#[derive(Clone, Copy, Default)]
+struct Sums {
+    count: f64,
+    x: f64,
+    y: f64,
+    xx: f64,
+    yy: f64,
+    xy: f64,
+}
+
+impl Sums {
+    fn add(&mut self, x: i32, y: i32) {
+        let (x, y) = (x as f64 + 0.5, y as f64 + 0.5);
+        self.count += 1f64;
+        self.x += x;
+        self.y += y;
+        self.xx += x * x;
+        self.yy += y * y;
+        self.xy += x * y;
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.count += other.count;
+        self.x += other.x;
+        self.y += other.y;
+        self.xx += other.xx;
+        self.yy += other.yy;
+        self.xy += other.xy;
+        self
+    }
+
+    fn section(&self, layer: usize, z: i32) -> Section {
+        let mut section = Section {
+            layer,
+            z: z as f32 * UNIT,
+            ..Default::default()
+        };
+        if self.count == 0f64 {
+            return section;
+        }
+        let n = self.count;
+        let (cx, cy) = (self.x / n, self.y / n);
+        // each column is a unit square
+        let ixx = self.yy - n * cy * cy + n / 12f64;
+        let iyy = self.xx - n * cx * cx + n / 12f64;
+        let ixy = self.xy - n * cx * cy;
+
+        let mean = (ixx + iyy) / 2f64;
+        let radius = (((ixx - iyy) / 2f64).powi(2) + ixy * ixy).sqrt();
+        // axis of the least second moment, about which the section bends most easily
+        let angle = 0.5 * (-2f64 * ixy).atan2(ixx - iyy);
+        let weak_axis = (angle + std::f64::consts::FRAC_PI_2)
+            .to_degrees()
+            .rem_euclid(180f64);
+
+        let unit = UNIT as f64;
+        section.area = (n * unit * unit) as f32;
+        section.centroid = [(cx * unit) as f32, (cy * unit) as f32];
+        section.moments = [mean - radius, mean + radius].map(|m| (m * unit.powi(4)) as f32);
+        section.weak_axis = weak_axis as f32;
+        section
+    }
+}
+
+/// Bonded cross-sections at every layer boundary of `v`.
+pub fn sections<V: Voxel + Sync>(v: &V) -> Vec<Section> {
+    let bb = v.bounding_box();
+    if bb.count == 0 {
+        return Vec::new();
+    }
+    let (min, max) = (bb.bound_min, bb.bound_max);
+    let h = (LAYER_HEIGHT / UNIT).round() as i32;
+    let boundaries = ((max[2] - min[2]) / h) as usize;
+    // voxel index of the lowest voxel above boundary `i`
+    let boundary = |i: usize| min[2] + (i as i32 + 1) * h;
+
+    let rows = (min[1]..=max[1]).into_par_iter().map(|y| {
+        let mut sums = vec![Sums::default(); boundaries];
+        for x in min[0]..=max[0] {
+            for r in v.column(x, y) {
+                // boundaries with voxels right below and above inside the range
+                let first = ((r.start - min[2]) / h).max(0) as usize;
+                for (i, s) in sums.iter_mut().enumerate().skip(first) {
+                    let b = boundary(i);
+                    if b >= r.end {
+                        break;
+                    }
+                    if b > r.start {
+                        s.add(x, y);
+                    }
+                }
+            }
+        }
+        sums
+    });
+    let sums = deterministic::reduce(
+        rows,
+        || vec![Sums::default(); boundaries],
+        |a, b| a.into_iter().zip(b).map(|(a, b)| a.merge(b)).collect(),
+    );
+
+    sums.iter()
+        .enumerate()
+        .map(|(i, s)| s.section(i + 1, boundary(i)))
+        .collect()
+}
+
+/// Boundary with the least bonded area, the likeliest to split under load.
+pub fn weakest(sections: &[Section]) -> Option<&Section> {
+    sections.iter().min_by(|a, b| a.area.total_cmp(&b.area))
+}
+
+pub fn write_csv(sections: &[Section], path: &str) -> Result<()> {
+    let mut w = storage::create(path)?;
+    writeln!(
+        w,
+        "layer,z,area,centroid_x,centroid_y,moment_min,moment_max,weak_axis"
+    )?;
+    for s in sections {
+        writeln!(
+            w,
+            "{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.1}",
+            s.layer,
+            s.z,
+            s.area,
+            s.centroid[0],
+            s.centroid[1],
+            s.moments[0],
+            s.moments[1],
+            s.weak_axis
+        )?;
+    }
+    w.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_sections() {
+        // 4x1mm slab, then a 1x1mm neck, then the slab again, rotated
+        let mut v = MonotonicVoxel::default();
+        let h = (LAYER_HEIGHT / UNIT).round() as i32;
+        for z in 0..h * 6 {
+            let (w, d) = match z / h {
+                0 | 1 => (100, 25),
+                2 | 3 => (25, 25),
+                _ => (25, 100),
+            };
+            for x in 0..w {
+                for y in 0..d {
+                    v.add([x, y, z].into());
+                }
+            }
+        }
+        let sections = sections(&v);
+        assert_eq!(sections.len(), 5);
+
+        let s = &sections[0];
+        assert!((s.area - 4f32).abs() < 1e-4, "{:?}", s);
+        assert!((s.centroid[0] - 2f32).abs() < 1e-4, "{:?}", s);
+        // 4x1mm rectangle: 4 * 1^3 / 12 about its long axis
+        assert!((s.moments[0] - 1f32 / 3f32).abs() < 1e-3, "{:?}", s);
+        assert!((s.moments[1] - 16f32 / 3f32).abs() < 1e-3, "{:?}", s);
+        assert!(s.weak_axis.abs() < 1e-3 || (s.weak_axis - 180f32).abs() < 1e-3);
+
+        let s = &sections[4];
+        assert!((s.weak_axis - 90f32).abs() < 1e-3, "{:?}", s);
+
+        let neck = weakest(&sections).unwrap();
+        assert!((neck.area - 1f32).abs() < 1e-4, "{:?}", neck);
+        assert_eq!(neck.layer, 2);
+        assert!((neck.centroid[0] - 0.5f32).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,200 @@
+use super::{storage, Frame, Observer, Simulation, Voxel, UNIT};
+use anyhow::Result;
+use rayon::prelude::*;
+use std::io::Write;
+use std::ops::Range;
+
+/// Voxel statistics after a frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub frame: usize,
+    pub layer: usize,
+    /// simulated print time, in seconds
+    pub time: f32,
+    pub blocks: usize,
+    /// lowest and highest corners of deposited voxels in millimeters, none if empty
+    pub bounds: Option<[[f32; 3]; 2]>,
+    /// exposed faces of deposited voxels, in square millimeters
+    pub area: f32,
+}
+
+/// Length of z where both sorted `a` and `b` are occupied.
+fn overlap(a: &[Range<i32>], b: &[Range<i32>]) -> i32 {
+    let (mut i, mut j, mut len) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        len += (a[i].end.min(b[j].end) - a[i].start.max(b[j].start)).max(0);
+        if a[i].end < b[j].end {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    len
+}
+
+/// Exposed faces of `v`, from its columns: tops and bottoms of ranges, and sides where
+/// neighbor columns are empty.
+pub fn exposed_faces<V: Voxel + Sync>(v: &V) -> usize {
+    let bb = v.bounding_box();
+    if bb.count == 0 {
+        return 0;
+    }
+    let (min, max) = (bb.bound_min, bb.bound_max);
+    let len = |c: &[Range<i32>]| c.iter().map(|r| r.end - r.start).sum::<i32>();
+    // faces between each column and its neighbors along +X and +Y, from a column left
+    // and below the bounds
+    (min[1] - 1..=max[1])
+        .into_par_iter()
+        .map(|y| {
+            let mut faces = 0;
+            let mut column = v.column(min[0] - 1, y);
+            for x in min[0] - 1..=max[0] {
+                let (right, up) = (v.column(x + 1, y), v.column(x, y + 1));
+                faces += 2 * column.len() as i32;
+                for other in [&right, &up] {
+                    faces += len(&column) + len(other) - 2 * overlap(&column, other);
+                }
+                column = right;
+            }
+            faces as usize
+        })
+        .sum()
+}
+
+impl Sample {
+    pub fn of<V: Voxel + Sync>(v: &V, frame: usize, layer: usize, time: f32) -> Self {
+        let bb = v.bounding_box();
+        let bounds = (bb.count > 0).then(|| {
+            [
+                [0, 1, 2].map(|i| bb.bound_min[i] as f32 * UNIT),
+                [0, 1, 2].map(|i| (bb.bound_max[i] + 1) as f32 * UNIT),
+            ]
+        });
+        Self {
+            frame,
+            layer,
+            time,
+            blocks: v.blocks(),
+            bounds,
+            area: exposed_faces(v) as f32 * UNIT * UNIT,
+        }
+    }
+}
+
+/// Voxel statistics at every frame, for plotting deposition over the print.
+#[derive(Default)]
+pub struct Series {
+    pub samples: Vec<Sample>,
+}
+
+impl Series {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn write_csv<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "frame,layer,time,blocks,min_x,min_y,min_z,max_x,max_y,max_z,area"
+        )?;
+        for s in &self.samples {
+            let bounds = match s.bounds {
+                Some([lo, hi]) => lo
+                    .iter()
+                    .chain(&hi)
+                    .map(|x| format!("{:.3}", x))
+                    .collect::<Vec<_>>()
+                    .join(","),
+                None => ",,,,,".to_owned(),
+            };
+            writeln!(
+                w,
+                "{},{},{:.3},{},{},{:.3}",
+                s.frame, s.layer, s.time, s.blocks, bounds, s.area
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        let samples = self
+            .samples
+            .iter()
+            .map(|s| {
+                json!({
+                    "frame": s.frame,
+                    "layer": s.layer,
+                    "time": s.time,
+                    "blocks": s.blocks,
+                    "bounds": s.bounds,
+                    "area": s.area,
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({ "samples": samples })
+    }
+
+    /// Writes the samples as json if `path` ends with `.json`, as csv otherwise.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let mut w = storage::create(path)?;
+        if path.ends_with(".json") {
+            serde_json::to_writer_pretty(&mut w, &self.to_json())?;
+        } else {
+            self.write_csv(&mut w)?;
+        }
+        w.finish()
+    }
+}
+
+/// Samples the voxels as each frame is written.
+impl<V: Voxel + Sync> Observer<V> for Series {
+    fn on_frame(&mut self, sim: &Simulation<V>, frame: &Frame) -> Result<()> {
+        let sample = Sample::of(&sim.voxel, self.samples.len() + 1, frame.layer, frame.time);
+        self.samples.push(sample);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_series() {
+        let r = 2..7;
+        assert_eq!(overlap(&[0..4, 6..8], std::slice::from_ref(&r)), 3);
+
+        // a 2x1x3 block, and a voxel floating above it
+        let mut v = MonotonicVoxel::default();
+        for z in 0..3 {
+            v.add([0, 0, z].into());
+            v.add([1, 0, z].into());
+        }
+        v.add([0, 0, 4].into());
+        let mut series = Series::new();
+        series.samples.push(Sample::of(&v, 1, 0, 2.5));
+        series
+            .samples
+            .push(Sample::of(&MonotonicVoxel::default(), 2, 1, 3.0));
+        let s = &series.samples[0];
+        assert_eq!(s.blocks, 7);
+        assert_eq!(exposed_faces(&v), 2 * (2 + 2 * 3 + 3) + 6);
+        assert_eq!(s.bounds.unwrap()[1][2], 5f32 * UNIT);
+        assert!((s.area - 28f32 * UNIT * UNIT).abs() < 1e-9);
+
+        let mut buf = Vec::new();
+        series.write_csv(&mut buf).unwrap();
+        let csv = String::from_utf8(buf).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("1,0,2.500,7,0.000,0.000,0.000,"));
+        assert_eq!(lines[2], "2,1,3.000,0,,,,,,,0.000");
+        assert_eq!(
+            series.to_json()["samples"][1]["bounds"],
+            serde_json::Value::Null
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use super::BoundingBox;
+
+/// Shrinkage of parts after cooling, applied to exported models only.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Shrinkage {
+    /// per axis, in percent
+    pub axes: [f32; 3],
+    /// extra XY shrinkage at the top of the part in percent, none at the bed where the
+    /// part is held, so walls lean in like warped prints
+    pub warp: f32,
+}
+
+impl Shrinkage {
+    pub fn is_none(&self) -> bool {
+        self.axes == [0f32; 3] && self.warp == 0f32
+    }
+
+    /// Moves `p` in voxels towards the XY center of `bb`, and down towards the bed.
+    pub fn apply(&self, p: [f32; 3], bb: &BoundingBox) -> [f32; 3] {
+        if bb.count == 0 || self.is_none() {
+            return p;
+        }
+        let center = [0, 1].map(|i| (bb.bound_min[i] + bb.bound_max[i] + 1) as f32 / 2f32);
+        let height = (bb.bound_max[2] + 1).max(1) as f32;
+        let warp = self.warp * (p[2] / height).clamp(0f32, 1f32);
+
+        let xy = |i: usize| {
+            let k = 1f32 - (self.axes[i] + warp) / 100f32;
+            center[i] + (p[i] - center[i]) * k
+        };
+        [xy(0), xy(1), p[2] * (1f32 - self.axes[2] / 100f32)]
+    }
+
+    pub fn describe(&self) -> String {
+        let [x, y, z] = self.axes;
+        format!("{},{},{}%, warp {}%", x, y, z, self.warp)
+    }
+}
+
+impl std::str::FromStr for Shrinkage {
+    type Err = String;
+
+    /// `p` for every axis, or `x,y,z`, in percent.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid shrinkage {}: {}", s, e))?;
+        let axes = match values.as_slice() {
+            [p] => [*p; 3],
+            [x, y, z] => [*x, *y, *z],
+            _ => return Err(format!("expected p or x,y,z percentages, got {}", s)),
+        };
+        Ok(Self { axes, warp: 0f32 })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{MonotonicVoxel, Voxel};
+
+    #[test]
+    pub fn test_shrinkage() {
+        let mut v = MonotonicVoxel::default();
+        v.add([0, 0, 0].into());
+        v.add([99, 99, 99].into());
+        let bb = v.bounding_box();
+
+        let s = "1".parse::<Shrinkage>().unwrap();
+        assert_eq!(s.apply([0., 0., 0.], bb), [0.5, 0.5, 0.]);
+        assert_eq!(s.apply([100., 50., 100.], bb), [99.5, 50., 99.]);
+
+        let s = Shrinkage {
+            axes: [0f32; 3],
+            warp: 2f32,
+        };
+        // held at the bed, leaning in at the top
+        assert_eq!(s.apply([0., 0., 0.], bb), [0., 0., 0.]);
+        assert_eq!(s.apply([0., 0., 100.], bb), [1., 1., 100.]);
+
+        assert_eq!("1,2,0.5".parse::<Shrinkage>().unwrap().axes, [1., 2., 0.5]);
+        assert!("1,2".parse::<Shrinkage>().is_err());
+        assert!(Shrinkage::default().is_none());
+    }
+}
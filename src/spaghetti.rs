@@ -0,0 +1,105 @@
+use super::{render::random, Voxel, VoxelIdx, UNIT};
+use nalgebra::Vector2;
+
+/// Print detached from the bed, the failure known as spaghetti. From the detach layer,
+/// beads are not anchored under the nozzle: each falls onto whatever is below, the bed,
+/// the part or earlier strands, drifting away from the nozzle in a random walk, so
+/// strands tangle and pile up near the bed.
+#[derive(Clone, Debug)]
+pub struct Spaghetti {
+    /// first detached layer
+    layer: usize,
+    seed: u32,
+    active: bool,
+    /// offset of the strand from the nozzle, in millimeters
+    drift: Vector2<f32>,
+    /// random walk steps so far
+    steps: u32,
+}
+
+/// Random walk step, in millimeters.
+const STEP: f32 = 0.2;
+/// Pull back towards the nozzle on each step, which keeps strands within a few
+/// millimeters of it.
+const PULL: f32 = 0.995;
+
+impl Spaghetti {
+    pub fn new(layer: usize, seed: u32) -> Self {
+        Self {
+            layer,
+            seed,
+            active: false,
+            drift: Vector2::zeros(),
+            steps: 0,
+        }
+    }
+
+    /// Follows a layer change of the simulation.
+    pub fn set_layer(&mut self, layer: usize) {
+        self.active = layer >= self.layer;
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.active
+    }
+
+    /// Where a bead of `depth` voxels lands, deposited with the nozzle at `pos`.
+    pub fn land<V: Voxel>(&mut self, v: &V, pos: VoxelIdx, depth: i32) -> VoxelIdx {
+        let s = self.seed.wrapping_mul(0x2545f491).wrapping_add(self.steps);
+        let step = Vector2::new(random(s * 2) - 0.5, random(s * 2 + 1) - 0.5) * 2f32 * STEP;
+        self.drift = self.drift * PULL + step;
+        self.steps = self.steps.wrapping_add(1);
+
+        let x = pos[0] + (self.drift[0] / UNIT).round() as i32;
+        let y = pos[1] + (self.drift[1] / UNIT).round() as i32;
+        // top of material below the nozzle, 0 for the bed
+        let top = v
+            .column(x, y)
+            .into_iter()
+            .filter(|r| r.start <= pos[2])
+            .map(|r| r.end)
+            .max()
+            .unwrap_or(0);
+        [x, y, top + depth].into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_spaghetti() {
+        let mut v = MonotonicVoxel::default();
+        for x in -10..=10 {
+            for y in -10..=10 {
+                for z in 0..10 {
+                    v.add([x, y, z].into());
+                }
+            }
+        }
+        let mut spaghetti = Spaghetti::new(3, 1);
+        spaghetti.set_layer(2);
+        assert!(!spaghetti.is_detached());
+        spaghetti.set_layer(3);
+        assert!(spaghetti.is_detached());
+
+        // strands drift, but stay near the nozzle, and fall to the bed
+        let mut landed = Vec::new();
+        for _ in 0..1000 {
+            let p = spaghetti.land(&v, [500, 500, 250].into(), 5);
+            assert_eq!(p[2], 5);
+            landed.push(p);
+        }
+        assert!(landed.windows(2).any(|w| w[0] != w[1]));
+        assert!(landed
+            .iter()
+            .all(|p| (p[0] - 500).abs() < 250 && (p[1] - 500).abs() < 250));
+
+        // or onto material below, within a step of the nozzle
+        let mut spaghetti = Spaghetti::new(0, 1);
+        let p = spaghetti.land(&v, [0, 0, 250].into(), 5);
+        assert_eq!(p[2], 15);
+    }
+}
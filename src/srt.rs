@@ -0,0 +1,100 @@
+use super::eta::clock;
+use super::{storage, Frame, Observer, Simulation};
+use anyhow::Result;
+use std::io::Write;
+
+/// `hh:mm:ss,mmm` timestamp of srt subtitles.
+fn timestamp(seconds: f64) -> String {
+    let ms = (seconds.max(0f64) * 1000f64).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Statistics of frames as a subtitle track, for players and overlays to show them
+/// during playback of videos of the frames, one frame after another at `fps`.
+pub struct Subtitles {
+    fps: f32,
+    frames: Vec<Frame>,
+}
+
+impl Subtitles {
+    pub fn new(fps: f32) -> Self {
+        Self {
+            fps,
+            frames: Vec::new(),
+        }
+    }
+
+    fn write<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+        let step = 1f64 / self.fps as f64;
+        for (i, f) in self.frames.iter().enumerate() {
+            writeln!(w, "{}", i + 1)?;
+            writeln!(
+                w,
+                "{} --> {}",
+                timestamp(i as f64 * step),
+                timestamp((i + 1) as f64 * step)
+            )?;
+            writeln!(
+                w,
+                "layer {} | z {:.2}mm | {} | {:.1}mm³/s",
+                f.layer,
+                f.z,
+                clock(f.time),
+                f.flow
+            )?;
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the track as srt.
+    pub fn write_srt(&self, path: &str) -> Result<()> {
+        let mut w = storage::create(path)?;
+        self.write(&mut w)?;
+        w.finish()
+    }
+}
+
+/// Collects frames as they are written.
+impl<V> Observer<V> for Subtitles {
+    fn on_frame(&mut self, _sim: &Simulation<V>, frame: &Frame) -> Result<()> {
+        self.frames.push(frame.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_subtitles() {
+        let mut subtitles = Subtitles::new(24f32);
+        for (i, time) in [3.0, 3725.5].into_iter().enumerate() {
+            subtitles.frames.push(Frame {
+                file: format!("gcode_{:03}.obj", i + 1),
+                layer: i + 1,
+                time,
+                clock: time,
+                z: 0.2 * (i + 2) as f32,
+                flow: 4.25,
+                blocks: 0,
+                hash: 0,
+            });
+        }
+        let mut buf = Vec::new();
+        subtitles.write(&mut buf).unwrap();
+        let srt = String::from_utf8(buf).unwrap();
+        let lines = srt.lines().collect::<Vec<_>>();
+        assert_eq!(lines[1], "00:00:00,000 --> 00:00:00,042");
+        assert_eq!(lines[2], "layer 1 | z 0.40mm | 0:00:03 | 4.2mm³/s");
+        assert_eq!(lines[5], "00:00:00,042 --> 00:00:00,083");
+        assert_eq!(lines[6], "layer 2 | z 0.60mm | 1:02:05 | 4.2mm³/s");
+    }
+}
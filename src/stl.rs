@@ -0,0 +1,95 @@
+use super::{Model, VoxelIdx};
+use anyhow::Result;
+use nalgebra::Vector3;
+use std::io::Write;
+
+const HEADER_LEN: usize = 80;
+
+/// Header of binary stl files: metadata as `key=value` pairs, cut at 80 bytes. Never
+/// starts with `solid`, so readers do not take it for ascii stl.
+fn header(model: &Model) -> [u8; HEADER_LEN] {
+    let text = model
+        .metadata
+        .entries()
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut header = [b' '; HEADER_LEN];
+    let bytes = text.as_bytes();
+    let len = bytes.len().min(HEADER_LEN);
+    header[..len].copy_from_slice(&bytes[..len]);
+    header
+}
+
+/// Writes `model` as binary stl to `w`, with vertex positions from `position`. Quads are
+/// split in two triangles, with normals from their winding; groups and colors are lost.
+pub fn write_stl<W, F>(model: &Model, mut w: W, position: F) -> Result<()>
+where
+    W: Write,
+    F: Fn(VoxelIdx) -> [f32; 3],
+{
+    w.write_all(&header(model))?;
+    let triangles = model.faces.len() * 2;
+    anyhow::ensure!(
+        triangles <= u32::MAX as usize,
+        "too many triangles for stl: {}",
+        triangles
+    );
+    w.write_all(&(triangles as u32).to_le_bytes())?;
+
+    let mut buf = Vec::with_capacity(50);
+    for face in &model.faces {
+        let [p0, p1, p2, p3] = face.map(|i| Vector3::from(position(model.vertices[i])));
+        for [a, b, c] in [[p0, p1, p2], [p0, p2, p3]] {
+            let normal = (b - a).cross(&(c - a));
+            let normal = normal.try_normalize(0f32).unwrap_or_else(Vector3::zeros);
+            buf.clear();
+            for p in [normal, a, b, c] {
+                p.iter()
+                    .for_each(|x| buf.extend_from_slice(&x.to_le_bytes()));
+            }
+            // attribute byte count, unused
+            buf.extend_from_slice(&0u16.to_le_bytes());
+            w.write_all(&buf)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{surface, Metadata};
+
+    #[test]
+    pub fn test_stl() {
+        let mut model = Model::default();
+        model.add_cube([0, 0, 0].into());
+        surface::orient(&mut model, |c| c == [0, 0, 0].into());
+        model.metadata = Metadata::new();
+
+        let mut buf = Vec::new();
+        write_stl(&model, &mut buf, |idx| {
+            [0, 1, 2].map(|i| idx[i] as f32 * 2f32)
+        })
+        .unwrap();
+        assert_eq!(buf.len(), 80 + 4 + 12 * 50);
+        assert!(buf.starts_with(b"generator=tdp-tl"));
+        assert_eq!(u32::from_le_bytes(buf[80..84].try_into().unwrap()), 12);
+
+        // normals are unit vectors along an axis, pointing out of the cube
+        let f = |at: usize| f32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+        for t in 0..12 {
+            let at = 84 + t * 50;
+            let normal = [0, 1, 2].map(|i| f(at + i * 4));
+            let centroid =
+                [0, 1, 2].map(|i| (0..3).map(|v| f(at + 12 + v * 12 + i * 4)).sum::<f32>() / 3f32);
+            assert_eq!(normal.iter().map(|x| x.abs()).sum::<f32>(), 1f32);
+            let out = (0..3)
+                .map(|i| normal[i] * (centroid[i] - 1f32))
+                .sum::<f32>();
+            assert!(out > 0f32);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use super::s3::{self, Storage, Upload};
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Output file being written: a local file, or an object of S3-compatible storage for
+/// `s3://bucket/key` paths, uploaded in parts as it is written and completed by
+/// `finish`, without staging on local disk. Objects of sinks dropped without `finish`
+/// are not created.
+pub enum Sink {
+    File(BufWriter<File>),
+    Object(Box<Upload>),
+}
+
+/// Creates the output file `path`, local or `s3://bucket/key`.
+pub fn create(path: &str) -> Result<Sink> {
+    match s3::split(path) {
+        Some((bucket, key)) => {
+            anyhow::ensure!(
+                !bucket.is_empty() && !key.is_empty(),
+                "{}: expected s3://bucket/key",
+                path
+            );
+            let storage = Storage::from_env()?;
+            Ok(Sink::Object(Box::new(Upload::new(storage, bucket, key))))
+        }
+        None => Ok(Sink::File(BufWriter::new(File::create(path)?))),
+    }
+}
+
+/// Writes `data` to the output file `path`, like `std::fs::write`.
+pub fn write(path: &str, data: impl AsRef<[u8]>) -> Result<()> {
+    let mut sink = create(path)?;
+    sink.write_all(data.as_ref())?;
+    sink.finish()
+}
+
+/// Creates the output directory `dir` and its parents. Buckets have no directories.
+pub fn create_dir_all(dir: &str) -> Result<()> {
+    if s3::split(dir).is_none() {
+        std::fs::create_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+impl Sink {
+    /// Flushes the file, or uploads the object.
+    pub fn finish(self) -> Result<()> {
+        match self {
+            Self::File(mut w) => w.flush()?,
+            Self::Object(upload) => upload.finish()?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::File(w) => w.write(buf),
+            Self::Object(upload) => upload.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::File(w) => w.flush(),
+            Self::Object(upload) => upload.flush(),
+        }
+    }
+}
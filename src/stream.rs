@@ -0,0 +1,278 @@
+use super::chunk::{self, ChunkMesh};
+use super::{metadata::hash_bytes, s3, Cancel, Metadata, Observer, Simulation, Voxel, UNIT};
+use anyhow::Result;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const BUFFER: &str = "chunks.bin";
+const MANIFEST: &str = "stream.json";
+
+/// Height of bricks, in voxels. Chunks span columns up to the top, so chunks are split
+/// into bricks along Z, and only bricks which changed are appended.
+const BRICK_HEIGHT: i32 = 16;
+
+/// Chunk coordinates, and the brick along Z.
+type BrickId = [i32; 3];
+
+/// Brick mesh appended to the buffer: positions and normals as little endian f32
+/// triples, then u32 indices. Each array can be used as a glTF buffer view, or a
+/// `BufferAttribute` in three.js, without copying. Empty records clear the brick.
+#[derive(Debug)]
+struct Record {
+    brick: BrickId,
+    /// in bytes, from the start of the buffer
+    offset: u64,
+    vertices: usize,
+    indices: usize,
+}
+
+#[derive(Debug)]
+struct StreamFrame {
+    layer: usize,
+    /// simulated print time, in seconds
+    time: f32,
+    blocks: usize,
+    records: Vec<Record>,
+}
+
+/// Progressive mesh stream for web viewers. Each frame appends meshes of chunks changed
+/// since the previous frame to a single buffer, which is never rewritten, so viewers
+/// fetch new bytes with range requests instead of whole frames. A record replaces
+/// earlier records of the same chunk.
+pub struct Stream {
+    dir: String,
+    meta: Metadata,
+    buffer: BufWriter<File>,
+    offset: u64,
+    frames: Vec<StreamFrame>,
+    /// hash of the last record of each brick
+    bricks: HashMap<BrickId, u64>,
+    cancel: Cancel,
+}
+
+impl Stream {
+    pub fn new(dir: &str, meta: &Metadata) -> Result<Self> {
+        // objects are written whole, not appended to or renamed while viewers poll them
+        anyhow::ensure!(
+            s3::split(dir).is_none(),
+            "{}: streams are written to local directories only, not s3",
+            dir
+        );
+        std::fs::create_dir_all(dir)?;
+        let buffer = BufWriter::new(File::create(format!("{}/{}", dir, BUFFER))?);
+        Ok(Self {
+            dir: dir.to_owned(),
+            meta: meta.clone(),
+            buffer,
+            offset: 0,
+            frames: Vec::new(),
+            bricks: HashMap::new(),
+            cancel: Cancel::default(),
+        })
+    }
+
+    /// Stops meshing frames once `cancel` is set. Chunks left out of a cancelled frame
+    /// keep their earlier records.
+    pub fn with_cancel(mut self, cancel: &Cancel) -> Self {
+        self.cancel = cancel.clone();
+        self
+    }
+
+    /// Appends meshes of bricks changed since the last frame, and rewrites the manifest
+    /// after the buffer is flushed, so listed records are always complete.
+    pub fn frame<V: Voxel + Sync>(&mut self, v: &mut V, layer: usize, time: f32) -> Result<()> {
+        let dirty = v.take_dirty();
+        let meshes = chunk::mesh_chunks(&*v, dirty, &self.cancel)
+            .into_par_iter()
+            .map(|(id, mesh)| (id, bricks(mesh)))
+            .collect::<Vec<_>>();
+
+        let mut records = Vec::new();
+        for ([x, y], bricks) in meshes {
+            // bricks of the chunk without faces anymore
+            let stale = self
+                .bricks
+                .keys()
+                .filter(|[bx, by, bz]| [*bx, *by] == [x, y] && !bricks.contains_key(bz))
+                .copied()
+                .collect::<Vec<_>>();
+            for brick in stale {
+                self.bricks.remove(&brick);
+                records.push(Record {
+                    brick,
+                    offset: self.offset,
+                    vertices: 0,
+                    indices: 0,
+                });
+            }
+
+            for (z, mesh) in bricks {
+                let data = encode(&mesh);
+                let hash = hash_bytes(&data);
+                if self.bricks.insert([x, y, z], hash) == Some(hash) {
+                    continue;
+                }
+                records.push(Record {
+                    brick: [x, y, z],
+                    offset: self.offset,
+                    vertices: mesh.positions.len(),
+                    indices: mesh.indices.len(),
+                });
+                self.buffer.write_all(&data)?;
+                self.offset += data.len() as u64;
+            }
+        }
+        self.buffer.flush()?;
+
+        self.frames.push(StreamFrame {
+            layer,
+            time,
+            blocks: v.blocks(),
+            records,
+        });
+        self.write_manifest()
+    }
+
+    /// Appends the state after the last layer, which has no layer change after it.
+    pub fn finish<V: Voxel + Sync>(&mut self, v: &mut V, time: f32) -> Result<()> {
+        let layer = self.frames.last().map(|f| f.layer + 1).unwrap_or(0);
+        self.frame(v, layer, time)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        let frames = self
+            .frames
+            .iter()
+            .map(|f| {
+                let chunks = f
+                    .records
+                    .iter()
+                    .map(|r| {
+                        json!({
+                            "brick": r.brick,
+                            "offset": r.offset,
+                            "vertices": r.vertices,
+                            "indices": r.indices,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({
+                    "layer": f.layer,
+                    "time": f.time,
+                    "blocks": f.blocks,
+                    "chunks": chunks,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "metadata": self.meta.to_json(),
+            "buffer": BUFFER,
+            "length": self.offset,
+            "layout": ["position:f32x3", "normal:f32x3", "index:u32"],
+            "units": "mm",
+            "brick_height": BRICK_HEIGHT as f32 * UNIT,
+            "frames": frames,
+        })
+    }
+
+    fn write_manifest(&self) -> Result<()> {
+        // renamed into place, so viewers polling the manifest never read half of it
+        let path = format!("{}/{}", self.dir, MANIFEST);
+        let tmp = format!("{}.tmp", path);
+        let f = File::create(&tmp)?;
+        serde_json::to_writer(BufWriter::new(f), &self.to_json())?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Appends a frame before each layer change.
+impl<V: Voxel + Sync> Observer<V> for Stream {
+    fn on_layer_complete(&mut self, sim: &mut Simulation<V>, layer: usize) -> Result<()> {
+        self.frame(&mut sim.voxel, layer, sim.time)
+    }
+}
+
+/// Splits quads of `mesh_chunk`, 4 vertices and 6 indices each, into bricks by their
+/// lowest corner.
+fn bricks(mesh: ChunkMesh) -> BTreeMap<i32, ChunkMesh> {
+    let mut bricks = BTreeMap::<i32, ChunkMesh>::new();
+    for q in 0..mesh.positions.len() / 4 {
+        let corners = &mesh.positions[q * 4..q * 4 + 4];
+        let z = corners
+            .iter()
+            .map(|p| (p[2] / UNIT).round() as i32)
+            .min()
+            .unwrap_or(0);
+        let brick = bricks.entry(z.div_euclid(BRICK_HEIGHT)).or_default();
+        let base = brick.positions.len() as u32;
+        brick.positions.extend_from_slice(corners);
+        brick
+            .normals
+            .extend_from_slice(&mesh.normals[q * 4..q * 4 + 4]);
+        let indices = &mesh.indices[q * 6..q * 6 + 6];
+        brick
+            .indices
+            .extend(indices.iter().map(|i| i - q as u32 * 4 + base));
+    }
+    bricks
+}
+
+fn encode(mesh: &ChunkMesh) -> Vec<u8> {
+    let mut data = Vec::with_capacity(mesh.positions.len() * 24 + mesh.indices.len() * 4);
+    for p in mesh.positions.iter().chain(&mesh.normals) {
+        for c in p {
+            data.extend_from_slice(&c.to_le_bytes());
+        }
+    }
+    for i in &mesh.indices {
+        data.extend_from_slice(&i.to_le_bytes());
+    }
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_stream() {
+        let dir = std::env::temp_dir().join(format!("tdp-tl-stream-{}", std::process::id()));
+        let dir = dir.to_string_lossy().into_owned();
+        let mut stream = Stream::new(&dir, &Metadata::new()).unwrap();
+
+        let mut v = MonotonicVoxel::default();
+        v.add([0, 0, 0].into());
+        v.add([0, 0, 20].into());
+        stream.frame(&mut v, 1, 1.0).unwrap();
+        // a brick above, and nothing changed below
+        v.add([0, 0, 40].into());
+        stream.frame(&mut v, 2, 2.0).unwrap();
+        stream.finish(&mut v, 3.0).unwrap();
+
+        let json = stream.to_json();
+        let frames = json["frames"].as_array().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0]["chunks"].as_array().unwrap().len(), 2);
+        assert_eq!(frames[2]["layer"], 3);
+        assert!(frames[2]["chunks"].as_array().unwrap().is_empty());
+        // a cube: 24 vertices and 36 indices, appended after two cubes
+        let chunks = frames[1]["chunks"].as_array().unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0]["brick"], serde_json::json!([0, 0, 2]));
+        assert_eq!(chunks[0]["vertices"], 24);
+        assert_eq!(chunks[0]["offset"], 2 * (24 * 24 + 36 * 4));
+
+        let len = std::fs::metadata(format!("{}/{}", dir, BUFFER))
+            .unwrap()
+            .len();
+        assert_eq!(json["length"], len);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
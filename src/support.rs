@@ -0,0 +1,55 @@
+use super::{MonotonicVoxel, Voxel, VoxelIdx};
+
+/// Returns true for `;TYPE:` comments of support moves, from Cura (`SUPPORT`,
+/// `SUPPORT-INTERFACE`) and PrusaSlicer (`Support material`, ...).
+pub fn is_support(feature: &str) -> bool {
+    feature.to_ascii_lowercase().starts_with("support")
+}
+
+/// Returns how far up, in voxels, a bead at `pos` spanning `depth` voxels below has to
+/// move to leave `gap` voxels of air above `support`.
+pub fn support_offset(support: &MonotonicVoxel, pos: VoxelIdx, depth: i32, gap: i32) -> i32 {
+    let zlow = pos[2] - depth;
+
+    let mut top = None;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let column_top = (zlow - gap - 1..=pos[2])
+                .rev()
+                .find(|&z| support.occupied([pos[0] + dx, pos[1] + dy, z].into()));
+            top = top.max(column_top);
+        }
+    }
+
+    match top {
+        Some(top) => (top + 1 + gap - zlow).max(0),
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_support_offset() {
+        let mut support = MonotonicVoxel::default();
+        for z in 0..5 {
+            support.add([0, 0, z].into());
+        }
+
+        // bead right above support, [5, 10]
+        assert_eq!(support_offset(&support, [0, 0, 10].into(), 5, 5), 5);
+        assert_eq!(support_offset(&support, [1, 0, 10].into(), 5, 5), 5);
+        assert_eq!(support_offset(&support, [0, 0, 10].into(), 5, 0), 0);
+        // already far enough
+        assert_eq!(support_offset(&support, [0, 0, 15].into(), 5, 5), 0);
+        assert_eq!(support_offset(&support, [3, 0, 10].into(), 5, 5), 0);
+        // thin bead of a thin layer, [8, 10]
+        assert_eq!(support_offset(&support, [0, 0, 10].into(), 2, 5), 2);
+
+        assert!(is_support("SUPPORT-INTERFACE"));
+        assert!(is_support("Support material"));
+        assert!(!is_support("WALL-OUTER"));
+    }
+}
@@ -0,0 +1,130 @@
+use super::flood::{self, Connectivity};
+use super::{Model, Voxel, VoxelIdx};
+
+/// Which faces of the voxel model are exported.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ExportMode {
+    /// every face generated by the backend
+    #[default]
+    Full,
+    /// upward-facing faces only, for top surface inspection
+    Top,
+    /// faces reachable from outside the model, flood filled from the bounding box
+    Silhouette,
+}
+
+impl std::str::FromStr for ExportMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Self::Full),
+            "top" => Ok(Self::Top),
+            "silhouette" => Ok(Self::Silhouette),
+            _ => Err(format!(
+                "unknown export mode: {}, expected one of full, top, silhouette",
+                s
+            )),
+        }
+    }
+}
+
+/// Returns voxels on both sides of a quad face, ordered along the face's axis,
+/// and the axis itself.
+pub fn face_cells(model: &Model, face: &[usize; 4]) -> (VoxelIdx, VoxelIdx, usize) {
+    let verts = face.map(|i| model.vertices[i]);
+    let lo = verts.iter().fold(verts[0], |acc, v| acc.bb_min(v));
+    let hi = verts.iter().fold(verts[0], |acc, v| acc.bb_max(v));
+
+    let axis = (0..3).find(|&axis| lo[axis] == hi[axis]).unwrap_or(2);
+    let mut behind = lo;
+    behind.idx[axis] -= 1;
+    (behind, lo, axis)
+}
+
+/// Winds faces counterclockwise seen from their empty side, so normals from the winding
+/// point out of `occupied` voxels, as formats with normals like stl need. Faces are
+/// wound by their axis otherwise; faces without a single occupied side are kept as is.
+pub fn orient(model: &mut Model, occupied: impl Fn(VoxelIdx) -> bool) {
+    for i in 0..model.faces.len() {
+        let face = model.faces[i];
+        let (behind, front, axis) = face_cells(model, &face);
+        let outward = match (occupied(behind), occupied(front)) {
+            (true, false) => 1,
+            (false, true) => -1,
+            _ => continue,
+        };
+        let [v0, v1, v2, _] = face.map(|i| model.vertices[i]);
+        let (a, b) = (v1 - v0, v2 - v0);
+        let (j, k) = ((axis + 1) % 3, (axis + 2) % 3);
+        let normal = a[j] * b[k] - a[k] * b[j];
+        if normal * outward < 0 {
+            model.faces[i].reverse();
+        }
+    }
+}
+
+/// Generates model from voxels, keeping faces selected by `mode`.
+pub fn to_model<V: Voxel>(v: &V, mode: ExportMode) -> Model {
+    let model = v.to_model();
+
+    match mode {
+        ExportMode::Full => model,
+        ExportMode::Top => model.retain_faces(|model, face| {
+            let (below, above, axis) = face_cells(model, face);
+            axis == 2 && v.occupied(below) && !v.occupied(above)
+        }),
+        ExportMode::Silhouette => {
+            if v.bounding_box().count == 0 {
+                return model;
+            }
+            let ext = flood::exterior(v, Connectivity::Face);
+            model.retain_faces(|model, face| {
+                let (c0, c1, _) = face_cells(model, face);
+                (v.occupied(c0) && ext.contains(c1)) || (v.occupied(c1) && ext.contains(c0))
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RangeSetVoxel;
+
+    // hollow 5x5x5 box, with a single voxel floating inside
+    fn hollow_box() -> RangeSetVoxel {
+        let mut v = RangeSetVoxel::default();
+        for z in 0..5 {
+            for y in 0..5 {
+                for x in 0..5 {
+                    let shell = [x, y, z].iter().any(|&c| c == 0 || c == 4);
+                    if shell || [x, y, z] == [2, 2, 2] {
+                        v.add([x, y, z].into());
+                    }
+                }
+            }
+        }
+        v
+    }
+
+    #[test]
+    pub fn test_top() {
+        let v = hollow_box();
+        let model = to_model(&v, ExportMode::Top);
+
+        // 25 faces on the lid, 9 on the floor under the cavity, and one on top of
+        // the inner voxel
+        assert_eq!(model.faces.len(), 25 + 9 + 1);
+    }
+
+    #[test]
+    pub fn test_silhouette() {
+        let v = hollow_box();
+        let full = to_model(&v, ExportMode::Full);
+        let model = to_model(&v, ExportMode::Silhouette);
+
+        assert_eq!(model.faces.len(), 6 * 25);
+        assert!(model.faces.len() < full.faces.len());
+    }
+}
@@ -0,0 +1,236 @@
+use super::{storage, Params, Simulation, Voxel, UNIT};
+use anyhow::Result;
+use std::io::Write;
+
+/// Values of a parameter to sweep, from `name=v1,v2,...`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Axis {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+impl std::str::FromStr for Axis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, values) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=v1,v2,..., got {}", s))?;
+        let values = values
+            .split(',')
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            return Err(format!("no values for {}", name));
+        }
+        // fail before simulating anything
+        for value in &values {
+            Params::default()
+                .set(name.trim(), value)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(Self {
+            name: name.trim().to_owned(),
+            values,
+        })
+    }
+}
+
+/// Every combination of values of `axes`, as indices into the values of each axis. The
+/// last axis changes fastest.
+pub fn grid(axes: &[Axis]) -> Vec<Vec<usize>> {
+    let mut grid = vec![vec![]];
+    for axis in axes {
+        grid = grid
+            .into_iter()
+            .flat_map(|point| {
+                (0..axis.values.len()).map(move |i| {
+                    let mut point = point.clone();
+                    point.push(i);
+                    point
+                })
+            })
+            .collect();
+    }
+    grid
+}
+
+/// `params` with values of a grid point.
+pub fn params_at(params: &Params, axes: &[Axis], point: &[usize]) -> Result<Params> {
+    let mut params = params.clone();
+    for (axis, i) in axes.iter().zip(point) {
+        params.set(&axis.name, &axis.values[*i])?;
+    }
+    Ok(params)
+}
+
+/// Metrics of a run, to compare against measurements of a real print.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    pub blocks: usize,
+    /// in cubic millimeters
+    pub volume: f32,
+    /// bounding box size, in millimeters
+    pub size: [f32; 3],
+    /// simulated print time, in seconds
+    pub time: f32,
+}
+
+impl Metrics {
+    pub fn of<V: Voxel>(sim: &Simulation<V>) -> Self {
+        let bb = sim.voxel.bounding_box();
+        let size = if bb.count > 0 {
+            [0, 1, 2].map(|i| (bb.bound_max[i] - bb.bound_min[i] + 1) as f32 * UNIT)
+        } else {
+            [0f32; 3]
+        };
+        let blocks = sim.voxel.blocks();
+        Self {
+            blocks,
+            volume: blocks as f32 * UNIT * UNIT * UNIT,
+            size,
+            time: sim.time,
+        }
+    }
+}
+
+/// Summary table of a sweep, a row for each run.
+pub struct Summary {
+    axes: Vec<Axis>,
+    rows: Vec<(String, Vec<usize>, Metrics)>,
+}
+
+impl Summary {
+    pub fn new(axes: &[Axis]) -> Self {
+        Self {
+            axes: axes.to_vec(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, file: &str, point: &[usize], metrics: Metrics) {
+        self.rows.push((file.to_owned(), point.to_vec(), metrics));
+    }
+
+    fn header(&self) -> Vec<String> {
+        let mut header = vec!["file".to_owned()];
+        header.extend(self.axes.iter().map(|a| a.name.clone()));
+        for name in ["blocks", "volume_mm3", "x_mm", "y_mm", "z_mm", "time_s"] {
+            header.push(name.to_owned());
+        }
+        header
+    }
+
+    fn cells(&self) -> Vec<Vec<String>> {
+        self.rows
+            .iter()
+            .map(|(file, point, m)| {
+                let mut row = vec![file.clone()];
+                row.extend(
+                    self.axes
+                        .iter()
+                        .zip(point)
+                        .map(|(a, i)| a.values[*i].clone()),
+                );
+                row.push(m.blocks.to_string());
+                row.push(format!("{:.3}", m.volume));
+                row.extend(m.size.iter().map(|s| format!("{:.2}", s)));
+                row.push(format!("{:.1}", m.time));
+                row
+            })
+            .collect()
+    }
+
+    pub fn write_csv(&self, path: &str) -> Result<()> {
+        let mut w = storage::create(path)?;
+        writeln!(w, "{}", self.header().join(","))?;
+        for row in self.cells() {
+            writeln!(w, "{}", row.join(","))?;
+        }
+        w.finish()
+    }
+
+    /// Aligned table, for terminals.
+    pub fn to_table(&self) -> String {
+        let header = self.header();
+        let cells = self.cells();
+        let widths = (0..header.len())
+            .map(|i| {
+                cells
+                    .iter()
+                    .map(|row| row[i].len())
+                    .chain([header[i].len()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+
+        let mut table = String::new();
+        for row in std::iter::once(&header).chain(&cells) {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, w)| format!("{:>w$}", cell, w = w))
+                .collect::<Vec<_>>();
+            table.push_str(line.join("  ").trim_end());
+            table.push('\n');
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_grid() {
+        let axes = ["flow=0.9,1.0,1.1", "spread_depth=4,5"]
+            .map(|s| s.parse::<Axis>().unwrap())
+            .to_vec();
+        let points = grid(&axes);
+        assert_eq!(points.len(), 6);
+        assert_eq!(points[1], vec![0, 1]);
+        assert_eq!(points[5], vec![2, 1]);
+
+        let params = params_at(&Params::default(), &axes, &points[5]).unwrap();
+        assert_eq!(params.flow, 1.1);
+        assert_eq!(params.spread_depth, 5);
+
+        assert!("nozzle=0.4".parse::<Axis>().is_err());
+        assert!("flow=".parse::<Axis>().is_err());
+        assert!("flow=a".parse::<Axis>().is_err());
+        assert_eq!(grid(&[]).len(), 1);
+    }
+    #[test]
+    pub fn test_summary_s3() {
+        let (endpoint, server) = crate::s3::fake_server(1);
+        crate::s3::set_endpoint(&endpoint);
+        std::env::set_var("AWS_ACCESS_KEY_ID", "a");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", "b");
+
+        let axes = ["flow=0.9,1.1".parse::<Axis>().unwrap()];
+        let mut summary = Summary::new(&axes);
+        let metrics = Metrics {
+            blocks: 10,
+            volume: 0.5,
+            size: [1f32, 2f32, 3f32],
+            time: 4f32,
+        };
+        summary.push("sweep_001.obj", &[1], metrics);
+        summary.write_csv("s3://renders/sweep/summary.csv").unwrap();
+
+        let requests = server.join().unwrap();
+        let (method, target, body) = &requests[0];
+        assert_eq!(
+            (method.as_str(), target.as_str()),
+            ("PUT", "/renders/sweep/summary.csv")
+        );
+        assert_eq!(
+            String::from_utf8_lossy(body),
+            "file,flow,blocks,volume_mm3,x_mm,y_mm,z_mm,time_s\n\
+            sweep_001.obj,1.1,10,0.500,1.00,2.00,3.00,4.0\n"
+        );
+    }
+}
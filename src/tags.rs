@@ -0,0 +1,118 @@
+use super::{surface, BoundingBox, ChunkId, Model, MonotonicVoxel, Voxel, VoxelIdx};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Voxels tagged by the feature which deposited them, e.g. bridges.
+#[derive(Default, Clone)]
+pub struct Tags {
+    sets: BTreeMap<&'static str, MonotonicVoxel>,
+}
+
+impl Tags {
+    pub fn tag_of(&self, coord: VoxelIdx) -> Option<&'static str> {
+        for (tag, set) in &self.sets {
+            if set.occupied(coord) {
+                return Some(tag);
+            }
+        }
+        None
+    }
+
+    /// Records an already deposited voxel under `tag`.
+    pub fn add(&mut self, tag: &'static str, coord: VoxelIdx) {
+        self.sets.entry(tag).or_default().add(coord);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    pub fn get(&self, tag: &str) -> Option<&MonotonicVoxel> {
+        self.sets.get(tag)
+    }
+
+    /// Wraps `v`, so voxels added through the wrapper are recorded under `tag`.
+    pub fn tagging<'a, V: Voxel>(
+        &'a mut self,
+        v: &'a mut V,
+        tag: Option<&'static str>,
+    ) -> Tagging<'a, V> {
+        Tagging {
+            voxel: v,
+            tags: self,
+            tag,
+        }
+    }
+
+    /// Groups faces of `model` by the tag of the voxel each face belongs to.
+    pub fn group<V: Voxel>(&self, model: &mut Model, v: &V) {
+        if self.sets.is_empty() {
+            return;
+        }
+        model.group_faces(|model, face| {
+            let (c0, c1, _) = surface::face_cells(model, face);
+            let coord = if v.occupied(c0) { c0 } else { c1 };
+            self.tag_of(coord)
+        });
+    }
+}
+
+pub struct Tagging<'a, V> {
+    voxel: &'a mut V,
+    tags: &'a mut Tags,
+    tag: Option<&'static str>,
+}
+
+impl<'a, V> Tagging<'a, V> {
+    pub fn tags(&self) -> &Tags {
+        self.tags
+    }
+}
+
+impl<'a, V: Voxel> Voxel for Tagging<'a, V> {
+    fn blocks(&self) -> usize {
+        self.voxel.blocks()
+    }
+
+    fn ranges(&self) -> usize {
+        self.voxel.ranges()
+    }
+
+    fn bounding_box(&self) -> &BoundingBox {
+        self.voxel.bounding_box()
+    }
+
+    fn occupied(&self, coord: VoxelIdx) -> bool {
+        self.voxel.occupied(coord)
+    }
+
+    fn add(&mut self, coord: VoxelIdx) -> bool {
+        if !self.voxel.add(coord) {
+            return false;
+        }
+        if let Some(tag) = self.tag {
+            self.tags.add(tag, coord);
+        }
+        true
+    }
+
+    fn to_model(&self) -> Model {
+        self.voxel.to_model()
+    }
+
+    fn chunk_model(&self, chunk: ChunkId) -> Model {
+        self.voxel.chunk_model(chunk)
+    }
+
+    fn take_dirty(&mut self) -> Vec<ChunkId> {
+        self.voxel.take_dirty()
+    }
+
+    fn reserve(&mut self, bounds: &BoundingBox, blocks: usize) {
+        self.voxel.reserve(bounds, blocks)
+    }
+
+    fn column(&self, x: i32, y: i32) -> Vec<Range<i32>> {
+        self.voxel.column(x, y)
+    }
+}
@@ -0,0 +1,321 @@
+use super::{
+    distance, storage, surface, ExportMode, Metadata, Model, Rgb, Voxel, VoxelIdx, UNIT, Z_OFFSET,
+};
+use anyhow::Result;
+use rayon::prelude::*;
+use std::io::Write;
+use std::ops::Range;
+
+/// Radius in voxels up to which thickness is measured, so parts thicker than about 2mm
+/// are all 2mm thick. Keeps stamping of inscribed discs cheap in solid layers.
+const MAX_RADIUS: f32 = 25f32;
+
+/// Distance of each cell of a `width` by `height` slice to the nearest empty cell, in
+/// voxels, with everything outside of the slice empty.
+fn distances(occupied: &[bool], width: usize, height: usize) -> Vec<f32> {
+    // padded by a cell of empty space on each side
+    let (w, h) = (width + 2, height + 2);
+    let mut d = vec![0f32; w * h];
+    for y in 0..height {
+        for x in 0..width {
+            if occupied[y * width + x] {
+                d[(y + 1) * w + x + 1] = f32::INFINITY;
+            }
+        }
+    }
+    distance::transform(&mut d, &[w, h]);
+    (0..width * height)
+        .map(|i| d[(i / width + 1) * w + i % width + 1].sqrt())
+        .collect()
+}
+
+/// Local thickness of each cell of a slice in voxels, 0 for empty cells: the diameter of
+/// the largest disc within the slice covering the cell, from the distance transform.
+/// Discs are centered on ridges of the distance, where it is no lower than around.
+fn local_thickness(occupied: &[bool], width: usize, height: usize) -> Vec<u8> {
+    let d = distances(occupied, width, height);
+    let max = (2f32 * MAX_RADIUS - 1f32) as u8;
+    let mut t = vec![0u8; d.len()];
+    for i in (0..d.len()).filter(|i| occupied[*i]) {
+        let (x, y) = ((i % width) as i32, (i / width) as i32);
+        let ridge = (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .filter(|(x, y)| (0..width as i32).contains(x) && (0..height as i32).contains(y))
+            .all(|(x, y)| d[y as usize * width + x as usize] <= d[i]);
+        if !ridge || d[i] > MAX_RADIUS {
+            continue;
+        }
+        // a lone cell is 1 from empty cells, and 1 thick
+        let r = d[i];
+        let thickness = (2f32 * r - 1f32).round().clamp(1f32, max as f32) as u8;
+        let reach = r.ceil() as i32;
+        for sy in (y - reach).max(0)..(y + reach + 1).min(height as i32) {
+            for sx in (x - reach).max(0)..(x + reach + 1).min(width as i32) {
+                let j = sy as usize * width + sx as usize;
+                let (dx, dy) = ((sx - x) as f32, (sy - y) as f32);
+                if occupied[j] && dx * dx + dy * dy < r * r {
+                    t[j] = t[j].max(thickness);
+                }
+            }
+        }
+    }
+    // cells only covered by discs beyond the limit
+    for (t, _) in t.iter_mut().zip(occupied).filter(|(t, o)| **o && **t == 0) {
+        *t = max;
+    }
+    t
+}
+
+/// Connected part of a band of layers, with its thinnest spot.
+#[derive(Clone, Debug)]
+pub struct Wall {
+    pub layers: Range<usize>,
+    /// center of the region, in millimeters
+    pub center: [f32; 2],
+    /// in square millimeters
+    pub area: f32,
+    /// minimum local thickness, in millimeters
+    pub min: f32,
+    /// where it is thinnest, in millimeters
+    pub thinnest: [f32; 3],
+}
+
+/// Local wall thickness of voxels, measured on the slice through the middle of each
+/// layer, so walls are measured across and not through their height. Thickness is
+/// precise to a voxel, and capped at about 2mm.
+pub struct Thickness {
+    min: VoxelIdx,
+    width: usize,
+    height: usize,
+    /// local thickness of each cell of each layer, in voxels
+    layers: Vec<Vec<u8>>,
+}
+
+impl Thickness {
+    pub fn new<V: Voxel + Sync>(v: &V) -> Self {
+        let bb = v.bounding_box();
+        let (min, max) = (bb.bound_min, bb.bound_max);
+        if bb.count == 0 {
+            return Self {
+                min,
+                width: 0,
+                height: 0,
+                layers: Vec::new(),
+            };
+        }
+        let (width, height) = (
+            (max[0] - min[0] + 1) as usize,
+            (max[1] - min[1] + 1) as usize,
+        );
+        let columns = (min[1]..=max[1])
+            .into_par_iter()
+            .flat_map_iter(|y| (min[0]..=max[0]).map(move |x| v.column(x, y)))
+            .collect::<Vec<_>>();
+        let layers = (max[2] / Z_OFFSET + 1).max(0) as usize;
+        let layers = (0..layers)
+            .into_par_iter()
+            .map(|layer| {
+                let z = layer as i32 * Z_OFFSET + Z_OFFSET / 2;
+                let occupied = columns
+                    .iter()
+                    .map(|c| c.iter().any(|r| r.contains(&z)))
+                    .collect::<Vec<_>>();
+                local_thickness(&occupied, width, height)
+            })
+            .collect();
+        Self {
+            min,
+            width,
+            height,
+            layers,
+        }
+    }
+
+    /// Local thickness of the voxel at `coord` in millimeters, if it is occupied in the
+    /// slice of its layer.
+    pub fn at(&self, coord: VoxelIdx) -> Option<f32> {
+        let (x, y) = (coord[0] - self.min[0], coord[1] - self.min[1]);
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return None;
+        }
+        // layer tops are at multiples of Z_OFFSET, filled downwards
+        let layer = self
+            .layers
+            .get(((coord[2] - 1).max(0) / Z_OFFSET) as usize)?;
+        match layer[y as usize * self.width + x as usize] {
+            0 => None,
+            t => Some(t as f32 * UNIT),
+        }
+    }
+
+    /// Connected parts of each band of `band` layers, 8-connected through cells occupied
+    /// in any layer of the band.
+    pub fn walls(&self, band: usize) -> Vec<Wall> {
+        let (width, height) = (self.width, self.height);
+        let mut walls = Vec::new();
+        for start in (0..self.layers.len()).step_by(band.max(1)) {
+            let end = (start + band.max(1)).min(self.layers.len());
+            // thinnest layer of each cell over the band
+            let mut thinnest = vec![(u8::MAX, 0usize); width * height];
+            for (layer, t) in self.layers[start..end].iter().enumerate() {
+                for (min, t) in thinnest.iter_mut().zip(t).filter(|(_, t)| **t > 0) {
+                    if *t < min.0 {
+                        *min = (*t, start + layer);
+                    }
+                }
+            }
+
+            let mut visited = vec![false; width * height];
+            for seed in 0..visited.len() {
+                if thinnest[seed].0 == u8::MAX || visited[seed] {
+                    continue;
+                }
+                visited[seed] = true;
+                let mut stack = vec![seed];
+                let (mut cells, mut sum, mut min) = (0usize, [0f64; 2], (u8::MAX, 0, seed));
+                while let Some(i) = stack.pop() {
+                    let (x, y) = ((i % width) as i32, (i / width) as i32);
+                    cells += 1;
+                    sum[0] += x as f64;
+                    sum[1] += y as f64;
+                    if thinnest[i].0 < min.0 {
+                        min = (thinnest[i].0, thinnest[i].1, i);
+                    }
+                    for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if nx < 0 || ny < 0 || nx >= width as i32 || ny >= height as i32 {
+                            continue;
+                        }
+                        let j = ny as usize * width + nx as usize;
+                        if thinnest[j].0 != u8::MAX && !visited[j] {
+                            visited[j] = true;
+                            stack.push(j);
+                        }
+                    }
+                }
+                let cell = |x: f32, a: usize| (x + self.min[a] as f32 + 0.5) * UNIT;
+                let (t, layer, i) = min;
+                walls.push(Wall {
+                    layers: start..end,
+                    center: [0, 1].map(|a| cell((sum[a] / cells as f64) as f32, a)),
+                    area: cells as f32 * UNIT * UNIT,
+                    min: t as f32 * UNIT,
+                    thinnest: [
+                        cell((i % width) as f32, 0),
+                        cell((i / width) as f32, 1),
+                        (layer as i32 * Z_OFFSET + Z_OFFSET / 2) as f32 * UNIT,
+                    ],
+                });
+            }
+        }
+        walls
+    }
+
+    /// Colors each vertex of `model` by the thickness of faces around it: red at `thin`
+    /// millimeters or below, through yellow, to green at twice `thin` or above.
+    pub fn paint<V: Voxel>(&self, model: &mut Model, v: &V, thin: f32) {
+        let mut sums = vec![(0f32, 0u32); model.vertices.len()];
+        for face in &model.faces {
+            let (c0, c1, _) = surface::face_cells(model, face);
+            let coord = if v.occupied(c0) { c0 } else { c1 };
+            let t = match self.at(coord) {
+                Some(t) => t,
+                None => continue,
+            };
+            for &i in face {
+                sums[i].0 += t;
+                sums[i].1 += 1;
+            }
+        }
+        model.colors = sums
+            .into_iter()
+            .map(|(t, n)| ramp((t / n.max(1) as f32 / thin - 1f32).clamp(0f32, 1f32)))
+            .collect();
+    }
+
+    /// Writes the surface of `v` as obj with vertex colors of `paint`, with the bed
+    /// center at the origin.
+    pub fn write_obj<V: Voxel>(&self, v: &V, thin: f32, path: &str, meta: Metadata) -> Result<()> {
+        let mut model = surface::to_model(v, ExportMode::Full);
+        self.paint(&mut model, v, thin);
+        model.metadata = meta;
+        model.serialize_with(path, 3, |idx| {
+            let offset = [-90f32, -90f32, 0f32];
+            [0, 1, 2].map(|i| idx[i] as f32 * UNIT + offset[i])
+        })?;
+        Ok(())
+    }
+}
+
+/// Red at 0, yellow at 0.5, green at 1.
+fn ramp(t: f32) -> Rgb {
+    let r = (2f32 - 2f32 * t).min(1f32);
+    let g = (2f32 * t).min(1f32);
+    [(r * 255f32) as u8, (g * 255f32) as u8, 0]
+}
+
+pub fn write_csv(walls: &[Wall], thin: f32, path: &str) -> Result<()> {
+    let mut w = storage::create(path)?;
+    writeln!(
+        w,
+        "layer_start,layer_end,center_x,center_y,area,min_thickness,thinnest_x,thinnest_y,thinnest_z,thin"
+    )?;
+    for wall in walls {
+        writeln!(
+            w,
+            "{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{}",
+            wall.layers.start,
+            wall.layers.end,
+            wall.center[0],
+            wall.center[1],
+            wall.area,
+            wall.min,
+            wall.thinnest[0],
+            wall.thinnest[1],
+            wall.thinnest[2],
+            wall.min < thin
+        )?;
+    }
+    w.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_thickness() {
+        let mut v = MonotonicVoxel::default();
+        // a layer of a 60x60 block with a 5 voxel thick fin, and a 3 voxel thick wall
+        // apart from them
+        for z in 1..=Z_OFFSET {
+            for x in 0..100 {
+                for y in 0..60 {
+                    let block = x < 60;
+                    let fin = (60..80).contains(&x) && (10..15).contains(&y);
+                    let wall = (90..93).contains(&x);
+                    if block || fin || wall {
+                        v.add([x, y, z].into());
+                    }
+                }
+            }
+        }
+
+        let thickness = Thickness::new(&v);
+        let at = |x: i32, y: i32| (thickness.at([x, y, 1].into()).unwrap() / UNIT).round();
+        assert_eq!(at(75, 12), 5f32);
+        assert_eq!(at(75, 10), 5f32);
+        assert_eq!(at(91, 30), 3f32);
+        assert_eq!(at(30, 30), 2f32 * MAX_RADIUS - 1f32);
+        assert_eq!(thickness.at([85, 12, 1].into()), None);
+
+        let walls = thickness.walls(10);
+        assert_eq!(walls.len(), 2);
+        assert_eq!(walls[0].area, (60 * 60 + 20 * 5) as f32 * UNIT * UNIT);
+        assert_eq!(walls[0].min, 5f32 * UNIT);
+        assert!(walls[0].thinnest[0] > 60f32 * UNIT);
+        assert_eq!(walls[1].min, 3f32 * UNIT);
+        assert_eq!(walls[1].area, (3 * 60) as f32 * UNIT * UNIT);
+    }
+}
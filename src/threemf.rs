@@ -0,0 +1,137 @@
+use super::units::Units;
+use super::zip;
+use super::{Model, VoxelIdx};
+use anyhow::Result;
+use std::io::Write;
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+ <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+ <Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/>
+</Types>
+"#;
+
+const RELS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+ <Relationship Target="/3D/3dmodel.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>
+"#;
+
+/// Namespace of metadata without a name in the 3MF core specification.
+const NAMESPACE: &str = "https://github.com/yjh0502/tdp-tl";
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn write_model<F>(model: &Model, w: &mut dyn Write, position: F) -> Result<()>
+where
+    F: Fn(VoxelIdx) -> [f32; 3],
+{
+    let units = model
+        .metadata
+        .get("units")
+        .and_then(|u| u.parse::<Units>().ok())
+        .unwrap_or_default();
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        w,
+        r#"<model unit="{}" xml:lang="en-US" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02" xmlns:tdptl="{}">"#,
+        units.long_name(),
+        NAMESPACE
+    )?;
+    for (key, value) in model.metadata.entries() {
+        let name = match key.as_str() {
+            "generator" => "Application".to_owned(),
+            key => format!("tdptl:{}", key),
+        };
+        writeln!(
+            w,
+            r#" <metadata name="{}">{}</metadata>"#,
+            escape(&name),
+            escape(value)
+        )?;
+    }
+    writeln!(w, r#" <resources>"#)?;
+    writeln!(w, r#"  <object id="1" type="model">"#)?;
+    writeln!(w, r#"   <mesh>"#)?;
+    writeln!(w, r#"    <vertices>"#)?;
+    for idx in &model.vertices {
+        let [x, y, z] = position(*idx);
+        writeln!(w, r#"     <vertex x="{}" y="{}" z="{}"/>"#, x, y, z)?;
+    }
+    writeln!(w, r#"    </vertices>"#)?;
+    writeln!(w, r#"    <triangles>"#)?;
+    for [i0, i1, i2, i3] in &model.faces {
+        for [a, b, c] in [[i0, i1, i2], [i0, i2, i3]] {
+            writeln!(w, r#"     <triangle v1="{}" v2="{}" v3="{}"/>"#, a, b, c)?;
+        }
+    }
+    writeln!(w, r#"    </triangles>"#)?;
+    writeln!(w, r#"   </mesh>"#)?;
+    writeln!(w, r#"  </object>"#)?;
+    writeln!(w, r#" </resources>"#)?;
+    writeln!(w, r#" <build>"#)?;
+    writeln!(w, r#"  <item objectid="1"/>"#)?;
+    writeln!(w, r#" </build>"#)?;
+    writeln!(w, r#"</model>"#)?;
+    Ok(())
+}
+
+/// Writes `model` as 3MF to `w`, with vertex positions from `position`, in the units of
+/// its `units` metadata, millimeters without any. Metadata other than the generator
+/// goes under the `tdptl` namespace; groups and colors are lost.
+pub fn write_3mf<W, F>(model: &Model, w: W, position: F) -> Result<()>
+where
+    W: Write,
+    F: Fn(VoxelIdx) -> [f32; 3],
+{
+    let mut zip = zip::Writer::new(w);
+    zip.add("[Content_Types].xml", |w| {
+        Ok(w.write_all(CONTENT_TYPES.as_bytes())?)
+    })?;
+    zip.add("_rels/.rels", |w| Ok(w.write_all(RELS.as_bytes())?))?;
+    zip.add("3D/3dmodel.model", |w| write_model(model, w, position))?;
+    zip.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zip::Archive;
+    use crate::Metadata;
+
+    #[test]
+    pub fn test_3mf() {
+        let mut model = Model::default();
+        model.add_cube([0, 0, 0].into());
+        model.metadata = Metadata::new().with("units", "cm").with("params", "a<b");
+
+        let mut buf = Vec::new();
+        write_3mf(&model, &mut buf, |idx| {
+            [0, 1, 2].map(|i| idx[i] as f32 * 0.5)
+        })
+        .unwrap();
+        let archive = Archive::new(buf).unwrap();
+        assert!(archive.read("_rels/.rels").is_ok());
+        assert!(archive.read("[Content_Types].xml").is_ok());
+        let xml = String::from_utf8(archive.read("3D/3dmodel.model").unwrap()).unwrap();
+        assert!(xml.contains(r#"<model unit="centimeter""#));
+        assert!(xml.contains(r#"<metadata name="Application">tdp-tl</metadata>"#));
+        assert!(xml.contains(r#"<metadata name="tdptl:params">a&lt;b</metadata>"#));
+        assert!(xml.contains(r#"<vertex x="0.5" y="0.5" z="0.5"/>"#));
+        assert_eq!(xml.matches("<vertex ").count(), 8);
+        assert_eq!(xml.matches("<triangle ").count(), 12);
+    }
+}
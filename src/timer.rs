@@ -0,0 +1,28 @@
+/// Elapsed time for logs. wasm32 has no clock without JavaScript, so it reads 0 there.
+pub struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+}
+
+impl Stopwatch {
+    pub fn start_new() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> std::time::Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed();
+        #[cfg(target_arch = "wasm32")]
+        return std::time::Duration::ZERO;
+    }
+
+    pub fn elapsed_ms(&self) -> i64 {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed().as_millis() as i64;
+        #[cfg(target_arch = "wasm32")]
+        return 0;
+    }
+}
@@ -0,0 +1,131 @@
+use super::{LAYER_HEIGHT, UNIT};
+
+/// Length unit of exported models.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Units {
+    #[default]
+    Millimeter,
+    Centimeter,
+    Meter,
+    Inch,
+}
+
+impl Units {
+    /// Exported length of one millimeter.
+    pub fn per_mm(self) -> f32 {
+        match self {
+            Self::Millimeter => 1f32,
+            Self::Centimeter => 0.1f32,
+            Self::Meter => 0.001f32,
+            Self::Inch => 1f32 / 25.4f32,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Millimeter => "mm",
+            Self::Centimeter => "cm",
+            Self::Meter => "m",
+            Self::Inch => "inch",
+        }
+    }
+
+    /// Name of the unit in 3MF models.
+    pub fn long_name(self) -> &'static str {
+        match self {
+            Self::Millimeter => "millimeter",
+            Self::Centimeter => "centimeter",
+            Self::Meter => "meter",
+            Self::Inch => "inch",
+        }
+    }
+}
+
+impl std::str::FromStr for Units {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mm" => Ok(Self::Millimeter),
+            "cm" => Ok(Self::Centimeter),
+            "m" => Ok(Self::Meter),
+            "inch" | "in" => Ok(Self::Inch),
+            _ => Err(format!(
+                "unknown units: {}, expected one of mm, cm, m, inch",
+                s
+            )),
+        }
+    }
+}
+
+/// Depth along Z in layers like `2layers`, or in millimeters like `0.4mm`, so the
+/// physical depth does not change with the voxel resolution.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Depth {
+    Layers(f32),
+    Millimeters(f32),
+}
+
+impl Depth {
+    pub fn mm(self) -> f32 {
+        match self {
+            Self::Layers(n) => n * LAYER_HEIGHT,
+            Self::Millimeters(mm) => mm,
+        }
+    }
+
+    /// Depth in voxels, at least one.
+    pub fn voxels(self) -> i32 {
+        ((self.mm() / UNIT).round() as i32).max(1)
+    }
+}
+
+impl Default for Depth {
+    fn default() -> Self {
+        Self::Layers(1f32)
+    }
+}
+
+impl std::str::FromStr for Depth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (value, depth): (&str, fn(f32) -> Self) =
+            if let Some(v) = s.strip_suffix("layers").or_else(|| s.strip_suffix("layer")) {
+                (v, Self::Layers)
+            } else if let Some(v) = s.strip_suffix("mm") {
+                (v, Self::Millimeters)
+            } else {
+                return Err(format!("expected depth like 2layers or 0.4mm, got {}", s));
+            };
+        let value = value
+            .trim()
+            .parse::<f32>()
+            .map_err(|e| format!("invalid depth {}: {}", s, e))?;
+        if value <= 0f32 {
+            return Err(format!("depth must be positive, got {}", s));
+        }
+        Ok(depth(value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Z_OFFSET;
+
+    #[test]
+    pub fn test_depth() {
+        assert_eq!("2layers".parse::<Depth>().unwrap(), Depth::Layers(2f32));
+        assert_eq!("1 layer".parse::<Depth>().unwrap(), Depth::Layers(1f32));
+        assert_eq!("0.4mm".parse::<Depth>().unwrap(), Depth::Millimeters(0.4));
+        assert!("5".parse::<Depth>().is_err());
+        assert!("0layers".parse::<Depth>().is_err());
+
+        assert_eq!(Depth::default().voxels(), Z_OFFSET);
+        assert_eq!(Depth::Layers(2f32).voxels(), 2 * Z_OFFSET);
+        assert_eq!(Depth::Millimeters(0.4).voxels(), 10);
+        assert_eq!(Depth::Millimeters(0.001).voxels(), 1);
+    }
+}
@@ -0,0 +1,225 @@
+use super::{storage, Metadata, Voxel, UNIT};
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+// OpenVDB file format 224, written by OpenVDB 10
+const MAGIC: i64 = 0x56444220;
+const FILE_VERSION: u32 = 224;
+const LIBRARY_VERSION: [u32; 2] = [10, 0];
+// metadata byte of node values: no mask compression, all values follow
+const NO_MASK_AND_ALL_VALS: u8 = 6;
+
+/// log2 of node sizes in voxels: leaf, lower and upper internal nodes of `Tree_float_5_4_3`
+const LOG2: [i32; 3] = [3, 7, 12];
+
+type Coord = [i32; 3];
+/// Active voxels of a leaf, 8 words of 64 bits.
+type LeafMask = [u64; 8];
+/// Upper internal nodes, lower internal nodes, and leaves, by origin.
+type Tree = BTreeMap<Coord, BTreeMap<Coord, BTreeMap<Coord, LeafMask>>>;
+
+fn origin(c: Coord, level: usize) -> Coord {
+    c.map(|v| v & !((1 << LOG2[level]) - 1))
+}
+
+/// Index of `c` in the node of `level` containing it, x first.
+fn offset(c: Coord, level: usize) -> usize {
+    let child = if level == 0 { 0 } else { LOG2[level - 1] };
+    let dim = LOG2[level] - child;
+    let [x, y, z] = c.map(|v| ((v & ((1 << LOG2[level]) - 1)) >> child) as usize);
+    (x << (2 * dim)) | (y << dim) | z
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, b: &[u8]) {
+        self.buf.extend_from_slice(b);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes(s.as_bytes());
+    }
+
+    fn mask(&mut self, words: &[u64]) {
+        for w in words {
+            self.bytes(&w.to_le_bytes());
+        }
+    }
+
+    /// Node values, uncompressed.
+    fn values(&mut self, values: impl Iterator<Item = f32>) {
+        self.bytes(&[NO_MASK_AND_ALL_VALS]);
+        for v in values {
+            self.bytes(&v.to_le_bytes());
+        }
+    }
+
+    /// Internal node without tiles, `children` by their offsets in the node.
+    fn internal(&mut self, level: usize, children: impl Iterator<Item = Coord>) {
+        let size = 1usize << (3 * (LOG2[level] - LOG2[level - 1]));
+        let mut child_mask = vec![0u64; size / 64];
+        for c in children {
+            let i = offset(c, level);
+            child_mask[i / 64] |= 1 << (i % 64);
+        }
+        self.mask(&child_mask);
+        self.mask(&vec![0u64; size / 64]);
+        self.values(std::iter::repeat_n(0f32, size));
+    }
+}
+
+fn build<V: Voxel>(v: &V) -> Tree {
+    let mut tree = Tree::new();
+    let bb = v.bounding_box();
+    if bb.count == 0 {
+        return tree;
+    }
+    for y in bb.bound_min[1]..=bb.bound_max[1] {
+        for x in bb.bound_min[0]..=bb.bound_max[0] {
+            for r in v.column(x, y) {
+                for z in r {
+                    let c = [x, y, z];
+                    let leaf = tree
+                        .entry(origin(c, 2))
+                        .or_default()
+                        .entry(origin(c, 1))
+                        .or_default()
+                        .entry(origin(c, 0))
+                        .or_default();
+                    let i = offset(c, 0);
+                    leaf[i / 64] |= 1 << (i % 64);
+                }
+            }
+        }
+    }
+    tree
+}
+
+/// Writes occupied voxels as a float fog volume named `density`, 1 inside, for
+/// Houdini and Blender volumes. Coordinates are in millimeters, like `Volume`.
+pub fn to_vdb<V: Voxel>(v: &V, meta: &Metadata) -> Vec<u8> {
+    let tree = build(v);
+    let mut w = Writer::default();
+
+    // archive header
+    w.bytes(&MAGIC.to_le_bytes());
+    w.u32(FILE_VERSION);
+    w.u32(LIBRARY_VERSION[0]);
+    w.u32(LIBRARY_VERSION[1]);
+    // has grid offsets
+    w.bytes(&[1]);
+    w.bytes(b"00000000-0000-4000-8000-000000000000");
+    // file metadata, and a single grid
+    w.u32(0);
+    w.u32(1);
+
+    // grid descriptor: name, type, instance parent, and offsets patched below
+    w.string("density");
+    w.string("Tree_float_5_4_3");
+    w.string("");
+    let offsets = w.buf.len();
+    w.bytes(&[0u8; 24]);
+    let grid_pos = w.buf.len();
+
+    // no compression
+    w.u32(0);
+
+    let mut entries = vec![
+        ("class".to_owned(), "fog volume".to_owned()),
+        ("name".to_owned(), "density".to_owned()),
+    ];
+    entries.extend(meta.entries().iter().cloned());
+    w.u32(entries.len() as u32);
+    for (k, v) in &entries {
+        w.string(k);
+        w.string("string");
+        w.string(v);
+    }
+
+    // voxel index to world: uniform scale, no translation
+    w.string("UniformScaleTranslateMap");
+    for v in [0f64, 0f64, 0f64, UNIT as f64, UNIT as f64, UNIT as f64] {
+        w.bytes(&v.to_le_bytes());
+    }
+
+    // topology: buffer count, root with background, no tiles, and children
+    w.u32(1);
+    w.bytes(&0f32.to_le_bytes());
+    w.u32(0);
+    w.u32(tree.len() as u32);
+    for (upper, lowers) in &tree {
+        for v in upper {
+            w.bytes(&v.to_le_bytes());
+        }
+        w.internal(2, lowers.keys().copied());
+        for leaves in lowers.values() {
+            // lower internal nodes follow their parent, in offset order
+            w.internal(1, leaves.keys().copied());
+            for mask in leaves.values() {
+                w.mask(mask);
+            }
+        }
+    }
+
+    // leaf buffers, in the same order
+    let block_pos = w.buf.len();
+    for leaves in tree.values().flat_map(|lowers| lowers.values()) {
+        for mask in leaves.values() {
+            w.mask(mask);
+            let bits = (0..512).map(|i| (mask[i / 64] >> (i % 64)) & 1);
+            w.values(bits.map(|b| b as f32));
+        }
+    }
+    let end_pos = w.buf.len();
+
+    for (i, pos) in [grid_pos, block_pos, end_pos].into_iter().enumerate() {
+        let at = offsets + i * 8;
+        w.buf[at..at + 8].copy_from_slice(&(pos as i64).to_le_bytes());
+    }
+    w.buf
+}
+
+pub fn write_vdb<V: Voxel>(v: &V, path: &str, meta: &Metadata) -> Result<()> {
+    storage::write(path, to_vdb(v, meta))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_vdb() {
+        assert_eq!(offset([1, 2, 3], 0), (1 << 6) | (2 << 3) | 3);
+        assert_eq!(offset([-1, 0, 0], 0), 7 << 6);
+        assert_eq!(offset([136, 0, 8], 1), (1 << 8) | 1);
+        assert_eq!(offset([136, 0, 8], 2), 1 << 10);
+        assert_eq!(origin([-1, 9, 4096], 1), [-128, 0, 4096]);
+
+        let mut v = MonotonicVoxel::default();
+        v.add([0, 0, 0].into());
+        v.add([0, 0, 1].into());
+        v.add([200, 0, 0].into());
+        let data = to_vdb(&v, &Metadata::new());
+
+        assert_eq!(&data[..8], &MAGIC.to_le_bytes());
+        // end offset of the grid descriptor is the end of file
+        let names = "density".len() + "Tree_float_5_4_3".len() + 12;
+        let at = 8 + 12 + 1 + 36 + 8 + names + 16;
+        let end = i64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+        assert_eq!(end as usize, data.len());
+
+        // two leaves, each with a mask and all values
+        let block = i64::from_le_bytes(data[at - 8..at].try_into().unwrap()) as usize;
+        assert_eq!(data.len() - block, 2 * (64 + 1 + 512 * 4));
+    }
+}
@@ -0,0 +1,768 @@
+use super::chunk::{self, ChunkId, ChunkMesh};
+use super::overlay::{Overlay, Stats};
+use super::render::{self, Still};
+use super::{headless, project::read_gcode, simulate_gcode, Estimate, MonotonicVoxel, Params};
+use super::{Simulation, Voxel, UNIT};
+use anyhow::Result;
+use log::*;
+use nalgebra::{Matrix4, Point3, Vector3};
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use wgpu::util::DeviceExt;
+use winit::event::{ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::keyboard::{Key, NamedKey};
+use winit::window::{Window, WindowBuilder};
+
+const SHADER: &str = r#"
+struct Uniforms {
+    view_proj: mat4x4<f32>,
+    light: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> u: Uniforms;
+
+struct VsOut {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) pos: vec3<f32>, @location(1) normal: vec3<f32>) -> VsOut {
+    var out: VsOut;
+    out.pos = u.view_proj * vec4<f32>(pos, 1.0);
+    out.normal = normal;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VsOut) -> @location(0) vec4<f32> {
+    let n = normalize(in.normal);
+    let diffuse = max(dot(n, u.light.xyz), 0.0);
+    let ambient = 0.3 + 0.1 * n.z;
+    let base = vec3<f32>(0.8, 0.8, 0.78);
+    return vec4<f32>(base * (0.6 * diffuse + ambient), 1.0);
+}
+"#;
+
+const BACKGROUND: wgpu::Color = wgpu::Color {
+    r: 0.2,
+    g: 0.22,
+    b: 0.26,
+    a: 1.0,
+};
+
+/// nalgebra projections map depth to [-1, 1], wgpu expects [0, 1].
+#[rustfmt::skip]
+const OPENGL_TO_WGPU: [f32; 16] = [
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+];
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Vertex {
+    pos: [f32; 3],
+    normal: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Uniforms {
+    view_proj: [[f32; 4]; 4],
+    light: [f32; 4],
+}
+
+/// Orbit camera around `target`, in millimeters and radians.
+#[derive(Clone, Copy)]
+struct Camera {
+    target: Vector3<f32>,
+    distance: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl Camera {
+    fn eye(&self) -> Vector3<f32> {
+        let back = Vector3::new(
+            self.pitch.cos() * self.yaw.cos(),
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+        );
+        self.target + back * self.distance
+    }
+
+    fn uniforms(&self, aspect: f32) -> Uniforms {
+        let eye = self.eye();
+        let view = Matrix4::look_at_rh(
+            &Point3::from(eye),
+            &Point3::from(self.target),
+            &Vector3::z(),
+        );
+        let near = (self.distance * 0.01).max(0.1);
+        let proj = Matrix4::new_perspective(aspect, 35f32.to_radians(), near, near * 1e4);
+        let view_proj = Matrix4::from_column_slice(&OPENGL_TO_WGPU) * proj * view;
+
+        // key light over the shoulder of the camera
+        let light = ((eye - self.target).normalize() + Vector3::new(0f32, 0f32, 1.5)).normalize();
+        Uniforms {
+            view_proj: view_proj.into(),
+            light: [light[0], light[1], light[2], 0f32],
+        }
+    }
+}
+
+struct GpuChunk {
+    vertices: wgpu::Buffer,
+    indices: wgpu::Buffer,
+    count: u32,
+}
+
+/// Chunk meshes on the GPU, and the pipeline drawing them.
+struct Scene {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::RenderPipeline,
+    uniforms: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    chunks: HashMap<ChunkId, GpuChunk>,
+    /// background, transparent for frames composited over others
+    background: wgpu::Color,
+}
+
+impl Scene {
+    async fn new(adapter: &wgpu::Adapter, format: wgpu::TextureFormat) -> Result<Self> {
+        info!("adapter: {:?}", adapter.get_info());
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor::default(), None)
+            .await?;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("voxel"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("uniforms"),
+            size: std::mem::size_of::<Uniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniforms.as_entire_binding(),
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("voxel"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<Vertex>() as u64,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+            uniforms,
+            bind_group,
+            chunks: HashMap::new(),
+            background: BACKGROUND,
+        })
+    }
+
+    fn upload(&mut self, id: ChunkId, mesh: &ChunkMesh) {
+        if mesh.is_empty() {
+            self.chunks.remove(&id);
+            return;
+        }
+        let vertices = mesh
+            .positions
+            .iter()
+            .zip(&mesh.normals)
+            .map(|(pos, normal)| Vertex {
+                pos: *pos,
+                normal: *normal,
+            })
+            .collect::<Vec<_>>();
+
+        let vertices = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        let indices = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&mesh.indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+        let chunk = GpuChunk {
+            vertices,
+            indices,
+            count: mesh.indices.len() as u32,
+        };
+        self.chunks.insert(id, chunk);
+    }
+
+    /// Draws every chunk into `color` and `depth` targets.
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        color: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        camera: &Camera,
+        aspect: f32,
+    ) {
+        let uniforms = camera.uniforms(aspect);
+        self.queue
+            .write_buffer(&self.uniforms, 0, bytemuck::bytes_of(&uniforms));
+
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: color,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.background),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            for chunk in self.chunks.values() {
+                pass.set_vertex_buffer(0, chunk.vertices.slice(..));
+                pass.set_index_buffer(chunk.indices.slice(..), wgpu::IndexFormat::Uint32);
+                pass.draw_indexed(0..chunk.count, 0, 0..1);
+            }
+        }
+    }
+}
+
+/// Scene drawn into a window.
+struct Renderer {
+    window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    config: wgpu::SurfaceConfiguration,
+    depth: wgpu::TextureView,
+    scene: Scene,
+}
+
+impl Renderer {
+    async fn new(window: Arc<Window>) -> Result<Self> {
+        let size = window.inner_size();
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let surface = instance.create_surface(window.clone())?;
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: Some(&surface),
+                force_fallback_adapter: false,
+            })
+            .await
+            .ok_or_else(|| anyhow::anyhow!("no graphics adapter"))?;
+
+        let config = surface
+            .get_default_config(&adapter, size.width.max(1), size.height.max(1))
+            .ok_or_else(|| anyhow::anyhow!("surface not supported by the adapter"))?;
+        let scene = Scene::new(&adapter, config.format).await?;
+        surface.configure(&scene.device, &config);
+
+        let depth = create_depth(&scene.device, config.width, config.height);
+        Ok(Self {
+            window,
+            surface,
+            config,
+            depth,
+            scene,
+        })
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.config.width = width;
+        self.config.height = height;
+        self.surface.configure(&self.scene.device, &self.config);
+        self.depth = create_depth(&self.scene.device, width, height);
+    }
+
+    fn render(&mut self, camera: &Camera) -> Result<()> {
+        let frame = match self.surface.get_current_texture() {
+            Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                self.surface.configure(&self.scene.device, &self.config);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let view = frame
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let aspect = self.config.width as f32 / self.config.height as f32;
+        let mut encoder = self
+            .scene
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        self.scene
+            .draw(&mut encoder, &view, &self.depth, camera, aspect);
+        self.scene.queue.submit([encoder.finish()]);
+        self.window.pre_present_notify();
+        frame.present();
+        Ok(())
+    }
+}
+
+/// Scene drawn into a texture, for servers without a display.
+struct Offscreen {
+    width: u32,
+    height: u32,
+    color: wgpu::Texture,
+    depth: wgpu::TextureView,
+    scene: Scene,
+}
+
+impl Offscreen {
+    /// Prefers hardware adapters, then software rasterizers, e.g. llvmpipe or WARP.
+    async fn new(width: u32, height: u32) -> Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter = adapter.ok_or_else(|| anyhow::anyhow!("no graphics adapter"))?;
+
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let scene = Scene::new(&adapter, format).await?;
+        let color = scene.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("color"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let depth = create_depth(&scene.device, width, height);
+        Ok(Self {
+            width,
+            height,
+            color,
+            depth,
+            scene,
+        })
+    }
+
+    /// Draws the scene with `overlay` of statistics, if any, and writes it as png.
+    fn capture(
+        &self,
+        camera: &Camera,
+        overlay: Option<(&Overlay, &Stats)>,
+        out: &str,
+    ) -> Result<()> {
+        let device = &self.scene.device;
+        let view = self
+            .color
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        // rows of a texture copy are aligned to 256 bytes
+        let row = self.width * 4;
+        let padded =
+            row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: padded as u64 * self.height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let aspect = self.width as f32 / self.height as f32;
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        self.scene
+            .draw(&mut encoder, &view, &self.depth, camera, aspect);
+        encoder.copy_texture_to_buffer(
+            self.color.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded),
+                    rows_per_image: None,
+                },
+            },
+            self.color.size(),
+        );
+        self.scene.queue.submit([encoder.finish()]);
+
+        let (tx, rx) = mpsc::channel();
+        let slice = buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |r| {
+            let _ = tx.send(r);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+
+        let data = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((row * self.height) as usize);
+        for y in 0..self.height as usize {
+            let start = y * padded as usize;
+            pixels.extend_from_slice(&data[start..start + row as usize]);
+        }
+        if let Some((overlay, stats)) = overlay {
+            overlay.draw(&mut pixels, self.width as usize, 4, stats);
+        }
+
+        let (width, height) = (self.width, self.height);
+        render::write_png(out, width, height, png::ColorType::Rgba, false, &pixels)
+    }
+}
+
+fn create_depth(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("depth"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Depth32Float,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+type Update = Vec<(ChunkId, ChunkMesh)>;
+
+/// Runs the simulation, sending snapshots and chunks changed by each layer.
+fn simulate(
+    gcode: &str,
+    layer: usize,
+    params: &Params,
+    tx: mpsc::Sender<(MonotonicVoxel, Vec<ChunkId>)>,
+) -> Result<()> {
+    let send = |sim: &mut Simulation<MonotonicVoxel>| -> Result<()> {
+        let dirty = sim.voxel.take_dirty();
+        // stop simulating once the viewer is closed
+        tx.send((sim.voxel.clone(), dirty))
+            .map_err(|_| anyhow::anyhow!("viewer closed"))
+    };
+
+    let mut sim = simulate_gcode::<MonotonicVoxel, _>(gcode, layer, params, |sim, _| send(sim))?;
+    send(&mut sim)
+}
+
+/// Meshes dirty chunks of snapshots, while the simulation continues. If meshing falls
+/// behind, pending snapshots are merged into the latest one.
+fn mesh(rx: mpsc::Receiver<(MonotonicVoxel, Vec<ChunkId>)>, tx: mpsc::Sender<Update>) {
+    while let Ok((mut snapshot, dirty)) = rx.recv() {
+        let mut dirty = dirty.into_iter().collect::<BTreeSet<_>>();
+        for (next, next_dirty) in rx.try_iter() {
+            snapshot = next;
+            dirty.extend(next_dirty);
+        }
+
+        let update = dirty
+            .into_par_iter()
+            .map(|id| (id, chunk::mesh_chunk(&snapshot, id)))
+            .collect::<Vec<_>>();
+        if tx.send(update).is_err() {
+            return;
+        }
+    }
+}
+
+/// Whether a window can be opened. Only X11 and Wayland need a display server.
+pub fn has_display() -> bool {
+    if cfg!(not(unix)) || cfg!(target_os = "macos") {
+        return true;
+    }
+    ["DISPLAY", "WAYLAND_DISPLAY", "WAYLAND_SOCKET"]
+        .iter()
+        .any(|key| std::env::var_os(key).is_some())
+}
+
+/// Camera framing the bounding box of `v`.
+fn frame_camera(v: &MonotonicVoxel) -> Camera {
+    let bb = v.bounding_box();
+    let lo = Vector3::new(bb.bound_min[0], bb.bound_min[1], bb.bound_min[2]).cast::<f32>() * UNIT;
+    let hi = Vector3::new(bb.bound_max[0], bb.bound_max[1], bb.bound_max[2]).cast::<f32>() * UNIT;
+    frame_bounds(lo, hi)
+}
+
+/// Camera framing bounds in millimeters.
+fn frame_bounds(lo: Vector3<f32>, hi: Vector3<f32>) -> Camera {
+    let radius = ((hi - lo).magnitude() * 0.5).max(1f32);
+    Camera {
+        target: (lo + hi) * 0.5,
+        distance: radius / (35f32.to_radians() * 0.5).sin() * 1.1,
+        yaw: -60f32.to_radians(),
+        pitch: 30f32.to_radians(),
+    }
+}
+
+/// Renders the model after each layer into `{outdir}/view_{layer}.png`, without a
+/// window. Uses the GPU, or a software rasterizer adapter, if available, and the CPU
+/// ray tracer otherwise. Frames the whole part from a pre-scan of the gcode, so the
+/// camera stays still across layers, and the overlay of `still` counts layers and
+/// progress by extruded volume from it.
+pub fn headless(
+    gcode: &str,
+    layer: usize,
+    params: &Params,
+    outdir: &str,
+    still: &Still,
+) -> Result<()> {
+    let estimate = plan(gcode, layer)?;
+    let planned = planned_camera(&estimate);
+    // stereo views, aovs and ortho views are ray traced
+    let offscreen = match still.stereo.is_some() || still.aovs || still.ortho.is_some() {
+        true => None,
+        false => match pollster::block_on(Offscreen::new(still.width, still.height)) {
+            Ok(offscreen) => Some(offscreen),
+            Err(e) => {
+                warn!("{}, falling back to the cpu ray tracer", e);
+                None
+            }
+        },
+    };
+    let gpu = offscreen.map(|mut offscreen| {
+        if still.transparent {
+            offscreen.scene.background = wgpu::Color::TRANSPARENT;
+        }
+        Box::new(
+            move |sim: &mut Simulation<MonotonicVoxel>, stats: &Stats, out: &str| {
+                let dirty = sim.voxel.take_dirty();
+                let meshes = dirty
+                    .into_par_iter()
+                    .map(|id| (id, chunk::mesh_chunk(&sim.voxel, id)))
+                    .collect::<Vec<_>>();
+                for (id, mesh) in &meshes {
+                    offscreen.scene.upload(*id, mesh);
+                }
+                let camera = planned.unwrap_or_else(|| frame_camera(&sim.voxel));
+                let overlay = still.overlay.as_ref().map(|o| (o, stats));
+                offscreen.capture(&camera, overlay, out)
+            },
+        ) as headless::Gpu
+    });
+    headless::frames(gcode, layer, params, outdir, still, &estimate, gpu)
+}
+
+/// Pre-scan of the gcode in `filename`.
+fn plan(filename: &str, layer: usize) -> Result<Estimate> {
+    Ok(Estimate::scan(&read_gcode(filename)?, layer))
+}
+
+/// Camera framing extruding moves of a pre-scan.
+fn planned_camera(estimate: &Estimate) -> Option<Camera> {
+    estimate.bounds.map(|[lo, hi]| {
+        info!(
+            "plan: layers={}, bounds={:?}..{:?}mm",
+            estimate.layers(),
+            lo,
+            hi
+        );
+        frame_bounds(Vector3::from(lo), Vector3::from(hi))
+    })
+}
+
+/// Opens a window showing the model while it is simulated.
+pub fn view(gcode: &str, layer: usize, params: &Params) -> Result<()> {
+    let planned = planned_camera(&plan(gcode, layer)?);
+    let (snapshot_tx, snapshot_rx) = mpsc::channel();
+    let (tx, rx) = mpsc::channel();
+    let worker = {
+        let gcode = gcode.to_owned();
+        let params = params.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = simulate(&gcode, layer, &params, snapshot_tx) {
+                warn!("simulation stopped: {}", e);
+            }
+        })
+    };
+    let mesher = std::thread::spawn(move || mesh(snapshot_rx, tx));
+
+    let event_loop = EventLoop::new()?;
+    let window = WindowBuilder::new()
+        .with_title(format!("tdp-tl: {}", gcode))
+        .build(&event_loop)?;
+    let window = Arc::new(window);
+    let mut renderer = pollster::block_on(Renderer::new(window.clone()))?;
+
+    let mut camera = planned.unwrap_or(Camera {
+        target: Vector3::new(90f32, 90f32, 0f32),
+        distance: 150f32,
+        yaw: -60f32.to_radians(),
+        pitch: 30f32.to_radians(),
+    });
+    // model bounds, camera targets the center until moved, unless framed by the plan
+    let mut bounds: Option<(Vector3<f32>, Vector3<f32>)> = None;
+    let mut drag = false;
+    let mut cursor: Option<(f64, f64)> = None;
+    let mut result = Ok(());
+
+    event_loop.run(|event, elwt| match event {
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::CloseRequested => elwt.exit(),
+            WindowEvent::KeyboardInput { event, .. }
+                if event.logical_key == Key::Named(NamedKey::Escape) =>
+            {
+                elwt.exit()
+            }
+            WindowEvent::Resized(size) => {
+                renderer.resize(size.width, size.height);
+                window.request_redraw();
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => drag = state == ElementState::Pressed,
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some((x, y))) = (drag, cursor) {
+                    camera.yaw -= (position.x - x) as f32 * 0.01;
+                    camera.pitch = (camera.pitch + (position.y - y) as f32 * 0.01).clamp(-1.5, 1.5);
+                    window.request_redraw();
+                }
+                cursor = Some((position.x, position.y));
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / 40f32,
+                };
+                camera.distance = (camera.distance * 0.9f32.powf(lines)).clamp(1f32, 2000f32);
+                window.request_redraw();
+            }
+            WindowEvent::RedrawRequested => {
+                if let Err(e) = renderer.render(&camera) {
+                    result = Err(e);
+                    elwt.exit();
+                }
+            }
+            _ => (),
+        },
+        Event::AboutToWait => {
+            let mut updated = false;
+            for update in rx.try_iter() {
+                for (id, mesh) in &update {
+                    for pos in &mesh.positions {
+                        let pos = Vector3::from(*pos);
+                        bounds = Some(match bounds {
+                            Some((lo, hi)) => (lo.inf(&pos), hi.sup(&pos)),
+                            None => (pos, pos),
+                        });
+                    }
+                    renderer.scene.upload(*id, mesh);
+                }
+                updated = true;
+            }
+            if updated {
+                if let (None, Some((lo, hi))) = (planned, bounds) {
+                    camera.target = (lo + hi) * 0.5;
+                }
+                window.request_redraw();
+            }
+            elwt.set_control_flow(ControlFlow::WaitUntil(
+                Instant::now() + Duration::from_millis(16),
+            ));
+        }
+        _ => (),
+    })?;
+
+    // the mesher stops once the receiver is gone, and the worker at the next layer
+    drop(rx);
+    if mesher.join().is_err() || worker.join().is_err() {
+        warn!("simulation panicked");
+    }
+    result
+}
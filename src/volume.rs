@@ -0,0 +1,158 @@
+use super::{storage, Metadata, Voxel, VoxelIdx, UNIT};
+use anyhow::Result;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+
+/// Occupancy of every voxel in the bounding box, for volume renderers and scientific
+/// tools. Samples are ordered x fastest, then y, then z, and coordinates are machine
+/// coordinates in millimeters, without the bed centering of mesh exports.
+pub struct Volume {
+    dims: [usize; 3],
+    min: VoxelIdx,
+    /// ranges of each column in the bounding box, rows from `min`
+    columns: Vec<Vec<Range<i32>>>,
+}
+
+impl Volume {
+    pub fn new<V: Voxel>(v: &V) -> Self {
+        let bb = v.bounding_box();
+        if bb.count == 0 {
+            return Self {
+                dims: [0; 3],
+                min: bb.bound_min,
+                columns: Vec::new(),
+            };
+        }
+
+        let (min, max) = (bb.bound_min, bb.bound_max);
+        let dims = [0, 1, 2].map(|i| (max[i] - min[i] + 1) as usize);
+        let mut columns = Vec::with_capacity(dims[0] * dims[1]);
+        for y in min[1]..=max[1] {
+            for x in min[0]..=max[0] {
+                columns.push(v.column(x, y));
+            }
+        }
+        Self { dims, min, columns }
+    }
+
+    /// Center of the first sample, in millimeters.
+    fn origin(&self) -> [f32; 3] {
+        [0, 1, 2].map(|i| self.min[i] as f32 * UNIT)
+    }
+
+    /// Number of samples along x, y and z.
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    /// Calls `f` with the coordinate and occupancy of every sample, in file order.
+    pub fn for_each(&self, mut f: impl FnMut(VoxelIdx, bool)) {
+        for dz in 0..self.dims[2] {
+            let z = self.min[2] + dz as i32;
+            for (i, column) in self.columns.iter().enumerate() {
+                let x = self.min[0] + (i % self.dims[0]) as i32;
+                let y = self.min[1] + (i / self.dims[0]) as i32;
+                f([x, y, z].into(), column.iter().any(|r| r.contains(&z)));
+            }
+        }
+    }
+
+    /// NRRD with a byte per sample, 1 for occupied voxels.
+    pub fn write_nrrd(&self, path: &str, meta: &Metadata) -> Result<()> {
+        let mut w = BufWriter::new(storage::create(path)?);
+        let [x, y, z] = self.origin();
+        writeln!(w, "NRRD0004")?;
+        writeln!(w, "type: uint8")?;
+        writeln!(w, "dimension: 3")?;
+        writeln!(w, "space dimension: 3")?;
+        writeln!(
+            w,
+            "sizes: {} {} {}",
+            self.dims[0], self.dims[1], self.dims[2]
+        )?;
+        writeln!(
+            w,
+            "space directions: ({u},0,0) (0,{u},0) (0,0,{u})",
+            u = UNIT
+        )?;
+        writeln!(w, "space origin: ({},{},{})", x, y, z)?;
+        writeln!(w, "space units: \"mm\" \"mm\" \"mm\"")?;
+        writeln!(w, "encoding: raw")?;
+        for (k, v) in meta.entries() {
+            writeln!(w, "{}:={}", k, v.replace('\n', " "))?;
+        }
+        writeln!(w)?;
+
+        let mut result = Ok(());
+        self.for_each(|_, occupied| {
+            if result.is_ok() {
+                result = w.write_all(&[occupied as u8]);
+            }
+        });
+        result?;
+        w.into_inner().map_err(|e| e.into_error())?.finish()
+    }
+
+    /// Samples packed 8 per byte, least significant bit first.
+    pub fn to_bits(&self) -> Vec<u8> {
+        let mut bits = vec![0u8; self.dims.iter().product::<usize>().div_ceil(8)];
+        let mut i = 0;
+        self.for_each(|_, occupied| {
+            if occupied {
+                bits[i / 8] |= 1 << (i % 8);
+            }
+            i += 1;
+        });
+        bits
+    }
+
+    /// Raw bitmask from `to_bits`, with a JSON header at `<path>.json`.
+    pub fn write_raw(&self, path: &str, meta: &Metadata) -> Result<()> {
+        storage::write(path, self.to_bits())?;
+
+        let file = std::path::Path::new(path)
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let header = serde_json::json!({
+            "file": file,
+            "dims": self.dims,
+            "order": "xyz",
+            "encoding": "bits-lsb-first",
+            "spacing_mm": UNIT,
+            "origin_mm": self.origin(),
+            "metadata": meta.to_json(),
+        });
+        let header = serde_json::to_vec_pretty(&header)?;
+        storage::write(&format!("{}.json", path), header)
+    }
+
+    /// Writes NRRD for `.nrrd` files, a raw bitmask with a JSON header otherwise.
+    pub fn write(&self, path: &str, meta: &Metadata) -> Result<()> {
+        if path.ends_with(".nrrd") {
+            self.write_nrrd(path, meta)
+        } else {
+            self.write_raw(path, meta)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_volume() {
+        let mut v = MonotonicVoxel::default();
+        v.add([1, 1, 1].into());
+        v.add([2, 1, 1].into());
+        v.add([1, 2, 3].into());
+
+        let volume = Volume::new(&v);
+        assert_eq!(volume.dims, [2, 2, 3]);
+        assert_eq!(volume.origin(), [0.04, 0.04, 0.04]);
+        // z=1: [1, 1], [2, 1] occupied; z=3: [1, 2]
+        assert_eq!(volume.to_bits(), vec![0b0000_0011, 0b0000_0100]);
+    }
+}
@@ -0,0 +1,211 @@
+use super::color::{Colors, Rgb};
+use super::{storage, Voxel, VoxelIdx};
+use anyhow::Result;
+use log::*;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Largest edge of MagicaVoxel models; larger grids are split into models of this size.
+const MODEL_SIZE: i32 = 256;
+/// Palette entry of voxels without a filament color, like renders.
+const BASE: Rgb = [204, 204, 199];
+
+/// Occupied voxels of any backend as MagicaVoxel models, keeping the voxel grid instead
+/// of meshing it. Grids over 256 voxels along an axis are split into several models,
+/// placed by a scene graph. Filament colors become palette entries, up to 255.
+pub struct Vox {
+    /// voxels of each model by its index along each axis, in model coordinates
+    models: BTreeMap<[i32; 3], Vec<[u8; 4]>>,
+    min: VoxelIdx,
+    max: VoxelIdx,
+    palette: Vec<Rgb>,
+}
+
+impl Vox {
+    pub fn new<V: Voxel + ?Sized>(v: &V, colors: Option<&Colors>) -> Self {
+        let bb = v.bounding_box();
+        let (min, max) = (bb.bound_min, bb.bound_max);
+        let mut vox = Self {
+            models: BTreeMap::new(),
+            min,
+            max,
+            palette: vec![BASE],
+        };
+        if bb.count == 0 {
+            return vox;
+        }
+        for y in min[1]..=max[1] {
+            for x in min[0]..=max[0] {
+                for range in v.column(x, y) {
+                    for z in range {
+                        let c = VoxelIdx::from([x, y, z]);
+                        let index = colors
+                            .and_then(|colors| colors.color_of(c))
+                            .map_or(1, |rgb| vox.index_of(rgb));
+                        let d = c - min;
+                        let key = [0, 1, 2].map(|i| d[i] / MODEL_SIZE);
+                        let [x, y, z] = [0, 1, 2].map(|i| (d[i] % MODEL_SIZE) as u8);
+                        vox.models.entry(key).or_default().push([x, y, z, index]);
+                    }
+                }
+            }
+        }
+        vox
+    }
+
+    /// Palette index of `rgb`, from 1. Colors past the palette fall back to the base.
+    fn index_of(&mut self, rgb: Rgb) -> u8 {
+        if let Some(i) = self.palette.iter().position(|c| *c == rgb) {
+            return i as u8 + 1;
+        }
+        if self.palette.len() == 255 {
+            warn!(
+                "vox: palette full, #{:02x}{:02x}{:02x} as the base",
+                rgb[0], rgb[1], rgb[2]
+            );
+            return 1;
+        }
+        self.palette.push(rgb);
+        self.palette.len() as u8
+    }
+
+    /// Size of the model of `key`, clipped to the bounding box.
+    fn size(&self, key: [i32; 3]) -> [i32; 3] {
+        [0, 1, 2].map(|i| {
+            let extent = self.max[i] - self.min[i] + 1;
+            (extent - key[i] * MODEL_SIZE).min(MODEL_SIZE)
+        })
+    }
+
+    pub fn models(&self) -> usize {
+        self.models.len()
+    }
+
+    fn write_to<W: Write>(&self, mut w: W) -> Result<()> {
+        let mut children = Vec::new();
+        for (key, voxels) in &self.models {
+            let mut size = Vec::with_capacity(12);
+            self.size(*key)
+                .iter()
+                .for_each(|s| size.extend(s.to_le_bytes()));
+            chunk(&mut children, b"SIZE", &size);
+            let mut xyzi = Vec::with_capacity(4 + voxels.len() * 4);
+            xyzi.extend((voxels.len() as u32).to_le_bytes());
+            voxels.iter().for_each(|v| xyzi.extend(v));
+            chunk(&mut children, b"XYZI", &xyzi);
+        }
+
+        // root transform and group, then a transform and shape per model; transforms
+        // place the centers of models, rounded down
+        let n = self.models.len() as i32;
+        let mut root = node(0, &[]);
+        root.extend(transform(1, &[]));
+        chunk(&mut children, b"nTRN", &root);
+        let mut group = node(1, &[]);
+        group.extend(n.to_le_bytes());
+        (0..n).for_each(|m| group.extend((2 + 2 * m).to_le_bytes()));
+        chunk(&mut children, b"nGRP", &group);
+        for (m, key) in self.models.keys().enumerate() {
+            let id = 2 + 2 * m as i32;
+            let size = self.size(*key);
+            let t = [0, 1, 2].map(|i| key[i] * MODEL_SIZE + size[i] / 2);
+            let t = format!("{} {} {}", t[0], t[1], t[2]);
+            let mut trn = node(id, &[]);
+            trn.extend(transform(id + 1, &[("_t", &t)]));
+            chunk(&mut children, b"nTRN", &trn);
+            let mut shp = node(id + 1, &[]);
+            shp.extend(1i32.to_le_bytes());
+            shp.extend((m as i32).to_le_bytes());
+            shp.extend(dict(&[]));
+            chunk(&mut children, b"nSHP", &shp);
+        }
+
+        let mut rgba = Vec::with_capacity(256 * 4);
+        for i in 0..256 {
+            let [r, g, b] = self.palette.get(i).copied().unwrap_or([0; 3]);
+            rgba.extend([r, g, b, 255]);
+        }
+        chunk(&mut children, b"RGBA", &rgba);
+
+        w.write_all(b"VOX ")?;
+        w.write_all(&150u32.to_le_bytes())?;
+        w.write_all(b"MAIN")?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(&(children.len() as u32).to_le_bytes())?;
+        w.write_all(&children)?;
+        Ok(())
+    }
+
+    /// Writes the models as a .vox file.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let mut w = storage::create(path)?;
+        self.write_to(&mut w)?;
+        w.finish()
+    }
+}
+
+/// Appends a chunk of `id` without children.
+fn chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    out.extend(id);
+    out.extend((content.len() as u32).to_le_bytes());
+    out.extend(0u32.to_le_bytes());
+    out.extend(content);
+}
+
+fn dict(entries: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = (entries.len() as i32).to_le_bytes().to_vec();
+    for s in entries.iter().flat_map(|(k, v)| [k, v]) {
+        out.extend((s.len() as i32).to_le_bytes());
+        out.extend(s.as_bytes());
+    }
+    out
+}
+
+/// Node id and attributes, heading every scene graph node.
+fn node(id: i32, attributes: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = id.to_le_bytes().to_vec();
+    out.extend(dict(attributes));
+    out
+}
+
+/// Rest of a transform node of `child`, with a single frame.
+fn transform(child: i32, frame: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    // child, reserved, layer, frames
+    for v in [child, -1, 0, 1] {
+        out.extend(v.to_le_bytes());
+    }
+    out.extend(dict(frame));
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MonotonicVoxel;
+
+    #[test]
+    pub fn test_vox() {
+        let mut v = MonotonicVoxel::default();
+        v.add([10, 0, 0].into());
+        v.add([10, 1, 0].into());
+        v.add([310, 0, 2].into());
+        let vox = Vox::new(&v, None);
+        // 301 voxels along x make two models, of 256 and 45
+        assert_eq!(vox.models(), 2);
+        assert_eq!(vox.size([0, 0, 0]), [256, 2, 3]);
+        assert_eq!(vox.size([1, 0, 0]), [45, 2, 3]);
+        assert_eq!(vox.models[&[1, 0, 0]], vec![[44, 0, 2, 1]]);
+
+        let mut buf = Vec::new();
+        vox.write_to(&mut buf).unwrap();
+        assert_eq!(&buf[..4], b"VOX ");
+        let children = u32::from_le_bytes(buf[16..20].try_into().unwrap()) as usize;
+        assert_eq!(buf.len(), 20 + children);
+        let count = |id: &[u8]| buf.windows(4).filter(|w| *w == id).count();
+        assert_eq!((count(b"SIZE"), count(b"XYZI"), count(b"nSHP")), (2, 2, 2));
+        assert_eq!(count(b"nTRN"), 3);
+        // the second model is centered 22 voxels into it
+        assert!(buf.windows(7).any(|w| w == b"278 1 1"));
+    }
+}
@@ -133,6 +133,92 @@ impl std::ops::SubAssign for VoxelIdx {
     }
 }
 
+/// Voxel entered by a `Traversal`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Crossing {
+    pub voxel: VoxelIdx,
+    /// fraction of the segment where it is entered, 0 for the first voxel
+    pub t: f32,
+    /// axis and direction, 1 or -1, of the step into it, none for the first voxel
+    pub step: Option<(usize, i32)>,
+}
+
+/// Voxels crossed by a segment in order, by the traversal of Amanatides and Woo, so each
+/// shares a face with the one before. Voxel `i` spans from `i` to `i + 1` along each
+/// axis; see `Traversal::centered` for voxels centered on integer coordinates.
+pub struct Traversal {
+    cell: [i32; 3],
+    end: [i32; 3],
+    step: [i32; 3],
+    /// fraction of the segment of the next boundary along each axis
+    t_max: [f32; 3],
+    /// fraction of the segment between boundaries along each axis
+    t_delta: [f32; 3],
+    next: Option<Crossing>,
+}
+
+impl Traversal {
+    pub fn new(from: [f32; 3], to: [f32; 3]) -> Self {
+        let cell = from.map(|c| c.floor() as i32);
+        let mut step = [0i32; 3];
+        let mut t_max = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for axis in 0..3 {
+            let d = to[axis] - from[axis];
+            if d > 0f32 {
+                step[axis] = 1;
+                t_max[axis] = ((cell[axis] + 1) as f32 - from[axis]) / d;
+                t_delta[axis] = 1f32 / d;
+            } else if d < 0f32 {
+                step[axis] = -1;
+                t_max[axis] = (cell[axis] as f32 - from[axis]) / d;
+                t_delta[axis] = -1f32 / d;
+            }
+        }
+        Self {
+            cell,
+            end: to.map(|c| c.floor() as i32),
+            step,
+            t_max,
+            t_delta,
+            next: Some(Crossing {
+                voxel: cell.into(),
+                t: 0f32,
+                step: None,
+            }),
+        }
+    }
+
+    /// Voxels centered on integer coordinates, like positions rounded to voxels.
+    pub fn centered(from: [f32; 3], to: [f32; 3]) -> Self {
+        Self::new(from.map(|c| c + 0.5f32), to.map(|c| c + 0.5f32))
+    }
+}
+
+impl Iterator for Traversal {
+    type Item = Crossing;
+
+    fn next(&mut self) -> Option<Crossing> {
+        let current = self.next.take()?;
+        // only axes with boundaries left to cross, so rounding never overshoots the end.
+        // ties through edges and corners go to the last axis
+        let axis = (0..3)
+            .rev()
+            .filter(|&a| self.cell[a] != self.end[a])
+            .min_by(|&a, &b| self.t_max[a].total_cmp(&self.t_max[b]));
+        if let Some(axis) = axis {
+            self.cell[axis] += self.step[axis];
+            self.next = Some(Crossing {
+                voxel: self.cell.into(),
+                t: self.t_max[axis].clamp(0f32, 1f32),
+                step: Some((axis, self.step[axis])),
+            });
+            self.t_max[axis] += self.t_delta[axis];
+        }
+        Some(current)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -145,6 +231,25 @@ mod test {
         assert_eq!(idx0.bb_max(&idx1), VoxelIdx::new([4, 3, 3]));
     }
 
+    #[test]
+    pub fn test_traversal() {
+        let voxels = |t: Traversal| t.map(|c| <[i32; 3]>::from(c.voxel)).collect::<Vec<_>>();
+        assert_eq!(
+            voxels(Traversal::new([0.5, 0.5, 0.5], [2.5, 1.5, 0.5])),
+            vec![[0, 0, 0], [1, 0, 0], [1, 1, 0], [2, 1, 0]]
+        );
+        assert_eq!(
+            voxels(Traversal::centered([0.0, 0.0, 0.0], [-1.2, 0.0, 0.0])),
+            vec![[0, 0, 0], [-1, 0, 0]]
+        );
+        assert_eq!(voxels(Traversal::new([0.2; 3], [0.7; 3])), vec![[0, 0, 0]]);
+
+        let crossings = Traversal::new([0.0, 0.5, 0.5], [4.0, 0.5, 0.5]).collect::<Vec<_>>();
+        assert_eq!(crossings.len(), 5);
+        assert_eq!(crossings[2].t, 0.5);
+        assert_eq!(crossings[2].step, Some((0, 1)));
+    }
+
     #[test]
     pub fn test_map() {
         let idx3 = VoxelIdx::new([1, 2, 2]);
@@ -0,0 +1,17 @@
+use super::{gltf, simulate, surface, Metadata, MonotonicVoxel, Params, UNIT};
+use wasm_bindgen::prelude::*;
+
+/// Converts gcode bytes to binary glTF in millimeters, with the bed center at the
+/// origin like obj exports. Stops before `layer` if given.
+#[wasm_bindgen(js_name = gcodeToGlb)]
+pub fn gcode_to_glb(gcode: &[u8], layer: Option<u32>) -> Result<Vec<u8>, JsError> {
+    let gcode = std::str::from_utf8(gcode)?;
+    let layer = layer.map(|l| l as usize).unwrap_or(usize::MAX);
+    let params = Params::default();
+    let sim = simulate::<MonotonicVoxel, _>(gcode, layer, &params, |_, _| Ok(()))
+        .map_err(|e| JsError::new(&e.to_string()))?;
+
+    let mut model = surface::to_model(&sim.voxel, surface::ExportMode::Full);
+    model.metadata = Metadata::new().with("layer", layer);
+    Ok(gltf::to_glb(&model, [-90f32, -90f32, 0f32], UNIT))
+}
@@ -0,0 +1,231 @@
+use super::http::{self, Url};
+use super::{Observer, Simulation, Voxel};
+use anyhow::Result;
+use log::*;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+
+/// Most events waiting to be posted, beyond which events are dropped.
+const QUEUE: usize = 16;
+
+/// Posts JSON progress events of a conversion job to an HTTP endpoint, for farm
+/// orchestrators: `started`, `layer` after each layer, then `finished` or `failed`. Each
+/// event carries the gcode filename. Plain `http://` only, without a TLS client.
+///
+/// Events are posted by a background thread, so a slow endpoint does not hold up the
+/// simulation. When the queue is full, `layer` events are dropped, while `finished` and
+/// `failed` wait for room, as orchestrators rely on them. The first failed post is
+/// logged and disables the hook for the rest of the job, which goes on.
+#[derive(Debug)]
+pub struct Webhook {
+    events: Option<SyncSender<(String, String)>>,
+    worker: Option<JoinHandle<()>>,
+    gcode: String,
+}
+
+/// Posts `body` to `url`.
+fn post(url: &Url, body: &str) -> Result<()> {
+    let headers = [("Content-Type", "application/json")];
+    http::request(url, "POST", &url.path, &headers, body.as_bytes())?;
+    Ok(())
+}
+
+impl Webhook {
+    /// Webhook of `url` like `http://orchestrator:8080/jobs/42`, for jobs of `gcode`.
+    pub fn new(url: &str, gcode: &str) -> Result<Self> {
+        let url = Url::parse(url).map_err(|e| e.context("webhook"))?;
+        let (events, queue) = mpsc::sync_channel::<(String, String)>(QUEUE);
+        let worker = std::thread::spawn(move || {
+            for (event, body) in queue.iter() {
+                if let Err(e) = post(&url, &body) {
+                    warn!(
+                        "webhook {}{}: {} event not posted, no more events are: {}",
+                        url.host, url.path, event, e
+                    );
+                    return;
+                }
+            }
+        });
+        Ok(Self {
+            events: Some(events),
+            worker: Some(worker),
+            gcode: gcode.to_owned(),
+        })
+    }
+
+    /// Body of `event` with the fields of the `fields` object.
+    fn body(&self, event: &str, fields: serde_json::Value) -> String {
+        let mut body = serde_json::json!({
+            "event": event,
+            "gcode": self.gcode,
+        });
+        if let (Some(body), serde_json::Value::Object(fields)) = (body.as_object_mut(), fields) {
+            body.extend(fields);
+        }
+        body.to_string()
+    }
+
+    /// Queues `event` with the fields of the `fields` object, or drops it if the queue is
+    /// full.
+    pub fn send(&self, event: &str, fields: serde_json::Value) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        // a disconnected queue was logged by the worker
+        let body = self.body(event, fields);
+        if let Err(TrySendError::Full(_)) = events.try_send((event.to_owned(), body)) {
+            warn!("webhook: {} event dropped, {} events queued", event, QUEUE);
+        }
+    }
+
+    pub fn started(&self) {
+        self.send("started", serde_json::json!({}));
+    }
+
+    /// Posts `finished` with the fields of `result`, or `failed` with its error, after
+    /// queued events, waiting for room in the queue instead of dropping it.
+    pub fn finish(mut self, result: &Result<serde_json::Value>) {
+        let (event, fields) = match result {
+            Ok(fields) => ("finished", fields.clone()),
+            Err(e) => ("failed", serde_json::json!({ "error": format!("{:#}", e) })),
+        };
+        let body = self.body(event, fields);
+        if let Some(events) = &self.events {
+            // fails only once the worker stopped, which it logged
+            let _ = events.send((event.to_owned(), body));
+        }
+        self.close();
+    }
+
+    fn close(&mut self) {
+        self.events = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for Webhook {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Posts `layer` events with the layer index, simulated time in seconds and blocks.
+impl<V: Voxel> Observer<V> for Webhook {
+    fn on_layer_complete(&mut self, sim: &mut Simulation<V>, layer: usize) -> Result<()> {
+        self.send(
+            "layer",
+            serde_json::json!({
+                "layer": layer,
+                "time": sim.time,
+                "blocks": sim.voxel.blocks(),
+            }),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    /// Reads the body of a request from `stream`.
+    fn read_body(stream: TcpStream) -> (String, TcpStream) {
+        let mut reader = BufReader::new(stream);
+        let mut len = 0;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+            if let Some(v) = line.strip_prefix("Content-Length: ") {
+                len = v.trim().parse().unwrap();
+            }
+            line.clear();
+        }
+        let mut body = vec![0u8; len];
+        reader.read_exact(&mut body).unwrap();
+        (String::from_utf8(body).unwrap(), reader.into_inner())
+    }
+
+    #[test]
+    pub fn test_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in ["200 OK", "500 Internal Server Error"] {
+                let (stream, _) = listener.accept().unwrap();
+                let (body, mut stream) = read_body(stream);
+                bodies.push(body);
+                write!(stream, "HTTP/1.1 {}\r\n\r\n", status).unwrap();
+            }
+            (bodies, listener)
+        });
+
+        let url = format!("http://127.0.0.1:{}/jobs/1", port);
+        let webhook = Webhook::new(&url, "a.gcode").unwrap();
+        webhook.started();
+        webhook.send("layer", serde_json::json!({ "layer": 3 }));
+        // the post of layer 3 fails, which disables the hook
+        webhook.send("layer", serde_json::json!({ "layer": 4 }));
+        webhook.finish(&Err(anyhow::anyhow!("bad gcode")));
+
+        let (bodies, listener) = server.join().unwrap();
+        let started: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(started["event"], "started");
+        assert_eq!(started["gcode"], "a.gcode");
+        let layer: serde_json::Value = serde_json::from_str(&bodies[1]).unwrap();
+        assert_eq!(layer["layer"], 3);
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_err());
+
+        assert!(Webhook::new("https://a/b", "a.gcode").is_err());
+    }
+    #[test]
+    pub fn test_webhook_full() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (accepted, stalled) = mpsc::channel();
+        let (resume, resumed) = mpsc::channel();
+        // holds the post of `started` until resumed, then takes events until an empty
+        // connection
+        let server = std::thread::spawn(move || {
+            let mut events = Vec::new();
+            loop {
+                let (stream, _) = listener.accept().unwrap();
+                let (body, mut stream) = read_body(stream);
+                if body.is_empty() {
+                    return events;
+                }
+                if events.is_empty() {
+                    accepted.send(()).unwrap();
+                    resumed.recv().unwrap();
+                }
+                write!(stream, "HTTP/1.1 200 OK\r\n\r\n").unwrap();
+                events.push(serde_json::from_str::<serde_json::Value>(&body).unwrap());
+            }
+        });
+
+        let url = format!("http://127.0.0.1:{}/jobs/1", port);
+        let webhook = Webhook::new(&url, "a.gcode").unwrap();
+        webhook.started();
+        stalled.recv().unwrap();
+        for layer in 0..QUEUE + 4 {
+            webhook.send("layer", serde_json::json!({ "layer": layer }));
+        }
+        // the queue is full while finishing
+        let finish = std::thread::spawn(move || {
+            webhook.finish(&Ok(serde_json::json!({ "blocks": 10 })));
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        resume.send(()).unwrap();
+        finish.join().unwrap();
+        TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+        let events = server.join().unwrap();
+        assert_eq!(events.len(), 1 + QUEUE + 1);
+        assert_eq!(events[QUEUE]["layer"], QUEUE - 1);
+        assert_eq!(events[QUEUE + 1]["blocks"], 10);
+    }
+}
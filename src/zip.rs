@@ -0,0 +1,346 @@
+use anyhow::Result;
+use std::io::{Read, Write};
+
+/// Zip archive in memory, like 3MF project files. Reads what slicers write: stored or
+/// deflated entries, without zip64.
+pub struct Archive {
+    data: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed: usize,
+    size: usize,
+    /// offset of the local header
+    offset: usize,
+}
+
+const END_SIGNATURE: u32 = 0x06054b50;
+const CENTRAL_SIGNATURE: u32 = 0x02014b50;
+const LOCAL_SIGNATURE: u32 = 0x04034b50;
+const DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+/// 1980-01-01 in dos dates, the earliest
+const EPOCH: u16 = 0x21;
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16> {
+    let b = data
+        .get(at..at + 2)
+        .ok_or_else(|| anyhow::anyhow!("truncated zip"))?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32> {
+    let b = data
+        .get(at..at + 4)
+        .ok_or_else(|| anyhow::anyhow!("truncated zip"))?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+impl Archive {
+    pub fn new(data: Vec<u8>) -> Result<Self> {
+        // the end record is last, before a comment of up to 64KiB
+        let min = data.len().saturating_sub(22 + 0xffff);
+        let end = (min..data.len().saturating_sub(21))
+            .rev()
+            .find(|&at| u32_at(&data, at).ok() == Some(END_SIGNATURE))
+            .ok_or_else(|| anyhow::anyhow!("not a zip archive"))?;
+        let count = u16_at(&data, end + 10)? as usize;
+        let mut at = u32_at(&data, end + 16)? as usize;
+        anyhow::ensure!(
+            count != 0xffff && at != 0xffffffff,
+            "zip64 archives are not supported"
+        );
+
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            anyhow::ensure!(
+                u32_at(&data, at)? == CENTRAL_SIGNATURE,
+                "broken zip directory"
+            );
+            let name_len = u16_at(&data, at + 28)? as usize;
+            let extra_len = u16_at(&data, at + 30)? as usize;
+            let comment_len = u16_at(&data, at + 32)? as usize;
+            let name = data
+                .get(at + 46..at + 46 + name_len)
+                .ok_or_else(|| anyhow::anyhow!("truncated zip"))?;
+            let entry = Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: u16_at(&data, at + 10)?,
+                crc: u32_at(&data, at + 16)?,
+                compressed: u32_at(&data, at + 20)? as usize,
+                size: u32_at(&data, at + 24)? as usize,
+                offset: u32_at(&data, at + 42)? as usize,
+            };
+            anyhow::ensure!(
+                [entry.compressed, entry.size, entry.offset]
+                    .iter()
+                    .all(|&v| v != 0xffffffff),
+                "zip64 entries are not supported: {}",
+                entry.name
+            );
+            entries.push(entry);
+            at += 46 + name_len + extra_len + comment_len;
+        }
+        Ok(Self { data, entries })
+    }
+
+    /// Names of files in the archive, in directory order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|e| e.name.as_str())
+    }
+
+    /// Contents of the file `name`.
+    pub fn read(&self, name: &str) -> Result<Vec<u8>> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|e| e.name == name)
+            .ok_or_else(|| anyhow::anyhow!("no {} in the archive", name))?;
+        let at = entry.offset;
+        anyhow::ensure!(
+            u32_at(&self.data, at)? == LOCAL_SIGNATURE,
+            "broken zip entry: {}",
+            name
+        );
+        // sizes come from the directory, as local headers may leave them to a trailer
+        let start =
+            at + 30 + u16_at(&self.data, at + 26)? as usize + u16_at(&self.data, at + 28)? as usize;
+        let raw = self
+            .data
+            .get(start..start + entry.compressed)
+            .ok_or_else(|| anyhow::anyhow!("truncated zip entry: {}", name))?;
+
+        let data = match entry.method {
+            0 => raw.to_vec(),
+            8 => {
+                let mut data = Vec::with_capacity(entry.size);
+                flate2::read::DeflateDecoder::new(raw).read_to_end(&mut data)?;
+                data
+            }
+            method => anyhow::bail!("unsupported zip compression {}: {}", method, name),
+        };
+        let mut crc = flate2::Crc::new();
+        crc.update(&data);
+        anyhow::ensure!(
+            data.len() == entry.size && crc.sum() == entry.crc,
+            "corrupt zip entry: {}",
+            name
+        );
+        Ok(data)
+    }
+}
+
+/// Bytes written through to `inner`, with their checksum if `crc`.
+struct Tally<W> {
+    inner: W,
+    len: u64,
+    crc: Option<flate2::Crc>,
+}
+
+impl<W: Write> Write for Tally<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.len += n as u64;
+        if let Some(crc) = &mut self.crc {
+            crc.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Zip archive written as a stream, like 3MF files: deflated entries, with sizes and
+/// checksums in trailers after their data, without zip64.
+pub struct Writer<W: Write> {
+    w: Tally<W>,
+    directory: Vec<u8>,
+    count: usize,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            w: Tally {
+                inner: w,
+                len: 0,
+                crc: None,
+            },
+            directory: Vec::new(),
+            count: 0,
+        }
+    }
+
+    /// Adds the file `name`, with contents written by `write`.
+    pub fn add<F>(&mut self, name: &str, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut dyn Write) -> Result<()>,
+    {
+        let offset = self.w.len;
+        let name_len = name.len() as u16;
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(LOCAL_SIGNATURE.to_le_bytes());
+        // version 2.0, sizes in a trailer, deflated
+        header.extend([20, 0, 8, 0, 8, 0, 0, 0]);
+        header.extend(EPOCH.to_le_bytes());
+        header.extend([0u8; 12]);
+        header.extend(name_len.to_le_bytes());
+        header.extend([0, 0]);
+        header.extend(name.as_bytes());
+        self.w.write_all(&header)?;
+
+        let start = self.w.len;
+        let (size, crc) = {
+            let mut data = Tally {
+                inner: flate2::write::DeflateEncoder::new(&mut self.w, Default::default()),
+                len: 0,
+                crc: Some(flate2::Crc::new()),
+            };
+            write(&mut data)?;
+            data.inner.try_finish()?;
+            (data.len, data.crc.map_or(0, |c| c.sum()))
+        };
+        let compressed = self.w.len - start;
+        anyhow::ensure!(
+            [size, compressed, offset]
+                .iter()
+                .all(|&v| v < u32::MAX as u64)
+                && self.count < 0xffff,
+            "zip64 archives are not supported: {}",
+            name
+        );
+
+        let mut trailer = Vec::with_capacity(16);
+        trailer.extend(DESCRIPTOR_SIGNATURE.to_le_bytes());
+        trailer.extend(crc.to_le_bytes());
+        trailer.extend((compressed as u32).to_le_bytes());
+        trailer.extend((size as u32).to_le_bytes());
+        self.w.write_all(&trailer)?;
+
+        let d = &mut self.directory;
+        d.extend(CENTRAL_SIGNATURE.to_le_bytes());
+        d.extend([20, 0, 20, 0, 8, 0, 8, 0, 0, 0]);
+        d.extend(EPOCH.to_le_bytes());
+        d.extend(crc.to_le_bytes());
+        d.extend((compressed as u32).to_le_bytes());
+        d.extend((size as u32).to_le_bytes());
+        d.extend(name_len.to_le_bytes());
+        d.extend([0u8; 12]);
+        d.extend((offset as u32).to_le_bytes());
+        d.extend(name.as_bytes());
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Writes the directory, and returns the writer.
+    pub fn finish(mut self) -> Result<W> {
+        let at = self.w.len;
+        anyhow::ensure!(at < u32::MAX as u64, "zip64 archives are not supported");
+        let mut end = Vec::with_capacity(22);
+        end.extend(END_SIGNATURE.to_le_bytes());
+        end.extend([0u8; 4]);
+        end.extend((self.count as u16).to_le_bytes());
+        end.extend((self.count as u16).to_le_bytes());
+        end.extend((self.directory.len() as u32).to_le_bytes());
+        end.extend((at as u32).to_le_bytes());
+        end.extend([0, 0]);
+        self.w.write_all(&self.directory)?;
+        self.w.write_all(&end)?;
+        Ok(self.w.inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Zip of `files`, deflated if `deflate`.
+    fn zip(files: &[(&str, &[u8])], deflate: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for (name, content) in files {
+            let data = if deflate {
+                let mut e = flate2::write::DeflateEncoder::new(Vec::new(), Default::default());
+                e.write_all(content).unwrap();
+                e.finish().unwrap()
+            } else {
+                content.to_vec()
+            };
+            let mut crc = flate2::Crc::new();
+            crc.update(content);
+            let method: u16 = if deflate { 8 } else { 0 };
+            let offset = out.len() as u32;
+
+            // local header, with sizes in a trailer like streaming writers
+            out.extend(LOCAL_SIGNATURE.to_le_bytes());
+            out.extend([20, 0, 8, 0]);
+            out.extend(method.to_le_bytes());
+            out.extend([0u8; 16]);
+            out.extend((name.len() as u16).to_le_bytes());
+            out.extend([0, 0]);
+            out.extend(name.as_bytes());
+            out.extend(&data);
+
+            directory.extend(CENTRAL_SIGNATURE.to_le_bytes());
+            directory.extend([20, 0, 20, 0, 8, 0]);
+            directory.extend(method.to_le_bytes());
+            directory.extend([0u8; 4]);
+            directory.extend(crc.sum().to_le_bytes());
+            directory.extend((data.len() as u32).to_le_bytes());
+            directory.extend((content.len() as u32).to_le_bytes());
+            directory.extend((name.len() as u16).to_le_bytes());
+            directory.extend([0u8; 12]);
+            directory.extend(offset.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+        let at = out.len() as u32;
+        out.extend(&directory);
+        out.extend(END_SIGNATURE.to_le_bytes());
+        out.extend([0u8; 4]);
+        out.extend((files.len() as u16).to_le_bytes());
+        out.extend((files.len() as u16).to_le_bytes());
+        out.extend((directory.len() as u32).to_le_bytes());
+        out.extend(at.to_le_bytes());
+        out.extend([0, 0]);
+        out
+    }
+
+    #[test]
+    pub fn test_archive() {
+        let gcode = "G1 X10 Y10 E1\n".repeat(100);
+        for deflate in [false, true] {
+            let data = zip(
+                &[("a.txt", b"a"), ("dir/b.gcode", gcode.as_bytes())],
+                deflate,
+            );
+            let archive = Archive::new(data).unwrap();
+            assert_eq!(
+                archive.names().collect::<Vec<_>>(),
+                ["a.txt", "dir/b.gcode"]
+            );
+            assert_eq!(archive.read("dir/b.gcode").unwrap(), gcode.as_bytes());
+            assert_eq!(archive.read("a.txt").unwrap(), b"a");
+            assert!(archive.read("c.txt").is_err());
+        }
+
+        // streamed archives read back
+        let mut writer = Writer::new(Vec::new());
+        writer.add("a.txt", |w| Ok(w.write_all(b"a")?)).unwrap();
+        writer
+            .add("dir/b.gcode", |w| Ok(w.write_all(gcode.as_bytes())?))
+            .unwrap();
+        let archive = Archive::new(writer.finish().unwrap()).unwrap();
+        assert_eq!(archive.read("dir/b.gcode").unwrap(), gcode.as_bytes());
+        assert_eq!(archive.read("a.txt").unwrap(), b"a");
+
+        let mut data = zip(&[("a.txt", b"abc")], false);
+        data[35] = b'x';
+        assert!(Archive::new(data).unwrap().read("a.txt").is_err());
+        assert!(Archive::new(b"G1 X10\n".to_vec()).is_err());
+    }
+}